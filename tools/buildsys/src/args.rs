@@ -15,10 +15,11 @@ use url::Url;
 /// variable changes. The build type is represented with bit flags so that we can easily list
 /// multiple build types for a single variable. See `[BuildType]` and `[rerun_for_envs]` below to
 /// see how this list is used.
-const REBUILD_VARS: [(&str, u8); 14] = [
+const REBUILD_VARS: [(&str, u8); 15] = [
     ("BUILDSYS_ARCH", PACKAGE | KIT | VARIANT),
     ("BUILDSYS_CACERTS_BUNDLE_OVERRIDE", VARIANT),
     ("BUILDSYS_KITS_DIR", KIT),
+    ("BUILDSYS_KIT_METADATA_VERSIONS", KIT),
     ("BUILDSYS_EXTERNAL_KITS_DIR", PACKAGE | KIT | VARIANT),
     ("BUILDSYS_NAME", VARIANT),
     ("BUILDSYS_IMAGES_DIR", VARIANT),
@@ -85,6 +86,11 @@ pub(crate) struct Common {
     #[arg(long, env = "TWOLITER_TOOLS_DIR")]
     pub(crate) tools_dir: PathBuf,
 
+    /// events_json is the path of a file to which build events are appended as JSON lines. See
+    /// `buildsys::events` for the events that are written.
+    #[arg(long, env = "BUILDSYS_EVENTS_JSON")]
+    pub(crate) events_json: Option<PathBuf>,
+
     /// cicd_hack is used to suppress builds from running after all the cargo-related metadata is
     /// emitted. This allows cargo to create a fresh crate, and assumes that the corresponding
     /// build artifacts are already present. It is intended for use in a CI/CD scenario where some
@@ -148,6 +154,16 @@ pub(crate) struct BuildKitArgs {
     #[arg(long, env = "BUILDSYS_VERSION_IMAGE")]
     pub(crate) version_image: String,
 
+    /// The kit metadata versions to label the kit with, e.g. `v1,v2`, so that the kit can also be
+    /// used by releases of twoliter that only read older versions. Defaults to the newest version
+    #[arg(
+        long,
+        env = "BUILDSYS_KIT_METADATA_VERSIONS",
+        value_delimiter = ',',
+        value_parser = ["v1", "v2"]
+    )]
+    pub(crate) metadata_versions: Vec<String>,
+
     #[command(flatten)]
     pub(crate) common: Common,
 }
@@ -8,6 +8,7 @@ pub(crate) mod error;
 
 use crate::args::{BuildKitArgs, BuildPackageArgs, BuildVariantArgs, RepackVariantArgs};
 use bottlerocket_variant::Variant;
+use buildsys::events::{millis, BuildStatus, Event, EventLog};
 use buildsys::manifest::{
     ExternalKitMetadataView, ImageFeature, ImageFormat, ImageLayout, Manifest, PartitionPlan,
     SupportedArch,
@@ -30,6 +31,7 @@ use std::num::NonZeroU16;
 use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 use std::process::Output;
+use std::time::Instant;
 use walkdir::{DirEntry, WalkDir};
 
 /*
@@ -159,6 +161,7 @@ impl KitBuildArgs {
         args.build_arg("EXTERNAL_KIT_METADATA", &self.external_kit_metadata);
         args.build_arg("VENDOR", &self.vendor);
         args.build_arg("LOCAL_KIT_DEPENDENCIES", self.local_kits.join(" "));
+        args.build_arg("KIT_METADATA_VERSIONS", self.metadata_versions.join(" "));
         args
     }
 }
@@ -168,6 +171,7 @@ struct KitBuildArgs {
     package_dependencies: Vec<String>,
     external_kit_metadata: String,
     local_kits: Vec<String>,
+    metadata_versions: Vec<String>,
     vendor: String,
     version_build: String,
     version_id: String,
@@ -323,6 +327,7 @@ pub(crate) struct DockerBuild {
     common_build_args: CommonBuildArgs,
     target_build_args: TargetBuildArgs,
     secrets_args: Vec<String>,
+    events: EventLog,
 }
 
 impl DockerBuild {
@@ -347,6 +352,7 @@ impl DockerBuild {
             root_dir: args.common.root_dir.clone(),
             artifacts_dirs: vec![per_package_dir, old_package_dir],
             state_dir: args.common.state_dir,
+            events: EventLog::new(args.common.events_json.clone()),
             artifact_name: package.to_string(),
             common_build_args: CommonBuildArgs::new(
                 &args.common.root_dir,
@@ -387,6 +393,7 @@ impl DockerBuild {
             root_dir: args.common.root_dir.clone(),
             artifacts_dirs: vec![per_kit_dir],
             state_dir: args.common.state_dir,
+            events: EventLog::new(args.common.events_json.clone()),
             artifact_name: kit.to_string(),
             common_build_args: CommonBuildArgs::new(
                 &args.common.root_dir,
@@ -399,6 +406,7 @@ impl DockerBuild {
                 vendor: manifest.info().kit_vendor().context(error::GraphSnafu)?,
                 local_kits: manifest.kit_dependencies().context(error::GraphSnafu)?,
                 external_kit_metadata: EXTERNAL_KIT_METADATA.into(),
+                metadata_versions: args.metadata_versions,
                 package_dependencies: manifest.package_dependencies().context(error::GraphSnafu)?,
                 version_build: args.version_build,
                 version_id: args.version_image,
@@ -441,6 +449,7 @@ impl DockerBuild {
                 .image_dir
                 .join(format!("{}-{}", args.common.arch, variant))],
             state_dir: args.common.state_dir,
+            events: EventLog::new(args.common.events_json.clone()),
             artifact_name: variant.clone(),
             common_build_args: CommonBuildArgs::new(
                 &args.common.root_dir,
@@ -524,6 +533,7 @@ impl DockerBuild {
                 .image_dir
                 .join(format!("{}-{}", args.common.arch, variant))],
             state_dir: args.common.state_dir,
+            events: EventLog::new(args.common.events_json.clone()),
             artifact_name: variant.clone(),
             common_build_args: CommonBuildArgs::new(
                 &args.common.root_dir,
@@ -645,9 +655,17 @@ impl DockerBuild {
             let _ = docker(&run_bypass, Retry::No);
         });
 
+        self.emit(Event::BuildStarted {
+            artifact: self.artifact_name.clone(),
+            build_type: self.target_build_args.build_type(),
+            arch: self.common_build_args.arch.to_string(),
+        });
+        let start = Instant::now();
+
         // Build the image, which builds the artifacts we want.
         // Work around transient, known failure cases with Docker.
-        let build_result = docker(
+        let mut attempts = DockerAttempts::default();
+        let build_result = docker_with_attempts(
             &build,
             Retry::Yes {
                 attempts: DOCKER_BUILD_MAX_ATTEMPTS,
@@ -658,6 +676,7 @@ impl DockerBuild {
                     &*CREATEREPO_C_READ_HEADER_ERROR,
                 ],
             },
+            &mut attempts,
         );
 
         // Clean up our bypass container.
@@ -666,16 +685,35 @@ impl DockerBuild {
         // Stop the runtime and the background threads.
         runtime.shutdown_background();
 
-        // Check whether the build succeeded before continuing.
-        build_result?;
-
-        // Clean up our image now that we're done.
-        docker(&rm_image, Retry::No)?;
+        // Check whether the build succeeded before continuing, then clean up our image now that
+        // we're done and copy artifacts to the expected directory and write markers to track them.
+        let result = build_result
+            .and_then(|_| docker(&rm_image, Retry::No))
+            .and_then(|_| copy_build_files(&marker_dir, &self.artifacts_dirs[0]));
+
+        self.emit(Event::BuildFinished {
+            artifact: self.artifact_name.clone(),
+            build_type: self.target_build_args.build_type(),
+            arch: self.common_build_args.arch.to_string(),
+            duration_ms: millis(start.elapsed()),
+            attempts: attempts.count,
+            status: if result.is_ok() {
+                BuildStatus::Succeeded
+            } else {
+                BuildStatus::Failed
+            },
+            exit_code: attempts.exit_code,
+        });
 
-        // Copy artifacts to the expected directory and write markers to track them.
-        copy_build_files(&marker_dir, &self.artifacts_dirs[0])?;
+        result
+    }
 
-        Ok(())
+    /// Writes a build event. Failing to write an event should not fail the build, so errors are
+    /// reported as warnings.
+    fn emit(&self, event: Event) {
+        if let Err(e) = self.events.emit(&event) {
+            println!("cargo:warning=Failed to write build event: {e}");
+        }
     }
 
     fn build_args(&self) -> Vec<String> {
@@ -699,6 +737,22 @@ impl DockerBuild {
 
 /// Run `docker` with the specified arguments.
 fn docker(args: &[String], retry: Retry) -> Result<Output> {
+    docker_with_attempts(args, retry, &mut DockerAttempts::default())
+}
+
+/// The number of times a `docker` command ran, and the exit code of its last run.
+#[derive(Debug, Default)]
+struct DockerAttempts {
+    count: u16,
+    exit_code: Option<i32>,
+}
+
+/// Run `docker` with the specified arguments, recording each attempt in `attempts`.
+fn docker_with_attempts(
+    args: &[String],
+    retry: Retry,
+    attempts: &mut DockerAttempts,
+) -> Result<Output> {
     let mut max_attempts: u16 = 1;
     let mut retry_messages: &[&Regex] = &[];
     if let Retry::Yes { attempts, messages } = retry {
@@ -714,6 +768,8 @@ fn docker(args: &[String], retry: Retry) -> Result<Output> {
             .unchecked()
             .run()
             .context(error::CommandStartSnafu)?;
+        attempts.count = attempt;
+        attempts.exit_code = output.status.code();
 
         let stdout = String::from_utf8_lossy(&output.stdout);
         println!("{}", &stdout);
@@ -13,6 +13,7 @@ It implements a two-tier approach to retrieval: files are first pulled from the
 pub(crate) mod error;
 use error::Result;
 
+use buildsys::events::{millis, Event, EventLog, FetchSource};
 use buildsys::manifest;
 use filetime::{set_file_mtime, FileTime};
 use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT};
@@ -21,6 +22,7 @@ use snafu::{ensure, OptionExt, ResultExt};
 use std::fs::{self, File};
 use std::io::{self, BufWriter};
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 use url::Url;
 
 pub(crate) struct LookasideCache {
@@ -33,6 +35,9 @@ pub(crate) struct LookasideCache {
     /// Whether we are allowed to pull sources from upstream URLs. When this is false, it can be
     /// overridden by `upstream-fallback` in the manifest.
     upstream_fallback: bool,
+
+    /// Where to record each download.
+    events: EventLog,
 }
 
 impl LookasideCache {
@@ -40,11 +45,13 @@ impl LookasideCache {
         version: impl AsRef<str>,
         lookaside_cache: Url,
         upstream_fallback: bool,
+        events: EventLog,
     ) -> Self {
         Self {
             version: version.as_ref().to_string(),
             lookaside_cache,
             upstream_fallback,
+            events,
         }
     }
 
@@ -83,7 +90,7 @@ impl LookasideCache {
                 })?
                 .extend([name, hash, name]);
             let url = url.to_string();
-            match self.fetch_file(&url, &tmp, hash) {
+            match self.fetch_and_record(name, &url, FetchSource::Lookaside, &tmp, hash) {
                 Ok(_) => {
                     fs::rename(&tmp, path)
                         .context(error::ExternalFileRenameSnafu { path: &tmp })?;
@@ -95,7 +102,7 @@ impl LookasideCache {
                     if f.force_upstream.unwrap_or(false) || self.upstream_fallback {
                         println!("Error fetching from lookaside cache: {}", e);
                         println!("Fetching {:?} from upstream source", url_file_name);
-                        self.fetch_file(&f.url, &tmp, hash)?;
+                        self.fetch_and_record(name, &f.url, FetchSource::Upstream, &tmp, hash)?;
                         fs::rename(&tmp, path)
                             .context(error::ExternalFileRenameSnafu { path: &tmp })?;
                         set_file_mtime(path, mtime).context(error::SetMtimeSnafu { path })?;
@@ -111,6 +118,37 @@ impl LookasideCache {
         Ok(())
     }
 
+    /// Calls `fetch_file` and records the outcome as a build event.
+    fn fetch_and_record(
+        &self,
+        name: &str,
+        url: &str,
+        source: FetchSource,
+        path: &Path,
+        hash: &str,
+    ) -> Result<()> {
+        let start = Instant::now();
+        let result = self.fetch_file(url, path, hash);
+        let event = match &result {
+            Ok(()) => Event::ExternalFileFetched {
+                file: name.to_string(),
+                url: url.to_string(),
+                source,
+                duration_ms: millis(start.elapsed()),
+            },
+            Err(e) => Event::ExternalFileFetchFailed {
+                file: name.to_string(),
+                url: url.to_string(),
+                source,
+                error: e.to_string(),
+            },
+        };
+        if let Err(e) = self.events.emit(&event) {
+            println!("cargo:warning=Failed to write build event: {e}");
+        }
+        result
+    }
+
     /// Retrieves a file from the specified URL and write it to the given path,
     /// then verifies the contents against the SHA-512 hash provided.
     fn fetch_file<P: AsRef<Path>>(&self, url: &str, path: P, hash: &str) -> Result<()> {
@@ -0,0 +1,215 @@
+/*!
+# Build events
+
+Twoliter and buildsys can describe the progress of a build as a stream of events, so that tools
+such as CI dashboards do not need to scrape the interleaved output of `cargo make`. The stream is
+enabled by passing `--events-json <path>` to `twoliter build`, which hands the path to buildsys
+through the `BUILDSYS_EVENTS_JSON` environment variable.
+
+Each event is written to the file as a single line of JSON, for example:
+```ignore
+{"time-ms":1700000000000,"event":"build-finished","artifact":"kernel-6.1","build-type":"package","arch":"x86_64","duration-ms":81234,"attempts":1,"status":"succeeded","exit-code":0}
+```
+
+Many buildsys processes run at the same time during a build, so the file is opened in append mode
+for every event and each event is written with a single call.
+*/
+
+use crate::BuildType;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The environment variable that holds the path of the events file.
+pub const EVENTS_JSON_ENV: &str = "BUILDSYS_EVENTS_JSON";
+
+/// Something that happened during a build.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    /// The project's SDK and kits were resolved to specific images.
+    #[serde(rename_all = "kebab-case")]
+    KitsResolved {
+        kits: Vec<ResolvedKit>,
+        duration_ms: u64,
+    },
+
+    /// The build tools were installed into the project.
+    #[serde(rename_all = "kebab-case")]
+    ToolsInstalled {
+        tools_dir: PathBuf,
+        duration_ms: u64,
+    },
+
+    /// A `docker build` of an artifact is about to start.
+    #[serde(rename_all = "kebab-case")]
+    BuildStarted {
+        artifact: String,
+        build_type: BuildType,
+        arch: String,
+    },
+
+    /// The build of an artifact finished. `attempts` is the number of times `docker build` ran,
+    /// including retries after known transient errors, and `exit-code` is the exit code of the
+    /// last attempt.
+    #[serde(rename_all = "kebab-case")]
+    BuildFinished {
+        artifact: String,
+        build_type: BuildType,
+        arch: String,
+        duration_ms: u64,
+        attempts: u16,
+        status: BuildStatus,
+        exit_code: Option<i32>,
+    },
+
+    /// An external file was downloaded for a package.
+    #[serde(rename_all = "kebab-case")]
+    ExternalFileFetched {
+        file: String,
+        url: String,
+        source: FetchSource,
+        duration_ms: u64,
+    },
+
+    /// An external file could not be downloaded from one of its sources.
+    #[serde(rename_all = "kebab-case")]
+    ExternalFileFetchFailed {
+        file: String,
+        url: String,
+        source: FetchSource,
+        error: String,
+    },
+}
+
+/// An image that the project's lock resolved.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ResolvedKit {
+    pub name: String,
+    pub version: String,
+    pub vendor: String,
+    pub source: String,
+    pub digest: String,
+}
+
+/// Whether a build succeeded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BuildStatus {
+    Succeeded,
+    Failed,
+}
+
+/// Where an external file was fetched from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FetchSource {
+    Lookaside,
+    Upstream,
+}
+
+/// An event along with the time at which it happened.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Record<'a> {
+    time_ms: u64,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+/// Writes events to the events file, if there is one.
+#[derive(Debug, Clone, Default)]
+pub struct EventLog {
+    path: Option<PathBuf>,
+}
+
+impl EventLog {
+    /// Creates a log that appends to the file at `path`, or one that discards events when `path`
+    /// is `None`.
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self { path }
+    }
+
+    /// The path of the events file, if events are being written.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Appends `event` to the events file.
+    pub fn emit(&self, event: &Event) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut line = serde_json::to_vec(&Record {
+            time_ms: millis(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default(),
+            ),
+            event,
+        })?;
+        line.push(b'\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(&line)
+    }
+}
+
+/// Converts a duration to whole milliseconds, as used in events.
+pub fn millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_emit_appends_lines() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("events.json");
+        let log = EventLog::new(Some(path.clone()));
+        log.emit(&Event::BuildStarted {
+            artifact: "kernel-6.1".to_string(),
+            build_type: BuildType::Package,
+            arch: "x86_64".to_string(),
+        })
+        .unwrap();
+        log.emit(&Event::ExternalFileFetched {
+            file: "linux-6.1.tar.xz".to_string(),
+            url: "https://cache.bottlerocket.aws/linux-6.1.tar.xz".to_string(),
+            source: FetchSource::Upstream,
+            duration_ms: 1500,
+        })
+        .unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["event"], "build-started");
+        assert_eq!(lines[0]["build-type"], "package");
+        assert!(lines[0]["time-ms"].as_u64().unwrap() > 0);
+        assert_eq!(lines[1]["event"], "external-file-fetched");
+        assert_eq!(lines[1]["source"], "upstream");
+        assert_eq!(lines[1]["duration-ms"], 1500);
+    }
+
+    #[test]
+    fn test_disabled_log() {
+        let log = EventLog::default();
+        assert!(log.path().is_none());
+        log.emit(&Event::ToolsInstalled {
+            tools_dir: PathBuf::from("build/tools"),
+            duration_ms: 0,
+        })
+        .unwrap();
+    }
+}
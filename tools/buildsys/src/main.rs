@@ -19,6 +19,7 @@ use crate::args::{
     BuildKitArgs, BuildPackageArgs, BuildVariantArgs, Buildsys, Command, RepackVariantArgs,
 };
 use crate::builder::DockerBuild;
+use buildsys::events::EventLog;
 use buildsys::manifest::{BundleModule, Manifest, ManifestInfo, SupportedArch};
 use buildsys_config::EXTERNAL_KIT_METADATA;
 use cache::LookasideCache;
@@ -144,6 +145,7 @@ fn build_package(args: BuildPackageArgs) -> Result<()> {
             &args.common.version_full,
             args.lookaside_cache.clone(),
             args.upstream_source_fallback == "true",
+            EventLog::new(args.common.events_json.clone()),
         );
 
         lookaside_cache
@@ -726,6 +726,17 @@ impl ImageLayout {
         DEFAULT_PARTITION_PLAN
     }
 
+    pub fn publish_image_size_hint_gib(&self) -> ImageSize {
+        self.publish_image_size_hint_gib
+    }
+
+    /// Returns true if the OS and data images together are larger than the publish image size
+    /// hint, in which case the hint is ignored at publish time.
+    pub fn exceeds_publish_image_size_hint(&self) -> bool {
+        u32::from(self.os_image_size_gib.0) + u32::from(self.data_image_size_gib.0)
+            > u32::from(self.publish_image_size_hint_gib.0)
+    }
+
     // At publish time we will need specific sizes for the OS image and the (optional) data image.
     // The sizes returned by this function depend on the image layout, and whether the publish
     // image hint is larger than the required minimum size.
@@ -0,0 +1,329 @@
+//! Credentials for the container registries that images are pulled from and pushed to.
+//!
+//! By default the image tool uses whatever docker credential configuration it finds in the
+//! environment. [`RegistryCredentials`] instead names a [`CredentialSource`] for each registry, so
+//! that images can be pulled from one registry and pushed to another with different identities.
+//! Before the image tool is run for an image, `DOCKER_CONFIG` is pointed at a docker config that
+//! provides the credentials for the image's registry.
+//!
+//! A source can be given for a whole registry host, such as `public.ecr.aws`, or for a repository
+//! prefix within it, such as `public.ecr.aws/bottlerocket`, so that vendors who share a registry
+//! host can use different identities. An image uses the source with the longest prefix of its URI.
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use snafu::{OptionExt, ResultExt};
+use tempfile::TempDir;
+
+use crate::{error, Result};
+
+/// Where to find the credentials for a registry.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CredentialSource {
+    /// The docker credential helper `docker-credential-<name>`, which must be on the `PATH`
+    CredentialHelper(String),
+    /// A directory holding a docker `config.json`, as would be given in `DOCKER_CONFIG`
+    DockerConfig(PathBuf),
+    /// A file holding a bearer token for the registry
+    TokenFile(PathBuf),
+}
+
+impl CredentialSource {
+    /// Resolves relative paths against `dir`.
+    pub fn relative_to(self, dir: &Path) -> Self {
+        match self {
+            CredentialSource::CredentialHelper(name) => CredentialSource::CredentialHelper(name),
+            CredentialSource::DockerConfig(path) => CredentialSource::DockerConfig(dir.join(path)),
+            CredentialSource::TokenFile(path) => CredentialSource::TokenFile(dir.join(path)),
+        }
+    }
+}
+
+/// Parses a credential source given as `credential-helper=<name>`, `docker-config=<directory>`,
+/// or `token-file=<path>`.
+impl FromStr for CredentialSource {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (kind, value) = s
+            .split_once('=')
+            .context(error::CredentialSourceParseSnafu { value: s })?;
+        match (kind, value) {
+            (_, "") => error::CredentialSourceParseSnafu { value: s }.fail(),
+            ("credential-helper", name) => Ok(CredentialSource::CredentialHelper(name.into())),
+            ("docker-config", path) => Ok(CredentialSource::DockerConfig(path.into())),
+            ("token-file", path) => Ok(CredentialSource::TokenFile(path.into())),
+            _ => error::CredentialSourceParseSnafu { value: s }.fail(),
+        }
+    }
+}
+
+impl Display for CredentialSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CredentialSource::CredentialHelper(name) => write!(f, "credential-helper={name}"),
+            CredentialSource::DockerConfig(path) => write!(f, "docker-config={}", path.display()),
+            CredentialSource::TokenFile(path) => write!(f, "token-file={}", path.display()),
+        }
+    }
+}
+
+/// The credential source to use for each registry or repository prefix. Images without a source
+/// use the docker credential configuration from the environment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegistryCredentials {
+    sources: HashMap<String, CredentialSource>,
+}
+
+impl RegistryCredentials {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses `source` for the credentials of the images under `registry`, which is a registry host
+    /// optionally followed by a repository prefix. Fails if `registry` already has a different
+    /// source.
+    pub fn insert(&mut self, registry: &str, source: CredentialSource) -> Result<()> {
+        let registry = registry.trim_end_matches('/');
+        match self.sources.get(registry) {
+            Some(existing) if existing != &source => error::CredentialConflictSnafu {
+                registry,
+                first: existing.clone(),
+                second: source,
+            }
+            .fail(),
+            _ => {
+                self.sources.insert(registry.to_string(), source);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// Returns the docker config to run the image tool with for `uri`, or `None` if the image
+    /// tool should use the environment's credentials.
+    pub(crate) fn docker_config(&self, uri: &str) -> Result<Option<DockerConfig>> {
+        let host = registry_host(uri);
+        let Some(source) = self
+            .sources
+            .iter()
+            .filter(|(prefix, _)| has_prefix(uri, prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, source)| source)
+        else {
+            return Ok(None);
+        };
+        let config = match source {
+            CredentialSource::DockerConfig(path) => {
+                return Ok(Some(DockerConfig::Existing(path.clone())))
+            }
+            CredentialSource::CredentialHelper(name) => json!({ "credHelpers": { host: name } }),
+            CredentialSource::TokenFile(path) => {
+                let token =
+                    std::fs::read_to_string(path).context(error::TokenFileReadSnafu { path })?;
+                json!({ "auths": { host: { "registrytoken": token.trim() } } })
+            }
+        };
+        let dir = TempDir::new().context(error::DockerConfigWriteSnafu)?;
+        std::fs::write(dir.path().join("config.json"), config.to_string())
+            .context(error::DockerConfigWriteSnafu)?;
+        Ok(Some(DockerConfig::Generated(dir)))
+    }
+}
+
+/// A docker config directory for a single run of the image tool.
+#[derive(Debug)]
+pub(crate) enum DockerConfig {
+    /// A directory given by the user
+    Existing(PathBuf),
+    /// A directory with a config written for this run, which is removed when dropped
+    Generated(TempDir),
+}
+
+impl DockerConfig {
+    pub(crate) fn path(&self) -> &Path {
+        match self {
+            DockerConfig::Existing(path) => path,
+            DockerConfig::Generated(dir) => dir.path(),
+        }
+    }
+}
+
+/// Whether the image URI or repository `uri` is under `prefix`, which is either a registry host or
+/// a repository within one. A repository is followed by a tag or digest in an image URI, while a
+/// host can only be followed by a repository, since a `:` after it would start a port.
+fn has_prefix(uri: &str, prefix: &str) -> bool {
+    let Some(rest) = uri.strip_prefix(prefix) else {
+        return false;
+    };
+    let boundaries: &[char] = if prefix.contains('/') {
+        &['/', ':', '@']
+    } else {
+        &['/']
+    };
+    rest.is_empty() || rest.starts_with(boundaries)
+}
+
+/// Returns the registry host of an image URI, repository, or registry, e.g. `public.ecr.aws` for
+/// `public.ecr.aws/bottlerocket/core-kit:v1.0.0`.
+pub fn registry_host(uri: &str) -> &str {
+    uri.split('/').next().unwrap_or(uri)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_credential_source() {
+        for source in [
+            CredentialSource::CredentialHelper("ecr-login".into()),
+            CredentialSource::DockerConfig("/home/user/.docker-publish".into()),
+            CredentialSource::TokenFile("/run/secrets/token".into()),
+        ] {
+            assert_eq!(
+                source.to_string().parse::<CredentialSource>().unwrap(),
+                source
+            );
+        }
+        "ecr-login".parse::<CredentialSource>().unwrap_err();
+        "password=hunter2".parse::<CredentialSource>().unwrap_err();
+        "token-file=".parse::<CredentialSource>().unwrap_err();
+    }
+
+    #[test]
+    fn test_docker_config_for_registry() {
+        let token_dir = TempDir::new().unwrap();
+        let token_file = token_dir.path().join("token");
+        std::fs::write(&token_file, "abc123\n").unwrap();
+
+        let mut credentials = RegistryCredentials::new();
+        credentials
+            .insert(
+                "public.ecr.aws/bottlerocket",
+                CredentialSource::CredentialHelper("ecr-login".into()),
+            )
+            .unwrap();
+        credentials
+            .insert(
+                "registry.example.com",
+                CredentialSource::TokenFile(token_file),
+            )
+            .unwrap();
+        credentials
+            .insert(
+                "localhost:5000",
+                CredentialSource::DockerConfig("/etc/docker-local".into()),
+            )
+            .unwrap();
+
+        assert!(credentials
+            .docker_config("docker.io/library/alpine:latest")
+            .unwrap()
+            .is_none());
+        assert!(credentials
+            .docker_config("public.ecr.aws/other-vendor/core-kit:v1.0.0")
+            .unwrap()
+            .is_none());
+
+        let config = credentials
+            .docker_config("public.ecr.aws/bottlerocket/core-kit:v1.0.0")
+            .unwrap()
+            .unwrap();
+        let json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(config.path().join("config.json")).unwrap())
+                .unwrap();
+        assert_eq!(json["credHelpers"]["public.ecr.aws"], "ecr-login");
+
+        let config = credentials
+            .docker_config("registry.example.com/kits/extra-kit")
+            .unwrap()
+            .unwrap();
+        let json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(config.path().join("config.json")).unwrap())
+                .unwrap();
+        assert_eq!(
+            json["auths"]["registry.example.com"]["registrytoken"],
+            "abc123"
+        );
+
+        let config = credentials
+            .docker_config("localhost:5000/core-kit:v1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.path(), Path::new("/etc/docker-local"));
+    }
+
+    #[test]
+    fn test_vendors_sharing_a_registry_host() {
+        let mut credentials = RegistryCredentials::new();
+        credentials
+            .insert(
+                "registry.example.com",
+                CredentialSource::DockerConfig("/etc/docker-default".into()),
+            )
+            .unwrap();
+        credentials
+            .insert(
+                "registry.example.com/vendor-a/",
+                CredentialSource::DockerConfig("/etc/docker-a".into()),
+            )
+            .unwrap();
+        credentials
+            .insert(
+                "registry.example.com/vendor-b",
+                CredentialSource::DockerConfig("/etc/docker-b".into()),
+            )
+            .unwrap();
+
+        for (uri, config) in [
+            ("registry.example.com/vendor-a/core-kit:v1", "/etc/docker-a"),
+            (
+                "registry.example.com/vendor-b/core-kit@sha256:abc",
+                "/etc/docker-b",
+            ),
+            ("registry.example.com/vendor-b", "/etc/docker-b"),
+            (
+                "registry.example.com/vendor-bc/core-kit:v1",
+                "/etc/docker-default",
+            ),
+            (
+                "registry.example.com/vendor-c/core-kit:v1",
+                "/etc/docker-default",
+            ),
+        ] {
+            let docker_config = credentials.docker_config(uri).unwrap().unwrap();
+            assert_eq!(docker_config.path(), Path::new(config), "{uri}");
+        }
+
+        // The same prefix can only have one source
+        credentials
+            .insert(
+                "registry.example.com/vendor-a",
+                CredentialSource::DockerConfig("/etc/docker-a".into()),
+            )
+            .unwrap();
+        credentials
+            .insert(
+                "registry.example.com/vendor-a",
+                CredentialSource::CredentialHelper("ecr-login".into()),
+            )
+            .unwrap_err();
+    }
+
+    #[test]
+    fn test_has_prefix() {
+        assert!(has_prefix("localhost:5000/kit:v1", "localhost:5000"));
+        assert!(!has_prefix("localhost:5000/kit:v1", "localhost"));
+        assert!(has_prefix("a.com/vendor/kit:v1", "a.com/vendor/kit"));
+        assert!(!has_prefix("a.com/vendor/kit-2:v1", "a.com/vendor/kit"));
+    }
+}
@@ -4,9 +4,11 @@ use tokio::process::Command;
 
 use crate::{error, Result};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct CommandLine {
     pub(crate) path: PathBuf,
+    /// The directory to run the command with as `DOCKER_CONFIG`, if any.
+    pub(crate) docker_config: Option<PathBuf>,
 }
 
 impl CommandLine {
@@ -19,7 +21,8 @@ impl CommandLine {
                 .collect::<Vec<_>>()
                 .join(", ")
         );
-        let output = Command::new(&self.path)
+        let output = self
+            .command()
             .args(args)
             .output()
             .await
@@ -48,7 +51,8 @@ impl CommandLine {
                 .collect::<Vec<_>>()
                 .join(", ")
         );
-        let status = Command::new(&self.path)
+        let status = self
+            .command()
             .args(args)
             .spawn()
             .context(error::CommandFailedSnafu {
@@ -69,4 +73,12 @@ impl CommandLine {
         );
         Ok(())
     }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.path);
+        if let Some(docker_config) = &self.docker_config {
+            command.env("DOCKER_CONFIG", docker_config);
+        }
+        command
+    }
 }
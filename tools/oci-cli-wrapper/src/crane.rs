@@ -6,40 +6,74 @@ use snafu::ResultExt;
 use tar::Archive as TarArchive;
 use tempfile::TempDir;
 
+use crate::auth::DockerConfig;
 use crate::{
-    cli::CommandLine, error, ConfigView, DockerArchitecture, ImageToolImpl, ImageView, Result,
+    cli::CommandLine, error, ConfigView, DockerArchitecture, ImageToolImpl, ImageView,
+    RegistryCredentials, Result,
 };
 
 #[derive(Debug)]
 pub struct CraneCLI {
     pub(crate) cli: CommandLine,
+    pub(crate) credentials: RegistryCredentials,
+}
+
+impl CraneCLI {
+    /// Returns the command line to use for `uri`, which uses the credentials configured for its
+    /// registry. The returned docker config must be kept until the command has finished.
+    fn cli_for(&self, uri: &str) -> Result<(CommandLine, Option<DockerConfig>)> {
+        let docker_config = self.credentials.docker_config(uri)?;
+        let cli = CommandLine {
+            path: self.cli.path.clone(),
+            docker_config: docker_config
+                .as_ref()
+                .map(|config| config.path().to_path_buf()),
+        };
+        Ok((cli, docker_config))
+    }
 }
 
 #[async_trait]
 impl ImageToolImpl for CraneCLI {
     async fn pull_oci_image(&self, path: &Path, uri: &str) -> Result<()> {
         let archive_path = path.to_string_lossy();
-        self.cli
-            .spawn(
-                &["pull", "--format", "oci", uri, archive_path.as_ref()],
-                format!("failed to pull image archive from {}", uri),
-            )
-            .await?;
+        let (cli, _docker_config) = self.cli_for(uri)?;
+        cli.spawn(
+            &["pull", "--format", "oci", uri, archive_path.as_ref()],
+            format!("failed to pull image archive from {}", uri),
+        )
+        .await?;
         Ok(())
     }
 
     async fn get_manifest(&self, uri: &str) -> Result<Vec<u8>> {
-        self.cli
+        let (cli, _docker_config) = self.cli_for(uri)?;
+        cli.output(
+            &["manifest", uri],
+            format!("failed to fetch manifest for resource at {}", uri),
+        )
+        .await
+    }
+
+    async fn list_tags(&self, repository: &str) -> Result<Vec<String>> {
+        let (cli, _docker_config) = self.cli_for(repository)?;
+        let bytes = cli
             .output(
-                &["manifest", uri],
-                format!("failed to fetch manifest for resource at {}", uri),
+                &["ls", repository],
+                format!("failed to list tags in repository {}", repository),
             )
-            .await
+            .await?;
+        Ok(String::from_utf8_lossy(&bytes)
+            .lines()
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(ToString::to_string)
+            .collect())
     }
 
     async fn get_config(&self, uri: &str) -> Result<ConfigView> {
-        let bytes = self
-            .cli
+        let (cli, _docker_config) = self.cli_for(uri)?;
+        let bytes = cli
             .output(
                 &["config", uri],
                 format!("failed to fetch image config from {}", uri),
@@ -59,12 +93,12 @@ impl ImageToolImpl for CraneCLI {
         oci_archive
             .unpack(temp_dir.path())
             .context(error::ArchiveExtractSnafu)?;
-        self.cli
-            .spawn(
-                &["push", &temp_dir.path().to_string_lossy(), uri],
-                format!("failed to push image {}", uri),
-            )
-            .await
+        let (cli, _docker_config) = self.cli_for(uri)?;
+        cli.spawn(
+            &["push", &temp_dir.path().to_string_lossy(), uri],
+            format!("failed to push image {}", uri),
+        )
+        .await
     }
 
     async fn push_multi_platform_manifest(
@@ -82,12 +116,12 @@ impl ImageToolImpl for CraneCLI {
             manifest_create_args.extend_from_slice(&["-m", image])
         }
         manifest_create_args.extend_from_slice(&["-t", uri]);
-        self.cli
-            .output(
-                &manifest_create_args,
-                format!("could not push multi-platform manifest to {}", uri),
-            )
-            .await?;
+        let (cli, _docker_config) = self.cli_for(uri)?;
+        cli.output(
+            &manifest_create_args,
+            format!("could not push multi-platform manifest to {}", uri),
+        )
+        .await?;
 
         Ok(())
     }
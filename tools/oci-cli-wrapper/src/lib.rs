@@ -15,15 +15,21 @@ use std::fmt::{Display, Formatter};
 use std::{collections::HashMap, path::Path};
 
 use async_trait::async_trait;
+pub use auth::{registry_host, CredentialSource, RegistryCredentials};
 use cli::CommandLine;
 use crane::CraneCLI;
 use krane_bundle::KRANE;
 use olpc_cjson::CanonicalFormatter;
 use serde::{Deserialize, Serialize};
+use signature::{ImageSignature, SignaturePayload};
 use snafu::ResultExt;
+use tempfile::TempDir;
 
+mod auth;
 mod cli;
 mod crane;
+pub mod manifest;
+pub mod signature;
 
 #[derive(Debug)]
 pub struct ImageTool {
@@ -33,10 +39,18 @@ pub struct ImageTool {
 impl ImageTool {
     /// Uses the builtin `krane` provided by the `tools/krane` crate.
     pub fn from_builtin_krane() -> Self {
+        Self::from_builtin_krane_with_credentials(RegistryCredentials::default())
+    }
+
+    /// Uses the builtin `krane` provided by the `tools/krane` crate, with the given credentials
+    /// for each registry.
+    pub fn from_builtin_krane_with_credentials(credentials: RegistryCredentials) -> Self {
         let image_tool_impl = Box::new(CraneCLI {
             cli: CommandLine {
                 path: KRANE.path().to_path_buf(),
+                docker_config: None,
             },
+            credentials,
         });
         Self { image_tool_impl }
     }
@@ -74,6 +88,50 @@ impl ImageTool {
         Ok(canonicalized_manifest)
     }
 
+    /// List the tags present in a repository
+    pub async fn list_tags(&self, repository: &str) -> Result<Vec<String>> {
+        self.image_tool_impl.list_tags(repository).await
+    }
+
+    /// Fetch the signatures of the image in `repository` with the manifest digest `digest`. A
+    /// missing signature image is reported as an error by the underlying image tool.
+    pub async fn get_signatures(
+        &self,
+        repository: &str,
+        digest: &str,
+    ) -> Result<Vec<ImageSignature>> {
+        let temp_dir = TempDir::new().context(error::SignatureTempSnafu)?;
+        let layout = temp_dir.path().join("signature");
+        let uri = format!("{repository}:{}", signature::signature_tag(digest));
+        self.image_tool_impl.pull_oci_image(&layout, &uri).await?;
+        signature::read_signature_layout(&layout)
+    }
+
+    /// Push a signature of the image named by `signature`'s payload
+    pub async fn push_signature(&self, signature: &ImageSignature) -> Result<()> {
+        let payload = SignaturePayload::from_slice(&signature.payload)?;
+        let temp_dir = TempDir::new().context(error::SignatureTempSnafu)?;
+        let layout = temp_dir.path().join("signature");
+        signature::write_signature_layout(&layout, std::slice::from_ref(signature))?;
+
+        let archive_path = temp_dir.path().join("signature.tar");
+        let archive = std::fs::File::create(&archive_path).context(error::ArchiveWriteSnafu)?;
+        let mut builder = tar::Builder::new(archive);
+        builder
+            .append_dir_all(".", &layout)
+            .context(error::ArchiveWriteSnafu)?;
+        builder.finish().context(error::ArchiveWriteSnafu)?;
+
+        let uri = format!(
+            "{}:{}",
+            payload.repository(),
+            signature::signature_tag(payload.digest())
+        );
+        self.image_tool_impl
+            .push_oci_archive(&archive_path, &uri)
+            .await
+    }
+
     /// Push a single-arch image in oci archive format
     pub async fn push_oci_archive(&self, path: &Path, uri: &str) -> Result<()> {
         self.image_tool_impl.push_oci_archive(path, uri).await
@@ -99,6 +157,8 @@ pub trait ImageToolImpl: std::fmt::Debug + Send + Sync + 'static {
     async fn get_config(&self, uri: &str) -> Result<ConfigView>;
     /// Fetch the manifest
     async fn get_manifest(&self, uri: &str) -> Result<Vec<u8>>;
+    /// List the tags present in a repository
+    async fn list_tags(&self, repository: &str) -> Result<Vec<String>>;
     /// Push a single-arch image in oci archive format
     async fn push_oci_archive(&self, path: &Path, uri: &str) -> Result<()>;
     /// Push the multi-arch kit manifest list
@@ -161,12 +221,24 @@ pub mod error {
     #[derive(Snafu, Debug)]
     #[snafu(visibility(pub(super)))]
     pub enum Error {
+        #[snafu(display("No index.json found in oci archive '{}'", path.display()))]
+        ArchiveIndexMissing { path: PathBuf },
+
+        #[snafu(display(
+            "Expected one image manifest in oci archive '{}', found {count}",
+            path.display()
+        ))]
+        ArchiveManifestCount { path: PathBuf, count: usize },
+
         #[snafu(display("Failed to extract archive: {source}"))]
         ArchiveExtract { source: std::io::Error },
 
         #[snafu(display("Failed to read archive: {source}"))]
         ArchiveRead { source: std::io::Error },
 
+        #[snafu(display("Failed to write archive: {source}"))]
+        ArchiveWrite { source: std::io::Error },
+
         #[snafu(display("Failed to execute image tool, {message}: {source}"))]
         CommandFailed {
             message: String,
@@ -176,6 +248,24 @@ pub mod error {
         #[snafu(display("Failed to deserialize image config: {source}"))]
         ConfigDeserialize { source: serde_json::Error },
 
+        #[snafu(display(
+            "Registry '{registry}' is given two credential sources, '{first}' and '{second}'"
+        ))]
+        CredentialConflict {
+            registry: String,
+            first: crate::CredentialSource,
+            second: crate::CredentialSource,
+        },
+
+        #[snafu(display(
+            "Invalid credential source '{value}', expected 'credential-helper=<name>', \
+            'docker-config=<directory>', or 'token-file=<path>'"
+        ))]
+        CredentialSourceParse { value: String },
+
+        #[snafu(display("Failed to write docker config for registry credentials: {source}"))]
+        DockerConfigWrite { source: std::io::Error },
+
         #[snafu(display("Failed to create temporary directory for crane push: {source}"))]
         CraneTemp { source: std::io::Error },
 
@@ -216,7 +306,92 @@ pub mod error {
         #[snafu(display("Failed to parse kit filename: {}", source))]
         Regex { source: regex::Error },
 
+        #[snafu(display("Failed to decode image signature: {source}"))]
+        SignatureDecode { source: base64::DecodeError },
+
+        #[snafu(display("Failed to deserialize image signature: {source}"))]
+        SignatureDeserialize { source: serde_json::Error },
+
+        #[snafu(display("Invalid signature image layout: {message}"))]
+        SignatureLayout { message: String },
+
+        #[snafu(display("Failed to read signature image layout at '{}': {source}", path.display()))]
+        SignatureLayoutRead {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to write signature image layout at '{}': {source}", path.display()))]
+        SignatureLayoutWrite {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to serialize image signature: {source}"))]
+        SignatureSerialize { source: serde_json::Error },
+
+        #[snafu(display("Failed to create temporary directory for image signature: {source}"))]
+        SignatureTemp { source: std::io::Error },
+
+        #[snafu(display("Failed to read registry token from '{}': {source}", path.display()))]
+        TokenFileRead {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Unsupported image signature type '{type_}'"))]
+        SignatureType { type_: String },
+
         #[snafu(display("Unsupported container image tool '{}'", name))]
         Unsupported { name: String },
     }
+
+    /// The registry error codes and HTTP status that crane reports when a manifest or repository
+    /// does not exist.
+    const NOT_FOUND_ERRORS: [&str; 3] = ["MANIFEST_UNKNOWN", "NAME_UNKNOWN", "404 Not Found"];
+
+    impl Error {
+        /// Whether the registry answered that the image or repository does not exist, rather than
+        /// the image tool failing for another reason, such as missing credentials or a network
+        /// error.
+        pub fn is_not_found(&self) -> bool {
+            match self {
+                Error::OperationFailed { message, .. } => NOT_FOUND_ERRORS
+                    .iter()
+                    .any(|not_found| message.contains(not_found)),
+                _ => false,
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn test_is_not_found() {
+            let failed = |message: &str| Error::OperationFailed {
+                message: message.to_string(),
+                program: PathBuf::from("krane"),
+                args: vec!["manifest".to_string()],
+            };
+            assert!(failed(
+                "GET https://a.com/v2/kit/manifests/v1: MANIFEST_UNKNOWN: manifest unknown"
+            )
+            .is_not_found());
+            assert!(
+                failed("NAME_UNKNOWN: The repository with name 'kit' does not exist")
+                    .is_not_found()
+            );
+            assert!(!failed(
+                "GET https://a.com/v2/kit/manifests/v1: UNAUTHORIZED: authentication required"
+            )
+            .is_not_found());
+            assert!(!failed("dial tcp: lookup a.com: no such host").is_not_found());
+            assert!(!Error::Unsupported {
+                name: "docker".to_string()
+            }
+            .is_not_found());
+        }
+    }
 }
@@ -0,0 +1,207 @@
+//! Works out the manifests that pushing kit images produces without pushing anything, so that a
+//! kit can be compared with what is already in a registry, or previewed before it is published.
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use olpc_cjson::CanonicalFormatter;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use snafu::{ensure, OptionExt, ResultExt};
+use tar::Archive as TarArchive;
+
+use crate::{error, DockerArchitecture, Result};
+
+const INDEX_FILE: &str = "index.json";
+const OCI_INDEX_MEDIA_TYPE: &str = "application/vnd.oci.image.index.v1+json";
+
+/// Describes an image manifest by its media type, size and digest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Descriptor {
+    pub media_type: String,
+    pub size: u64,
+    pub digest: String,
+}
+
+#[derive(Deserialize)]
+struct IndexView {
+    manifests: Vec<Descriptor>,
+}
+
+#[derive(Deserialize)]
+struct ManifestListView {
+    manifests: Vec<PlatformManifestView>,
+}
+
+#[derive(Deserialize)]
+struct PlatformManifestView {
+    digest: String,
+    platform: Option<PlatformView>,
+}
+
+#[derive(Deserialize)]
+struct PlatformView {
+    architecture: String,
+}
+
+/// Reads the descriptor of the single image manifest in the oci-archive tarball at `path`, which
+/// is the manifest that `push_oci_archive` pushes.
+pub fn archive_manifest(path: &Path) -> Result<Descriptor> {
+    let file = File::open(path).context(error::ArchiveReadSnafu)?;
+    let mut archive = TarArchive::new(file);
+    let mut index = None;
+    for entry in archive.entries().context(error::ArchiveReadSnafu)? {
+        let mut entry = entry.context(error::ArchiveReadSnafu)?;
+        let entry_path = entry.path().context(error::ArchiveReadSnafu)?;
+        if entry_path
+            .components()
+            .eq(Path::new(INDEX_FILE).components())
+        {
+            let mut data = Vec::new();
+            entry
+                .read_to_end(&mut data)
+                .context(error::ArchiveReadSnafu)?;
+            index = Some(data);
+            break;
+        }
+    }
+    let index = index.context(error::ArchiveIndexMissingSnafu { path })?;
+    let index: IndexView =
+        serde_json::from_slice(&index).context(error::ManifestDeserializeSnafu)?;
+    ensure!(
+        index.manifests.len() == 1,
+        error::ArchiveManifestCountSnafu {
+            path,
+            count: index.manifests.len()
+        }
+    );
+    Ok(index.manifests.into_iter().next().unwrap())
+}
+
+/// Returns the canonical JSON of the manifest list that `push_multi_platform_manifest` creates
+/// for the given images, as `get_manifest` would return it once pushed. It is only good for
+/// comparing the images that manifest lists refer to, with [`platform_digests`]. The image tool
+/// serializes the manifest list that it pushes in its own way, so its digest is not the digest of
+/// these bytes.
+pub fn multi_platform_manifest(
+    platform_images: &[(DockerArchitecture, Descriptor)],
+) -> Result<Vec<u8>> {
+    let manifests: Vec<_> = platform_images
+        .iter()
+        .map(|(arch, descriptor)| {
+            json!({
+                "mediaType": descriptor.media_type,
+                "size": descriptor.size,
+                "digest": descriptor.digest,
+                "platform": {
+                    "architecture": arch.to_string(),
+                    "os": "linux",
+                },
+            })
+        })
+        .collect();
+    let index = json!({
+        "schemaVersion": 2,
+        "mediaType": OCI_INDEX_MEDIA_TYPE,
+        "manifests": manifests,
+    });
+
+    let mut canonicalized = Vec::new();
+    let mut ser =
+        serde_json::Serializer::with_formatter(&mut canonicalized, CanonicalFormatter::new());
+    index
+        .serialize(&mut ser)
+        .context(error::ManifestCanonicalizeSnafu)?;
+    Ok(canonicalized)
+}
+
+/// Returns the architecture and manifest digest of each image in a manifest list, sorted so that
+/// two manifest lists of the same images can be compared.
+pub fn platform_digests(manifest_list: &[u8]) -> Result<Vec<(String, String)>> {
+    let manifest_list: ManifestListView =
+        serde_json::from_slice(manifest_list).context(error::ManifestDeserializeSnafu)?;
+    let mut digests: Vec<(String, String)> = manifest_list
+        .manifests
+        .into_iter()
+        .map(|manifest| {
+            let arch = manifest
+                .platform
+                .map(|platform| platform.architecture)
+                .unwrap_or_default();
+            (arch, manifest.digest)
+        })
+        .collect();
+    digests.sort();
+    Ok(digests)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write_archive(dir: &Path, index: &serde_json::Value) -> std::path::PathBuf {
+        let path = dir.join("kit.tar");
+        let mut builder = tar::Builder::new(File::create(&path).unwrap());
+        let data = serde_json::to_vec(index).unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "./index.json", data.as_slice())
+            .unwrap();
+        builder.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn test_archive_manifest() {
+        let dir = TempDir::new().unwrap();
+        let path = write_archive(
+            dir.path(),
+            &json!({
+                "schemaVersion": 2,
+                "manifests": [{
+                    "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                    "size": 1234,
+                    "digest": "sha256:aaaa",
+                    "annotations": { "org.opencontainers.image.ref.name": "latest" },
+                }],
+            }),
+        );
+        assert_eq!(
+            archive_manifest(&path).unwrap(),
+            Descriptor {
+                media_type: "application/vnd.oci.image.manifest.v1+json".to_string(),
+                size: 1234,
+                digest: "sha256:aaaa".to_string(),
+            }
+        );
+
+        let path = write_archive(dir.path(), &json!({ "manifests": [] }));
+        archive_manifest(&path).unwrap_err();
+    }
+
+    #[test]
+    fn test_multi_platform_manifest() {
+        let descriptor = |digest: &str| Descriptor {
+            media_type: "application/vnd.oci.image.manifest.v1+json".to_string(),
+            size: 100,
+            digest: digest.to_string(),
+        };
+        let manifest_list = multi_platform_manifest(&[
+            (DockerArchitecture::Arm64, descriptor("sha256:bbbb")),
+            (DockerArchitecture::Amd64, descriptor("sha256:aaaa")),
+        ])
+        .unwrap();
+        assert_eq!(
+            platform_digests(&manifest_list).unwrap(),
+            vec![
+                ("amd64".to_string(), "sha256:aaaa".to_string()),
+                ("arm64".to_string(), "sha256:bbbb".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,245 @@
+//! Signatures of multi-platform images, stored in the same repository as the image they sign.
+//!
+//! The format follows the "simple signing" scheme used by cosign so that signatures can be
+//! inspected with common tooling. An image with the manifest list digest `sha256:<hex>` is signed
+//! by pushing a signature image tagged `sha256-<hex>.sig`. Each layer of the signature image is a
+//! JSON payload naming the signed digest, and the layer's annotations hold a base64 encoded
+//! signature of that payload.
+use std::fs::{create_dir_all, read, write};
+use std::path::Path;
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Digest;
+use snafu::{ensure, OptionExt, ResultExt};
+
+use crate::{error, Result};
+
+/// The media type of the layers holding signature payloads.
+pub const SIGNATURE_MEDIA_TYPE: &str = "application/vnd.dev.cosign.simplesigning.v1+json";
+
+/// The layer annotation holding the base64 encoded signature of the layer's payload.
+pub const SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+
+const SIGNATURE_TYPE: &str = "cosign container image signature";
+const INDEX_FILE: &str = "index.json";
+const OCI_LAYOUT_FILE: &str = "oci-layout";
+
+/// Returns the digest of the given manifest or manifest list in the form `sha256:<hex>`.
+pub fn manifest_digest(manifest: &[u8]) -> String {
+    format!("sha256:{:x}", sha2::Sha256::digest(manifest))
+}
+
+/// Returns the tag of the signature image for the image with the given manifest digest.
+pub fn signature_tag(digest: &str) -> String {
+    format!("{}.sig", digest.replace(':', "-"))
+}
+
+/// The payload that is signed, which names the signed image by its repository and digest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignaturePayload {
+    critical: Critical,
+    optional: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Critical {
+    identity: Identity,
+    image: SignedImage,
+    #[serde(rename = "type")]
+    type_: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Identity {
+    #[serde(rename = "docker-reference")]
+    docker_reference: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct SignedImage {
+    #[serde(rename = "docker-manifest-digest")]
+    docker_manifest_digest: String,
+}
+
+impl SignaturePayload {
+    /// Creates the payload for the image in `repository` with the manifest digest `digest`.
+    pub fn new(repository: &str, digest: &str) -> Self {
+        Self {
+            critical: Critical {
+                identity: Identity {
+                    docker_reference: repository.to_string(),
+                },
+                image: SignedImage {
+                    docker_manifest_digest: digest.to_string(),
+                },
+                type_: SIGNATURE_TYPE.to_string(),
+            },
+            optional: None,
+        }
+    }
+
+    /// Parses a payload read from a signature image.
+    pub fn from_slice(payload: &[u8]) -> Result<Self> {
+        let payload: Self =
+            serde_json::from_slice(payload).context(error::SignatureDeserializeSnafu)?;
+        ensure!(
+            payload.critical.type_ == SIGNATURE_TYPE,
+            error::SignatureTypeSnafu {
+                type_: payload.critical.type_
+            }
+        );
+        Ok(payload)
+    }
+
+    /// Serializes the payload into the bytes that are signed.
+    pub fn to_vec(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self).context(error::SignatureSerializeSnafu)
+    }
+
+    /// The repository of the signed image.
+    pub fn repository(&self) -> &str {
+        &self.critical.identity.docker_reference
+    }
+
+    /// The manifest digest of the signed image.
+    pub fn digest(&self) -> &str {
+        &self.critical.image.docker_manifest_digest
+    }
+}
+
+/// A signature along with the exact payload bytes that were signed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageSignature {
+    pub payload: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+/// Writes an OCI image layout to `dir` holding a signature image with one layer per signature.
+pub fn write_signature_layout(dir: &Path, signatures: &[ImageSignature]) -> Result<()> {
+    let blobs = dir.join("blobs").join("sha256");
+    create_dir_all(&blobs).context(error::SignatureLayoutWriteSnafu { path: &blobs })?;
+    let write_blob = |content: &[u8]| -> Result<Value> {
+        let digest = manifest_digest(content);
+        let path = dir.join("blobs").join(digest.replace(':', "/"));
+        write(&path, content).context(error::SignatureLayoutWriteSnafu { path })?;
+        Ok(json!({"digest": digest, "size": content.len()}))
+    };
+
+    let mut layers = Vec::new();
+    let mut diff_ids = Vec::new();
+    for signature in signatures {
+        let mut layer = write_blob(&signature.payload)?;
+        diff_ids.push(layer["digest"].clone());
+        layer["mediaType"] = SIGNATURE_MEDIA_TYPE.into();
+        layer["annotations"] = json!({
+            SIGNATURE_ANNOTATION:
+                base64::engine::general_purpose::STANDARD.encode(&signature.signature),
+        });
+        layers.push(layer);
+    }
+    let config = json!({
+        "architecture": "",
+        "os": "",
+        "config": {},
+        "rootfs": {"type": "layers", "diff_ids": diff_ids},
+    });
+    let mut config = write_blob(config.to_string().as_bytes())?;
+    config["mediaType"] = "application/vnd.oci.image.config.v1+json".into();
+    let manifest = json!({
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.oci.image.manifest.v1+json",
+        "config": config,
+        "layers": layers,
+    });
+    let mut manifest = write_blob(manifest.to_string().as_bytes())?;
+    manifest["mediaType"] = "application/vnd.oci.image.manifest.v1+json".into();
+    let index = json!({"schemaVersion": 2, "manifests": [manifest]});
+
+    let index_path = dir.join(INDEX_FILE);
+    write(&index_path, index.to_string())
+        .context(error::SignatureLayoutWriteSnafu { path: index_path })?;
+    let layout_path = dir.join(OCI_LAYOUT_FILE);
+    write(&layout_path, r#"{"imageLayoutVersion": "1.0.0"}"#)
+        .context(error::SignatureLayoutWriteSnafu { path: layout_path })
+}
+
+/// Reads the signatures from the signature image in the OCI image layout at `dir`.
+pub fn read_signature_layout(dir: &Path) -> Result<Vec<ImageSignature>> {
+    let read_json = |path: &Path| -> Result<Value> {
+        let bytes = read(path).context(error::SignatureLayoutReadSnafu { path })?;
+        serde_json::from_slice(&bytes).context(error::SignatureDeserializeSnafu)
+    };
+    let blob_path = |digest: &Value| -> Result<std::path::PathBuf> {
+        let digest = digest.as_str().context(error::SignatureLayoutSnafu {
+            message: "descriptor has no digest",
+        })?;
+        Ok(dir.join("blobs").join(digest.replace(':', "/")))
+    };
+
+    let index = read_json(&dir.join(INDEX_FILE))?;
+    let mut signatures = Vec::new();
+    for descriptor in index["manifests"].as_array().into_iter().flatten() {
+        let manifest = read_json(&blob_path(&descriptor["digest"])?)?;
+        for layer in manifest["layers"].as_array().into_iter().flatten() {
+            if layer["mediaType"] != SIGNATURE_MEDIA_TYPE {
+                continue;
+            }
+            let signature = layer["annotations"][SIGNATURE_ANNOTATION]
+                .as_str()
+                .context(error::SignatureLayoutSnafu {
+                    message: "signature layer has no signature annotation",
+                })?;
+            let signature = base64::engine::general_purpose::STANDARD
+                .decode(signature)
+                .context(error::SignatureDecodeSnafu)?;
+            let path = blob_path(&layer["digest"])?;
+            let payload = read(&path).context(error::SignatureLayoutReadSnafu { path })?;
+            signatures.push(ImageSignature { payload, signature });
+        }
+    }
+    Ok(signatures)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_signature_tag() {
+        let digest = manifest_digest(b"manifest");
+        assert!(digest.starts_with("sha256:"));
+        assert_eq!(
+            signature_tag(&digest),
+            format!("sha256-{}.sig", &digest["sha256:".len()..])
+        );
+    }
+
+    #[test]
+    fn test_signature_layout_round_trip() {
+        let payload = SignaturePayload::new("registry/my-kit", "sha256:abc");
+        let signatures = vec![
+            ImageSignature {
+                payload: payload.to_vec().unwrap(),
+                signature: b"first".to_vec(),
+            },
+            ImageSignature {
+                payload: payload.to_vec().unwrap(),
+                signature: b"second".to_vec(),
+            },
+        ];
+        let tempdir = TempDir::new().unwrap();
+        write_signature_layout(tempdir.path(), &signatures).unwrap();
+
+        let read = read_signature_layout(tempdir.path()).unwrap();
+        assert_eq!(read, signatures);
+        let read_payload = SignaturePayload::from_slice(&read[0].payload).unwrap();
+        assert_eq!(read_payload, payload);
+        assert_eq!(read_payload.repository(), "registry/my-kit");
+        assert_eq!(read_payload.digest(), "sha256:abc");
+
+        SignaturePayload::from_slice(br#"{"critical":{}}"#).unwrap_err();
+    }
+}
@@ -111,6 +111,8 @@ impl InfraConfig {
 #[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq, Clone)]
 pub struct Vendor {
     pub registry: String,
+    /// The key used to sign kits published to the vendor, if they should be signed
+    pub signing_keys: Option<SigningKeyConfig>,
 }
 
 /// S3-specific TUF infrastructure configuration
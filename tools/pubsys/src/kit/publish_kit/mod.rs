@@ -1,8 +1,14 @@
+use crate::repo::get_signing_key_source;
 use crate::Args;
 use clap::Parser;
-use log::{debug, info, trace};
-use oci_cli_wrapper::{DockerArchitecture, ImageTool};
-use pubsys_config::InfraConfig;
+use log::{debug, info, trace, warn};
+use oci_cli_wrapper::manifest::{
+    archive_manifest, multi_platform_manifest, platform_digests, Descriptor,
+};
+use oci_cli_wrapper::signature::{manifest_digest, ImageSignature, SignaturePayload};
+use oci_cli_wrapper::{CredentialSource, DockerArchitecture, ImageTool, RegistryCredentials};
+use pubsys_config::{InfraConfig, SigningKeyConfig};
+use ring::rand::SystemRandom;
 use snafu::{ensure, OptionExt, ResultExt};
 use std::path::PathBuf;
 
@@ -28,24 +34,39 @@ pub(crate) struct PublishKitArgs {
     /// The build id of the kit that should be published
     #[arg(long)]
     build_id: String,
+
+    /// Sign the kit with the private key at this path rather than with the vendor's
+    /// `signing_keys` from Infra.toml
+    #[arg(long)]
+    signing_key: Option<PathBuf>,
+
+    /// Where to find the credentials for the vendor's registry: `credential-helper=<name>`,
+    /// `docker-config=<directory>`, or `token-file=<path>`. The docker credential configuration
+    /// from the environment is used when absent
+    #[arg(long)]
+    auth: Option<CredentialSource>,
+
+    /// Overwrite the kit's version tag if it already refers to a different kit. Consumers that
+    /// have locked the existing kit will no longer be able to verify it
+    #[arg(long)]
+    force: bool,
+
+    /// Print the image URIs and digests that publishing would produce, and whether the kit would be
+    /// signed, without pushing anything
+    #[arg(long)]
+    dry_run: bool,
 }
 
 pub(crate) async fn run(args: &Args, publish_kit_args: &PublishKitArgs) -> Result<()> {
-    let image_tool = ImageTool::from_builtin_krane();
-
     // If a lock file exists, use that, otherwise use Infra.toml
     let infra_config = InfraConfig::from_path_or_lock(&args.infra_config_path, false)
         .context(error::ConfigSnafu)?;
     trace!("Parsed infra config: {:?}", infra_config);
 
-    publish_kit(infra_config, publish_kit_args, &image_tool).await
+    publish_kit(infra_config, publish_kit_args).await
 }
 
-async fn publish_kit(
-    infra_config: InfraConfig,
-    publish_kit_args: &PublishKitArgs,
-    image_tool: &ImageTool,
-) -> Result<()> {
+async fn publish_kit(infra_config: InfraConfig, publish_kit_args: &PublishKitArgs) -> Result<()> {
     // Fetch the vendor container registry uri
     let vendor = infra_config
         .vendor
@@ -61,6 +82,18 @@ async fn publish_kit(
         vendor_registry_uri
     );
 
+    let mut credentials = RegistryCredentials::new();
+    if let Some(auth) = &publish_kit_args.auth {
+        debug!(
+            "Using {} for the credentials of {}",
+            auth, vendor_registry_uri
+        );
+        credentials
+            .insert(&vendor_registry_uri, auth.clone())
+            .context(error::PublishKitSnafu)?;
+    }
+    let image_tool = &ImageTool::from_builtin_krane_with_credentials(credentials);
+
     // Auto resolve the expected paths for the kit contents archive
     let kit_path = publish_kit_args.kit_path.as_path();
     let kit_name = kit_path
@@ -75,6 +108,8 @@ async fn publish_kit(
         None => kit_name.to_string(),
     };
 
+    // Work out what will be pushed before pushing anything, so that the kit can be checked
+    // against any kit that has already been published with the same version.
     let mut platform_images = Vec::new();
     for arch in ["aarch64", "x86_64"] {
         let docker_arch =
@@ -92,39 +127,238 @@ async fn publish_kit(
             "{}/{}:{}-{}-{}",
             vendor_registry_uri, repository_target, &kit_version, &build_id, arch
         );
+        let descriptor = archive_manifest(&path).context(error::PublishKitSnafu)?;
+
+        platform_images.push(PlatformImage {
+            arch: docker_arch,
+            path,
+            uri: arch_specific_target_uri,
+            descriptor,
+        });
+    }
+    ensure!(
+        !platform_images.is_empty(),
+        error::NoArchiveSnafu { path: kit_path }
+    );
+
+    let repository = format!("{}/{}", vendor_registry_uri, repository_target);
+    let target_uri = format!("{}:{}", repository, kit_version);
+    let manifest_list = multi_platform_manifest(
+        &platform_images
+            .iter()
+            .map(|image| (image.arch.clone(), image.descriptor.clone()))
+            .collect::<Vec<_>>(),
+    )
+    .context(error::PublishKitSnafu)?;
+
+    if publish_kit_args.dry_run {
+        for image in &platform_images {
+            println!(
+                "{} image: {}@{}",
+                image.arch, image.uri, image.descriptor.digest
+            );
+        }
+        // The digest of the manifest list depends on how the image tool serializes it when it
+        // is pushed, so only the tag is shown.
+        println!("Manifest list: {}", target_uri);
+    }
+
+    // The digest of the manifest list that the tag already refers to, if it refers to these
+    // images.
+    let published = match existing_manifest(image_tool, &repository, &kit_version).await? {
+        Some(existing) => {
+            let existing_digests = platform_digests(&existing).context(error::PublishKitSnafu)?;
+            let new_digests = platform_digests(&manifest_list).context(error::PublishKitSnafu)?;
+            if existing_digests == new_digests {
+                Some(manifest_digest(&existing))
+            } else {
+                ensure!(
+                    publish_kit_args.force,
+                    error::TagExistsSnafu {
+                        uri: &target_uri,
+                        digest: manifest_digest(&existing),
+                    }
+                );
+                warn!(
+                    "Overwriting {}, which refers to different kit images ({})",
+                    target_uri,
+                    manifest_digest(&existing)
+                );
+                None
+            }
+        }
+        None => None,
+    };
+
+    let signing_key = match publish_kit_args.signing_key.as_ref() {
+        Some(path) => Some(SigningKeyConfig::file { path: path.clone() }),
+        None => vendor.signing_keys.clone(),
+    };
+    let sign = needs_signature(
+        image_tool,
+        signing_key.as_ref(),
+        &repository,
+        published.as_deref(),
+    )
+    .await?;
+
+    if publish_kit_args.dry_run {
+        println!(
+            "Signature: {}",
+            match (&signing_key, sign) {
+                (None, _) => "none, since no signing key is configured",
+                (Some(_), true) => "would be pushed",
+                (Some(_), false) => "already pushed",
+            }
+        );
+        info!("Dry run, so nothing was pushed");
+        return Ok(());
+    }
 
+    if published.is_some() {
+        if !sign {
+            info!(
+                "{} already refers to the same kit images, so there is nothing to publish",
+                target_uri
+            );
+            return Ok(());
+        }
         info!(
-            "Pushing kit image for platform {} to {}",
-            arch, &arch_specific_target_uri
+            "{} already refers to the same kit images, but has not been signed",
+            target_uri
         );
+    } else {
+        for image in &platform_images {
+            info!(
+                "Pushing kit image for platform {} to {}",
+                image.arch, &image.uri
+            );
+
+            image_tool
+                .push_oci_archive(&image.path, &image.uri)
+                .await
+                .context(error::PublishKitSnafu)?;
+        }
+
+        info!("Pushing kit to {}", &target_uri);
 
         image_tool
-            .push_oci_archive(&path, &arch_specific_target_uri)
+            .push_multi_platform_manifest(
+                platform_images
+                    .into_iter()
+                    .map(|image| (image.arch, image.uri))
+                    .collect(),
+                &target_uri,
+            )
             .await
             .context(error::PublishKitSnafu)?;
+    }
 
-        platform_images.push((docker_arch, arch_specific_target_uri.clone()));
+    if let Some(signing_key) = signing_key.filter(|_| sign) {
+        sign_kit(image_tool, &signing_key, &repository, &target_uri).await?;
     }
-    ensure!(
-        !platform_images.is_empty(),
-        error::NoArchiveSnafu { path: kit_path }
-    );
 
-    let target_uri = format!(
-        "{}/{}:{}",
-        vendor_registry_uri, repository_target, kit_version
-    );
+    info!("Successfully published kit to {}", target_uri);
+
+    Ok(())
+}
 
-    info!("Pushing kit to {}", &target_uri);
+/// A single-platform kit image and where it is pushed.
+struct PlatformImage {
+    arch: DockerArchitecture,
+    path: PathBuf,
+    uri: String,
+    descriptor: Descriptor,
+}
 
-    image_tool
-        .push_multi_platform_manifest(platform_images, &target_uri)
+/// Fetches the manifest list that `tag` refers to in `repository`, if the tag exists. The tag is
+/// only taken to be unpublished when the registry says that it does not exist; any other failure
+/// to look it up, such as missing credentials or a network error, is an error, so that a kit that
+/// was already published is never overwritten by mistake.
+async fn existing_manifest(
+    image_tool: &ImageTool,
+    repository: &str,
+    tag: &str,
+) -> Result<Option<Vec<u8>>> {
+    let uri = format!("{}:{}", repository, tag);
+    match image_tool.list_tags(repository).await {
+        Ok(tags) if !tags.iter().any(|existing| existing == tag) => return Ok(None),
+        Ok(_) => {}
+        // The repository does not exist until the first kit is pushed to it.
+        Err(e) if e.is_not_found() => {
+            debug!("Repository {} does not exist yet: {}", repository, e);
+            return Ok(None);
+        }
+        // Listing tags may not be allowed, so fall back to fetching the tag directly.
+        Err(e) => debug!("Unable to list the tags in {}: {}", repository, e),
+    }
+    match image_tool.get_manifest(&uri).await {
+        Ok(manifest) => Ok(Some(manifest)),
+        Err(e) if e.is_not_found() => {
+            debug!("{} does not exist yet: {}", uri, e);
+            Ok(None)
+        }
+        Err(e) => Err(e).context(error::PublishKitSnafu),
+    }
+}
+
+/// Whether the kit needs to be signed with `signing_key`. A kit that was already published, whose
+/// manifest list has the digest `published`, is signed again unless the registry holds a signature
+/// of it, since an earlier run may have pushed the kit but failed to sign it.
+async fn needs_signature(
+    image_tool: &ImageTool,
+    signing_key: Option<&SigningKeyConfig>,
+    repository: &str,
+    published: Option<&str>,
+) -> Result<bool> {
+    let (Some(_), Some(digest)) = (signing_key, published) else {
+        return Ok(signing_key.is_some());
+    };
+    match image_tool.get_signatures(repository, digest).await {
+        Ok(signatures) => Ok(!signatures.iter().any(|signature| {
+            SignaturePayload::from_slice(&signature.payload)
+                .is_ok_and(|payload| payload.digest() == digest)
+        })),
+        Err(e) if e.is_not_found() => {
+            debug!("{}@{} has no signature yet: {}", repository, digest, e);
+            Ok(true)
+        }
+        Err(e) => Err(e).context(error::PublishKitSnafu),
+    }
+}
+
+/// Signs the manifest list of the kit at `uri` and pushes the signature to the kit's repository,
+/// where Twoliter looks for it when the vendor requires signed kits.
+async fn sign_kit(
+    image_tool: &ImageTool,
+    signing_key: &SigningKeyConfig,
+    repository: &str,
+    uri: &str,
+) -> Result<()> {
+    let manifest_list = image_tool
+        .get_manifest(uri)
         .await
         .context(error::PublishKitSnafu)?;
+    let digest = manifest_digest(&manifest_list);
+    let payload = SignaturePayload::new(repository, &digest)
+        .to_vec()
+        .context(error::PublishKitSnafu)?;
 
-    info!("Successfully published kit to {}", target_uri);
+    let key_source = get_signing_key_source(signing_key).context(error::SigningKeySnafu)?;
+    let signer = key_source
+        .as_sign()
+        .await
+        .context(error::SignKitSnafu { digest: &digest })?;
+    let signature = signer
+        .sign(&payload, &SystemRandom::new())
+        .await
+        .context(error::SignKitSnafu { digest: &digest })?;
 
-    Ok(())
+    info!("Pushing signature of {}@{}", repository, digest);
+    image_tool
+        .push_signature(&ImageSignature { payload, signature })
+        .await
+        .context(error::PublishKitSnafu)
 }
 
 mod error {
@@ -157,6 +391,23 @@ mod error {
             source: oci_cli_wrapper::error::Error,
         },
 
+        #[snafu(display("Could not sign kit {}: {}", digest, source))]
+        SignKit {
+            digest: String,
+            source: Box<dyn std::error::Error + Send + Sync + 'static>,
+        },
+
+        #[snafu(display("Could not get key to sign kit: {}", source))]
+        SigningKey { source: crate::repo::Error },
+
+        #[snafu(display(
+            "{} already refers to a different kit with digest {}; publish with a new version, or \
+            use --force to overwrite it",
+            uri,
+            digest
+        ))]
+        TagExists { uri: String, digest: String },
+
         #[snafu(display("Vendor '{}' not specified in Infra.toml", name))]
         VendorNotFound { name: String },
     }
@@ -165,3 +416,104 @@ mod error {
 pub(crate) use error::Error;
 
 type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_trait::async_trait;
+    use oci_cli_wrapper::signature::{signature_tag, write_signature_layout};
+    use oci_cli_wrapper::{ConfigView, ImageToolImpl};
+    use std::path::Path;
+
+    const REPOSITORY: &str = "a.com/b/my-kit";
+    const DIGEST: &str = "sha256:aaaa";
+
+    /// A registry that only holds the signatures of the given manifest digests.
+    #[derive(Debug)]
+    struct SignatureRegistry {
+        signed: Vec<String>,
+    }
+
+    impl SignatureRegistry {
+        fn not_found(uri: &str) -> oci_cli_wrapper::error::Error {
+            oci_cli_wrapper::error::Error::OperationFailed {
+                message: format!("GET {uri}: MANIFEST_UNKNOWN: manifest unknown"),
+                program: "fake".into(),
+                args: Vec::new(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ImageToolImpl for SignatureRegistry {
+        async fn pull_oci_image(&self, path: &Path, uri: &str) -> oci_cli_wrapper::Result<()> {
+            let digest = self
+                .signed
+                .iter()
+                .find(|digest| uri == format!("{REPOSITORY}:{}", signature_tag(digest)))
+                .ok_or_else(|| Self::not_found(uri))?;
+            let payload = SignaturePayload::new(REPOSITORY, digest).to_vec()?;
+            write_signature_layout(
+                path,
+                &[ImageSignature {
+                    payload,
+                    signature: b"signature".to_vec(),
+                }],
+            )
+        }
+
+        async fn get_config(&self, uri: &str) -> oci_cli_wrapper::Result<ConfigView> {
+            Err(Self::not_found(uri))
+        }
+
+        async fn get_manifest(&self, uri: &str) -> oci_cli_wrapper::Result<Vec<u8>> {
+            Err(Self::not_found(uri))
+        }
+
+        async fn list_tags(&self, repository: &str) -> oci_cli_wrapper::Result<Vec<String>> {
+            Err(Self::not_found(repository))
+        }
+
+        async fn push_oci_archive(&self, _path: &Path, uri: &str) -> oci_cli_wrapper::Result<()> {
+            unreachable!("pushed {uri}")
+        }
+
+        async fn push_multi_platform_manifest(
+            &self,
+            _platform_images: Vec<(DockerArchitecture, String)>,
+            uri: &str,
+        ) -> oci_cli_wrapper::Result<()> {
+            unreachable!("pushed {uri}")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_needs_signature() {
+        let key = SigningKeyConfig::file {
+            path: "keys/root.pem".into(),
+        };
+        let unsigned = ImageTool::new(Box::new(SignatureRegistry { signed: Vec::new() }));
+        let signed = ImageTool::new(Box::new(SignatureRegistry {
+            signed: vec![DIGEST.to_string()],
+        }));
+
+        // A kit that is published by this run is always signed.
+        assert!(needs_signature(&signed, Some(&key), REPOSITORY, None)
+            .await
+            .unwrap());
+        // A kit that was already published but not signed is signed now.
+        assert!(
+            needs_signature(&unsigned, Some(&key), REPOSITORY, Some(DIGEST))
+                .await
+                .unwrap()
+        );
+        assert!(
+            !needs_signature(&signed, Some(&key), REPOSITORY, Some(DIGEST))
+                .await
+                .unwrap()
+        );
+        assert!(!needs_signature(&unsigned, None, REPOSITORY, None)
+            .await
+            .unwrap());
+    }
+}
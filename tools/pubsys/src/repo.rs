@@ -405,7 +405,9 @@ fn is_file_not_found_error(e: &tough::error::Error) -> bool {
 }
 
 /// Gets the corresponding `KeySource` according to the signing key config from Infra.toml
-fn get_signing_key_source(signing_key_config: &SigningKeyConfig) -> Result<Box<dyn KeySource>> {
+pub(crate) fn get_signing_key_source(
+    signing_key_config: &SigningKeyConfig,
+) -> Result<Box<dyn KeySource>> {
     match signing_key_config {
         SigningKeyConfig::file { path } => Ok(Box::new(LocalKeySource { path: path.clone() })),
         SigningKeyConfig::kms { key_id, config, .. } => Ok(Box::new(KmsKeySource {
@@ -1,12 +1,26 @@
 use super::build_clean::BuildClean;
+use super::build_graph::{BuildEnv, BuildGraph};
+use super::LockArgs;
 use crate::cargo_make::CargoMake;
 use crate::common::fs;
-use crate::project::{self, Locked};
+use crate::config::Config;
+use crate::project::{self, Locked, Project};
 use crate::tools::install_tools;
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use buildsys::events::{millis, Event, EventLog, ResolvedKit, EVENTS_JSON_ENV};
+use buildsys::manifest::{ManifestInfo, SupportedArch};
 use clap::Parser;
-use std::path::PathBuf;
+use futures::future::join_all;
+use std::collections::HashSet;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 use tempfile::TempDir;
+use tracing::info;
+
+/// Every architecture that Bottlerocket can be built for.
+const ALL_ARCHES: [SupportedArch; 2] = [SupportedArch::X86_64, SupportedArch::Aarch64];
 
 #[derive(Debug, Parser)]
 pub(crate) enum BuildCommand {
@@ -32,52 +46,94 @@ pub(crate) struct BuildKit {
     #[clap(long = "project-path")]
     pub(crate) project_path: Option<PathBuf>,
 
-    /// The architecture to build for.
-    #[clap(long = "arch", default_value = "x86_64")]
-    pub(crate) arch: String,
+    #[clap(flatten)]
+    pub(crate) arch: ArchArgs,
 
     /// The name of the kit to build.
     pub(crate) kit: String,
 
     /// The URL to the lookaside cache where sources are stored to avoid pulling them from upstream.
-    /// Defaults to https://cache.bottlerocket.aws
+    /// Defaults to the `lookaside-cache` from Twoliter's configuration, or
+    /// https://cache.bottlerocket.aws
     pub(crate) lookaside_cache: Option<String>,
 
     /// If sources are not found in the lookaside cache, this flag will cause buildsys to pull them
     /// from the upstream URL found in a package's `Cargo.toml`.
     #[clap(long = "upstream-source-fallback")]
     pub(crate) upstream_source_fallback: bool,
+
+    /// Write events describing the progress of the build to this file, one JSON object per line.
+    /// Any existing file is replaced.
+    #[clap(long = "events-json")]
+    pub(crate) events_json: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub(crate) schedule: ScheduleArgs,
+
+    #[clap(flatten)]
+    pub(crate) lock: LockArgs,
 }
 
 impl BuildKit {
     pub(super) async fn run(&self) -> Result<()> {
-        let project = project::load_or_find_project(self.project_path.clone()).await?;
-        let project = project.load_lock::<Locked>().await?;
-        let toolsdir = project.project_dir().join("build/tools");
-        install_tools(&toolsdir).await?;
+        let events = events_log(self.events_json.as_deref()).await?;
+        let project = load_project(self.project_path.clone(), &self.lock, &events).await?;
+        let toolsdir = install_build_tools(&project, &events).await?;
         let makefile_path = toolsdir.join("Makefile.toml");
 
+        let config = Config::load(Some(&project.project_dir())).await?;
+
+        let arches = self.arch.arches(config.arch.value, None);
+        fetch_arches(&project, &arches, &self.lock).await?;
+
         let mut optional_envs = Vec::new();
 
-        if let Some(lookaside_cache) = &self.lookaside_cache {
+        if let Some(lookaside_cache) = config.lookaside_cache(self.lookaside_cache.as_ref()) {
             optional_envs.push(("BUILDSYS_LOOKASIDE_CACHE", lookaside_cache))
         }
 
-        CargoMake::new(&project.sdk_image().project_image_uri().to_string())?
+        if let Some(cargo_home) = config.cargo_home(None) {
+            optional_envs.push(("CARGO_HOME", cargo_home.display().to_string()))
+        }
+
+        let cargo_make = CargoMake::new(&project.sdk_image().project_image_uri().to_string())?
             .env("TWOLITER_TOOLS_DIR", toolsdir.display().to_string())
-            .env("BUILDSYS_ARCH", &self.arch)
             .env("BUILDSYS_KIT", &self.kit)
             .env("BUILDSYS_VERSION_IMAGE", project.release_version())
             .env("GO_MODULES", project.find_go_modules().await?.join(" "))
             .env(
                 "BUILDSYS_UPSTREAM_SOURCE_FALLBACK",
-                self.upstream_source_fallback.to_string(),
+                config
+                    .upstream_source_fallback(self.upstream_source_fallback)
+                    .to_string(),
             )
+            .env("BUILDSYS_JOBS", self.schedule.jobs.to_string())
             .envs(optional_envs.into_iter())
+            .envs(events_env(&events))
             .makefile(makefile_path)
-            .project_dir(project.project_dir())
-            .exec("build-kit")
-            .await
+            .project_dir(project.project_dir());
+
+        let kit_manifest = project
+            .project_dir()
+            .join("kits")
+            .join(&self.kit)
+            .join("Cargo.toml");
+        prepare_arches(&cargo_make, &arches, "prepare-kit").await?;
+        build_arches(&arches, self.arch.parallel, |arch| {
+            let cargo_make = for_arch(&cargo_make, arch);
+            let kit_manifest = &kit_manifest;
+            async move {
+                if !self.schedule.native {
+                    return cargo_make.exec("build-kit-prepared").await;
+                }
+                let env = BuildEnv::prepare(&cargo_make, "write-build-env").await?;
+                BuildGraph::load(&env, kit_manifest)
+                    .await?
+                    .build(&env, self.schedule.jobs)
+                    .await
+            }
+        })
+        .await
     }
 }
 
@@ -88,15 +144,15 @@ pub(crate) struct BuildVariant {
     #[clap(long = "project-path")]
     project_path: Option<PathBuf>,
 
-    /// The architecture to build for.
-    #[clap(long = "arch", default_value = "x86_64")]
-    arch: String,
+    #[clap(flatten)]
+    arch: ArchArgs,
 
     /// The variant to build.
     variant: String,
 
     /// The URL to the lookaside cache where sources are stored to avoid pulling them from upstream.
-    /// Defaults to https://cache.bottlerocket.aws
+    /// Defaults to the `lookaside-cache` from Twoliter's configuration, or
+    /// https://cache.bottlerocket.aws
     lookaside_cache: Option<String>,
 
     /// If sources are not found in the lookaside cache, this flag will cause buildsys to pull them
@@ -107,14 +163,24 @@ pub(crate) struct BuildVariant {
     /// Path to the Infra.toml file
     #[clap(long)]
     infra_toml: Option<PathBuf>,
+
+    /// Write events describing the progress of the build to this file, one JSON object per line.
+    /// Any existing file is replaced.
+    #[clap(long = "events-json")]
+    events_json: Option<PathBuf>,
+
+    #[clap(flatten)]
+    schedule: ScheduleArgs,
+
+    #[clap(flatten)]
+    lock: LockArgs,
 }
 
 impl BuildVariant {
     pub(super) async fn run(&self) -> Result<()> {
-        let project = project::load_or_find_project(self.project_path.clone()).await?;
-        let project = project.load_lock::<Locked>().await?;
-        let toolsdir = project.project_dir().join("build/tools");
-        install_tools(&toolsdir).await?;
+        let events = events_log(self.events_json.as_deref()).await?;
+        let project = load_project(self.project_path.clone(), &self.lock, &events).await?;
+        let toolsdir = install_build_tools(&project, &events).await?;
         let makefile_path = toolsdir.join("Makefile.toml");
         // A temporary directory in the `build` directory
         let build_temp_dir = TempDir::new_in(project.project_dir())
@@ -122,33 +188,377 @@ impl BuildVariant {
         let packages_dir = build_temp_dir.path().join("sdk_rpms");
         fs::create_dir_all(&packages_dir).await?;
 
+        let variant_manifest = project
+            .project_dir()
+            .join("variants")
+            .join(&self.variant)
+            .join("Cargo.toml");
+        let variant_info = ManifestInfo::new(&variant_manifest).context(format!(
+            "Unable to read the manifest of variant '{}'",
+            self.variant
+        ))?;
+        let config = Config::load(Some(&project.project_dir())).await?;
+        let arches = self
+            .arch
+            .arches(config.arch.value, variant_info.supported_arches());
+        fetch_arches(&project, &arches, &self.lock).await?;
+
         let mut optional_envs = Vec::new();
 
-        if let Some(lookaside_cache) = &self.lookaside_cache {
-            optional_envs.push(("BUILDSYS_LOOKASIDE_CACHE", lookaside_cache.to_string()))
+        if let Some(lookaside_cache) = config.lookaside_cache(self.lookaside_cache.as_ref()) {
+            optional_envs.push(("BUILDSYS_LOOKASIDE_CACHE", lookaside_cache))
+        }
+
+        if let Some(cargo_home) = config.cargo_home(None) {
+            optional_envs.push(("CARGO_HOME", cargo_home.display().to_string()))
         }
 
-        if let Some(infra_toml) = &self.infra_toml {
+        if let Some(infra_toml) = config.infra_toml(self.infra_toml.as_ref()) {
             optional_envs.push((
                 "PUBLISH_INFRA_CONFIG_PATH",
                 infra_toml.display().to_string(),
             ))
         }
 
-        CargoMake::new(&project.sdk_image().project_image_uri().to_string())?
+        let cargo_make = CargoMake::new(&project.sdk_image().project_image_uri().to_string())?
             .env("TWOLITER_TOOLS_DIR", toolsdir.display().to_string())
-            .env("BUILDSYS_ARCH", &self.arch)
             .env("BUILDSYS_VARIANT", &self.variant)
             .env("BUILDSYS_VERSION_IMAGE", project.release_version())
             .env("GO_MODULES", project.find_go_modules().await?.join(" "))
             .env(
                 "BUILDSYS_UPSTREAM_SOURCE_FALLBACK",
-                self.upstream_source_fallback.to_string(),
+                config
+                    .upstream_source_fallback(self.upstream_source_fallback)
+                    .to_string(),
             )
+            .env("BUILDSYS_JOBS", self.schedule.jobs.to_string())
             .envs(optional_envs.into_iter())
+            .envs(events_env(&events))
             .makefile(makefile_path)
-            .project_dir(project.project_dir())
-            .exec("build")
+            .project_dir(project.project_dir());
+
+        prepare_arches(&cargo_make, &arches, "prepare-build").await?;
+        build_arches(&arches, self.arch.parallel, |arch| {
+            let cargo_make = for_arch(&cargo_make, arch);
+            let variant_manifest = &variant_manifest;
+            async move {
+                if !self.schedule.native {
+                    return cargo_make.exec("build-variant-prepared").await;
+                }
+                let env = BuildEnv::prepare(&cargo_make, "write-build-env").await?;
+                let graph = BuildGraph::load(&env, variant_manifest).await?;
+                cargo_make.exec("unlink-latest-variant").await?;
+                graph.build(&env, self.schedule.jobs).await?;
+                cargo_make.exec("link-latest-variant").await
+            }
+        })
+        .await
+    }
+}
+
+/// Creates the file given by `--events-json`, if any, and returns a log that appends to it.
+async fn events_log(path: Option<&Path>) -> Result<EventLog> {
+    let Some(path) = path else {
+        return Ok(EventLog::default());
+    };
+    // buildsys runs in each package's directory, so it needs the absolute path
+    let path = std::path::absolute(path)
+        .context(format!("Unable to resolve the path '{}'", path.display()))?;
+    fs::write(&path, "").await?;
+    Ok(EventLog::new(Some(path)))
+}
+
+/// The environment variables that pass the events file on to buildsys.
+fn events_env(events: &EventLog) -> impl Iterator<Item = (&'static str, String)> {
+    events
+        .path()
+        .map(|path| (EVENTS_JSON_ENV, path.display().to_string()))
+        .into_iter()
+}
+
+/// Loads the project and resolves its SDK and kits, recording the images that were resolved.
+async fn load_project(
+    project_path: Option<PathBuf>,
+    lock: &LockArgs,
+    events: &EventLog,
+) -> Result<Project<Locked>> {
+    let start = Instant::now();
+    let project = project::load_or_find_project(project_path).await?;
+    let project = project.load_lock::<Locked>(lock.mode()).await?;
+    let kits = project
+        .locked_images()
+        .map(|image| ResolvedKit {
+            name: image.name.to_string(),
+            version: image.version.to_string(),
+            vendor: image.vendor.to_string(),
+            source: image.source.clone(),
+            digest: image.digest.clone(),
+        })
+        .collect();
+    events
+        .emit(&Event::KitsResolved {
+            kits,
+            duration_ms: millis(start.elapsed()),
+        })
+        .context("Unable to write a build event")?;
+    Ok(project)
+}
+
+/// Installs the build tools into the project and returns the directory they were installed to.
+async fn install_build_tools(project: &Project<Locked>, events: &EventLog) -> Result<PathBuf> {
+    let start = Instant::now();
+    let toolsdir = project.project_dir().join("build/tools");
+    install_tools(&toolsdir).await?;
+    events
+        .emit(&Event::ToolsInstalled {
+            tools_dir: toolsdir.clone(),
+            duration_ms: millis(start.elapsed()),
+        })
+        .context("Unable to write a build event")?;
+    Ok(toolsdir)
+}
+
+/// Arguments that choose how the packages, kits and variants of a build are scheduled.
+#[derive(Debug, Clone, Parser)]
+pub(crate) struct ScheduleArgs {
+    /// Run buildsys for each package, kit and variant in dependency order, rather than through
+    /// `cargo build`. Like cargo, only what changed since it was last built is built again.
+    #[clap(long = "native")]
+    pub(crate) native: bool,
+
+    /// The most packages, kits and variants to build at the same time with `--native`. Otherwise
+    /// this is the number of jobs that `cargo build` runs.
+    #[clap(
+        long = "jobs",
+        short = 'j',
+        env = "BUILDSYS_JOBS",
+        default_value_t = default_jobs()
+    )]
+    pub(crate) jobs: NonZeroUsize,
+}
+
+impl Default for ScheduleArgs {
+    fn default() -> Self {
+        Self {
+            native: false,
+            jobs: default_jobs(),
+        }
+    }
+}
+
+/// The same default as `BUILDSYS_JOBS` in Makefile.toml.
+fn default_jobs() -> NonZeroUsize {
+    NonZeroUsize::new(8).unwrap()
+}
+
+/// Arguments that select the architectures to build for.
+#[derive(Debug, Clone, Parser)]
+pub(crate) struct ArchArgs {
+    /// The architectures to build for, separated by commas, e.g. `x86_64,aarch64`. Defaults to the
+    /// `arch` from Twoliter's configuration, or x86_64.
+    #[clap(long = "arch", value_delimiter = ',', conflicts_with = "all_arches")]
+    pub(crate) arch: Vec<SupportedArch>,
+
+    /// Build for every architecture. Variants are only built for the architectures listed in their
+    /// `supported-arches`.
+    #[clap(long = "all-arches")]
+    pub(crate) all_arches: bool,
+
+    /// Build for each architecture at the same time rather than one after another.
+    #[clap(long = "parallel")]
+    pub(crate) parallel: bool,
+}
+
+impl ArchArgs {
+    /// The architectures to build for, in a stable order without duplicates. `default` is used
+    /// when no architecture was given, and `supported` limits the architectures chosen by
+    /// `--all-arches`.
+    fn arches(
+        &self,
+        default: SupportedArch,
+        supported: Option<&HashSet<SupportedArch>>,
+    ) -> Vec<SupportedArch> {
+        let requested: Vec<SupportedArch> = if self.all_arches {
+            ALL_ARCHES
+                .into_iter()
+                .filter(|arch| supported.map_or(true, |supported| supported.contains(arch)))
+                .collect()
+        } else if self.arch.is_empty() {
+            vec![default]
+        } else {
+            self.arch.clone()
+        };
+        let mut arches = Vec::new();
+        for arch in requested {
+            if !arches.contains(&arch) {
+                arches.push(arch);
+            }
+        }
+        arches
+    }
+}
+
+/// Fetches the project's external kits for each architecture, one after another, since fetching
+/// writes the external kit metadata that all architectures share.
+async fn fetch_arches(
+    project: &Project<Locked>,
+    arches: &[SupportedArch],
+    lock: &LockArgs,
+) -> Result<()> {
+    for arch in arches {
+        project.fetch(&arch.to_string(), lock.mode()).await?;
+    }
+    Ok(())
+}
+
+/// Runs the Makefile task `task` that prepares the build for each architecture, one after another,
+/// since preparing writes the Cargo metadata, vendored Go modules and keys that all architectures
+/// share. The builds themselves can then run in parallel.
+async fn prepare_arches(
+    cargo_make: &CargoMake,
+    arches: &[SupportedArch],
+    task: &str,
+) -> Result<()> {
+    for arch in arches {
+        for_arch(cargo_make, *arch).exec(task).await?;
+    }
+    Ok(())
+}
+
+/// Sets the architecture for a build. buildsys keeps the state of each architecture apart within
+/// the state directory, so the architectures can be built at the same time.
+fn for_arch(cargo_make: &CargoMake, arch: SupportedArch) -> CargoMake {
+    cargo_make.clone().env("BUILDSYS_ARCH", arch.to_string())
+}
+
+/// Runs `build` for each of `arches`, either one after another or all at once. When there are
+/// several architectures, every build runs to completion and a summary of their results is printed
+/// at the end.
+async fn build_arches<F, Fut>(arches: &[SupportedArch], parallel: bool, build: F) -> Result<()>
+where
+    F: Fn(SupportedArch) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    if let [arch] = arches {
+        return build(*arch).await;
+    }
+    let results = if parallel {
+        join_all(arches.iter().map(|arch| build(*arch))).await
+    } else {
+        let mut results = Vec::new();
+        for arch in arches {
+            info!("Building for {arch}");
+            results.push(build(*arch).await);
+        }
+        results
+    };
+    let (summary, failed) = summarize(arches, &results);
+    println!("{}", summary.trim_end());
+    if failed > 0 {
+        bail!(
+            "the build failed for {failed} of {} architectures",
+            arches.len()
+        );
+    }
+    Ok(())
+}
+
+/// Describes the result of the build for each architecture, and counts the builds that failed.
+fn summarize(arches: &[SupportedArch], results: &[Result<()>]) -> (String, usize) {
+    let mut summary = String::from("Build summary:\n");
+    let mut failed = 0;
+    for (arch, result) in arches.iter().zip(results.iter()) {
+        match result {
+            Ok(()) => summary.push_str(&format!("  {arch}: succeeded\n")),
+            Err(e) => {
+                failed += 1;
+                summary.push_str(&format!("  {arch}: failed: {e:#}\n"));
+            }
+        }
+    }
+    (summary, failed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_arches() {
+        let kit = BuildKit::try_parse_from(["kit", "my-kit"]).unwrap();
+        assert_eq!(
+            kit.arch.arches(SupportedArch::Aarch64, None),
+            vec![SupportedArch::Aarch64]
+        );
+
+        let kit = BuildKit::try_parse_from(["kit", "my-kit", "--arch", "aarch64,x86_64,aarch64"])
+            .unwrap();
+        assert_eq!(
+            kit.arch.arches(SupportedArch::X86_64, None),
+            vec![SupportedArch::Aarch64, SupportedArch::X86_64]
+        );
+
+        let variant =
+            BuildVariant::try_parse_from(["variant", "my-variant", "--all-arches"]).unwrap();
+        assert_eq!(
+            variant.arch.arches(SupportedArch::X86_64, None),
+            ALL_ARCHES.to_vec()
+        );
+        let supported = HashSet::from([SupportedArch::Aarch64]);
+        assert_eq!(
+            variant.arch.arches(SupportedArch::X86_64, Some(&supported)),
+            vec![SupportedArch::Aarch64]
+        );
+
+        BuildKit::try_parse_from(["kit", "my-kit", "--arch", "x86_64", "--all-arches"])
+            .unwrap_err();
+        BuildKit::try_parse_from(["kit", "my-kit", "--arch", "riscv64"]).unwrap_err();
+    }
+
+    #[test]
+    fn test_schedule_args() {
+        let kit = BuildKit::try_parse_from(["kit", "my-kit"]).unwrap();
+        assert!(!kit.schedule.native);
+        assert_eq!(kit.schedule.jobs.get(), 8);
+
+        let variant =
+            BuildVariant::try_parse_from(["variant", "my-variant", "--native", "-j", "3"]).unwrap();
+        assert!(variant.schedule.native);
+        assert_eq!(variant.schedule.jobs.get(), 3);
+
+        BuildKit::try_parse_from(["kit", "my-kit", "--jobs", "0"]).unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_build_arches() {
+        build_arches(&ALL_ARCHES, true, |_| async { Ok(()) })
             .await
+            .unwrap();
+
+        // Every architecture is built even when an earlier one fails
+        let built = std::sync::Mutex::new(Vec::new());
+        let result = build_arches(&ALL_ARCHES, false, |arch| {
+            built.lock().unwrap().push(arch);
+            async move {
+                match arch {
+                    SupportedArch::X86_64 => Err(anyhow!("no space left on device")),
+                    SupportedArch::Aarch64 => Ok(()),
+                }
+            }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(*built.lock().unwrap(), ALL_ARCHES.to_vec());
+    }
+
+    #[test]
+    fn test_summarize() {
+        let (summary, failed) = summarize(&ALL_ARCHES, &[Err(anyhow!("oops")), Ok(())]);
+        assert_eq!(failed, 1);
+        assert_eq!(
+            summary,
+            "Build summary:\n  x86_64: failed: oops\n  aarch64: succeeded\n"
+        );
     }
 }
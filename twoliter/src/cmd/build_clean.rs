@@ -1,3 +1,4 @@
+use super::LockArgs;
 use crate::cargo_make::CargoMake;
 use crate::project::{self, Locked};
 use crate::tools;
@@ -10,12 +11,15 @@ pub(crate) struct BuildClean {
     /// Path to Twoliter.toml. Will search for Twoliter.toml when absent.
     #[clap(long = "project-path")]
     project_path: Option<PathBuf>,
+
+    #[clap(flatten)]
+    lock: LockArgs,
 }
 
 impl BuildClean {
     pub(super) async fn run(&self) -> Result<()> {
         let project = project::load_or_find_project(self.project_path.clone()).await?;
-        let project = project.load_lock::<Locked>().await?;
+        let project = project.load_lock::<Locked>(self.lock.mode()).await?;
         let toolsdir = project.project_dir().join("build/tools");
         tools::install_tools(&toolsdir).await?;
         let makefile_path = toolsdir.join("Makefile.toml");
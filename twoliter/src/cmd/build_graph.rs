@@ -0,0 +1,747 @@
+//! Builds a kit or variant by running buildsys for each package, kit and variant that it needs, in
+//! dependency order, instead of letting `cargo build` run buildsys from each build script.
+//!
+//! Once the build is prepared, the `write-build-env` Makefile task records the environment that
+//! buildsys would see when run by `cargo build`. Each build then runs the installed buildsys in
+//! that environment, from the directory of the manifest being built, as a build script would. A
+//! build starts once everything it depends on has been built, up to a limit of builds at a time,
+//! and the first build to fail cancels the others.
+//!
+//! Like cargo, a build is skipped when it is up to date. buildsys reports the files and environment
+//! variables that a build depends on with `rerun-if-changed` and `rerun-if-env-changed` directives,
+//! which are recorded in a fingerprint in the build's state directory once the build succeeds. The
+//! build runs again when one of those files was modified after the build started, one of those
+//! variables changed, or something it depends on was built after it.
+use super::workspace::member_manifests;
+use crate::cargo_make::CargoMake;
+use crate::common::fs;
+use anyhow::{bail, ensure, Context, Result};
+use buildsys::manifest::Manifest;
+use buildsys::BuildType;
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tempfile::TempDir;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tracing::{debug, info};
+
+/// The number of lines of a failed build's output that are repeated in its error.
+const FAILURE_OUTPUT_LINES: usize = 20;
+
+/// The environment that buildsys runs in, as recorded by the `write-build-env` Makefile task.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct BuildEnv {
+    vars: BTreeMap<String, String>,
+}
+
+impl BuildEnv {
+    /// Runs the Makefile task `task`, which prepares the build and records its environment.
+    pub(super) async fn prepare(cargo_make: &CargoMake, task: &str) -> Result<Self> {
+        let dir =
+            TempDir::new().context("Unable to create a directory for the build environment")?;
+        let path = dir.path().join("build-env");
+        cargo_make
+            .clone()
+            .env("BUILDSYS_BUILD_ENV_PATH", path.display().to_string())
+            .exec(task)
+            .await?;
+        Self::parse(&fs::read(&path).await?)
+    }
+
+    /// Parses the output of `env -0`.
+    fn parse(env: &[u8]) -> Result<Self> {
+        let vars = env
+            .split(|&byte| byte == 0)
+            .filter(|var| !var.is_empty())
+            .map(|var| {
+                let var = String::from_utf8_lossy(var);
+                let (key, value) = var.split_once('=').context(format!(
+                    "Unable to parse the build environment variable '{var}'"
+                ))?;
+                Ok((key.to_string(), value.to_string()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { vars })
+    }
+
+    /// Returns the value of `key`, which the build environment must have.
+    pub(super) fn get(&self, key: &str) -> Result<&str> {
+        self.vars
+            .get(key)
+            .map(String::as_str)
+            .context(format!("The build environment does not set '{key}'"))
+    }
+}
+
+/// A package, kit or variant that can be built.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(super) struct NodeId {
+    build_type: BuildType,
+    name: String,
+}
+
+impl Display for NodeId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let build_type = match self.build_type {
+            BuildType::Package => "package",
+            BuildType::Kit => "kit",
+            BuildType::Variant => "variant",
+            BuildType::Repack => "repack",
+        };
+        write!(f, "{build_type} '{}'", self.name)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BuildNode {
+    manifest_dir: PathBuf,
+    /// Everything that must be built before this, including indirect dependencies.
+    dependencies: BTreeSet<NodeId>,
+}
+
+/// A target and everything that must be built before it.
+#[derive(Debug, Clone)]
+pub(super) struct BuildGraph {
+    nodes: BTreeMap<NodeId, BuildNode>,
+}
+
+impl BuildGraph {
+    /// Loads the graph for the workspace member with the manifest `target`, using the Cargo
+    /// metadata of the build environment.
+    pub(super) async fn load(env: &BuildEnv, target: &Path) -> Result<Self> {
+        let metadata_path = PathBuf::from(env.get("BUILDSYS_CARGO_METADATA_PATH")?);
+        let metadata = fs::read_to_string(&metadata_path).await?;
+        let target = fs::canonicalize(target).await?;
+
+        let mut nodes = BTreeMap::new();
+        let mut target_id = None;
+        for manifest_path in member_manifests(&metadata)? {
+            let manifest = Manifest::new(&manifest_path, &metadata_path).context(format!(
+                "Unable to read the manifest '{}'",
+                manifest_path.display()
+            ))?;
+            let id = NodeId {
+                build_type: manifest.info().build_type()?,
+                name: manifest.info().package_name().to_string(),
+            };
+            let packages = manifest
+                .package_dependencies()?
+                .into_iter()
+                .map(|name| NodeId {
+                    build_type: BuildType::Package,
+                    name,
+                });
+            let kits = manifest.kit_dependencies()?.into_iter().map(|name| NodeId {
+                build_type: BuildType::Kit,
+                name,
+            });
+            if manifest_path == target {
+                target_id = Some(id.clone());
+            }
+            let manifest_dir = manifest_path
+                .parent()
+                .context(format!(
+                    "Unable to find the directory of '{}'",
+                    manifest_path.display()
+                ))?
+                .to_path_buf();
+            let node = BuildNode {
+                manifest_dir,
+                dependencies: packages.chain(kits).collect(),
+            };
+            nodes.insert(id, node);
+        }
+        let target_id = target_id.context(format!(
+            "'{}' is not a member of the project's Cargo workspace",
+            target.display()
+        ))?;
+        Ok(Self::for_target(nodes, &target_id))
+    }
+
+    /// Keeps `target` and the nodes it depends on. Dependencies that are not in the workspace,
+    /// such as the kits that the project fetches, are not built.
+    fn for_target(mut nodes: BTreeMap<NodeId, BuildNode>, target: &NodeId) -> Self {
+        let mut needed = BTreeMap::new();
+        let mut queue = vec![target.clone()];
+        while let Some(id) = queue.pop() {
+            if needed.contains_key(&id) {
+                continue;
+            }
+            let Some(node) = nodes.remove(&id) else {
+                debug!("Not building {id}, which is not in the workspace");
+                continue;
+            };
+            queue.extend(node.dependencies.iter().cloned());
+            needed.insert(id, node);
+        }
+        let ids: BTreeSet<NodeId> = needed.keys().cloned().collect();
+        for node in needed.values_mut() {
+            node.dependencies.retain(|id| ids.contains(id));
+        }
+        Self { nodes: needed }
+    }
+
+    /// Builds every node that is not up to date with buildsys, running at most `jobs` builds at a
+    /// time.
+    pub(super) async fn build(&self, env: &BuildEnv, jobs: NonZeroUsize) -> Result<()> {
+        let env = Arc::new(env.clone());
+        let buildsys = PathBuf::from(env.get("TWOLITER_TOOLS_DIR")?).join("buildsys");
+        let fingerprints = PathBuf::from(env.get("BUILDSYS_STATE_DIR")?)
+            .join(env.get("BUILDSYS_ARCH")?)
+            .join("native");
+        fs::create_dir_all(&fingerprints).await?;
+        info!("Building {} packages, kits and variants", self.nodes.len());
+        self.schedule(jobs, |id, cancel| {
+            let env = Arc::clone(&env);
+            let buildsys = buildsys.clone();
+            let node = self.nodes[&id].clone();
+            let fingerprints = fingerprints.clone();
+            async move {
+                let path = Fingerprint::path(&fingerprints, &id);
+                if let Some(fingerprint) = Fingerprint::load(&path).await {
+                    let dependencies = node
+                        .dependencies
+                        .iter()
+                        .map(|dependency| Fingerprint::path(&fingerprints, dependency));
+                    if fingerprint.is_fresh(&env, dependencies).await {
+                        info!("{id} is up to date");
+                        return Ok(());
+                    }
+                }
+                // A build that fails or is cancelled must not leave the old fingerprint behind.
+                if path.exists() {
+                    fs::remove_file(&path).await?;
+                }
+                let started = SystemTime::now();
+                let directives =
+                    run_buildsys(&buildsys, &id, &node.manifest_dir, &env, cancel).await?;
+                Fingerprint::new(started, &node.manifest_dir, &directives, &env)
+                    .write(&path)
+                    .await
+            }
+        })
+        .await
+    }
+
+    /// Runs `build` for each node once everything it depends on has been built, running at most
+    /// `jobs` builds at a time. Nodes that more of the remaining nodes depend on are started
+    /// first. When a build fails, no more builds are started and the running builds are told to
+    /// cancel.
+    async fn schedule<F, Fut>(&self, jobs: NonZeroUsize, build: F) -> Result<()>
+    where
+        F: Fn(NodeId, Cancel) -> Fut,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let mut waiting: BTreeMap<NodeId, BTreeSet<NodeId>> = self
+            .nodes
+            .iter()
+            .map(|(id, node)| (id.clone(), node.dependencies.clone()))
+            .collect();
+        let (cancel_sender, cancel_receiver) = watch::channel(false);
+        let mut running = JoinSet::new();
+        let mut failure = None;
+        let mut cancelled = 0;
+        loop {
+            if failure.is_none() {
+                let mut ready: Vec<&NodeId> = waiting
+                    .iter()
+                    .filter(|(_, dependencies)| dependencies.is_empty())
+                    .map(|(id, _)| id)
+                    .collect();
+                ready.sort_by_key(|id| {
+                    std::cmp::Reverse(
+                        waiting
+                            .values()
+                            .filter(|dependencies| dependencies.contains(*id))
+                            .count(),
+                    )
+                });
+                let ready: Vec<NodeId> = ready
+                    .into_iter()
+                    .take(jobs.get().saturating_sub(running.len()))
+                    .cloned()
+                    .collect();
+                for id in ready {
+                    waiting.remove(&id);
+                    debug!("Starting the build of {id}");
+                    let build = build(id.clone(), Cancel(cancel_receiver.clone()));
+                    running.spawn(async move { (id, build.await) });
+                }
+            }
+
+            let Some(finished) = running.join_next().await else {
+                break;
+            };
+            let (id, result) = finished.context("A build task panicked")?;
+            match result {
+                Ok(()) => {
+                    debug!("Finished the build of {id}");
+                    for dependencies in waiting.values_mut() {
+                        dependencies.remove(&id);
+                    }
+                }
+                Err(_) if failure.is_some() => cancelled += 1,
+                Err(e) => {
+                    failure = Some((id, e));
+                    cancel_sender.send_replace(true);
+                }
+            }
+        }
+
+        if let Some((id, e)) = failure {
+            let stopped = match (cancelled, waiting.len()) {
+                (0, 0) => String::new(),
+                (cancelled, waiting) => format!(
+                    " ({cancelled} running builds were cancelled and {waiting} were not started)"
+                ),
+            };
+            return Err(e.context(format!("Failed to build {id}{stopped}")));
+        }
+        ensure!(
+            waiting.is_empty(),
+            "Unable to build {} because their dependencies form a cycle",
+            waiting
+                .keys()
+                .map(NodeId::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        Ok(())
+    }
+}
+
+/// Tells a running build that it should stop because another build failed.
+#[derive(Debug, Clone)]
+struct Cancel(watch::Receiver<bool>);
+
+impl Cancel {
+    /// Completes once the build should stop.
+    async fn cancelled(&mut self) {
+        // The sender lives until every build has finished, so this only returns when cancelled.
+        let _ = self.0.wait_for(|cancelled| *cancelled).await;
+    }
+}
+
+/// What a successful build depended on, besides the builds that came before it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Fingerprint {
+    /// When the build started.
+    started: SystemTime,
+    /// The files that buildsys named with `rerun-if-changed`.
+    files: BTreeSet<PathBuf>,
+    /// The values of the environment variables that buildsys named with `rerun-if-env-changed`,
+    /// or `None` for those that were not set.
+    env: BTreeMap<String, Option<String>>,
+}
+
+impl Fingerprint {
+    /// Records the dependencies of a build from the cargo directives that buildsys wrote.
+    /// Relative paths are relative to the manifest directory, as they are for cargo.
+    fn new(
+        started: SystemTime,
+        manifest_dir: &Path,
+        directives: &[String],
+        env: &BuildEnv,
+    ) -> Self {
+        let mut fingerprint = Self {
+            started,
+            files: BTreeSet::new(),
+            env: BTreeMap::new(),
+        };
+        for directive in directives {
+            if let Some(file) = directive.strip_prefix("rerun-if-changed=") {
+                fingerprint.files.insert(manifest_dir.join(file));
+            } else if let Some(var) = directive.strip_prefix("rerun-if-env-changed=") {
+                fingerprint
+                    .env
+                    .insert(var.to_string(), env.vars.get(var).cloned());
+            }
+        }
+        fingerprint
+    }
+
+    fn path(dir: &Path, id: &NodeId) -> PathBuf {
+        let build_type = match id.build_type {
+            BuildType::Package => "package",
+            BuildType::Kit => "kit",
+            BuildType::Variant => "variant",
+            BuildType::Repack => "repack",
+        };
+        dir.join(format!("{build_type}-{}.json", id.name))
+    }
+
+    /// Loads the fingerprint at `path`, or returns `None` if there is no usable fingerprint.
+    async fn load(path: &Path) -> Option<Self> {
+        let bytes = tokio::fs::read(path).await.ok()?;
+        serde_json::from_slice(&bytes)
+            .map_err(|e| debug!("Ignoring the fingerprint '{}': {e}", path.display()))
+            .ok()
+    }
+
+    async fn write(&self, path: &Path) -> Result<()> {
+        let bytes = serde_json::to_vec(self).context("Unable to serialize a build fingerprint")?;
+        fs::write(path, bytes).await
+    }
+
+    /// Whether the build is still up to date in `env`, given the paths of the fingerprints of the
+    /// builds it depends on.
+    async fn is_fresh(&self, env: &BuildEnv, dependencies: impl Iterator<Item = PathBuf>) -> bool {
+        for (var, value) in self.env.iter() {
+            if env.vars.get(var) != value.as_ref() {
+                debug!("'{var}' changed");
+                return false;
+            }
+        }
+        for path in dependencies {
+            match Self::load(&path).await {
+                Some(dependency) if dependency.started <= self.started => {}
+                _ => {
+                    debug!("'{}' was built again", path.display());
+                    return false;
+                }
+            }
+        }
+        for file in self.files.iter() {
+            let modified = tokio::fs::metadata(file)
+                .await
+                .and_then(|metadata| metadata.modified());
+            match modified {
+                Ok(modified) if modified <= self.started => {}
+                _ => {
+                    debug!("'{}' changed", file.display());
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Runs buildsys to build `id`, as its build script would, and returns the cargo directives that
+/// it wrote, without the `cargo:` prefix.
+async fn run_buildsys(
+    buildsys: &Path,
+    id: &NodeId,
+    manifest_dir: &Path,
+    env: &BuildEnv,
+    mut cancel: Cancel,
+) -> Result<Vec<String>> {
+    let subcommand = match id.build_type {
+        BuildType::Package => "build-package",
+        BuildType::Kit => "build-kit",
+        BuildType::Variant => "build-variant",
+        BuildType::Repack => bail!("Unable to build {id} natively"),
+    };
+    let mut child = Command::new(buildsys)
+        .arg(subcommand)
+        .env_clear()
+        .envs(&env.vars)
+        .env("CARGO_MANIFEST_DIR", manifest_dir)
+        .current_dir(manifest_dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // buildsys runs docker, so the whole process group is signalled to cancel the build.
+        .process_group(0)
+        .kill_on_drop(true)
+        .spawn()
+        .context(format!("Unable to run '{}'", buildsys.display()))?;
+
+    let tail = Arc::new(Mutex::new(VecDeque::new()));
+    let directives = Arc::new(Mutex::new(Vec::new()));
+    let stdout = forward_output(
+        child.stdout.take(),
+        &id.name,
+        Arc::clone(&tail),
+        Arc::clone(&directives),
+    );
+    let stderr = forward_output(
+        child.stderr.take(),
+        &id.name,
+        Arc::clone(&tail),
+        Arc::clone(&directives),
+    );
+
+    let status = tokio::select! {
+        status = child.wait() => status,
+        _ = cancel.cancelled() => {
+            if let Some(pid) = child.id() {
+                let _ = killpg(Pid::from_raw(pid as i32), Signal::SIGTERM);
+            }
+            child.wait().await.context("Unable to wait for buildsys to stop")?;
+            bail!("The build of {id} was cancelled");
+        }
+    }
+    .context("Unable to wait for buildsys")?;
+    let _ = tokio::join!(stdout, stderr);
+
+    if !status.success() {
+        let tail = tail.lock().unwrap();
+        let output = Vec::from(tail.clone()).join("\n");
+        bail!("buildsys exited with {status}. The last lines of its output were:\n{output}");
+    }
+    let directives = directives.lock().unwrap().clone();
+    Ok(directives)
+}
+
+/// Prints each line of a build's output with the name of what is being built, leaving out the
+/// directives that buildsys writes for cargo, which are kept in `directives`. The last lines are
+/// kept in `tail`.
+fn forward_output<R>(
+    output: Option<R>,
+    name: &str,
+    tail: Arc<Mutex<VecDeque<String>>>,
+    directives: Arc<Mutex<Vec<String>>>,
+) -> tokio::task::JoinHandle<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let name = name.to_string();
+    tokio::spawn(async move {
+        let Some(output) = output else {
+            return;
+        };
+        let mut lines = BufReader::new(output).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let line = match line.strip_prefix("cargo:") {
+                Some(directive) => match directive.strip_prefix("warning=") {
+                    Some(warning) => format!("warning: {warning}"),
+                    None => {
+                        directives.lock().unwrap().push(directive.to_string());
+                        continue;
+                    }
+                },
+                None => line,
+            };
+            println!("[{name}] {line}");
+            let mut tail = tail.lock().unwrap();
+            if tail.len() == FAILURE_OUTPUT_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(line);
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::anyhow;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn id(build_type: BuildType, name: &str) -> NodeId {
+        NodeId {
+            build_type,
+            name: name.to_string(),
+        }
+    }
+
+    fn package(name: &str) -> NodeId {
+        id(BuildType::Package, name)
+    }
+
+    fn node(dependencies: &[NodeId]) -> BuildNode {
+        BuildNode {
+            manifest_dir: PathBuf::new(),
+            dependencies: dependencies.iter().cloned().collect(),
+        }
+    }
+
+    /// A kit with two packages that share a dependency, a package the kit does not need, and a
+    /// kit that is fetched rather than built.
+    fn graph() -> BuildGraph {
+        let nodes = BTreeMap::from([
+            (package("glibc"), node(&[])),
+            (package("kernel"), node(&[package("glibc")])),
+            (package("systemd"), node(&[package("glibc")])),
+            (package("unused"), node(&[])),
+            (
+                id(BuildType::Kit, "core-kit"),
+                node(&[
+                    package("glibc"),
+                    package("kernel"),
+                    package("systemd"),
+                    id(BuildType::Kit, "external-kit"),
+                ]),
+            ),
+        ]);
+        BuildGraph::for_target(nodes, &id(BuildType::Kit, "core-kit"))
+    }
+
+    #[test]
+    fn test_build_env() {
+        let env = BuildEnv::parse(
+            b"BUILDSYS_ARCH=x86_64\0CARGO_MAKE_CARGO_ARGS=--offline --locked\0EMPTY=\0",
+        )
+        .unwrap();
+        assert_eq!(env.get("BUILDSYS_ARCH").unwrap(), "x86_64");
+        assert_eq!(
+            env.get("CARGO_MAKE_CARGO_ARGS").unwrap(),
+            "--offline --locked"
+        );
+        assert_eq!(env.get("EMPTY").unwrap(), "");
+        env.get("BUILDSYS_VARIANT").unwrap_err();
+        BuildEnv::parse(b"NOT_A_VARIABLE\0").unwrap_err();
+    }
+
+    #[test]
+    fn test_for_target() {
+        let graph = graph();
+        assert_eq!(
+            graph.nodes.keys().cloned().collect::<Vec<_>>(),
+            vec![
+                package("glibc"),
+                package("kernel"),
+                package("systemd"),
+                id(BuildType::Kit, "core-kit"),
+            ]
+        );
+        assert!(!graph.nodes[&id(BuildType::Kit, "core-kit")]
+            .dependencies
+            .contains(&id(BuildType::Kit, "external-kit")));
+    }
+
+    #[tokio::test]
+    async fn test_schedule_order() {
+        let built = Arc::new(Mutex::new(Vec::new()));
+        let running = Arc::new(AtomicUsize::new(0));
+        let most_running = Arc::new(AtomicUsize::new(0));
+        graph()
+            .schedule(NonZeroUsize::new(2).unwrap(), |id, _| {
+                let built = Arc::clone(&built);
+                let running = Arc::clone(&running);
+                let most_running = Arc::clone(&most_running);
+                async move {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most_running.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    built.lock().unwrap().push(id.name);
+                    Ok(())
+                }
+            })
+            .await
+            .unwrap();
+        let built = built.lock().unwrap();
+        assert_eq!(built.len(), 4);
+        assert_eq!(built[0], "glibc");
+        assert_eq!(built[3], "core-kit");
+        assert_eq!(most_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_cancels_on_failure() {
+        let started = Arc::new(Mutex::new(Vec::new()));
+        let error = graph()
+            .schedule(NonZeroUsize::new(4).unwrap(), |id, mut cancel| {
+                let started = Arc::clone(&started);
+                async move {
+                    started.lock().unwrap().push(id.name.clone());
+                    match id.name.as_str() {
+                        "glibc" => Ok(()),
+                        "kernel" => Err(anyhow!("rpmbuild failed")),
+                        _ => {
+                            cancel.cancelled().await;
+                            Err(anyhow!("cancelled"))
+                        }
+                    }
+                }
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(error.root_cause().to_string(), "rpmbuild failed");
+        assert_eq!(
+            error.to_string(),
+            "Failed to build package 'kernel' (1 running builds were cancelled and 1 were not \
+            started)"
+        );
+        assert!(!started.lock().unwrap().contains(&"core-kit".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_fingerprint() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("pkg.spec");
+        fs::write(&source, "Name: pkg").await.unwrap();
+        let set_modified = |path: &Path, time: SystemTime| {
+            std::fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap();
+        };
+        let hour = Duration::from_secs(3600);
+        let now = SystemTime::now();
+        set_modified(&source, now - hour);
+        let env = BuildEnv::parse(b"BUILDSYS_ARCH=x86_64\0").unwrap();
+        let directives = [
+            "rerun-if-changed=pkg.spec".to_string(),
+            "rerun-if-env-changed=BUILDSYS_ARCH".to_string(),
+            "rerun-if-env-changed=BUILDSYS_UNSET".to_string(),
+        ];
+        let fingerprint = Fingerprint::new(now, dir.path(), &directives, &env);
+        assert_eq!(fingerprint.files, BTreeSet::from([source.clone()]));
+        assert_eq!(fingerprint.env["BUILDSYS_ARCH"].as_deref(), Some("x86_64"));
+        assert_eq!(fingerprint.env["BUILDSYS_UNSET"], None);
+
+        let path = Fingerprint::path(dir.path(), &package("pkg"));
+        fingerprint.write(&path).await.unwrap();
+        let fingerprint = Fingerprint::load(&path).await.unwrap();
+        assert!(fingerprint.is_fresh(&env, std::iter::empty()).await);
+
+        // A changed environment variable
+        let other_env = BuildEnv::parse(b"BUILDSYS_ARCH=aarch64\0").unwrap();
+        assert!(!fingerprint.is_fresh(&other_env, std::iter::empty()).await);
+
+        // A dependency that was built later, or that has no fingerprint
+        let dependency = Fingerprint::path(dir.path(), &package("glibc"));
+        Fingerprint::new(now - hour, dir.path(), &[], &env)
+            .write(&dependency)
+            .await
+            .unwrap();
+        assert!(
+            fingerprint
+                .is_fresh(&env, [dependency.clone()].into_iter())
+                .await
+        );
+        Fingerprint::new(now + hour, dir.path(), &[], &env)
+            .write(&dependency)
+            .await
+            .unwrap();
+        assert!(!fingerprint.is_fresh(&env, [dependency].into_iter()).await);
+        let missing = Fingerprint::path(dir.path(), &package("missing"));
+        assert!(!fingerprint.is_fresh(&env, [missing].into_iter()).await);
+
+        // A file that was modified after the build started
+        set_modified(&source, now + hour);
+        assert!(!fingerprint.is_fresh(&env, std::iter::empty()).await);
+        fs::remove_file(&source).await.unwrap();
+        assert!(!fingerprint.is_fresh(&env, std::iter::empty()).await);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_cycle() {
+        let nodes = BTreeMap::from([
+            (package("a"), node(&[package("b")])),
+            (package("b"), node(&[package("a")])),
+        ]);
+        let error = BuildGraph::for_target(nodes, &package("a"))
+            .schedule(NonZeroUsize::new(1).unwrap(), |_, _| async { Ok(()) })
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("form a cycle"), "{error}");
+    }
+}
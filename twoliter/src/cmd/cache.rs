@@ -0,0 +1,214 @@
+use crate::project::{self, CacheEntry, OciStore, PruneFilter};
+use anyhow::{Context, Result};
+use chrono::{Duration, Utc};
+use clap::{ArgGroup, Parser, ValueEnum};
+use std::fmt::Write;
+use std::path::PathBuf;
+
+/// Manage the cache of kit images that are pulled when kits are fetched
+#[derive(Debug, Parser)]
+pub(crate) enum CacheCommand {
+    List(ListCache),
+    Prune(PruneCache),
+}
+
+impl CacheCommand {
+    pub(crate) async fn run(self) -> Result<()> {
+        match self {
+            CacheCommand::List(command) => command.run().await,
+            CacheCommand::Prune(command) => command.run().await,
+        }
+    }
+}
+
+/// The format in which to print the cache entries.
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub(crate) enum CacheFormat {
+    /// A table with one row for each cached image
+    #[default]
+    Text,
+    /// JSON
+    Json,
+}
+
+/// List the cached kit images along with their size, when they were last used, and the lockfiles
+/// that refer to them
+#[derive(Debug, Parser)]
+pub(crate) struct ListCache {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// The output format
+    #[clap(long = "format", value_enum, default_value_t)]
+    format: CacheFormat,
+}
+
+impl ListCache {
+    pub(super) async fn run(&self) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone()).await?;
+        let entries = project.kit_cache().entries().await?;
+        let output = match self.format {
+            CacheFormat::Text => render_text(&entries),
+            CacheFormat::Json => serde_json::to_string_pretty(&entries)
+                .context("failed to serialize cache entries")?,
+        };
+        println!("{}", output.trim_end());
+        Ok(())
+    }
+}
+
+/// Remove cached kit images that are no longer referenced or have not been used recently. Images
+/// in the shared image store that no project links any longer are removed as well, unless they
+/// were used within --unused-days
+#[derive(Debug, Parser)]
+#[clap(group(
+    ArgGroup::new("criteria")
+        .required(true)
+        .multiple(true)
+        .args(["unreferenced", "unused_days"])
+))]
+pub(crate) struct PruneCache {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// Remove images that are not referenced by the project's Twoliter.lock, or by the lockfile of
+    /// any project given with --keep-project
+    #[clap(long = "unreferenced")]
+    unreferenced: bool,
+
+    /// Path to the Twoliter.toml of another project whose kit images should be kept. May be given
+    /// more than once
+    #[clap(long = "keep-project", requires = "unreferenced")]
+    keep_project: Vec<PathBuf>,
+
+    /// Remove images that have not been used in this many days
+    #[clap(long = "unused-days")]
+    unused_days: Option<u32>,
+
+    /// List the images that would be removed without removing them
+    #[clap(long = "dry-run")]
+    dry_run: bool,
+}
+
+impl PruneCache {
+    pub(super) async fn run(&self) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone()).await?;
+        let keep_lockfiles = if self.unreferenced {
+            let mut lockfiles = vec![project.lockfile()];
+            for path in self.keep_project.iter() {
+                lockfiles.push(project::Project::load(path).await?.lockfile());
+            }
+            Some(lockfiles)
+        } else {
+            None
+        };
+        let filter = PruneFilter {
+            keep_lockfiles,
+            unused_since: self
+                .unused_days
+                .map(|days| Utc::now() - Duration::days(days.into())),
+        };
+
+        let pruned = project.kit_cache().prune(&filter, self.dry_run).await?;
+        let size: u64 = pruned.iter().map(|entry| entry.size).sum();
+        let verb = if self.dry_run {
+            "Would remove"
+        } else {
+            "Removed"
+        };
+        for entry in pruned.iter() {
+            println!("{verb} {}", entry.path.display());
+        }
+        println!(
+            "{verb} {} cached image(s), {}",
+            pruned.len(),
+            format_size(size)
+        );
+
+        // Removing archives unlinks their blobs from the shared image store, which can now be
+        // garbage collected.
+        if let Some(store) = OciStore::for_user() {
+            let pruned = store.prune(filter.unused_since, self.dry_run).await?;
+            for digest in pruned.images.iter() {
+                println!("{verb} {digest} from the shared image store");
+            }
+            println!(
+                "{verb} {} image(s) and {} blob(s) from the shared image store, {}",
+                pruned.images.len(),
+                pruned.blobs,
+                format_size(pruned.size)
+            );
+        }
+        Ok(())
+    }
+}
+
+fn render_text(entries: &[CacheEntry]) -> String {
+    if entries.is_empty() {
+        return "The kit cache is empty\n".to_string();
+    }
+    let mut out = String::new();
+    for entry in entries {
+        let _ = writeln!(
+            out,
+            "{}  {}  last used {}",
+            entry.name,
+            format_size(entry.size),
+            entry.last_used.format("%Y-%m-%d %H:%M:%S UTC")
+        );
+        if let Some(uri) = &entry.uri {
+            let _ = writeln!(out, "  image: {uri}");
+        }
+        if entry.referenced_by.is_empty() {
+            let _ = writeln!(out, "  not referenced by any known lockfile");
+        }
+        for lockfile in entry.referenced_by.iter() {
+            let _ = writeln!(out, "  referenced by: {}", lockfile.display());
+        }
+    }
+    let total: u64 = entries.iter().map(|entry| entry.size).sum();
+    let _ = writeln!(
+        out,
+        "{} cached image(s), {}",
+        entries.len(),
+        format_size(total)
+    );
+    out
+}
+
+/// Formats a size in bytes using binary units, e.g. `1.5 GiB`.
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if size < 1024 {
+        return format!("{size} B");
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[test]
+    fn test_prune_requires_criteria() {
+        PruneCache::try_parse_from(["prune"]).unwrap_err();
+        PruneCache::try_parse_from(["prune", "--keep-project", "a"]).unwrap_err();
+        PruneCache::try_parse_from(["prune", "--unused-days", "30"]).unwrap();
+        PruneCache::try_parse_from(["prune", "--unreferenced", "--keep-project", "a"]).unwrap();
+    }
+}
@@ -0,0 +1,331 @@
+use super::workspace::{find_rpms, load_members, Member, RpmFile};
+use crate::project::{self, Project, Unlocked};
+use anyhow::{bail, Result};
+use buildsys::manifest::SupportedArch;
+use buildsys::BuildType;
+use clap::Parser;
+use std::collections::BTreeSet;
+use std::fmt::{self, Display};
+use std::path::PathBuf;
+use tracing::info;
+
+/// Check the project's packages, kits and variants for mistakes that would otherwise only be found
+/// partway through a build. Nothing is built or pulled.
+#[derive(Debug, Parser)]
+pub(crate) struct Check {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    pub(crate) project_path: Option<PathBuf>,
+
+    /// Check that variants support this architecture, and look for the packages of external kits
+    /// that were fetched for it. By default, external kits fetched for any architecture are used.
+    #[clap(long = "arch")]
+    pub(crate) arch: Option<SupportedArch>,
+}
+
+impl Check {
+    pub(super) async fn run(&self) -> Result<()> {
+        // Vendors and overrides are checked when the project is loaded.
+        let project = project::load_or_find_project(self.project_path.clone()).await?;
+        let mut findings = Vec::new();
+        let mut members = Vec::new();
+        for (manifest_path, member) in load_members(&project.project_dir()).await? {
+            match member {
+                Ok(member) => members.push(member),
+                Err(e) => findings.push(Finding::error(format!(
+                    "unable to load '{}': {e:#}",
+                    manifest_path.display()
+                ))),
+            }
+        }
+        let external_packages = external_kit_packages(&project, self.arch).await?;
+        if external_packages.is_none() {
+            findings.push(Finding::warning(
+                "the project depends on kits which have not been fetched, so packages included \
+                in variants were not checked; run `twoliter fetch` first",
+            ));
+        }
+
+        let check = ProjectCheck {
+            project: &project,
+            members: &members,
+            external_packages: external_packages.as_ref(),
+            arch: self.arch,
+        };
+        findings.extend(check.findings());
+        findings.sort();
+
+        for finding in findings.iter() {
+            println!("{finding}");
+        }
+        let errors = findings
+            .iter()
+            .filter(|finding| finding.severity == Severity::Error)
+            .count();
+        if errors > 0 {
+            bail!(
+                "found {errors} problem(s) in '{}'",
+                project.filepath().display()
+            );
+        }
+        info!(
+            "Checked {} packages, kits and variants in '{}'",
+            members.len(),
+            project.filepath().display()
+        );
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in the project.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Finding {
+    severity: Severity,
+    message: String,
+}
+
+impl Finding {
+    fn error(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+
+    fn warning(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "error: {}", self.message),
+            Severity::Warning => write!(f, "warning: {}", self.message),
+        }
+    }
+}
+
+/// Lists the names of the packages in the external kits that have been fetched for `arch`, or for
+/// any architecture if it is not given. Returns `None` if the project depends on kits but none of
+/// them have been fetched.
+async fn external_kit_packages(
+    project: &Project<Unlocked>,
+    arch: Option<SupportedArch>,
+) -> Result<Option<BTreeSet<String>>> {
+    let has_kits = !project.direct_kit_deps().is_empty() || !project.local_kits().is_empty();
+    let packages: BTreeSet<String> = find_rpms(&project.external_kits_dir())
+        .await?
+        .iter()
+        .filter_map(|path| RpmFile::parse(path))
+        .filter(|rpm| rpm.is_for(arch))
+        .map(|rpm| rpm.package_name().to_string())
+        .collect();
+    Ok((!has_kits || !packages.is_empty()).then_some(packages))
+}
+
+/// Checks the loaded members of a project against each other and against the project.
+struct ProjectCheck<'a> {
+    project: &'a Project<Unlocked>,
+    members: &'a [Member],
+    /// The packages available from external kits, if they have been fetched.
+    external_packages: Option<&'a BTreeSet<String>>,
+    arch: Option<SupportedArch>,
+}
+
+impl ProjectCheck<'_> {
+    fn findings(&self) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        self.check_kits(&mut findings);
+        self.check_variants(&mut findings);
+        self.check_packages(&mut findings);
+        findings
+    }
+
+    fn members(&self, build_type: BuildType) -> impl Iterator<Item = &Member> {
+        self.members
+            .iter()
+            .filter(move |member| member.build_type == build_type)
+    }
+
+    fn check_kits(&self, findings: &mut Vec<Finding>) {
+        for kit in self.members(BuildType::Kit) {
+            match kit.info.kit_vendor() {
+                Ok(vendor) if self.project.has_vendor(&vendor) => {}
+                Ok(vendor) => findings.push(Finding::error(format!(
+                    "kit '{}' is published by vendor '{vendor}', which is not specified in \
+                    Twoliter.toml",
+                    kit.info.kit_name()
+                ))),
+                Err(e) => findings.push(Finding::error(format!(
+                    "kit '{}' in '{}' does not specify its vendor: {e}",
+                    kit.info.kit_name(),
+                    kit.manifest_path.display()
+                ))),
+            }
+        }
+    }
+
+    fn check_variants(&self, findings: &mut Vec<Finding>) {
+        let local_packages: BTreeSet<&String> = self
+            .members(BuildType::Package)
+            .flat_map(|package| package.packages.iter())
+            .collect();
+        for variant in self.members(BuildType::Variant) {
+            let name = variant.info.manifest_name();
+
+            if let Some(external_packages) = self.external_packages {
+                for package in variant.info.included_packages().into_iter().flatten() {
+                    if !local_packages.contains(package) && !external_packages.contains(package) {
+                        findings.push(Finding::error(format!(
+                            "variant '{name}' includes package '{package}', which is not built \
+                            by this project or provided by any fetched kit"
+                        )));
+                    }
+                }
+            }
+
+            if let Some(supported_arches) = variant.info.supported_arches() {
+                if supported_arches.is_empty() {
+                    findings.push(Finding::error(format!(
+                        "variant '{name}' has an empty list of supported-arches, so it cannot be \
+                        built for any architecture"
+                    )));
+                } else if let Some(arch) = self.arch.filter(|arch| !supported_arches.contains(arch))
+                {
+                    let mut supported: Vec<String> = supported_arches
+                        .iter()
+                        .map(|arch| arch.to_string())
+                        .collect();
+                    supported.sort();
+                    findings.push(Finding::error(format!(
+                        "variant '{name}' does not support '{arch}', only: {}",
+                        supported.join(", ")
+                    )));
+                }
+            }
+
+            if let Some(layout) = variant
+                .info
+                .image_layout()
+                .filter(|layout| layout.exceeds_publish_image_size_hint())
+            {
+                findings.push(Finding::warning(format!(
+                    "variant '{name}' has an OS image of {} GiB and a data image of {} GiB, which \
+                    exceed its publish-image-size-hint-gib of {} GiB",
+                    layout.os_image_size_gib,
+                    layout.data_image_size_gib,
+                    layout.publish_image_size_hint_gib()
+                )));
+            }
+        }
+    }
+
+    fn check_packages(&self, findings: &mut Vec<Finding>) {
+        let included: BTreeSet<&str> = self
+            .members(BuildType::Kit)
+            .flat_map(|kit| kit.packages.iter().map(String::as_str))
+            .collect();
+        for package in self.members(BuildType::Package) {
+            if !included.contains(package.info.manifest_name()) {
+                findings.push(Finding::warning(format!(
+                    "package '{}' is not included in any kit",
+                    package.info.manifest_name()
+                )));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::fs::{create_dir_all, write};
+    use buildsys::manifest::ManifestInfo;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    const PROJECT: &str = r#"
+schema-version = 1
+release-version = "1.0.0"
+
+[vendor.my-vendor]
+registry = "a.com/b"
+"#;
+
+    async fn member(
+        dir: &Path,
+        name: &str,
+        metadata: &str,
+        build_type: BuildType,
+        packages: &[&str],
+    ) -> Member {
+        let manifest_path = dir.join(name).join("Cargo.toml");
+        create_dir_all(manifest_path.parent().unwrap())
+            .await
+            .unwrap();
+        let manifest = format!(
+            "[package]\nname = \"{name}\"\nversion = \"0.1.0\"\n\n[package.metadata.{metadata}"
+        );
+        write(&manifest_path, manifest).await.unwrap();
+        Member {
+            info: ManifestInfo::new(&manifest_path).unwrap(),
+            manifest_path,
+            build_type,
+            packages: packages.iter().map(|package| package.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_project_check() {
+        let tempdir = TempDir::new().unwrap();
+        let dir = tempdir.path();
+        write(dir.join("Twoliter.toml"), PROJECT).await.unwrap();
+        let project = Project::load(dir.join("Twoliter.toml")).await.unwrap();
+
+        let members = vec![
+            member(dir, "hello", "build-package]\n", BuildType::Package, &["hello", "hello-bin"]).await,
+            member(dir, "unused", "build-package]\n", BuildType::Package, &["unused"]).await,
+            member(dir, "good-kit", "build-kit]\nvendor = \"my-vendor\"\n", BuildType::Kit, &["hello"]).await,
+            member(dir, "bad-kit", "build-kit]\nvendor = \"other-vendor\"\n", BuildType::Kit, &[]).await,
+            member(
+                dir,
+                "my-variant",
+                "build-variant]\nincluded-packages = [\"hello-bin\", \"kernel-6.1\", \"missing\"]\n\
+                supported-arches = [\"aarch64\"]\n\
+                [package.metadata.build-variant.image-layout]\nos-image-size-gib = 20\n\
+                data-image-size-gib = 20\n",
+                BuildType::Variant,
+                &[],
+            )
+            .await,
+        ];
+        let external_packages = BTreeSet::from(["kernel-6.1".to_string()]);
+        let check = ProjectCheck {
+            project: &project,
+            members: &members,
+            external_packages: Some(&external_packages),
+            arch: Some(SupportedArch::X86_64),
+        };
+        let mut findings = check.findings();
+        findings.sort();
+        let messages: Vec<String> = findings.iter().map(|finding| finding.to_string()).collect();
+        assert_eq!(messages.len(), 5, "{messages:#?}");
+        assert!(
+            messages[0].starts_with("error: kit 'bad-kit' is published by vendor 'other-vendor'")
+        );
+        assert!(messages[1].starts_with("error: variant 'my-variant' does not support 'x86_64'"));
+        assert!(messages[2].starts_with("error: variant 'my-variant' includes package 'missing'"));
+        assert!(messages[3].starts_with("warning: package 'unused' is not included in any kit"));
+        assert!(messages[4].starts_with("warning: variant 'my-variant' has an OS image of 20 GiB"));
+    }
+}
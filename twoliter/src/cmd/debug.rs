@@ -1,3 +1,5 @@
+use crate::config::Config;
+use crate::project;
 use crate::tools::install_tools;
 use anyhow::Result;
 use clap::Parser;
@@ -14,12 +16,14 @@ pub(crate) struct Debug {
 #[derive(Debug, Clone, Parser)]
 pub(crate) enum DebugAction {
     CheckTools(CheckToolArgs),
+    Config(ConfigArgs),
 }
 
 impl DebugAction {
     pub(crate) async fn run(&self) -> Result<()> {
         match self {
             DebugAction::CheckTools(c) => c.run().await,
+            DebugAction::Config(c) => c.run().await,
         }
     }
 }
@@ -53,3 +57,29 @@ impl CheckToolArgs {
         Ok(())
     }
 }
+
+/// Shows the effective value of each setting in Twoliter's configuration and where it came from.
+/// Settings are read from `/etc/twoliter/config.toml`, then `~/.config/twoliter/config.toml`,
+/// then `.twoliter/config.toml` in the project directory, where later files override earlier
+/// ones. Environment variables override the files, and command line flags override everything.
+#[derive(Debug, Default, Clone, Parser)]
+pub(crate) struct ConfigArgs {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent, and show only the system
+    /// and user configuration if there is no project.
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+}
+
+impl ConfigArgs {
+    pub(crate) async fn run(&self) -> Result<()> {
+        let config = match &self.project_path {
+            Some(project_path) => {
+                let project = project::load_or_find_project(Some(project_path.clone())).await?;
+                Config::load(Some(&project.project_dir())).await?
+            }
+            None => Config::load_for_current_dir().await?,
+        };
+        println!("{}", config.render_text().trim_end());
+        Ok(())
+    }
+}
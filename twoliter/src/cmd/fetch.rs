@@ -1,3 +1,4 @@
+use super::LockArgs;
 use crate::project::{self, Locked};
 use anyhow::Result;
 use clap::Parser;
@@ -12,13 +13,16 @@ pub(crate) struct Fetch {
     /// Architecture of images to fetch
     #[clap(long = "arch", default_value = "x86_64")]
     pub(crate) arch: String,
+
+    #[clap(flatten)]
+    pub(crate) lock: LockArgs,
 }
 
 impl Fetch {
     pub(super) async fn run(&self) -> Result<()> {
         let project = project::load_or_find_project(self.project_path.clone()).await?;
-        let project = project.load_lock::<Locked>().await?;
-        project.fetch(self.arch.as_str()).await?;
+        let project = project.load_lock::<Locked>(self.lock.mode()).await?;
+        project.fetch(self.arch.as_str(), self.lock.mode()).await?;
         Ok(())
     }
 }
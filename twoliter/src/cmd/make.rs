@@ -1,7 +1,10 @@
+use super::LockArgs;
 use crate::cargo_make::CargoMake;
+use crate::config::Config;
+use crate::makefile::Makefile;
 use crate::project::{self, Locked, SDKLocked, Unlocked};
 use crate::tools::install_tools;
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use clap::Parser;
 use std::path::PathBuf;
 
@@ -17,9 +20,12 @@ use std::path::PathBuf;
 const MUST_VALIDATE_KITS_TARGETS: &[&str] = &[
     "build-package",
     "build-kit",
+    "prepare-kit",
     "build-variant",
     "build-all",
     "build",
+    "prepare-build",
+    "prepare-variant",
     "default",
 ];
 
@@ -34,17 +40,26 @@ pub(crate) struct Make {
 
     /// Twoliter does not read this from the CARGO_HOME environment variable to avoid any possible
     /// confusion between a CARGO_HOME set on the system, and the path intended for the Bottlerocket
-    /// build.
+    /// build. It is required unless `cargo-home` is set in Twoliter's configuration.
     #[clap(long)]
-    cargo_home: PathBuf,
+    cargo_home: Option<PathBuf>,
 
-    /// This can be passed by environment variable. We require it as part of the command arguments
-    /// because we need it to pull the right SDK target architecture.
+    /// This can be passed by environment variable or set as `arch` in Twoliter's configuration. We
+    /// require it because we need it to pull the right SDK target architecture.
     #[clap(long, env = "BUILDSYS_ARCH")]
-    arch: String,
+    arch: Option<String>,
+
+    #[clap(flatten)]
+    lock: LockArgs,
+
+    /// List the public tasks of Twoliter's Makefile.toml with their descriptions, dependencies and
+    /// the environment variables they read, instead of running a task.
+    #[clap(long, conflicts_with = "makefile_task")]
+    list: bool,
 
     /// Cargo make task. E.g. the word "build" if we want to execute `cargo make build`.
-    makefile_task: String,
+    #[clap(required_unless_present = "list")]
+    makefile_task: Option<String>,
 
     /// Uninspected arguments to be passed to cargo make after the target name. For example, --foo
     /// in the following command : cargo make test --foo.
@@ -53,24 +68,64 @@ pub(crate) struct Make {
 
 impl Make {
     pub(super) async fn run(&self) -> Result<()> {
+        let makefile = Makefile::embedded()?;
+        let Some(makefile_task) = &self.makefile_task else {
+            print!("{}", list_tasks(&makefile));
+            return Ok(());
+        };
+        check_task(&makefile, makefile_task)?;
+
         let project = project::load_or_find_project(self.project_path.clone()).await?;
+        let config = Config::load(Some(&project.project_dir())).await?;
+        let cargo_home = config.cargo_home(self.cargo_home.as_ref()).context(
+            "The cargo home must be given with --cargo-home or as `cargo-home` in Twoliter's \
+            configuration",
+        )?;
+        let arch = self
+            .arch
+            .clone()
+            .unwrap_or_else(|| config.arch.value.to_string());
         let sdk_source = self.locked_sdk(&project).await?;
         let toolsdir = project.project_dir().join("build/tools");
         install_tools(&toolsdir).await?;
         let makefile_path = toolsdir.join("Makefile.toml");
         CargoMake::new(&sdk_source)?
-            .env("CARGO_HOME", self.cargo_home.display().to_string())
+            .env("CARGO_HOME", cargo_home.display().to_string())
+            .env("BUILDSYS_ARCH", arch)
             .env("TWOLITER_TOOLS_DIR", toolsdir.display().to_string())
             .env("BUILDSYS_VERSION_IMAGE", project.release_version())
+            .env(
+                "BUILDSYS_UPSTREAM_SOURCE_FALLBACK",
+                config.upstream_source_fallback(false).to_string(),
+            )
+            .envs(
+                config
+                    .lookaside_cache(None)
+                    .map(|lookaside_cache| ("BUILDSYS_LOOKASIDE_CACHE", lookaside_cache))
+                    .into_iter(),
+            )
+            .envs(
+                config
+                    .infra_toml(None)
+                    .map(|infra_toml| {
+                        (
+                            "PUBLISH_INFRA_CONFIG_PATH",
+                            infra_toml.display().to_string(),
+                        )
+                    })
+                    .into_iter(),
+            )
             .makefile(makefile_path)
             .project_dir(project.project_dir())
-            .exec_with_args(&self.makefile_task, self.additional_args.clone())
+            .exec_with_args(makefile_task, self.additional_args.clone())
             .await
     }
 
     fn can_skip_kit_verification(&self, project: &project::Project<Unlocked>) -> bool {
-        let target_allows_kit_verification_skip =
-            !MUST_VALIDATE_KITS_TARGETS.contains(&self.makefile_task.as_str());
+        let target_allows_kit_verification_skip = !self
+            .makefile_task
+            .as_deref()
+            .is_some_and(|task| MUST_VALIDATE_KITS_TARGETS.contains(&task));
         let project_has_explicit_sdk_dep = project.direct_sdk_image_dep().is_some();
 
         target_allows_kit_verification_skip && project_has_explicit_sdk_dep
@@ -79,21 +134,73 @@ impl Make {
     /// Returns the locked SDK image for the project.
     async fn locked_sdk(&self, project: &project::Project<Unlocked>) -> Result<String> {
         Ok(if self.can_skip_kit_verification(project) {
-            project.load_lock::<SDKLocked>().await?.sdk_image()
+            project
+                .load_lock::<SDKLocked>(self.lock.mode())
+                .await?
+                .sdk_image()
         } else {
-            project.load_lock::<Locked>().await?.sdk_image()
+            project
+                .load_lock::<Locked>(self.lock.mode())
+                .await?
+                .sdk_image()
         }
         .project_image_uri()
         .to_string())
     }
 }
 
+/// Fails with the names of similar tasks if `name` is not a task in the Makefile, so that a typo
+/// is reported before the SDK is resolved and pulled.
+fn check_task(makefile: &Makefile, name: &str) -> Result<()> {
+    if makefile.task(name).is_some() {
+        return Ok(());
+    }
+    let suggestion = match makefile.suggestions(name).as_slice() {
+        [] => String::new(),
+        [task] => format!(" Did you mean '{task}'?"),
+        tasks => format!(" Did you mean one of '{}'?", tasks.join("', '")),
+    };
+    bail!(
+        "There is no make task named '{name}'.{suggestion} Run `twoliter make --list` to see the \
+        available tasks."
+    )
+}
+
+/// Describes each public task of the Makefile, one block of lines per task.
+fn list_tasks(makefile: &Makefile) -> String {
+    let width = makefile
+        .public_tasks()
+        .map(|task| task.name.len())
+        .max()
+        .unwrap_or_default();
+    let mut list = String::new();
+    for task in makefile.public_tasks() {
+        let mut lines = vec![task.description.clone().unwrap_or_default()];
+        if !task.dependencies.is_empty() {
+            lines.push(format!("Depends on: {}", task.dependencies.join(", ")));
+        }
+        if let Some(runs) = &task.runs {
+            lines.push(format!("Runs: {runs}"));
+        }
+        if !task.env_vars.is_empty() {
+            let env_vars: Vec<_> = task.env_vars.iter().map(String::as_str).collect();
+            lines.push(format!("Reads: {}", env_vars.join(", ")));
+        }
+        for (i, line) in lines.iter().enumerate() {
+            let name = if i == 0 { task.name.as_str() } else { "" };
+            list.push_str(format!("{name:width$}  {line}").trim_end());
+            list.push('\n');
+        }
+    }
+    list
+}
+
 #[cfg(test)]
 mod test {
     use std::path::Path;
 
     use crate::cmd::update::Update;
-    use crate::project::VerificationTagger;
+    use crate::project::{LockMode, VerificationTagger};
 
     use super::*;
 
@@ -117,7 +224,7 @@ mod test {
         ])
         .unwrap();
 
-        assert_eq!(args.makefile_task, "testsys");
+        assert_eq!(args.makefile_task.as_deref(), Some("testsys"));
         assert_eq!(args.additional_args[0], "add");
         assert_eq!(args.additional_args[1], "secret");
         assert_eq!(args.additional_args[2], "map");
@@ -146,7 +253,7 @@ mod test {
         ])
         .unwrap();
 
-        assert_eq!(args.makefile_task, "testsys");
+        assert_eq!(args.makefile_task.as_deref(), Some("testsys"));
         assert_eq!(args.additional_args[0], "add");
         assert_eq!(args.additional_args[1], "secret");
         assert_eq!(args.additional_args[2], "map");
@@ -178,7 +285,7 @@ mod test {
         ])
         .unwrap();
 
-        assert_eq!(args.makefile_task, "testsys");
+        assert_eq!(args.makefile_task.as_deref(), Some("testsys"));
         assert_eq!(args.additional_args[0], "add");
         assert_eq!(args.additional_args[1], "secret");
         assert_eq!(args.additional_args[2], "map");
@@ -192,11 +299,58 @@ mod test {
         assert_eq!(args.additional_args[8], "--");
     }
 
+    #[test]
+    fn test_list_args() {
+        let args = Make::try_parse_from(["make", "--list"]).unwrap();
+        assert!(args.list);
+        assert!(args.makefile_task.is_none());
+
+        Make::try_parse_from(["make"]).unwrap_err();
+        Make::try_parse_from(["make", "--list", "build"]).unwrap_err();
+    }
+
+    #[test]
+    fn test_check_task() {
+        let makefile = Makefile::embedded().unwrap();
+        check_task(&makefile, "build-variant").unwrap();
+        check_task(&makefile, "_upload-ova-base").unwrap();
+
+        let error = check_task(&makefile, "buld-variant")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Did you mean 'build-variant'?"), "{error}");
+        let error = check_task(&makefile, "xyzzy-plugh")
+            .unwrap_err()
+            .to_string();
+        assert!(!error.contains("Did you mean"), "{error}");
+    }
+
+    #[test]
+    fn test_list_tasks() {
+        let list = list_tasks(&Makefile::embedded().unwrap());
+        let lines: Vec<_> = list.lines().collect();
+        let i = lines
+            .iter()
+            .position(|line| line.starts_with("build-package "))
+            .unwrap();
+        assert!(lines[i].ends_with("Builds a package and the packages it depends on."));
+        assert!(lines[i + 1]
+            .trim_start()
+            .starts_with("Depends on: check-cargo-version,"));
+        assert!(lines[i + 2].trim_start().starts_with("Reads: "));
+        assert!(lines[i + 2].contains(" PACKAGE"));
+        assert!(!list.contains("_upload-ova-base"));
+    }
+
     const PROJECT: &str = "local-kit";
 
     async fn twoliter_update(project_path: &Path) {
         let command = Update {
             project_path: Some(project_path.to_path_buf()),
+            kit: Vec::new(),
+            sdk: false,
+            check: false,
+            format: Default::default(),
         };
         command.run().await.unwrap();
     }
@@ -213,7 +367,10 @@ mod test {
         let project = project::load_or_find_project(Some(project_path))
             .await
             .unwrap();
-        let project = project.load_lock::<SDKLocked>().await.unwrap();
+        let project = project
+            .load_lock::<SDKLocked>(LockMode::Resolve)
+            .await
+            .unwrap();
         let sdk_source = project.sdk_image().project_image_uri().to_string();
 
         if delete_verifier_tags {
@@ -248,9 +405,11 @@ mod test {
 
         let make = Make {
             project_path: Some(project_path),
-            cargo_home: project_dir.to_owned(),
-            arch: "x86_64".to_string(),
-            makefile_task: target_name.to_string(),
+            cargo_home: Some(project_dir.to_owned()),
+            arch: Some("x86_64".to_string()),
+            lock: Default::default(),
+            list: false,
+            makefile_task: Some(target_name.to_string()),
             additional_args: Vec::new(),
         };
         make.can_skip_kit_verification(&project)
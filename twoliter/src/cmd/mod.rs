@@ -1,17 +1,33 @@
 mod build;
 mod build_clean;
+mod build_graph;
+mod cache;
+mod check;
 mod debug;
 mod fetch;
 mod make;
+mod new;
+mod outdated;
 mod publish_kit;
+mod publish_variant;
+mod sbom;
+mod tree;
 mod update;
+mod workspace;
 
 use self::build::BuildCommand;
+use crate::cmd::cache::CacheCommand;
+use crate::cmd::check::Check;
 use crate::cmd::debug::DebugAction;
 use crate::cmd::fetch::Fetch;
 use crate::cmd::make::Make;
+use crate::cmd::new::New;
+use crate::cmd::outdated::Outdated;
 use crate::cmd::publish_kit::PublishCommand;
+use crate::cmd::sbom::Sbom;
+use crate::cmd::tree::Tree;
 use crate::cmd::update::Update;
+use crate::project::LockMode;
 use anyhow::Result;
 use clap::Parser;
 use env_logger::Builder;
@@ -24,8 +40,8 @@ const DEFAULT_LEVEL_FILTER: LevelFilter = LevelFilter::Info;
 #[clap(about, long_about = None, version)]
 pub(crate) struct Args {
     /// Set the logging level. One of [off|error|warn|info|debug|trace]. Defaults to warn. You can
-    /// also leave this unset and use the RUST_LOG env variable. See
-    /// https://github.com/rust-cli/env_logger/
+    /// also leave this unset and use the RUST_LOG env variable, or `log-level` in Twoliter's
+    /// configuration. See https://github.com/rust-cli/env_logger/
     #[clap(long = "log-level")]
     pub(crate) log_level: Option<LevelFilter>,
 
@@ -39,14 +55,33 @@ pub(crate) enum Subcommand {
     #[clap(subcommand)]
     Build(BuildCommand),
 
+    /// Manage the cache of kit images that are pulled when kits are fetched
+    #[clap(subcommand)]
+    Cache(CacheCommand),
+
+    /// Check the project for mistakes without building it
+    Check(Check),
+
     Fetch(Fetch),
 
     Make(Make),
 
+    /// Create a new project
+    New(New),
+
+    /// List newer versions of the SDK and kits that have been published
+    Outdated(Outdated),
+
+    /// Generate a software bill of materials for a built kit or variant
+    Sbom(Sbom),
+
     /// Update Twoliter.lock
     Update(Update),
 
-    /// Publish something, such as a Kit
+    /// Print the tree of kit and SDK dependencies that make up Twoliter.lock
+    Tree(Tree),
+
+    /// Publish something, such as a Kit or a variant
     #[clap(subcommand)]
     Publish(PublishCommand),
 
@@ -55,13 +90,39 @@ pub(crate) enum Subcommand {
     Debug(DebugAction),
 }
 
+/// Arguments shared by commands which load Twoliter.lock.
+#[derive(Debug, Clone, Default, Parser)]
+pub(crate) struct LockArgs {
+    /// Trust Twoliter.lock instead of re-resolving it against the registry. Kits are verified
+    /// against the copies saved when they were last fetched, so they must have been fetched with
+    /// registry access at least once.
+    #[clap(long = "frozen", visible_alias = "offline")]
+    pub(crate) frozen: bool,
+}
+
+impl LockArgs {
+    pub(crate) fn mode(&self) -> LockMode {
+        if self.frozen {
+            LockMode::Frozen
+        } else {
+            LockMode::Resolve
+        }
+    }
+}
+
 /// Entrypoint for the `twoliter` command line program.
 pub(super) async fn run(args: Args) -> Result<()> {
     match args.subcommand {
         Subcommand::Build(build_command) => build_command.run().await,
+        Subcommand::Cache(cache_command) => cache_command.run().await,
+        Subcommand::Check(check_args) => check_args.run().await,
         Subcommand::Fetch(fetch_args) => fetch_args.run().await,
         Subcommand::Make(make_args) => make_args.run().await,
+        Subcommand::New(new_args) => new_args.run().await,
+        Subcommand::Outdated(outdated_args) => outdated_args.run().await,
+        Subcommand::Sbom(sbom_args) => sbom_args.run().await,
         Subcommand::Update(update_args) => update_args.run().await,
+        Subcommand::Tree(tree_args) => tree_args.run().await,
         Subcommand::Publish(publish_command) => publish_command.run().await,
         Subcommand::Debug(debug_action) => debug_action.run().await,
     }
@@ -90,7 +151,7 @@ pub(super) fn init_logger(level: Option<LevelFilter>) {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::cmd::build::BuildKit;
+    use crate::cmd::build::{ArchArgs, BuildKit};
     use async_walkdir::WalkDir;
     use futures::stream::StreamExt;
     use std::collections::HashSet;
@@ -132,6 +193,10 @@ mod test {
     async fn twoliter_update(project_path: &Path) {
         let command = Update {
             project_path: Some(project_path.to_path_buf()),
+            kit: Vec::new(),
+            sdk: false,
+            check: false,
+            format: Default::default(),
         };
         command.run().await.unwrap();
     }
@@ -140,6 +205,7 @@ mod test {
         let command = Fetch {
             project_path: Some(project_path.to_path_buf()),
             arch: arch.into(),
+            lock: Default::default(),
         };
         command.run().await.unwrap()
     }
@@ -157,10 +223,17 @@ mod test {
 
         let command = BuildKit {
             project_path: Some(project_path),
-            arch: arch.to_string(),
+            arch: ArchArgs {
+                arch: vec![arch.parse().unwrap()],
+                all_arches: false,
+                parallel: false,
+            },
             kit: kit_name.to_string(),
             lookaside_cache: None,
             upstream_source_fallback: false,
+            events_json: None,
+            schedule: Default::default(),
+            lock: Default::default(),
         };
 
         command.run().await.unwrap();
@@ -180,10 +253,17 @@ mod test {
 
         let command = BuildKit {
             project_path: Some(project_path),
-            arch: arch.to_string(),
+            arch: ArchArgs {
+                arch: vec![arch.parse().unwrap()],
+                all_arches: false,
+                parallel: false,
+            },
             kit: kit_name.to_string(),
             lookaside_cache: None,
             upstream_source_fallback: false,
+            events_json: None,
+            schedule: Default::default(),
+            lock: Default::default(),
         };
 
         command.run().await.unwrap();
@@ -204,10 +284,17 @@ mod test {
 
         let command = BuildKit {
             project_path: Some(project_path),
-            arch: arch.to_string(),
+            arch: ArchArgs {
+                arch: vec![arch.parse().unwrap()],
+                all_arches: false,
+                parallel: false,
+            },
             kit: kit_name.to_string(),
             lookaside_cache: None,
             upstream_source_fallback: false,
+            events_json: None,
+            schedule: Default::default(),
+            lock: Default::default(),
         };
 
         command.run().await.unwrap();
@@ -228,10 +315,17 @@ mod test {
 
         let command = BuildKit {
             project_path: Some(project_path),
-            arch: arch.to_string(),
+            arch: ArchArgs {
+                arch: vec![arch.parse().unwrap()],
+                all_arches: false,
+                parallel: false,
+            },
             kit: kit_name.to_string(),
             lookaside_cache: None,
             upstream_source_fallback: false,
+            events_json: None,
+            schedule: Default::default(),
+            lock: Default::default(),
         };
 
         command.run().await.unwrap();
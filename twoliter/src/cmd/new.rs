@@ -0,0 +1,429 @@
+use crate::common::exec;
+use crate::common::fs::{create_dir_all, write};
+use crate::project::ValidIdentifier;
+use anyhow::{ensure, Result};
+use clap::Parser;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tracing::{info, warn};
+
+/// The name of the sample package that every new project starts with.
+const SAMPLE_PACKAGE: &str = "hello";
+
+/// Create a new Twoliter project with Cargo workspaces for packages, kits and variants.
+#[derive(Debug, Parser)]
+pub(crate) struct New {
+    /// The directory to create the project in. It must not already exist.
+    pub(crate) path: PathBuf,
+
+    /// Also create a kit containing the sample package, optionally giving its name with
+    /// `--kit=<name>`.
+    #[clap(
+        long = "kit",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "my-kit"
+    )]
+    pub(crate) kit: Option<ValidIdentifier>,
+
+    /// Also create a variant, optionally giving its name with `--variant=<name>`. Since a variant's
+    /// packages come from kits, this also creates a kit.
+    #[clap(
+        long = "variant",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "my-variant"
+    )]
+    pub(crate) variant: Option<ValidIdentifier>,
+
+    /// The vendor whose registry the SDK is pulled from, and under which new kits are published.
+    #[clap(long = "vendor", default_value = "bottlerocket")]
+    pub(crate) vendor: ValidIdentifier,
+
+    /// The registry of the vendor.
+    #[clap(long = "registry", default_value = "public.ecr.aws/bottlerocket")]
+    pub(crate) registry: String,
+
+    /// The name of the SDK image.
+    #[clap(long = "sdk-name", default_value = "bottlerocket-sdk")]
+    pub(crate) sdk_name: ValidIdentifier,
+
+    /// The version of the SDK image.
+    #[clap(long = "sdk-version", default_value = "0.50.0")]
+    pub(crate) sdk_version: semver::Version,
+}
+
+impl New {
+    pub(super) async fn run(&self) -> Result<()> {
+        ensure!(
+            !self.path.exists(),
+            "cannot create a project at '{}' because it already exists",
+            self.path.display()
+        );
+        let files = self.files();
+        for (path, contents) in files.iter() {
+            let path = self.path.join(path);
+            if let Some(parent) = path.parent() {
+                create_dir_all(parent).await?;
+            }
+            write(&path, contents).await?;
+        }
+
+        // The Makefile fetches the project's dependencies with `--locked`, so a lockfile must exist.
+        let lockfile = exec(
+            Command::new("cargo")
+                .arg("generate-lockfile")
+                .arg("--manifest-path")
+                .arg(self.path.join("Cargo.toml")),
+            true,
+        )
+        .await;
+        if let Err(e) = lockfile {
+            warn!(
+                "Unable to create Cargo.lock, run `cargo generate-lockfile` in the project before \
+                building it: {e:?}"
+            );
+        }
+
+        info!("Created project at '{}'", self.path.display());
+        Ok(())
+    }
+
+    /// The kit to create, if any.
+    fn kit_name(&self) -> Option<ValidIdentifier> {
+        match (&self.kit, &self.variant) {
+            (Some(kit), _) => Some(kit.clone()),
+            (None, Some(_)) => Some(ValidIdentifier("my-kit".into())),
+            (None, None) => None,
+        }
+    }
+
+    /// Returns the path and contents of every file in the new project.
+    fn files(&self) -> Vec<(PathBuf, String)> {
+        let kit = self.kit_name();
+        let mut members = vec![format!("packages/{SAMPLE_PACKAGE}")];
+        members.extend(kit.iter().map(|kit| format!("kits/{kit}")));
+        members.extend(
+            self.variant
+                .iter()
+                .map(|variant| format!("variants/{variant}")),
+        );
+
+        let mut files = vec![
+            ("Twoliter.toml".into(), self.twoliter_toml()),
+            ("Cargo.toml".into(), workspace_toml(&members)),
+            (".gitignore".into(), GITIGNORE.into()),
+            // Go modules are searched for in `sources`, so it must exist even if it is empty.
+            ("sources/.gitkeep".into(), String::new()),
+            ("packages/build.rs".into(), build_rs("build-package")),
+            (
+                "packages/packages.rs".into(),
+                empty_lib("package", "packages"),
+            ),
+            ("kits/build.rs".into(), build_rs("build-kit")),
+            ("kits/kit.rs".into(), empty_lib("kit", "kits")),
+            ("variants/build.rs".into(), build_rs("build-variant")),
+            (
+                "variants/variants.rs".into(),
+                empty_lib("variant", "variants"),
+            ),
+            (
+                Path::new("packages")
+                    .join(SAMPLE_PACKAGE)
+                    .join("Cargo.toml"),
+                package_toml(SAMPLE_PACKAGE),
+            ),
+            (
+                Path::new("packages")
+                    .join(SAMPLE_PACKAGE)
+                    .join(format!("{SAMPLE_PACKAGE}.spec")),
+                package_spec(SAMPLE_PACKAGE),
+            ),
+        ];
+        if let Some(kit) = &kit {
+            files.push((
+                Path::new("kits").join(kit.as_ref()).join("Cargo.toml"),
+                kit_toml(kit, &self.vendor),
+            ));
+        }
+        if let (Some(variant), Some(kit)) = (&self.variant, &kit) {
+            files.push((
+                Path::new("variants")
+                    .join(variant.as_ref())
+                    .join("Cargo.toml"),
+                variant_toml(variant, kit),
+            ));
+        }
+        files
+    }
+
+    fn twoliter_toml(&self) -> String {
+        format!(
+            r#"schema-version = 1
+release-version = "0.1.0"
+
+[vendor.{vendor}]
+registry = "{registry}"
+
+[sdk]
+name = "{sdk_name}"
+version = "{sdk_version}"
+vendor = "{vendor}"
+
+# Kits from a vendor's registry that this project depends on, for example:
+# [[kit]]
+# name = "bottlerocket-core-kit"
+# version = "^3"
+# vendor = "{vendor}"
+"#,
+            vendor = self.vendor,
+            registry = self.registry,
+            sdk_name = self.sdk_name,
+            sdk_version = self.sdk_version,
+        )
+    }
+}
+
+fn workspace_toml(members: &[String]) -> String {
+    let members = members
+        .iter()
+        .map(|member| format!("    \"{member}\","))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(
+        r#"[workspace]
+resolver = "2"
+members = [
+{members}
+]
+
+[profile.dev]
+debug = false
+opt-level = 'z'
+
+[profile.dev.build-override]
+opt-level = 'z'
+"#
+    )
+}
+
+/// The `build.rs` shared by every crate of one kind, which hands the build over to `buildsys`.
+fn build_rs(buildsys_command: &str) -> String {
+    format!(
+        r#"use std::process::{{exit, Command}};
+
+fn main() -> Result<(), std::io::Error> {{
+    let ret = Command::new("buildsys").arg("{buildsys_command}").status()?;
+    if !ret.success() {{
+        exit(1);
+    }}
+    Ok(())
+}}
+"#
+    )
+}
+
+/// The empty `lib.rs` shared by every crate of one kind.
+fn empty_lib(kind: &str, dir: &str) -> String {
+    format!(
+        r#"/*!
+
+This is an intentionally empty file that all of the {kind} `Cargo.toml` files in `{dir}` can point to
+as their `lib.rs`. The build system uses `build.rs` to invoke `buildsys` but Cargo needs something
+to compile so we give it an empty `lib.rs` file.
+
+!*/
+"#
+    )
+}
+
+fn package_toml(name: &str) -> String {
+    format!(
+        r#"[package]
+name = "{name}"
+version = "0.1.0"
+edition = "2021"
+publish = false
+build = "../build.rs"
+
+[package.metadata.build-package]
+source-groups = []
+
+[lib]
+path = "../packages.rs"
+
+# RPM BuildRequires
+[build-dependencies]
+# None
+
+# RPM Requires
+[dependencies]
+# None
+"#
+    )
+}
+
+fn package_spec(name: &str) -> String {
+    format!(
+        r#"%global _cross_first_party 1
+%undefine _debugsource_packages
+
+Name: %{{_cross_os}}{name}
+Version: 0.1.0
+Release: 1%{{?dist}}
+Summary: A sample package
+License: Apache-2.0 OR MIT
+
+%description
+%{{summary}}.
+
+%prep
+%setup -T -c
+
+%build
+echo "Hello from {name}" > {name}.txt
+
+%install
+mkdir -p %{{buildroot}}%{{_cross_datadir}}
+install -p -m 0644 {name}.txt %{{buildroot}}%{{_cross_datadir}}/{name}.txt
+
+%files
+%{{_cross_datadir}}/{name}.txt
+"#
+    )
+}
+
+fn kit_toml(name: &ValidIdentifier, vendor: &ValidIdentifier) -> String {
+    format!(
+        r#"[package]
+name = "{name}"
+version = "0.1.0"
+edition = "2021"
+publish = false
+build = "../build.rs"
+
+[package.metadata.build-kit]
+vendor = "{vendor}"
+
+[lib]
+path = "../kit.rs"
+
+# The packages included in the kit
+[build-dependencies]
+{SAMPLE_PACKAGE} = {{ path = "../../packages/{SAMPLE_PACKAGE}" }}
+"#
+    )
+}
+
+fn variant_toml(name: &ValidIdentifier, kit: &ValidIdentifier) -> String {
+    format!(
+        r#"[package]
+name = "{name}"
+version = "0.1.0"
+edition = "2021"
+publish = false
+build = "../build.rs"
+
+[package.metadata.build-variant]
+# Packages from this project's kits and the kits in Twoliter.toml to install in the image. A
+# bootable image also needs packages such as a kernel, which usually come from a vendor's core kit.
+included-packages = ["{SAMPLE_PACKAGE}"]
+kernel-parameters = []
+
+[lib]
+path = "../variants.rs"
+
+# The kits from this project that the variant uses
+[build-dependencies]
+{kit} = {{ path = "../../kits/{kit}" }}
+"#
+    )
+}
+
+const GITIGNORE: &str = r#"/build/
+**/target/
+/.cargo/
+/.gomodcache/
+/keys/
+/roles/
+/sbkeys/
+Test.toml
+testsys.kubeconfig
+Infra.toml
+"#;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::project::Project;
+    use buildsys::manifest::ManifestInfo;
+    use buildsys::BuildType;
+    use tempfile::TempDir;
+
+    async fn write_project(args: &[&str]) -> (TempDir, PathBuf) {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("my-project");
+        let mut argv = vec!["new", path.to_str().unwrap()];
+        argv.extend(args);
+        let new = New::try_parse_from(argv).unwrap();
+        for (file, contents) in new.files() {
+            let file = path.join(file);
+            create_dir_all(file.parent().unwrap()).await.unwrap();
+            write(&file, contents).await.unwrap();
+        }
+        (tempdir, path)
+    }
+
+    #[tokio::test]
+    async fn test_new_project() {
+        let (_tempdir, path) = write_project(&[]).await;
+        let project = Project::load(path.join("Twoliter.toml")).await.unwrap();
+        assert_eq!(project.release_version(), "0.1.0");
+        assert!(project.direct_sdk_image_dep().is_some());
+
+        let package = ManifestInfo::new(path.join("packages/hello/Cargo.toml")).unwrap();
+        assert!(matches!(package.build_type().unwrap(), BuildType::Package));
+        assert!(!path.join("kits/my-kit").exists());
+        assert!(!path.join("variants/my-variant").exists());
+    }
+
+    #[tokio::test]
+    async fn test_new_project_with_variant() {
+        let (_tempdir, path) = write_project(&["--kit=extra-kit", "--variant"]).await;
+        Project::load(path.join("Twoliter.toml")).await.unwrap();
+
+        let kit = ManifestInfo::new(path.join("kits/extra-kit/Cargo.toml")).unwrap();
+        assert!(matches!(kit.build_type().unwrap(), BuildType::Kit));
+        assert_eq!(kit.kit_vendor().unwrap(), "bottlerocket");
+
+        let variant = ManifestInfo::new(path.join("variants/my-variant/Cargo.toml")).unwrap();
+        assert!(matches!(variant.build_type().unwrap(), BuildType::Variant));
+        assert_eq!(
+            variant.included_packages().unwrap(),
+            &vec![SAMPLE_PACKAGE.to_string()]
+        );
+
+        let workspace = std::fs::read_to_string(path.join("Cargo.toml")).unwrap();
+        let workspace: toml::Table = toml::from_str(&workspace).unwrap();
+        assert_eq!(
+            workspace["workspace"]["members"].as_array().unwrap().len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_names_require_equals() {
+        let new = New::try_parse_from(["new", "--kit", "dir"]).unwrap();
+        assert_eq!(new.path, PathBuf::from("dir"));
+        assert_eq!(new.kit.unwrap().to_string(), "my-kit");
+        assert!(new.variant.is_none());
+
+        let new = New::try_parse_from(["new", "--variant", "dir"]).unwrap();
+        assert_eq!(new.path, PathBuf::from("dir"));
+        assert_eq!(new.variant.unwrap().to_string(), "my-variant");
+
+        let new = New::try_parse_from(["new", "dir", "--kit=extra-kit"]).unwrap();
+        assert_eq!(new.path, PathBuf::from("dir"));
+        assert_eq!(new.kit.unwrap().to_string(), "extra-kit");
+    }
+}
@@ -0,0 +1,48 @@
+use crate::project;
+use anyhow::{ensure, Result};
+use clap::{Parser, ValueEnum};
+use std::path::PathBuf;
+
+/// The format in which to print the available versions.
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub(crate) enum OutdatedFormat {
+    /// A table with one row for the SDK and each kit
+    #[default]
+    Text,
+    /// JSON
+    Json,
+}
+
+/// List the versions of the SDK and kits in Twoliter.toml that are published to their vendors'
+/// registries, so that newer versions can be adopted with `twoliter update`.
+#[derive(Debug, Parser)]
+pub(crate) struct Outdated {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    pub(crate) project_path: Option<PathBuf>,
+
+    /// The output format
+    #[clap(long = "format", value_enum, default_value_t)]
+    pub(crate) format: OutdatedFormat,
+
+    /// Exit with an error if a newer version of the SDK or any kit has been published
+    #[clap(long = "exit-code")]
+    pub(crate) exit_code: bool,
+}
+
+impl Outdated {
+    pub(super) async fn run(&self) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone()).await?;
+        let report = project.outdated().await?;
+        let output = match self.format {
+            OutdatedFormat::Text => report.render_text(),
+            OutdatedFormat::Json => report.render_json()?,
+        };
+        println!("{}", output.trim_end());
+        ensure!(
+            !self.exit_code || !report.is_outdated(),
+            "newer versions of the SDK or kits are available"
+        );
+        Ok(())
+    }
+}
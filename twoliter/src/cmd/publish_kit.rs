@@ -1,4 +1,7 @@
+use super::publish_variant::PublishVariant;
+use super::LockArgs;
 use crate::cargo_make::CargoMake;
+use crate::config::Config;
 use crate::project::{self, Locked};
 use crate::tools::install_tools;
 use anyhow::Result;
@@ -9,12 +12,14 @@ use std::path::PathBuf;
 #[derive(Debug, Parser)]
 pub(crate) enum PublishCommand {
     Kit(PublishKit),
+    Variant(PublishVariant),
 }
 
 impl PublishCommand {
     pub(crate) async fn run(self) -> Result<()> {
         match self {
             PublishCommand::Kit(command) => command.run().await,
+            PublishCommand::Variant(command) => command.run().await,
         }
     }
 }
@@ -34,15 +39,38 @@ pub(crate) struct PublishKit {
 
     /// Publish kit image to a different repository than the kit's name
     kit_repo: Option<String>,
+
+    /// Sign the kit with the private key at this path rather than with the vendor's signing key
+    /// from Infra.toml
+    #[clap(long = "signing-key")]
+    signing_key: Option<PathBuf>,
+
+    /// Path to the Infra.toml file. Defaults to the `infra-toml` from Twoliter's configuration, or
+    /// the project's Infra.toml
+    #[clap(long = "infra-toml")]
+    infra_toml: Option<PathBuf>,
+
+    /// Overwrite the kit's version tag if it already refers to a different kit. Projects that have
+    /// locked the existing kit will no longer be able to verify it
+    #[clap(long = "force")]
+    force: bool,
+
+    /// Print the image URIs and digests that publishing would produce without pushing anything
+    #[clap(long = "dry-run")]
+    dry_run: bool,
+
+    #[clap(flatten)]
+    lock: LockArgs,
 }
 
 impl PublishKit {
     pub(super) async fn run(&self) -> Result<()> {
         let project = project::load_or_find_project(self.project_path.clone()).await?;
-        let project = project.load_lock::<Locked>().await?;
+        let project = project.load_lock::<Locked>(self.lock.mode()).await?;
         let toolsdir = project.project_dir().join("build/tools");
         install_tools(&toolsdir).await?;
         let makefile_path = toolsdir.join("Makefile.toml");
+        let config = Config::load(Some(&project.project_dir())).await?;
 
         let publish_kit_repo = match &self.kit_repo {
             Some(kit_repo) => kit_repo,
@@ -54,6 +82,30 @@ impl PublishKit {
             .env("BUILDSYS_VERSION_IMAGE", project.release_version())
             .env("PUBLISH_VENDOR", &self.vendor)
             .env("PUBLISH_KIT_REPO", publish_kit_repo)
+            .env("PUBLISH_KIT_FORCE", self.force.to_string())
+            .env("PUBLISH_KIT_DRY_RUN", self.dry_run.to_string())
+            .envs(
+                self.signing_key.iter().map(|signing_key| {
+                    ("PUBLISH_KIT_SIGNING_KEY", signing_key.display().to_string())
+                }),
+            )
+            .envs(
+                project
+                    .vendor_auth(&config, &self.vendor)
+                    .map(|auth| ("PUBLISH_KIT_AUTH", auth.to_string()))
+                    .into_iter(),
+            )
+            .envs(
+                config
+                    .infra_toml(self.infra_toml.as_ref())
+                    .map(|infra_toml| {
+                        (
+                            "PUBLISH_INFRA_CONFIG_PATH",
+                            infra_toml.display().to_string(),
+                        )
+                    })
+                    .into_iter(),
+            )
             .makefile(makefile_path)
             .project_dir(project.project_dir())
             .exec("publish-kit")
@@ -0,0 +1,493 @@
+//! Publishes a built variant by running the pubsys steps that would otherwise be run by hand with
+//! `twoliter make`: building the update repository, registering AMIs, and setting, promoting and
+//! validating SSM parameters.
+//!
+//! The outputs of each step are recorded in a publish-state file as soon as the step finishes.
+//! When a step fails, running the command again resumes from the step that failed rather than, for
+//! example, registering another set of AMIs.
+use super::LockArgs;
+use crate::cargo_make::CargoMake;
+use crate::common::fs::{self, read_to_string, rename, write};
+use crate::config::Config;
+use crate::project::{self, SDKLocked};
+use crate::tools::install_tools;
+use anyhow::{Context, Result};
+use buildsys::manifest::SupportedArch;
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+use tracing::info;
+
+/// The steps of publishing a variant, in the order in which they run.
+const STEPS: [Step; 6] = [
+    Step::Repo,
+    Step::Ami,
+    Step::Ssm,
+    Step::PromoteSsm,
+    Step::ValidateAmi,
+    Step::ValidateSsm,
+];
+
+/// Publish a built variant: build its update repository, register its AMIs, and set, promote and
+/// validate its SSM parameters
+#[derive(Debug, Parser)]
+pub(crate) struct PublishVariant {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// The variant to publish
+    variant: String,
+
+    /// The architecture to publish. Defaults to the `arch` from Twoliter's configuration, or x86_64
+    #[clap(long = "arch")]
+    arch: Option<SupportedArch>,
+
+    /// The repository from Infra.toml to publish to
+    #[clap(long = "repo", default_value = "default")]
+    repo: String,
+
+    /// Promote the SSM parameters to this version or pointer, e.g. `latest`. SSM parameters are
+    /// not promoted when absent
+    #[clap(long = "promote-to")]
+    promote_to: Option<String>,
+
+    /// Path to the Infra.toml file. Defaults to the `infra-toml` from Twoliter's configuration, or
+    /// the project's Infra.toml
+    #[clap(long = "infra-toml")]
+    infra_toml: Option<PathBuf>,
+
+    /// Path to the publish-state file. Defaults to a file for the variant, architecture and
+    /// repository in `build/state/publish`
+    #[clap(long = "state-file")]
+    state_file: Option<PathBuf>,
+
+    /// Run every step again, discarding the outputs recorded by an earlier run
+    #[clap(long = "restart")]
+    restart: bool,
+
+    #[clap(flatten)]
+    lock: LockArgs,
+}
+
+impl PublishVariant {
+    pub(super) async fn run(&self) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone()).await?;
+        let project = project.load_lock::<SDKLocked>(self.lock.mode()).await?;
+        let toolsdir = project.project_dir().join("build/tools");
+        install_tools(&toolsdir).await?;
+        let makefile_path = toolsdir.join("Makefile.toml");
+        let config = Config::load(Some(&project.project_dir())).await?;
+        let arch = self.arch.unwrap_or(config.arch.value);
+
+        let cargo_make = CargoMake::new(&project.sdk_image().project_image_uri().to_string())?
+            .env("TWOLITER_TOOLS_DIR", toolsdir.display().to_string())
+            .env("BUILDSYS_VARIANT", &self.variant)
+            .env("BUILDSYS_ARCH", arch.to_string())
+            .env("BUILDSYS_VERSION_IMAGE", project.release_version())
+            .env("PUBLISH_REPO", &self.repo)
+            .envs(
+                config
+                    .cargo_home(None)
+                    .map(|cargo_home| ("CARGO_HOME", cargo_home.display().to_string()))
+                    .into_iter(),
+            )
+            .envs(
+                config
+                    .infra_toml(self.infra_toml.as_ref())
+                    .map(|infra_toml| {
+                        (
+                            "PUBLISH_INFRA_CONFIG_PATH",
+                            infra_toml.display().to_string(),
+                        )
+                    })
+                    .into_iter(),
+            )
+            .makefile(makefile_path)
+            .project_dir(project.project_dir());
+
+        let paths = PublishPaths::load(&cargo_make, &project.project_dir()).await?;
+        let state_file = match &self.state_file {
+            Some(state_file) => state_file.clone(),
+            None => project
+                .project_dir()
+                .join("build/state/publish")
+                .join(format!("{}-{}-{}.json", self.variant, arch, self.repo)),
+        };
+        let fresh = PublishState {
+            variant: self.variant.clone(),
+            arch: arch.to_string(),
+            version: paths.version_full.clone(),
+            repo: self.repo.clone(),
+            steps: Vec::new(),
+        };
+        let mut state = if self.restart {
+            fresh
+        } else {
+            PublishState::resume(PublishState::read(&state_file).await?, fresh)
+        };
+
+        for step in STEPS {
+            if state.is_complete(step, self.promote_to.as_deref()) {
+                info!(
+                    "Skipping '{}', which was completed by an earlier run",
+                    step.task()
+                );
+                continue;
+            }
+            let mut step_make = cargo_make.clone();
+            if step == Step::PromoteSsm {
+                let Some(target) = &self.promote_to else {
+                    info!(
+                        "Skipping '{}' since --promote-to was not given",
+                        step.task()
+                    );
+                    continue;
+                };
+                step_make = step_make.env("SSM_TARGET", target);
+            }
+            info!("Running '{}'", step.task());
+            step_make.exec(step.task()).await.context(format!(
+                "Publishing stopped at '{}'. Run the command again to resume from this step",
+                step.task()
+            ))?;
+            let outputs = paths.outputs(step, self.promote_to.as_deref()).await?;
+            state.record(outputs);
+            state.write(&state_file).await?;
+        }
+
+        println!(
+            "Published {} for {} to repo '{}'. The outputs of each step are recorded in {}",
+            self.variant,
+            arch,
+            self.repo,
+            state_file.display()
+        );
+        Ok(())
+    }
+}
+
+/// A step in publishing a variant.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Step {
+    Repo,
+    Ami,
+    Ssm,
+    PromoteSsm,
+    ValidateAmi,
+    ValidateSsm,
+}
+
+impl Step {
+    /// The `cargo make` task that performs the step.
+    fn task(self) -> &'static str {
+        match self {
+            Step::Repo => "repo",
+            Step::Ami => "ami",
+            Step::Ssm => "ssm",
+            Step::PromoteSsm => "promote-ssm",
+            Step::ValidateAmi => "validate-ami",
+            Step::ValidateSsm => "validate-ssm",
+        }
+    }
+}
+
+/// What a step produced.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "step")]
+enum StepOutputs {
+    /// The directory holding the repository's metadata and targets
+    #[serde(rename_all = "kebab-case")]
+    Repo {
+        repo_dir: PathBuf,
+    },
+    /// The AMIs that were registered, by region, as written by pubsys
+    Ami {
+        amis: Value,
+    },
+    /// The SSM parameters that were set, as written by pubsys
+    #[serde(rename_all = "kebab-case")]
+    Ssm {
+        ssm_parameters: Value,
+    },
+    /// The SSM parameters that were set for the version or pointer named by `target`
+    #[serde(rename_all = "kebab-case")]
+    PromoteSsm {
+        target: String,
+        ssm_parameters: Value,
+    },
+    ValidateAmi,
+    ValidateSsm,
+}
+
+impl StepOutputs {
+    fn step(&self) -> Step {
+        match self {
+            StepOutputs::Repo { .. } => Step::Repo,
+            StepOutputs::Ami { .. } => Step::Ami,
+            StepOutputs::Ssm { .. } => Step::Ssm,
+            StepOutputs::PromoteSsm { .. } => Step::PromoteSsm,
+            StepOutputs::ValidateAmi => Step::ValidateAmi,
+            StepOutputs::ValidateSsm => Step::ValidateSsm,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct CompletedStep {
+    completed_at: DateTime<Utc>,
+    #[serde(flatten)]
+    outputs: StepOutputs,
+}
+
+/// The contents of the publish-state file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct PublishState {
+    variant: String,
+    arch: String,
+    /// The full version of the published images, including the build ID
+    version: String,
+    repo: String,
+    /// The steps that have been completed, in the order in which they ran
+    steps: Vec<CompletedStep>,
+}
+
+impl PublishState {
+    async fn read(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = read_to_string(path).await?;
+        let state = serde_json::from_str(&data).context(format!(
+            "Unable to parse the publish-state file '{}'. Use --restart to publish from the \
+            beginning",
+            path.display()
+        ))?;
+        Ok(Some(state))
+    }
+
+    async fn write(&self, path: &Path) -> Result<()> {
+        let data =
+            serde_json::to_string_pretty(self).context("failed to serialize publish state")?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        // Write to a temporary file first so that the state is never left partially written.
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        write(&temp_path, data).await?;
+        rename(&temp_path, path).await
+    }
+
+    /// Picks up the state recorded by an earlier run if it was publishing the same images, or
+    /// starts over from `fresh` otherwise.
+    fn resume(existing: Option<Self>, fresh: Self) -> Self {
+        match existing {
+            Some(existing)
+                if existing.variant == fresh.variant
+                    && existing.arch == fresh.arch
+                    && existing.version == fresh.version
+                    && existing.repo == fresh.repo =>
+            {
+                existing
+            }
+            Some(existing) => {
+                info!(
+                    "Ignoring the recorded state of publishing version {}, since version {} is \
+                    being published",
+                    existing.version, fresh.version
+                );
+                fresh
+            }
+            None => fresh,
+        }
+    }
+
+    /// Whether `step` was completed by an earlier run. Promoting the SSM parameters only counts
+    /// as complete if they were promoted to `promote_to`.
+    fn is_complete(&self, step: Step, promote_to: Option<&str>) -> bool {
+        self.steps.iter().any(|done| {
+            done.outputs.step() == step
+                && match &done.outputs {
+                    StepOutputs::PromoteSsm { target, .. } => Some(target.as_str()) == promote_to,
+                    _ => true,
+                }
+        })
+    }
+
+    /// Records the outputs of a step that has just run. Any outputs recorded for it or for the
+    /// steps after it are dropped, since a step only runs again when its earlier outputs are stale.
+    fn record(&mut self, outputs: StepOutputs) {
+        let position = |step| STEPS.iter().position(|s| *s == step);
+        let step = position(outputs.step());
+        self.steps
+            .retain(|done| position(done.outputs.step()) < step);
+        self.steps.push(CompletedStep {
+            completed_at: Utc::now(),
+            outputs,
+        });
+    }
+}
+
+/// Where the publishing tasks write their outputs, as reported by the Makefile.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct PublishPaths {
+    version_full: String,
+    repo_dir: PathBuf,
+    amis: PathBuf,
+    ssm_parameters: PathBuf,
+}
+
+impl PublishPaths {
+    async fn load(cargo_make: &CargoMake, project_dir: &Path) -> Result<Self> {
+        let temp_dir = TempDir::new_in(project_dir)
+            .context("Unable to create a tempdir for Twoliter's publish")?;
+        let path = temp_dir.path().join("publish-variant-paths.json");
+        cargo_make
+            .clone()
+            .env("PUBLISH_VARIANT_PATHS", path.display().to_string())
+            .exec("publish-variant-paths")
+            .await?;
+        let data = read_to_string(&path).await?;
+        serde_json::from_str(&data).context("Unable to parse the paths of the publish outputs")
+    }
+
+    /// Reads the outputs of `step`, which has just run.
+    async fn outputs(&self, step: Step, promote_to: Option<&str>) -> Result<StepOutputs> {
+        Ok(match step {
+            Step::Repo => StepOutputs::Repo {
+                repo_dir: self.repo_dir.clone(),
+            },
+            Step::Ami => StepOutputs::Ami {
+                amis: read_json(&self.amis).await?,
+            },
+            Step::Ssm => StepOutputs::Ssm {
+                ssm_parameters: read_json(&self.ssm_parameters).await?,
+            },
+            Step::PromoteSsm => StepOutputs::PromoteSsm {
+                target: promote_to.unwrap_or_default().to_string(),
+                ssm_parameters: read_json(&self.ssm_parameters).await?,
+            },
+            Step::ValidateAmi => StepOutputs::ValidateAmi,
+            Step::ValidateSsm => StepOutputs::ValidateSsm,
+        })
+    }
+}
+
+async fn read_json(path: &Path) -> Result<Value> {
+    let data = read_to_string(path).await?;
+    serde_json::from_str(&data).context(format!("Unable to parse '{}'", path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn state(version: &str) -> PublishState {
+        PublishState {
+            variant: "aws-dev".to_string(),
+            arch: "x86_64".to_string(),
+            version: version.to_string(),
+            repo: "default".to_string(),
+            steps: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_state_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("publish/aws-dev-x86_64-default.json");
+        assert!(PublishState::read(&path).await.unwrap().is_none());
+
+        let mut original = state("1.2.0-abcd1234");
+        original.steps.push(CompletedStep {
+            completed_at: Utc::now(),
+            outputs: StepOutputs::Repo {
+                repo_dir: "build/repos/default/bottlerocket-1.2.0-abcd1234".into(),
+            },
+        });
+        original.steps.push(CompletedStep {
+            completed_at: Utc::now(),
+            outputs: StepOutputs::Ami {
+                amis: serde_json::json!({ "us-west-2": { "id": "ami-0123456789abcdef0" } }),
+            },
+        });
+        original.steps.push(CompletedStep {
+            completed_at: Utc::now(),
+            outputs: StepOutputs::ValidateAmi,
+        });
+        original.write(&path).await.unwrap();
+
+        let data: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(data["steps"][1]["step"], "ami");
+        assert_eq!(
+            data["steps"][1]["amis"]["us-west-2"]["id"],
+            "ami-0123456789abcdef0"
+        );
+        assert_eq!(data["steps"][2]["step"], "validate-ami");
+
+        let read = PublishState::read(&path).await.unwrap().unwrap();
+        assert_eq!(read, original);
+        assert!(read.is_complete(Step::Repo, None));
+        assert!(read.is_complete(Step::Ami, None));
+        assert!(!read.is_complete(Step::Ssm, None));
+    }
+
+    #[test]
+    fn test_resume() {
+        let mut existing = state("1.2.0-abcd1234");
+        existing.steps.push(CompletedStep {
+            completed_at: Utc::now(),
+            outputs: StepOutputs::ValidateSsm,
+        });
+
+        let resumed = PublishState::resume(Some(existing.clone()), state("1.2.0-abcd1234"));
+        assert!(resumed.is_complete(Step::ValidateSsm, None));
+
+        let rebuilt = PublishState::resume(Some(existing), state("1.2.0-ef567890"));
+        assert!(rebuilt.steps.is_empty());
+        assert_eq!(rebuilt.version, "1.2.0-ef567890");
+
+        assert!(PublishState::resume(None, state("1.2.0-abcd1234"))
+            .steps
+            .is_empty());
+    }
+
+    #[test]
+    fn test_promote_to_another_target() {
+        let ssm_parameters = serde_json::json!({ "us-west-2": {} });
+        let mut state = state("1.2.0-abcd1234");
+        for outputs in [
+            StepOutputs::Ssm {
+                ssm_parameters: ssm_parameters.clone(),
+            },
+            StepOutputs::PromoteSsm {
+                target: "latest".to_string(),
+                ssm_parameters: ssm_parameters.clone(),
+            },
+            StepOutputs::ValidateAmi,
+            StepOutputs::ValidateSsm,
+        ] {
+            state.record(outputs);
+        }
+        assert!(state.is_complete(Step::PromoteSsm, Some("latest")));
+        assert!(!state.is_complete(Step::PromoteSsm, Some("1.2.0")));
+        assert!(!state.is_complete(Step::PromoteSsm, None));
+
+        // Promoting again replaces the earlier promotion and the validation that followed it.
+        state.record(StepOutputs::PromoteSsm {
+            target: "1.2.0".to_string(),
+            ssm_parameters,
+        });
+        assert!(state.is_complete(Step::Ssm, None));
+        assert!(state.is_complete(Step::PromoteSsm, Some("1.2.0")));
+        assert!(!state.is_complete(Step::PromoteSsm, Some("latest")));
+        assert!(!state.is_complete(Step::ValidateSsm, None));
+    }
+}
@@ -0,0 +1,736 @@
+use super::workspace::{find_rpms, load_members, RpmFile};
+use crate::common::fs::{read, read_to_string, write};
+use crate::project::{self, LockMode, Locked, LockedImage};
+use anyhow::{ensure, Context, Result};
+use buildsys::manifest::{ExternalFile, SupportedArch};
+use buildsys::BuildType;
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::{Parser, ValueEnum};
+use oci_cli_wrapper::DockerArchitecture;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::Digest;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// The format of the bill of materials.
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub(crate) enum SbomFormat {
+    /// SPDX 2.3 JSON
+    #[default]
+    Spdx,
+    /// CycloneDX 1.5 JSON
+    Cyclonedx,
+}
+
+/// Generate a software bill of materials for a kit or variant that has been built. It lists the
+/// RPMs in the kit or variant, the upstream sources they were built from, and the SDK and external
+/// kits recorded in Twoliter.lock.
+#[derive(Debug, Parser)]
+pub(crate) struct Sbom {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    pub(crate) project_path: Option<PathBuf>,
+
+    /// The kit to describe
+    #[clap(
+        long = "kit",
+        required_unless_present = "variant",
+        conflicts_with = "variant"
+    )]
+    pub(crate) kit: Option<String>,
+
+    /// The variant to describe. The variant must have been built, since its packages are read from
+    /// the image's application inventory
+    #[clap(long = "variant")]
+    pub(crate) variant: Option<String>,
+
+    /// The architecture that the kit or variant was built for
+    #[clap(long = "arch", default_value = "x86_64")]
+    pub(crate) arch: SupportedArch,
+
+    /// The format of the bill of materials
+    #[clap(long = "format", value_enum, default_value_t)]
+    pub(crate) format: SbomFormat,
+
+    /// Write the bill of materials to this file instead of printing it
+    #[clap(long = "output")]
+    pub(crate) output: Option<PathBuf>,
+}
+
+impl Sbom {
+    pub(super) async fn run(&self) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone()).await?;
+        // The bill of materials describes what was built, so the lock is not re-resolved.
+        let project = project.load_lock::<Locked>(LockMode::Frozen).await?;
+        let build_dir = project.project_dir().join("build");
+
+        let (kind, name) = match (&self.kit, &self.variant) {
+            (Some(kit), _) => (SubjectKind::Kit, kit.as_str()),
+            (None, Some(variant)) => (SubjectKind::Variant, variant.as_str()),
+            (None, None) => unreachable!("clap requires a kit or a variant"),
+        };
+        let rpms = match kind {
+            SubjectKind::Kit => {
+                let packages_dir = build_dir
+                    .join("kits")
+                    .join(name)
+                    .join(self.arch.to_string())
+                    .join("Packages");
+                kit_rpms(&packages_dir).await?
+            }
+            SubjectKind::Variant => {
+                let inventory = build_dir
+                    .join("images")
+                    .join(format!("{}-{name}", self.arch))
+                    .join("latest")
+                    .join("application-inventory.json");
+                variant_rpms(&inventory).await?
+            }
+        };
+        let upstream = upstream_files(&project.project_dir()).await?;
+        let packages = rpms
+            .into_iter()
+            .map(|(rpm, sha256)| BinaryPackage {
+                upstream: upstream
+                    .get(rpm.package_name())
+                    .cloned()
+                    .unwrap_or_default(),
+                rpm,
+                sha256,
+            })
+            .collect();
+        let docker_arch = DockerArchitecture::try_from(self.arch.to_string().as_str())?;
+        let images = project
+            .locked_images()
+            .enumerate()
+            .map(|(i, image)| ImageComponent::new(image, i == 0, &docker_arch))
+            .collect();
+
+        let bom = BillOfMaterials {
+            kind,
+            name: name.to_string(),
+            version: project.release_version().to_string(),
+            arch: self.arch,
+            packages,
+            images,
+        };
+        let document = match self.format {
+            SbomFormat::Spdx => bom.to_spdx(Utc::now(), Uuid::new_v4()),
+            SbomFormat::Cyclonedx => bom.to_cyclonedx(Utc::now(), Uuid::new_v4()),
+        };
+        let output =
+            serde_json::to_string_pretty(&document).context("unable to serialize the SBOM")?;
+        match &self.output {
+            Some(path) => {
+                write(path, output).await?;
+                info!(
+                    "Wrote the bill of materials for {kind} '{name}' to '{}'",
+                    path.display()
+                );
+            }
+            None => println!("{output}"),
+        }
+        Ok(())
+    }
+}
+
+/// Reads the RPMs that make up a kit, along with the SHA-256 of each RPM.
+async fn kit_rpms(packages_dir: &Path) -> Result<Vec<(RpmFile, Option<String>)>> {
+    ensure!(
+        packages_dir.is_dir(),
+        "'{}' does not exist, so the kit must be built before its SBOM can be generated",
+        packages_dir.display()
+    );
+    let mut rpms = Vec::new();
+    for path in find_rpms(packages_dir).await? {
+        if let Some(rpm) = RpmFile::parse(&path) {
+            let sha256 = format!("{:x}", sha2::Sha256::digest(read(&path).await?));
+            rpms.push((rpm, Some(sha256)));
+        }
+    }
+    Ok(rpms)
+}
+
+/// The parts of the application inventory written by `rpm2img` that describe each installed RPM.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InventoryView {
+    content: Vec<InventoryPackageView>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InventoryPackageView {
+    name: String,
+    version: String,
+    release: String,
+    architecture: String,
+}
+
+/// Reads the RPMs installed in a variant's image from the image's application inventory.
+async fn variant_rpms(inventory: &Path) -> Result<Vec<(RpmFile, Option<String>)>> {
+    ensure!(
+        inventory.is_file(),
+        "'{}' does not exist, so the variant must be built before its SBOM can be generated",
+        inventory.display()
+    );
+    let inventory: InventoryView = serde_json::from_str(&read_to_string(inventory).await?)
+        .context(format!(
+            "unable to deserialize application inventory '{}'",
+            inventory.display()
+        ))?;
+    Ok(inventory
+        .content
+        .into_iter()
+        .map(|package| {
+            let rpm = RpmFile {
+                name: package.name,
+                version: package.version,
+                release: package.release,
+                arch: package.architecture,
+            };
+            (rpm, None)
+        })
+        .collect())
+}
+
+/// Maps the name of each RPM built by the project, as found in `build/rpms`, to the upstream files
+/// of the package that built it.
+async fn upstream_files(project_dir: &Path) -> Result<BTreeMap<String, Vec<UpstreamFile>>> {
+    let mut files_by_package = BTreeMap::new();
+    for (manifest_path, member) in load_members(project_dir).await? {
+        match member {
+            Ok(member) if member.build_type == BuildType::Package => {
+                let files: Vec<UpstreamFile> = member
+                    .info
+                    .external_files()
+                    .into_iter()
+                    .flatten()
+                    .map(UpstreamFile::from)
+                    .collect();
+                files_by_package.insert(member.info.package_name().to_string(), files);
+            }
+            Ok(_) => {}
+            Err(e) => warn!(
+                "Unable to load '{}', so its upstream sources are not listed: {e:#}",
+                manifest_path.display()
+            ),
+        }
+    }
+
+    // buildsys writes each package's RPMs into a directory named for the package.
+    let mut upstream = BTreeMap::new();
+    for path in find_rpms(&project_dir.join("build").join("rpms")).await? {
+        let package = path
+            .parent()
+            .and_then(Path::file_name)
+            .and_then(|name| name.to_str())
+            .and_then(|name| files_by_package.get(name));
+        if let (Some(rpm), Some(files)) = (RpmFile::parse(&path), package) {
+            upstream.insert(rpm.package_name().to_string(), files.clone());
+        }
+    }
+    Ok(upstream)
+}
+
+/// What the bill of materials describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SubjectKind {
+    Kit,
+    Variant,
+}
+
+impl Display for SubjectKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Kit => f.write_str("kit"),
+            Self::Variant => f.write_str("variant"),
+        }
+    }
+}
+
+/// A file that a package downloads from upstream and builds from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct UpstreamFile {
+    name: String,
+    url: String,
+    sha512: String,
+}
+
+impl From<&ExternalFile> for UpstreamFile {
+    fn from(file: &ExternalFile) -> Self {
+        let name = file
+            .path
+            .as_ref()
+            .and_then(|path| path.file_name())
+            .map(|name| name.to_string_lossy().to_string())
+            .or_else(|| file.url.rsplit('/').next().map(str::to_string))
+            .unwrap_or_else(|| file.url.clone());
+        Self {
+            name,
+            url: file.url.clone(),
+            sha512: file.sha512.clone(),
+        }
+    }
+}
+
+/// An RPM in the kit or variant.
+#[derive(Debug)]
+struct BinaryPackage {
+    rpm: RpmFile,
+    /// The SHA-256 of the RPM, when the RPM file itself was read
+    sha256: Option<String>,
+    /// The upstream files of the package that built the RPM, if it was built by this project
+    upstream: Vec<UpstreamFile>,
+}
+
+impl BinaryPackage {
+    fn purl(&self) -> String {
+        format!(
+            "pkg:rpm/bottlerocket/{}@{}-{}?arch={}",
+            self.rpm.name, self.rpm.version, self.rpm.release, self.rpm.arch
+        )
+    }
+}
+
+/// The SDK or an external kit recorded in Twoliter.lock.
+#[derive(Debug)]
+struct ImageComponent {
+    name: String,
+    version: String,
+    is_sdk: bool,
+    /// The repository the image was resolved from, without a tag
+    repository: String,
+    /// The digest recorded in Twoliter.lock
+    lock_digest: String,
+    /// The digest of the image for the architecture, which is only recorded for kits
+    platform_digest: Option<String>,
+}
+
+impl ImageComponent {
+    fn new(image: &LockedImage, is_sdk: bool, arch: &DockerArchitecture) -> Self {
+        let tag = format!(":v{}", image.version);
+        Self {
+            name: image.name.to_string(),
+            version: image.version.to_string(),
+            is_sdk,
+            repository: image
+                .source
+                .strip_suffix(&tag)
+                .unwrap_or(&image.source)
+                .to_string(),
+            lock_digest: image.digest.clone(),
+            platform_digest: image
+                .platform
+                .iter()
+                .find(|platform| platform.arch == arch.to_string())
+                .map(|platform| platform.manifest_digest.clone()),
+        }
+    }
+
+    fn purl(&self) -> String {
+        match &self.platform_digest {
+            Some(digest) => format!(
+                "pkg:oci/{}@{}?repository_url={}",
+                self.name,
+                digest.replace(':', "%3A"),
+                self.repository
+            ),
+            None => format!(
+                "pkg:oci/{}?repository_url={}&tag=v{}",
+                self.name, self.repository, self.version
+            ),
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        if self.is_sdk {
+            "The SDK that was used for the build"
+        } else {
+            "An external kit that the project depends on"
+        }
+    }
+}
+
+/// The contents of a kit or variant, gathered from the build directory, the project's packages and
+/// Twoliter.lock.
+#[derive(Debug)]
+struct BillOfMaterials {
+    kind: SubjectKind,
+    name: String,
+    version: String,
+    arch: SupportedArch,
+    packages: Vec<BinaryPackage>,
+    images: Vec<ImageComponent>,
+}
+
+impl BillOfMaterials {
+    fn document_name(&self) -> String {
+        format!("{}-{}-{}", self.name, self.version, self.arch)
+    }
+
+    /// Renders the bill of materials as an SPDX 2.3 document.
+    fn to_spdx(&self, created: DateTime<Utc>, id: Uuid) -> Value {
+        let subject_id = spdx_id("Subject", &self.name);
+        let mut packages = vec![json!({
+            "SPDXID": subject_id,
+            "name": self.name,
+            "versionInfo": self.version,
+            "downloadLocation": "NOASSERTION",
+            "filesAnalyzed": false,
+            "primaryPackagePurpose": match self.kind {
+                SubjectKind::Kit => "CONTAINER",
+                SubjectKind::Variant => "OPERATING-SYSTEM",
+            },
+        })];
+        let mut relationships = vec![spdx_relationship(
+            "SPDXRef-DOCUMENT",
+            "DESCRIBES",
+            &subject_id,
+        )];
+
+        // Several RPMs are often built from the same upstream files, which are listed once.
+        let mut upstream_ids: BTreeMap<&str, String> = BTreeMap::new();
+        for package in self.packages.iter() {
+            let rpm = &package.rpm;
+            let rpm_id = spdx_id("Rpm", &rpm.name);
+            let mut spdx_package = json!({
+                "SPDXID": rpm_id,
+                "name": rpm.name,
+                "versionInfo": format!("{}-{}", rpm.version, rpm.release),
+                "downloadLocation": "NOASSERTION",
+                "filesAnalyzed": false,
+                "externalRefs": [{
+                    "referenceCategory": "PACKAGE-MANAGER",
+                    "referenceType": "purl",
+                    "referenceLocator": package.purl(),
+                }],
+            });
+            if let Some(sha256) = &package.sha256 {
+                spdx_package["checksums"] =
+                    json!([{"algorithm": "SHA256", "checksumValue": sha256}]);
+            }
+            packages.push(spdx_package);
+            relationships.push(spdx_relationship(&subject_id, "CONTAINS", &rpm_id));
+
+            for file in package.upstream.iter() {
+                let upstream_id = upstream_ids.entry(&file.url).or_insert_with(|| {
+                    let upstream_id = spdx_id("Upstream", &file.name);
+                    packages.push(json!({
+                        "SPDXID": upstream_id,
+                        "name": file.name,
+                        "downloadLocation": file.url,
+                        "filesAnalyzed": false,
+                        "checksums": [{"algorithm": "SHA512", "checksumValue": file.sha512}],
+                        "primaryPackagePurpose": "SOURCE",
+                    }));
+                    upstream_id
+                });
+                relationships.push(spdx_relationship(&rpm_id, "GENERATED_FROM", upstream_id));
+            }
+        }
+
+        for image in self.images.iter() {
+            let image_id = spdx_id("Image", &image.name);
+            packages.push(json!({
+                "SPDXID": image_id,
+                "name": image.name,
+                "versionInfo": image.version,
+                "downloadLocation": "NOASSERTION",
+                "filesAnalyzed": false,
+                "primaryPackagePurpose": "CONTAINER",
+                "comment": format!("{} (Twoliter.lock digest {})", image.description(), image.lock_digest),
+                "externalRefs": [{
+                    "referenceCategory": "PACKAGE-MANAGER",
+                    "referenceType": "purl",
+                    "referenceLocator": image.purl(),
+                }],
+            }));
+            relationships.push(if image.is_sdk {
+                spdx_relationship(&image_id, "BUILD_TOOL_OF", &subject_id)
+            } else {
+                spdx_relationship(&subject_id, "DEPENDS_ON", &image_id)
+            });
+        }
+
+        json!({
+            "spdxVersion": "SPDX-2.3",
+            "dataLicense": "CC0-1.0",
+            "SPDXID": "SPDXRef-DOCUMENT",
+            "name": self.document_name(),
+            "documentNamespace": format!(
+                "https://spdx.org/spdxdocs/{}-{id}",
+                self.document_name()
+            ),
+            "creationInfo": {
+                "created": created.to_rfc3339_opts(SecondsFormat::Secs, true),
+                "creators": [format!("Tool: twoliter-{}", env!("CARGO_PKG_VERSION"))],
+            },
+            "packages": packages,
+            "relationships": relationships,
+        })
+    }
+
+    /// Renders the bill of materials as a CycloneDX 1.5 document.
+    fn to_cyclonedx(&self, created: DateTime<Utc>, id: Uuid) -> Value {
+        let subject_ref = format!("{}:{}", self.kind, self.name);
+        let mut components = Vec::new();
+        let mut depends_on = Vec::new();
+        for package in self.packages.iter() {
+            let rpm = &package.rpm;
+            let rpm_ref = format!("rpm:{}", rpm.name);
+            let mut component = json!({
+                "type": "library",
+                "bom-ref": rpm_ref,
+                "name": rpm.name,
+                "version": format!("{}-{}", rpm.version, rpm.release),
+                "purl": package.purl(),
+            });
+            if let Some(sha256) = &package.sha256 {
+                component["hashes"] = json!([{"alg": "SHA-256", "content": sha256}]);
+            }
+            if !package.upstream.is_empty() {
+                let ancestors: Vec<Value> = package
+                    .upstream
+                    .iter()
+                    .map(|file| {
+                        json!({
+                            "type": "file",
+                            "name": file.name,
+                            "hashes": [{"alg": "SHA-512", "content": file.sha512}],
+                            "externalReferences": [{"type": "distribution", "url": file.url}],
+                        })
+                    })
+                    .collect();
+                component["pedigree"] = json!({"ancestors": ancestors});
+            }
+            components.push(component);
+            depends_on.push(rpm_ref);
+        }
+
+        for image in self.images.iter() {
+            let image_ref = format!("image:{}", image.name);
+            components.push(json!({
+                "type": "container",
+                "bom-ref": image_ref,
+                "name": image.name,
+                "version": image.version,
+                "description": image.description(),
+                "purl": image.purl(),
+                "properties": [{"name": "twoliter:lock-digest", "value": image.lock_digest}],
+            }));
+            if !image.is_sdk {
+                depends_on.push(image_ref);
+            }
+        }
+
+        json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "serialNumber": format!("urn:uuid:{id}"),
+            "version": 1,
+            "metadata": {
+                "timestamp": created.to_rfc3339_opts(SecondsFormat::Secs, true),
+                "tools": {
+                    "components": [{
+                        "type": "application",
+                        "name": "twoliter",
+                        "version": env!("CARGO_PKG_VERSION"),
+                    }],
+                },
+                "component": {
+                    "type": match self.kind {
+                        SubjectKind::Kit => "container",
+                        SubjectKind::Variant => "operating-system",
+                    },
+                    "bom-ref": subject_ref,
+                    "name": self.name,
+                    "version": self.version,
+                    "properties": [{"name": "twoliter:arch", "value": self.arch.to_string()}],
+                },
+            },
+            "components": components,
+            "dependencies": [{"ref": subject_ref, "dependsOn": depends_on}],
+        })
+    }
+}
+
+/// Creates an SPDX identifier, which may only contain letters, numbers, `.` and `-`.
+fn spdx_id(kind: &str, name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("SPDXRef-{kind}-{name}")
+}
+
+fn spdx_relationship(element: &str, relationship: &str, related: &str) -> Value {
+    json!({
+        "spdxElementId": element,
+        "relationshipType": relationship,
+        "relatedSpdxElement": related,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn bill_of_materials() -> BillOfMaterials {
+        let upstream = UpstreamFile {
+            name: "hello-1.0.tar.gz".into(),
+            url: "https://example.com/hello-1.0.tar.gz".into(),
+            sha512: "abc".into(),
+        };
+        let package = |name: &str| BinaryPackage {
+            rpm: RpmFile {
+                name: format!("bottlerocket-{name}"),
+                version: "1.0".into(),
+                release: "1.br1".into(),
+                arch: "x86_64".into(),
+            },
+            sha256: Some("def".into()),
+            upstream: vec![upstream.clone()],
+        };
+        BillOfMaterials {
+            kind: SubjectKind::Kit,
+            name: "my-kit".into(),
+            version: "1.2.3".into(),
+            arch: SupportedArch::X86_64,
+            packages: vec![package("hello"), package("hello-bin")],
+            images: vec![
+                ImageComponent {
+                    name: "my-sdk".into(),
+                    version: "0.50.0".into(),
+                    is_sdk: true,
+                    repository: "a.com/b/my-sdk".into(),
+                    lock_digest: "sdk-digest".into(),
+                    platform_digest: None,
+                },
+                ImageComponent {
+                    name: "core-kit".into(),
+                    version: "3.0.0".into(),
+                    is_sdk: false,
+                    repository: "a.com/b/core-kit".into(),
+                    lock_digest: "kit-digest".into(),
+                    platform_digest: Some("sha256:123".into()),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_spdx() {
+        let document = bill_of_materials().to_spdx(Utc::now(), Uuid::new_v4());
+        assert_eq!(document["spdxVersion"], "SPDX-2.3");
+        assert_eq!(document["name"], "my-kit-1.2.3-x86_64");
+
+        // The kit, two RPMs, one shared upstream file, the SDK and an external kit
+        let packages = document["packages"].as_array().unwrap();
+        assert_eq!(packages.len(), 6);
+        assert_eq!(packages[1]["SPDXID"], "SPDXRef-Rpm-bottlerocket-hello");
+        assert_eq!(
+            packages[1]["externalRefs"][0]["referenceLocator"],
+            "pkg:rpm/bottlerocket/bottlerocket-hello@1.0-1.br1?arch=x86_64"
+        );
+        assert_eq!(
+            packages[2]["downloadLocation"],
+            "https://example.com/hello-1.0.tar.gz"
+        );
+        assert_eq!(
+            packages[5]["externalRefs"][0]["referenceLocator"],
+            "pkg:oci/core-kit@sha256%3A123?repository_url=a.com/b/core-kit"
+        );
+
+        let relationships: Vec<String> = document["relationships"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| {
+                format!(
+                    "{} {} {}",
+                    r["spdxElementId"].as_str().unwrap(),
+                    r["relationshipType"].as_str().unwrap(),
+                    r["relatedSpdxElement"].as_str().unwrap()
+                )
+            })
+            .collect();
+        assert!(relationships.contains(
+            &"SPDXRef-Rpm-bottlerocket-hello-bin GENERATED_FROM SPDXRef-Upstream-hello-1.0.tar.gz"
+                .to_string()
+        ));
+        assert!(relationships
+            .contains(&"SPDXRef-Image-my-sdk BUILD_TOOL_OF SPDXRef-Subject-my-kit".to_string()));
+        assert!(relationships
+            .contains(&"SPDXRef-Subject-my-kit DEPENDS_ON SPDXRef-Image-core-kit".to_string()));
+    }
+
+    #[test]
+    fn test_cyclonedx() {
+        let document = bill_of_materials().to_cyclonedx(Utc::now(), Uuid::new_v4());
+        assert_eq!(document["bomFormat"], "CycloneDX");
+        assert_eq!(document["metadata"]["component"]["bom-ref"], "kit:my-kit");
+        let components = document["components"].as_array().unwrap();
+        assert_eq!(components.len(), 4);
+        assert_eq!(
+            components[0]["pedigree"]["ancestors"][0]["hashes"][0]["content"],
+            "abc"
+        );
+        assert_eq!(
+            components[2]["purl"],
+            "pkg:oci/my-sdk?repository_url=a.com/b/my-sdk&tag=v0.50.0"
+        );
+        // The SDK is used to build the kit, but the kit does not depend on it
+        assert_eq!(
+            document["dependencies"][0]["dependsOn"],
+            json!([
+                "rpm:bottlerocket-hello",
+                "rpm:bottlerocket-hello-bin",
+                "image:core-kit"
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_rpms() {
+        let tempdir = TempDir::new().unwrap();
+        let packages_dir = tempdir.path().join("Packages");
+        std::fs::create_dir_all(&packages_dir).unwrap();
+        std::fs::write(
+            packages_dir.join("bottlerocket-hello-1.0-1.br1.x86_64.rpm"),
+            "rpm",
+        )
+        .unwrap();
+        let rpms = kit_rpms(&packages_dir).await.unwrap();
+        assert_eq!(rpms.len(), 1);
+        assert_eq!(rpms[0].0.package_name(), "hello");
+        assert_eq!(
+            rpms[0].1.as_deref(),
+            Some(format!("{:x}", sha2::Sha256::digest(b"rpm")).as_str())
+        );
+        kit_rpms(&tempdir.path().join("missing")).await.unwrap_err();
+
+        let inventory = tempdir.path().join("application-inventory.json");
+        std::fs::write(
+            &inventory,
+            r#"{"Content": [{"Name": "kernel-6.1", "Publisher": "Bottlerocket",
+                "Version": "6.1.90", "Release": "1.br1", "Architecture": "x86_64"}]}"#,
+        )
+        .unwrap();
+        let rpms = variant_rpms(&inventory).await.unwrap();
+        assert_eq!(rpms[0].0.name, "kernel-6.1");
+        assert_eq!(rpms[0].0.version, "6.1.90");
+        assert!(rpms[0].1.is_none());
+    }
+}
@@ -0,0 +1,43 @@
+use crate::project;
+use anyhow::Result;
+use clap::{Parser, ValueEnum};
+use std::path::PathBuf;
+
+/// The format in which to print the dependency graph.
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub(crate) enum TreeFormat {
+    /// An indented tree, similar to `cargo tree`
+    #[default]
+    Text,
+    /// The Graphviz DOT language
+    Dot,
+    /// JSON
+    Json,
+}
+
+/// Print the resolved kit and SDK dependency graph of the project.
+#[derive(Debug, Parser)]
+pub(crate) struct Tree {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    pub(crate) project_path: Option<PathBuf>,
+
+    /// The output format
+    #[clap(long = "format", value_enum, default_value_t)]
+    pub(crate) format: TreeFormat,
+}
+
+impl Tree {
+    pub(super) async fn run(&self) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone()).await?;
+        let graph = project.dependency_graph().await?;
+        let root = project.filepath().display().to_string();
+        let output = match self.format {
+            TreeFormat::Text => graph.render_text(&root),
+            TreeFormat::Dot => graph.render_dot(&root),
+            TreeFormat::Json => graph.render_json()?,
+        };
+        println!("{}", output.trim_end());
+        Ok(())
+    }
+}
@@ -1,19 +1,74 @@
-use crate::project;
-use anyhow::Result;
-use clap::Parser;
+use crate::project::{self, UpdateScope, ValidIdentifier};
+use anyhow::{ensure, Result};
+use clap::{Parser, ValueEnum};
 use std::path::PathBuf;
 
+/// The format in which to print the result of `twoliter update --check`.
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub(crate) enum CheckFormat {
+    /// A human readable list of changes
+    #[default]
+    Text,
+    /// JSON
+    Json,
+}
+
 #[derive(Debug, Parser)]
 pub(crate) struct Update {
     /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
     #[clap(long = "project-path")]
     pub(crate) project_path: Option<PathBuf>,
+
+    /// Only update the named kit and the kits it depends on, leaving all other entries in
+    /// Twoliter.lock unchanged. May be given more than once
+    #[clap(long = "kit")]
+    pub(crate) kit: Vec<ValidIdentifier>,
+
+    /// Only update the SDK, leaving all other entries in Twoliter.lock unchanged
+    #[clap(long = "sdk")]
+    pub(crate) sdk: bool,
+
+    /// Report any differences between Twoliter.lock and the resolved dependencies without writing
+    /// Twoliter.lock. Exits with an error if there are differences
+    #[clap(long = "check", conflicts_with_all = ["kit", "sdk"])]
+    pub(crate) check: bool,
+
+    /// The format of the report printed by --check
+    #[clap(long = "format", value_enum, default_value_t, requires = "check")]
+    pub(crate) format: CheckFormat,
 }
 
 impl Update {
     pub(super) async fn run(&self) -> Result<()> {
         let project = project::load_or_find_project(self.project_path.clone()).await?;
-        project.create_lock().await?;
+        if self.check {
+            let drift = project.lock_drift().await?;
+            let output = match self.format {
+                CheckFormat::Text => drift.render_text(),
+                CheckFormat::Json => drift.render_json()?,
+            };
+            println!("{}", output.trim_end());
+            ensure!(drift.is_empty(), "Twoliter.lock is out of date");
+            return Ok(());
+        }
+
+        let scope = UpdateScope {
+            kits: self.kit.clone(),
+            sdk: self.sdk,
+        };
+        project.update_lock(&scope).await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_conflicts_with_scope() {
+        Update::try_parse_from(["update", "--check", "--format", "json"]).unwrap();
+        Update::try_parse_from(["update", "--check", "--kit", "core-kit"]).unwrap_err();
+        Update::try_parse_from(["update", "--format", "json"]).unwrap_err();
+    }
+}
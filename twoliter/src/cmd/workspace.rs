@@ -0,0 +1,235 @@
+//! Reads the packages, kits and variants in a project's Cargo workspace, and the RPMs that have been
+//! built or fetched for them, without building anything.
+use crate::common::exec;
+use crate::common::fs::{read_to_string, write};
+use anyhow::{Context, Result};
+use buildsys::manifest::{Manifest, ManifestInfo, SupportedArch};
+use buildsys::BuildType;
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+use tokio::process::Command;
+use tracing::debug;
+
+/// The prefix given to the names of the RPMs built for Bottlerocket, i.e. `%{_cross_os}`.
+const RPM_NAME_PREFIX: &str = "bottlerocket-";
+
+/// A package, kit or variant in the project's Cargo workspace.
+#[derive(Debug)]
+pub(super) struct Member {
+    pub(super) manifest_path: PathBuf,
+    pub(super) info: ManifestInfo,
+    pub(super) build_type: BuildType,
+    /// For packages, the names of the RPMs that the package's spec builds. For kits, the packages
+    /// that the kit includes.
+    pub(super) packages: BTreeSet<String>,
+}
+
+/// The parts of `cargo metadata` output that are needed to find the workspace's members.
+#[derive(Debug, Deserialize)]
+struct CargoMetadataView {
+    packages: Vec<CargoPackageView>,
+    workspace_members: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CargoPackageView {
+    id: String,
+    manifest_path: PathBuf,
+}
+
+/// Loads the manifest of every member of the Cargo workspace in `project_dir`. Each member is
+/// returned along with its manifest path, so that members which cannot be loaded can be reported
+/// without stopping the others from loading.
+pub(super) async fn load_members(project_dir: &Path) -> Result<Vec<(PathBuf, Result<Member>)>> {
+    let metadata = exec(
+        Command::new("cargo")
+            .args([
+                "metadata",
+                "--format-version",
+                "1",
+                "--offline",
+                "--all-features",
+            ])
+            .arg("--manifest-path")
+            .arg(project_dir.join("Cargo.toml")),
+        true,
+    )
+    .await
+    .context("unable to read the project's Cargo workspace with `cargo metadata`")?
+    .context("`cargo metadata` did not produce any output")?;
+
+    // buildsys reads the dependency graph from a file, as it does during a build.
+    let metadata_dir = TempDir::new().context("unable to create directory for cargo metadata")?;
+    let metadata_path = metadata_dir.path().join("cargo_metadata.json");
+    write(&metadata_path, &metadata).await?;
+
+    let mut members = Vec::new();
+    for manifest_path in member_manifests(&metadata)? {
+        debug!("Loading '{}'", manifest_path.display());
+        let member = load_member(&manifest_path, &metadata_path).await;
+        members.push((manifest_path, member));
+    }
+    Ok(members)
+}
+
+/// Returns the manifest paths of the workspace's members from `cargo metadata` output.
+pub(super) fn member_manifests(metadata: &str) -> Result<Vec<PathBuf>> {
+    let view: CargoMetadataView =
+        serde_json::from_str(metadata).context("unable to deserialize `cargo metadata` output")?;
+    Ok(view
+        .packages
+        .into_iter()
+        .filter(|package| view.workspace_members.contains(&package.id))
+        .map(|package| package.manifest_path)
+        .collect())
+}
+
+async fn load_member(manifest_path: &Path, metadata_path: &Path) -> Result<Member> {
+    let manifest = Manifest::new(manifest_path, metadata_path)?;
+    let build_type = manifest.info().build_type()?;
+    let packages = match build_type {
+        BuildType::Package => {
+            let spec =
+                manifest_path.with_file_name(format!("{}.spec", manifest.info().package_name()));
+            spec_packages(
+                manifest.info().package_name(),
+                &read_to_string(&spec).await?,
+            )
+        }
+        BuildType::Kit => manifest.package_dependencies()?.into_iter().collect(),
+        BuildType::Variant | BuildType::Repack => BTreeSet::new(),
+    };
+    Ok(Member {
+        manifest_path: manifest_path.to_path_buf(),
+        info: ManifestInfo::new(manifest_path)?,
+        build_type,
+        packages,
+    })
+}
+
+/// Lists the names of the RPMs built by a spec: the package itself and each `%package` section.
+/// Subpackages whose names are only known once macros are expanded are skipped.
+fn spec_packages(package_name: &str, spec: &str) -> BTreeSet<String> {
+    let mut packages = BTreeSet::from([package_name.to_string()]);
+    for line in spec.lines() {
+        let mut words = line.split_whitespace();
+        if words.next() != Some("%package") {
+            continue;
+        }
+        let name = match (words.next(), words.next()) {
+            (Some("-n"), Some(name)) => name
+                .strip_prefix("%{_cross_os}")
+                .map(|name| name.to_string()),
+            (Some(suffix), _) => Some(format!("{package_name}-{suffix}")),
+            _ => None,
+        };
+        if let Some(name) = name.filter(|name| !name.contains('%')) {
+            packages.insert(name);
+        }
+    }
+    packages
+}
+
+/// An RPM identified by its file name, such as `bottlerocket-kernel-6.1-6.1.90-1.x86_64.rpm`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) struct RpmFile {
+    pub(super) name: String,
+    pub(super) version: String,
+    pub(super) release: String,
+    pub(super) arch: String,
+}
+
+impl RpmFile {
+    /// Parses the name of the RPM at `path`, or returns `None` if the file is not an RPM.
+    pub(super) fn parse(path: &Path) -> Option<Self> {
+        let file_name = path.file_name()?.to_str()?;
+        let (rest, arch) = file_name.strip_suffix(".rpm")?.rsplit_once('.')?;
+        let mut parts = rest.rsplitn(3, '-');
+        let (release, version, name) = (parts.next()?, parts.next()?, parts.next()?);
+        Some(Self {
+            name: name.to_string(),
+            version: version.to_string(),
+            release: release.to_string(),
+            arch: arch.to_string(),
+        })
+    }
+
+    /// The name of the package without the `bottlerocket-` prefix, which is how packages are named
+    /// by kits and variants.
+    pub(super) fn package_name(&self) -> &str {
+        package_name(&self.name)
+    }
+
+    /// Returns true if the RPM is built for `arch`, or if no architecture is given.
+    pub(super) fn is_for(&self, arch: Option<SupportedArch>) -> bool {
+        arch.map_or(true, |arch| arch.to_string() == self.arch)
+    }
+}
+
+/// Removes the `bottlerocket-` prefix from an RPM name.
+pub(super) fn package_name(rpm_name: &str) -> &str {
+    rpm_name.strip_prefix(RPM_NAME_PREFIX).unwrap_or(rpm_name)
+}
+
+/// Lists the RPMs in `dir` and its subdirectories in a stable order. A missing directory has no
+/// RPMs.
+pub(super) async fn find_rpms(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut rpms = Vec::new();
+    let mut remaining = vec![dir.to_path_buf()];
+    while let Some(dir) = remaining.pop() {
+        if !dir.is_dir() {
+            continue;
+        }
+        let mut entries = tokio::fs::read_dir(&dir)
+            .await
+            .context(format!("unable to read directory '{}'", dir.display()))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .context(format!("unable to read directory '{}'", dir.display()))?
+        {
+            let path = entry.path();
+            if path.is_dir() {
+                remaining.push(path);
+            } else if RpmFile::parse(&path).is_some() {
+                rpms.push(path);
+            }
+        }
+    }
+    rpms.sort();
+    Ok(rpms)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_spec_packages() {
+        let spec = "Name: %{_cross_os}hello\n%package bin\n%package -n %{_cross_os}greeter\n\
+            %package -n %{_cross_os}hello-%{major}\n";
+        let packages = spec_packages("hello", spec);
+        assert_eq!(
+            packages.into_iter().collect::<Vec<_>>(),
+            vec!["greeter", "hello", "hello-bin"]
+        );
+    }
+
+    #[test]
+    fn test_rpm_file() {
+        let rpm = RpmFile::parse(Path::new(
+            "Packages/bottlerocket-kernel-6.1-6.1.90-1.1700000000.br1.x86_64.rpm",
+        ))
+        .unwrap();
+        assert_eq!(rpm.name, "bottlerocket-kernel-6.1");
+        assert_eq!(rpm.package_name(), "kernel-6.1");
+        assert_eq!(rpm.version, "6.1.90");
+        assert_eq!(rpm.release, "1.1700000000.br1");
+        assert!(rpm.is_for(None));
+        assert!(rpm.is_for(Some(SupportedArch::X86_64)));
+        assert!(!rpm.is_for(Some(SupportedArch::Aarch64)));
+        assert!(RpmFile::parse(Path::new("repodata/repomd.xml")).is_none());
+    }
+}
@@ -4,8 +4,18 @@
 /// Defines the exact supported schema version of Twoliter.toml supported by twoliter
 pub const SUPPORTED_TWOLITER_PROJECT_SCHEMA_VERSION: u32 = 1;
 
-/// Defines the kit metadata version supported by twoliter.
+/// Defines the newest schema version of Twoliter.lock supported by twoliter, which is the version
+/// written to new lock files. Lock files with an older schema version are migrated automatically.
+pub const SUPPORTED_TWOLITER_LOCK_SCHEMA_VERSION: u32 = 2;
+
+/// Defines the newest kit metadata version supported by twoliter.
 ///
 /// The kit metadata version is embeddeded in a label within the OCI image's configuration blob,
 /// with the value stored at that label including the kit metadata itself.
 pub const SUPPORTED_KIT_METADATA_VERSION: &str = "v2";
+
+/// The kit metadata versions that twoliter can read, oldest first. A kit may carry several versions
+/// of its metadata, in which case the newest readable version is used. Older versions are upgraded
+/// to the supported version. The first release of twoliter that reads each version is recorded in
+/// the kit's labels by `rpm2kit`, which keeps the only record of them.
+pub const READABLE_KIT_METADATA_VERSIONS: [u32; 2] = [1, 2];
@@ -0,0 +1,542 @@
+//! Twoliter's configuration files, which hold settings that would otherwise need to be passed as
+//! flags or environment variables on every invocation.
+//!
+//! Settings are read from these files, where each file overrides the ones before it:
+//!
+//! 1. The system configuration, `/etc/twoliter/config.toml`
+//! 2. The user configuration, `$XDG_CONFIG_HOME/twoliter/config.toml`, or
+//!    `~/.config/twoliter/config.toml`
+//! 3. The project configuration, `.twoliter/config.toml` in the directory that holds
+//!    `Twoliter.toml`
+//!
+//! Environment variables such as `BUILDSYS_LOOKASIDE_CACHE` override all of the files, and
+//! command line flags override everything else. A file may set any of these keys:
+//!
+//! ```toml
+//! lookaside-cache = "https://cache.example.com"
+//! upstream-source-fallback = true
+//! arch = "aarch64"
+//! log-level = "debug"
+//! cargo-home = "/home/user/.cargo"
+//! infra-toml = "Infra.toml"
+//!
+//! # Where to find the credentials for a vendor's registry, which takes precedence over the
+//! # vendor's `auth` in Twoliter.toml. One of `credential-helper`, `docker-config`, or `token-file`.
+//! [vendor-auth.my-vendor]
+//! credential-helper = "ecr-login"
+//! ```
+//!
+//! Relative paths are resolved against the project directory. `twoliter debug config` shows the
+//! value of each setting and where it came from.
+use crate::common::fs;
+use anyhow::{Context, Result};
+use buildsys::manifest::SupportedArch;
+use log::LevelFilter;
+use oci_cli_wrapper::CredentialSource;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+const CONFIG_DIRECTORY: &str = "twoliter";
+const CONFIG_FILE: &str = "config.toml";
+const SYSTEM_CONFIG_DIR: &str = "/etc";
+const PROJECT_CONFIG_DIR: &str = ".twoliter";
+const DEFAULT_ARCH: SupportedArch = SupportedArch::X86_64;
+
+const LOOKASIDE_CACHE_ENV: &str = "BUILDSYS_LOOKASIDE_CACHE";
+const UPSTREAM_SOURCE_FALLBACK_ENV: &str = "BUILDSYS_UPSTREAM_SOURCE_FALLBACK";
+const ARCH_ENV: &str = "BUILDSYS_ARCH";
+const INFRA_TOML_ENV: &str = "PUBLISH_INFRA_CONFIG_PATH";
+
+/// The configuration files, in increasing order of precedence.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Layer {
+    System,
+    User,
+    Project,
+}
+
+impl Display for Layer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Layer::System => write!(f, "system"),
+            Layer::User => write!(f, "user"),
+            Layer::Project => write!(f, "project"),
+        }
+    }
+}
+
+/// Where the value of a setting came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Origin {
+    Default,
+    File(Layer, PathBuf),
+    Env(&'static str),
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File(layer, path) => write!(f, "{layer} config {}", path.display()),
+            Origin::Env(var) => write!(f, "environment variable {var}"),
+        }
+    }
+}
+
+/// The value of a setting and where it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Setting<T> {
+    pub(crate) value: T,
+    pub(crate) origin: Origin,
+}
+
+/// The contents of a single configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ConfigFile {
+    lookaside_cache: Option<String>,
+    upstream_source_fallback: Option<bool>,
+    arch: Option<SupportedArch>,
+    log_level: Option<String>,
+    cargo_home: Option<PathBuf>,
+    infra_toml: Option<PathBuf>,
+    #[serde(default)]
+    vendor_auth: BTreeMap<String, CredentialSource>,
+}
+
+/// The effective configuration after all of the configuration files and environment variables
+/// have been read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Config {
+    /// The configuration files that were considered, whether or not they exist.
+    pub(crate) files: Vec<(Layer, PathBuf)>,
+    pub(crate) lookaside_cache: Option<Setting<String>>,
+    pub(crate) upstream_source_fallback: Setting<bool>,
+    pub(crate) arch: Setting<SupportedArch>,
+    pub(crate) log_level: Option<Setting<LevelFilter>>,
+    pub(crate) cargo_home: Option<Setting<PathBuf>>,
+    pub(crate) infra_toml: Option<Setting<PathBuf>>,
+    /// The credential source for the registry of each vendor, by vendor name.
+    pub(crate) vendor_auth: BTreeMap<String, Setting<CredentialSource>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            files: Vec::new(),
+            lookaside_cache: None,
+            upstream_source_fallback: Setting {
+                value: false,
+                origin: Origin::Default,
+            },
+            arch: Setting {
+                value: DEFAULT_ARCH,
+                origin: Origin::Default,
+            },
+            log_level: None,
+            cargo_home: None,
+            infra_toml: None,
+            vendor_auth: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the configuration for the project in `project_dir`, or only the system and user
+    /// configuration when there is no project.
+    pub(crate) async fn load(project_dir: Option<&Path>) -> Result<Self> {
+        let mut files = vec![(
+            Layer::System,
+            Path::new(SYSTEM_CONFIG_DIR)
+                .join(CONFIG_DIRECTORY)
+                .join(CONFIG_FILE),
+        )];
+        if let Some(user_dir) = user_config_dir() {
+            files.push((
+                Layer::User,
+                user_dir.join(CONFIG_DIRECTORY).join(CONFIG_FILE),
+            ));
+        }
+        if let Some(project_dir) = project_dir {
+            files.push((
+                Layer::Project,
+                project_dir.join(PROJECT_CONFIG_DIR).join(CONFIG_FILE),
+            ));
+        }
+
+        let mut config = Self::default();
+        for (layer, path) in files.iter() {
+            if !path.is_file() {
+                continue;
+            }
+            let contents = fs::read_to_string(path).await?;
+            let file: ConfigFile = toml::from_str(&contents)
+                .context(format!("Unable to parse config file '{}'", path.display()))?;
+            config
+                .merge(file, Origin::File(*layer, path.clone()))
+                .context(format!("Invalid config file '{}'", path.display()))?;
+        }
+        config.merge_env(|var| std::env::var(var).ok())?;
+        if let Some(project_dir) = project_dir {
+            config.resolve_paths(project_dir);
+        }
+        config.files = files;
+        Ok(config)
+    }
+
+    /// Reads the configuration for the project in the current directory or one of its parents,
+    /// if there is one.
+    pub(crate) async fn load_for_current_dir() -> Result<Self> {
+        let project_dir = std::env::current_dir()
+            .ok()
+            .and_then(|dir| find_project_dir(&dir));
+        Self::load(project_dir.as_deref()).await
+    }
+
+    /// Overrides settings with the values given in `file`.
+    fn merge(&mut self, file: ConfigFile, origin: Origin) -> Result<()> {
+        if let Some(lookaside_cache) = file.lookaside_cache {
+            self.lookaside_cache = Some(Setting {
+                value: lookaside_cache,
+                origin: origin.clone(),
+            });
+        }
+        if let Some(upstream_source_fallback) = file.upstream_source_fallback {
+            self.upstream_source_fallback = Setting {
+                value: upstream_source_fallback,
+                origin: origin.clone(),
+            };
+        }
+        if let Some(arch) = file.arch {
+            self.arch = Setting {
+                value: arch,
+                origin: origin.clone(),
+            };
+        }
+        if let Some(log_level) = file.log_level {
+            self.log_level = Some(Setting {
+                value: log_level
+                    .parse()
+                    .context(format!("Invalid log-level '{log_level}'"))?,
+                origin: origin.clone(),
+            });
+        }
+        if let Some(cargo_home) = file.cargo_home {
+            self.cargo_home = Some(Setting {
+                value: cargo_home,
+                origin: origin.clone(),
+            });
+        }
+        if let Some(infra_toml) = file.infra_toml {
+            self.infra_toml = Some(Setting {
+                value: infra_toml,
+                origin: origin.clone(),
+            });
+        }
+        for (vendor, auth) in file.vendor_auth {
+            self.vendor_auth.insert(
+                vendor,
+                Setting {
+                    value: auth,
+                    origin: origin.clone(),
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Overrides settings with the environment variables that `cargo make` would otherwise be
+    /// given, using `var` to look them up.
+    fn merge_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<()> {
+        if let Some(lookaside_cache) = var(LOOKASIDE_CACHE_ENV) {
+            self.lookaside_cache = Some(Setting {
+                value: lookaside_cache,
+                origin: Origin::Env(LOOKASIDE_CACHE_ENV),
+            });
+        }
+        if let Some(upstream_source_fallback) = var(UPSTREAM_SOURCE_FALLBACK_ENV) {
+            self.upstream_source_fallback = Setting {
+                value: upstream_source_fallback == "true",
+                origin: Origin::Env(UPSTREAM_SOURCE_FALLBACK_ENV),
+            };
+        }
+        if let Some(arch) = var(ARCH_ENV) {
+            self.arch = Setting {
+                value: arch
+                    .parse()
+                    .context(format!("Invalid architecture '{arch}' in {ARCH_ENV}"))?,
+                origin: Origin::Env(ARCH_ENV),
+            };
+        }
+        if let Some(infra_toml) = var(INFRA_TOML_ENV) {
+            self.infra_toml = Some(Setting {
+                value: infra_toml.into(),
+                origin: Origin::Env(INFRA_TOML_ENV),
+            });
+        }
+        // `RUST_LOG` is a filter rather than a level, so it is left for the logger to read.
+        if var(env_logger::DEFAULT_FILTER_ENV).is_some() {
+            self.log_level = None;
+        }
+        Ok(())
+    }
+
+    /// Makes relative paths from the configuration files relative to the project directory.
+    fn resolve_paths(&mut self, project_dir: &Path) {
+        for setting in [&mut self.cargo_home, &mut self.infra_toml]
+            .into_iter()
+            .flatten()
+        {
+            setting.value = project_dir.join(&setting.value);
+        }
+        for setting in self.vendor_auth.values_mut() {
+            setting.value = setting.value.clone().relative_to(project_dir);
+        }
+    }
+
+    /// The lookaside cache to use, preferring `flag` when it is given.
+    pub(crate) fn lookaside_cache(&self, flag: Option<&String>) -> Option<String> {
+        flag.cloned()
+            .or_else(|| self.lookaside_cache.as_ref().map(|s| s.value.clone()))
+    }
+
+    /// Whether to fall back to upstream sources, which is the case if either `flag` or the
+    /// configuration says so.
+    pub(crate) fn upstream_source_fallback(&self, flag: bool) -> bool {
+        flag || self.upstream_source_fallback.value
+    }
+
+    /// The cargo home to use, preferring `flag` when it is given.
+    pub(crate) fn cargo_home(&self, flag: Option<&PathBuf>) -> Option<PathBuf> {
+        flag.cloned()
+            .or_else(|| self.cargo_home.as_ref().map(|s| s.value.clone()))
+    }
+
+    /// The Infra.toml to use, preferring `flag` when it is given.
+    pub(crate) fn infra_toml(&self, flag: Option<&PathBuf>) -> Option<PathBuf> {
+        flag.cloned()
+            .or_else(|| self.infra_toml.as_ref().map(|s| s.value.clone()))
+    }
+
+    /// The credential source for the registry of the vendor named `vendor`, if one is configured.
+    pub(crate) fn vendor_auth(&self, vendor: &str) -> Option<&CredentialSource> {
+        self.vendor_auth.get(vendor).map(|s| &s.value)
+    }
+
+    /// The log level to use, preferring `flag` when it is given.
+    pub(crate) fn log_level(&self, flag: Option<LevelFilter>) -> Option<LevelFilter> {
+        flag.or_else(|| self.log_level.as_ref().map(|s| s.value))
+    }
+
+    /// Describes the value of each setting and its origin.
+    pub(crate) fn render_text(&self) -> String {
+        let mut out = String::from("Configuration files, from lowest to highest precedence:\n");
+        for (layer, path) in self.files.iter() {
+            let status = if path.is_file() { "" } else { " (not found)" };
+            out.push_str(&format!("  {layer}: {}{status}\n", path.display()));
+        }
+        out.push('\n');
+        let settings = [
+            ("arch", Some(describe(&self.arch, |v| v.to_string()))),
+            ("cargo-home", self.cargo_home.as_ref().map(describe_path)),
+            ("infra-toml", self.infra_toml.as_ref().map(describe_path)),
+            (
+                "log-level",
+                self.log_level
+                    .as_ref()
+                    .map(|s| describe(s, |v| v.to_string())),
+            ),
+            (
+                "lookaside-cache",
+                self.lookaside_cache
+                    .as_ref()
+                    .map(|s| describe(s, |v| v.clone())),
+            ),
+            (
+                "upstream-source-fallback",
+                Some(describe(&self.upstream_source_fallback, |v| v.to_string())),
+            ),
+        ];
+        for (name, setting) in settings {
+            match setting {
+                Some((value, origin)) => out.push_str(&format!("{name} = {value} ({origin})\n")),
+                None => out.push_str(&format!("{name} is not set\n")),
+            }
+        }
+        for (vendor, setting) in self.vendor_auth.iter() {
+            out.push_str(&format!(
+                "vendor-auth.{vendor} = {} ({})\n",
+                setting.value, setting.origin
+            ));
+        }
+        out
+    }
+}
+
+/// Formats a setting's value and origin for display.
+fn describe<T>(setting: &Setting<T>, value: impl Fn(&T) -> String) -> (String, &Origin) {
+    (value(&setting.value), &setting.origin)
+}
+
+fn describe_path(setting: &Setting<PathBuf>) -> (String, &Origin) {
+    describe(setting, |path| path.display().to_string())
+}
+
+/// The directory that holds the user's configuration files.
+fn user_config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| home::home_dir().map(|home| home.join(".config")))
+}
+
+/// Finds the directory in `dir` or its parents that holds a Twoliter.toml.
+fn find_project_dir(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .find(|dir| dir.join("Twoliter.toml").is_file())
+        .map(Path::to_path_buf)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn file(contents: &str) -> ConfigFile {
+        toml::from_str(contents).unwrap()
+    }
+
+    #[test]
+    fn test_layers_override_each_other() {
+        let user = Origin::File(Layer::User, PathBuf::from("/home/user/config.toml"));
+        let project = Origin::File(Layer::Project, PathBuf::from("/project/config.toml"));
+        let mut config = Config::default();
+        config
+            .merge(
+                file(
+                    r#"
+                    lookaside-cache = "https://cache.example.com"
+                    arch = "aarch64"
+                    log-level = "debug"
+                    cargo-home = "/home/user/.cargo"
+                    "#,
+                ),
+                user.clone(),
+            )
+            .unwrap();
+        config
+            .merge(
+                file("arch = \"x86_64\"\ninfra-toml = \"infra/Infra.toml\""),
+                project.clone(),
+            )
+            .unwrap();
+        config.merge_env(|_| None).unwrap();
+        config.resolve_paths(Path::new("/project"));
+
+        assert_eq!(
+            config.lookaside_cache,
+            Some(Setting {
+                value: "https://cache.example.com".to_string(),
+                origin: user.clone(),
+            })
+        );
+        assert_eq!(config.arch.value, SupportedArch::X86_64);
+        assert_eq!(config.arch.origin, project);
+        assert_eq!(config.log_level(None), Some(LevelFilter::Debug));
+        assert_eq!(
+            config.log_level(Some(LevelFilter::Trace)),
+            Some(LevelFilter::Trace)
+        );
+        assert_eq!(
+            config.cargo_home(None),
+            Some(PathBuf::from("/home/user/.cargo"))
+        );
+        assert_eq!(
+            config.infra_toml(None),
+            Some(PathBuf::from("/project/infra/Infra.toml"))
+        );
+        assert_eq!(config.upstream_source_fallback.origin, Origin::Default);
+        assert!(!config.upstream_source_fallback(false));
+    }
+
+    #[test]
+    fn test_env_overrides_files() {
+        let mut config = Config::default();
+        config
+            .merge(
+                file("lookaside-cache = \"https://a\"\nlog-level = \"warn\""),
+                Origin::File(Layer::System, PathBuf::from("/etc/twoliter/config.toml")),
+            )
+            .unwrap();
+        config
+            .merge_env(|var| match var {
+                "BUILDSYS_LOOKASIDE_CACHE" => Some("https://b".to_string()),
+                "BUILDSYS_UPSTREAM_SOURCE_FALLBACK" => Some("true".to_string()),
+                "RUST_LOG" => Some("twoliter=trace".to_string()),
+                _ => None,
+            })
+            .unwrap();
+
+        assert_eq!(config.lookaside_cache(None), Some("https://b".to_string()));
+        assert_eq!(
+            config.lookaside_cache(Some(&"https://c".to_string())),
+            Some("https://c".to_string())
+        );
+        assert!(config.upstream_source_fallback(false));
+        assert_eq!(
+            config.upstream_source_fallback.origin,
+            Origin::Env("BUILDSYS_UPSTREAM_SOURCE_FALLBACK")
+        );
+        assert_eq!(config.log_level(None), None);
+    }
+
+    #[test]
+    fn test_vendor_auth() {
+        let mut config = Config::default();
+        config
+            .merge(
+                file(
+                    r#"
+                    [vendor-auth.publish-vendor]
+                    token-file = "secrets/token"
+
+                    [vendor-auth.private-vendor]
+                    credential-helper = "ecr-login"
+                    "#,
+                ),
+                Origin::File(Layer::User, PathBuf::from("/home/user/config.toml")),
+            )
+            .unwrap();
+        config
+            .merge(
+                file("[vendor-auth.private-vendor]\ndocker-config = \"/etc/docker-private\""),
+                Origin::File(Layer::Project, PathBuf::from("/project/config.toml")),
+            )
+            .unwrap();
+        config.resolve_paths(Path::new("/project"));
+
+        assert_eq!(
+            config.vendor_auth("publish-vendor"),
+            Some(&CredentialSource::TokenFile(
+                "/project/secrets/token".into()
+            ))
+        );
+        assert_eq!(
+            config.vendor_auth("private-vendor"),
+            Some(&CredentialSource::DockerConfig(
+                "/etc/docker-private".into()
+            ))
+        );
+        assert_eq!(config.vendor_auth("bottlerocket"), None);
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        toml::from_str::<ConfigFile>("lookside-cache = \"https://a\"").unwrap_err();
+        toml::from_str::<ConfigFile>("arch = \"riscv64\"").unwrap_err();
+        Config::default()
+            .merge(file("log-level = \"loud\""), Origin::Default)
+            .unwrap_err();
+    }
+}
@@ -1,4 +1,5 @@
 use crate::cmd::{init_logger, Args};
+use crate::config::Config;
 use anyhow::Result;
 use clap::Parser;
 
@@ -6,7 +7,9 @@ mod cargo_make;
 mod cmd;
 mod common;
 mod compatibility;
+mod config;
 mod docker;
+mod makefile;
 mod project;
 mod schema_version;
 /// Test code that should only be compiled when running tests.
@@ -19,6 +22,7 @@ mod tools;
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
-    init_logger(args.log_level);
+    let config = Config::load_for_current_dir().await?;
+    init_logger(config.log_level(args.log_level));
     cmd::run(args).await
 }
@@ -0,0 +1,319 @@
+//! Reads the tasks of the `Makefile.toml` that Twoliter embeds, so that `twoliter make` can list
+//! them and can catch misspelled task names before it resolves the SDK.
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+const MAKEFILE_TOML: &str = include_str!("../embedded/Makefile.toml");
+
+/// Variables that a task reads but that Twoliter, cargo make or the shell provide, so they are not
+/// reported as inputs of the task.
+const PROVIDED_ENV_PREFIXES: &[&str] = &["CARGO_MAKE_", "TLPRIVATE_", "TWOLITER_"];
+const PROVIDED_ENV_VARS: &[&str] = &["PATH", "HOME", "PWD"];
+
+/// The most task names to suggest for a task that does not exist.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// A task in the embedded `Makefile.toml`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MakefileTask {
+    pub(crate) name: String,
+    pub(crate) description: Option<String>,
+    pub(crate) dependencies: Vec<String>,
+    /// The task that this task runs after its own script, or is an alias of.
+    pub(crate) runs: Option<String>,
+    /// The environment variables that the task's scripts read and do not set themselves.
+    pub(crate) env_vars: BTreeSet<String>,
+    private: bool,
+}
+
+impl MakefileTask {
+    /// Tasks that are marked private, or named with a leading underscore, are only meant to be
+    /// run by other tasks.
+    pub(crate) fn is_public(&self) -> bool {
+        !self.private && !self.name.starts_with('_')
+    }
+}
+
+/// The tasks of a `Makefile.toml`.
+#[derive(Debug, Clone)]
+pub(crate) struct Makefile {
+    tasks: BTreeMap<String, MakefileTask>,
+}
+
+impl Makefile {
+    /// Reads the `Makefile.toml` that Twoliter installs with its tools.
+    pub(crate) fn embedded() -> Result<Self> {
+        Self::parse(MAKEFILE_TOML).context("Unable to read the embedded Makefile.toml")
+    }
+
+    fn parse(makefile: &str) -> Result<Self> {
+        let makefile: MakefileView =
+            toml::from_str(makefile).context("Unable to deserialize Makefile.toml")?;
+        let tasks = makefile
+            .tasks
+            .iter()
+            .map(|(name, task)| {
+                // An extending task inherits whatever it does not override from its base task.
+                let base = task
+                    .extend
+                    .as_ref()
+                    .and_then(|base| makefile.tasks.get(base));
+                let inherit = |field: fn(&TaskView) -> bool| match base {
+                    Some(base) if !field(task) => base,
+                    _ => task,
+                };
+                let dependencies = inherit(|task| !task.dependencies.is_empty())
+                    .dependencies
+                    .clone();
+                let script = &inherit(|task| task.script.is_some()).script;
+                let runs = inherit(|task| task.alias.is_some() || task.run_task.is_some());
+                let runs = runs
+                    .alias
+                    .clone()
+                    .or_else(|| runs.run_task.as_ref().and_then(run_task_name));
+
+                let mut scripts = Vec::new();
+                script
+                    .iter()
+                    .for_each(|script| collect_strings(script, &mut scripts));
+                task.env
+                    .values()
+                    .for_each(|value| collect_strings(value, &mut scripts));
+                let mut env_vars = read_vars(&scripts);
+                for assigned in assigned_vars(&scripts).chain(task.env.keys().cloned()) {
+                    env_vars.remove(&assigned);
+                }
+
+                let task = MakefileTask {
+                    name: name.clone(),
+                    description: task.description.clone(),
+                    dependencies,
+                    runs,
+                    env_vars,
+                    private: task.private,
+                };
+                (name.clone(), task)
+            })
+            .collect();
+        Ok(Self { tasks })
+    }
+
+    /// Returns the task named `name`, if there is one.
+    pub(crate) fn task(&self, name: &str) -> Option<&MakefileTask> {
+        self.tasks.get(name)
+    }
+
+    /// Returns the public tasks, sorted by name.
+    pub(crate) fn public_tasks(&self) -> impl Iterator<Item = &MakefileTask> {
+        self.tasks.values().filter(|task| task.is_public())
+    }
+
+    /// Returns the names of the public tasks that `name` is most likely a misspelling of, closest
+    /// first.
+    pub(crate) fn suggestions(&self, name: &str) -> Vec<&str> {
+        let max_distance = (name.chars().count() / 3).max(2);
+        let mut candidates: Vec<(usize, &str)> = self
+            .public_tasks()
+            .filter_map(|task| {
+                let distance = edit_distance(name, &task.name);
+                let contains = name.len() >= 3 && task.name.contains(name);
+                (distance <= max_distance || contains).then_some((distance, task.name.as_str()))
+            })
+            .collect();
+        candidates.sort();
+        candidates
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(_, name)| name)
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MakefileView {
+    #[serde(default)]
+    tasks: BTreeMap<String, TaskView>,
+}
+
+/// The parts of a cargo make task that Twoliter reports on.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct TaskView {
+    description: Option<String>,
+    private: bool,
+    dependencies: Vec<String>,
+    script: Option<toml::Value>,
+    env: BTreeMap<String, toml::Value>,
+    extend: Option<String>,
+    alias: Option<String>,
+    run_task: Option<toml::Value>,
+}
+
+/// `run_task` is either the name of a task or a table with the name of a task.
+fn run_task_name(run_task: &toml::Value) -> Option<String> {
+    match run_task {
+        toml::Value::String(name) => Some(name.clone()),
+        toml::Value::Table(table) => table.get("name").and_then(run_task_name),
+        _ => None,
+    }
+}
+
+/// Collects the strings in a script or environment value, which may be a string, an array of
+/// strings, or a table such as `{ script = [...] }`.
+fn collect_strings(value: &toml::Value, strings: &mut Vec<String>) {
+    match value {
+        toml::Value::String(s) => strings.push(s.clone()),
+        toml::Value::Array(values) => values.iter().for_each(|v| collect_strings(v, strings)),
+        toml::Value::Table(table) => table.values().for_each(|v| collect_strings(v, strings)),
+        _ => {}
+    }
+}
+
+/// Returns the upper case variables that are expanded in the scripts, as `$NAME` or `${NAME...}`.
+fn read_vars(scripts: &[String]) -> BTreeSet<String> {
+    let mut vars = BTreeSet::new();
+    for script in scripts {
+        for (_, rest) in script
+            .match_indices('$')
+            .map(|(i, _)| script.split_at(i + 1))
+        {
+            let name = identifier(rest.strip_prefix('{').unwrap_or(rest));
+            if is_env_var_name(name) && !is_provided(name) {
+                vars.insert(name.to_string());
+            }
+        }
+    }
+    vars
+}
+
+/// Returns the variables that the scripts assign, as `NAME=...`, `export NAME=...` or
+/// `for NAME in ...`, at the start of a line.
+fn assigned_vars(scripts: &[String]) -> impl Iterator<Item = String> + '_ {
+    scripts
+        .iter()
+        .flat_map(|script| script.lines())
+        .filter_map(|line| {
+            let line = line.trim_start();
+            let line = ["export ", "local ", "readonly "]
+                .iter()
+                .find_map(|keyword| line.strip_prefix(keyword))
+                .unwrap_or(line);
+            if let Some(rest) = line.strip_prefix("for ") {
+                let name = identifier(rest);
+                return (!name.is_empty()).then(|| name.to_string());
+            }
+            let name = identifier(line);
+            let rest = &line[name.len()..];
+            (!name.is_empty() && (rest.starts_with('=') || rest.starts_with("+=")))
+                .then(|| name.to_string())
+        })
+}
+
+/// Returns the shell identifier at the start of `s`.
+fn identifier(s: &str) -> &str {
+    let end = s
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(s.len());
+    &s[..end]
+}
+
+/// Environment variables are named in upper case by convention; anything else is a shell local.
+fn is_env_var_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+fn is_provided(name: &str) -> bool {
+    PROVIDED_ENV_VARS.contains(&name)
+        || PROVIDED_ENV_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+}
+
+/// The number of single character insertions, deletions and substitutions needed to turn `a` into
+/// `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_embedded_tasks() {
+        let makefile = Makefile::embedded().unwrap();
+        for task in makefile.public_tasks() {
+            assert!(
+                task.description.is_some(),
+                "task '{}' has no description",
+                task.name
+            );
+        }
+        assert!(makefile.task("_upload-ova-base").is_some());
+        assert!(makefile
+            .public_tasks()
+            .all(|task| task.name != "_upload-ova-base"));
+
+        let build_package = makefile.task("build-package").unwrap();
+        assert_eq!(
+            build_package.dependencies,
+            [
+                "check-cargo-version",
+                "fetch",
+                "publish-setup",
+                "validate-kits"
+            ]
+        );
+        assert!(build_package.env_vars.contains("PACKAGE"));
+        assert!(build_package.env_vars.contains("BUILDSYS_ARCH"));
+        assert!(!build_package.env_vars.contains("WORKSPACE_MANIFEST"));
+        assert!(!build_package.env_vars.contains("TWOLITER_TOOLS_DIR"));
+
+        // `upload-ova` extends `_upload-ova-base` and `vmware-template` sets one of its variables.
+        let upload_ova = makefile.task("upload-ova").unwrap();
+        let vmware_template = makefile.task("vmware-template").unwrap();
+        assert!(upload_ova.env_vars.contains("MARK_OVA_AS_TEMPLATE"));
+        assert!(!vmware_template.env_vars.contains("MARK_OVA_AS_TEMPLATE"));
+        assert_eq!(
+            upload_ova.env_vars.len(),
+            vmware_template.env_vars.len() + 1
+        );
+
+        assert_eq!(
+            makefile.task("default").unwrap().runs.as_deref(),
+            Some("build")
+        );
+    }
+
+    #[test]
+    fn test_suggestions() {
+        let makefile = Makefile::embedded().unwrap();
+        assert_eq!(makefile.suggestions("buld")[0], "build");
+        assert_eq!(makefile.suggestions("build-pakage")[0], "build-package");
+        assert!(makefile.suggestions("ova").contains(&"fetch-ova"));
+        assert_eq!(makefile.suggestions("upload-ova-base")[0], "upload-ova");
+        assert!(makefile.suggestions("xyzzy-plugh").is_empty());
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("build", "build"), 0);
+        assert_eq!(edit_distance("buld", "build"), 1);
+        assert_eq!(edit_distance("", "ami"), 3);
+        assert_eq!(edit_distance("ssm", "ami"), 3);
+    }
+}
@@ -0,0 +1,393 @@
+//! Kits that are built from another Twoliter project on the local filesystem rather than pulled
+//! from a vendor's registry, e.g. `{ name = "my-kit", path = "../my-kit-project" }`.
+//!
+//! This lets a kit and the projects that consume it be developed side by side without publishing
+//! the kit after every change. The kit is built in its own project and then placed in the consuming
+//! project's external kits directory, laid out the same way as a kit extracted from an image.
+use super::lock::{Lock, LockMode, TWOLITER_LOCK};
+use super::{Image, KitDependency, KitRequirement, UnvalidatedProject, ValidIdentifier};
+use crate::common::exec_log;
+use crate::common::fs::{self, copy, create_dir_all, read, remove_dir_all};
+use anyhow::{bail, ensure, Context, Result};
+use base64::Engine;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tracing::{debug, info, instrument};
+
+/// The vendor name under which kits built from a local path are placed in the external kits
+/// directory. Projects with path dependencies may not define a vendor with this name.
+pub(crate) const LOCAL_KIT_VENDOR: &str = "local";
+
+/// A `[[kit]]` entry in Twoliter.toml which refers to a kit in another project by its path.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct PathKit {
+    pub name: ValidIdentifier,
+    /// The directory of the project that builds the kit, relative to the consuming project
+    pub path: PathBuf,
+}
+
+/// A kit from another project on the local filesystem, along with the dependencies declared by
+/// that project.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct LocalKit {
+    /// The dependency as written in Twoliter.toml
+    dependency: PathKit,
+    /// The canonical path of the directory containing the kit's project
+    project_dir: PathBuf,
+    /// The release version of the kit's project, which is given to the kit when it is built
+    version: Version,
+    /// The SDK declared by the kit's project, if any
+    sdk: Option<Image>,
+    /// The registry kits that the kit's project depends on
+    kit: Vec<KitRequirement>,
+}
+
+impl LocalKit {
+    /// Loads the project referred to by `dependency`, relative to the consuming project's directory.
+    #[instrument(level = "trace")]
+    pub(super) async fn load(project_dir: &Path, dependency: PathKit) -> Result<Self> {
+        let kit_project_dir = fs::canonicalize(project_dir.join(&dependency.path))
+            .await
+            .context(format!(
+                "unable to find the project for kit '{}' at '{}'",
+                dependency.name,
+                dependency.path.display()
+            ))?;
+        let project_file = kit_project_dir.join("Twoliter.toml");
+        let data = fs::read_to_string(&project_file).await.context(format!(
+            "unable to read project file for kit '{}'",
+            dependency.name
+        ))?;
+        // The kit's project is not fully validated since it is only built through its own
+        // invocation of twoliter, which will validate it then.
+        let unvalidated: UnvalidatedProject = toml::from_str(&data).context(format!(
+            "unable to deserialize project file '{}'",
+            project_file.display()
+        ))?;
+        ensure!(
+            kit_project_dir
+                .join("kits")
+                .join(dependency.name.as_ref())
+                .is_dir(),
+            "the project at '{}' does not contain a kit named '{}'",
+            kit_project_dir.display(),
+            dependency.name
+        );
+
+        let mut kit = Vec::new();
+        for kit_dependency in unvalidated.kit.unwrap_or_default() {
+            match kit_dependency {
+                KitDependency::Registry(requirement) => kit.push(requirement),
+                KitDependency::Path(path_kit) => bail!(
+                    "kit '{}' depends on kit '{}' by path, but kits from a path may only depend on \
+                    kits from a registry",
+                    dependency.name,
+                    path_kit.name
+                ),
+            }
+        }
+        let kit = Self::follow_lock(&kit_project_dir, &dependency.name, kit).await?;
+        let version = Version::parse(&unvalidated.release_version).context(format!(
+            "the release-version of the project at '{}' is not a valid semver version",
+            kit_project_dir.display()
+        ))?;
+
+        Ok(Self {
+            dependency,
+            project_dir: kit_project_dir,
+            version,
+            sdk: unvalidated.sdk,
+            kit,
+        })
+    }
+
+    /// Pins the kit project's registry dependencies to the versions in its own Twoliter.lock, since
+    /// those are the versions that the kit is built against. Without a lock the requirements are
+    /// left as they are, and the kit project must be updated before the kit can be built.
+    async fn follow_lock(
+        kit_project_dir: &Path,
+        name: &ValidIdentifier,
+        requirements: Vec<KitRequirement>,
+    ) -> Result<Vec<KitRequirement>> {
+        let lock_file = kit_project_dir.join(TWOLITER_LOCK);
+        if !lock_file.exists() {
+            debug!(
+                "The project for kit '{name}' at '{}' has no lock file",
+                kit_project_dir.display()
+            );
+            return Ok(requirements);
+        }
+        let data = fs::read_to_string(&lock_file).await?;
+        let lock: Lock = toml::from_str(&data).context(format!(
+            "unable to deserialize lock file '{}'",
+            lock_file.display()
+        ))?;
+
+        requirements
+            .into_iter()
+            .map(|requirement| {
+                let locked = lock
+                    .kit
+                    .iter()
+                    .find(|locked| {
+                        locked.name == requirement.name && locked.vendor == requirement.vendor
+                    })
+                    .filter(|locked| requirement.version.matches(&locked.version))
+                    .with_context(|| {
+                        format!(
+                            "'{}' does not lock a version of kit '{}' that satisfies {}, run \
+                            `twoliter update` in the project for kit '{name}' first",
+                            lock_file.display(),
+                            requirement.name,
+                            requirement.version
+                        )
+                    })?;
+                Ok(KitRequirement::exactly(&Image {
+                    name: locked.name.clone(),
+                    version: locked.version.clone(),
+                    vendor: locked.vendor.clone(),
+                }))
+            })
+            .collect()
+    }
+
+    pub(crate) fn name(&self) -> &ValidIdentifier {
+        &self.dependency.name
+    }
+
+    pub(crate) fn version(&self) -> &Version {
+        &self.version
+    }
+
+    /// The path to the kit's project as written in Twoliter.toml.
+    pub(crate) fn path(&self) -> &Path {
+        &self.dependency.path
+    }
+
+    pub(crate) fn sdk(&self) -> Option<&Image> {
+        self.sdk.as_ref()
+    }
+
+    pub(crate) fn kit_deps(&self) -> &[KitRequirement] {
+        self.kit.as_slice()
+    }
+
+    /// The image under which the kit is known to the consuming project.
+    pub(crate) fn image(&self) -> Image {
+        Image {
+            name: self.name().clone(),
+            version: self.version.clone(),
+            vendor: ValidIdentifier(LOCAL_KIT_VENDOR.to_string()),
+        }
+    }
+
+    /// Describes where the kit was built from, in the place of an image URI.
+    pub(crate) fn source(&self) -> String {
+        format!("path+{}", self.project_dir.display())
+    }
+
+    /// Fetches the kit project's own dependencies and builds the kit for `arch`, using the same
+    /// twoliter executable as the current process.
+    #[instrument(level = "trace", skip(self), fields(kit = %self.name()))]
+    pub(crate) async fn build(&self, arch: &str, mode: LockMode) -> Result<()> {
+        let twoliter = std::env::current_exe().context("unable to find the twoliter executable")?;
+        let project_path = self.project_dir.join("Twoliter.toml");
+        let frozen = (mode == LockMode::Frozen).then_some("--frozen");
+
+        info!(
+            "Building kit '{}' from '{}'",
+            self.name(),
+            self.project_dir.display()
+        );
+        exec_log(
+            Command::new(&twoliter)
+                .arg("fetch")
+                .arg("--project-path")
+                .arg(&project_path)
+                .args(["--arch", arch])
+                .args(frozen),
+        )
+        .await
+        .context(format!(
+            "failed to fetch the dependencies of kit '{}'",
+            self.name()
+        ))?;
+        exec_log(
+            Command::new(&twoliter)
+                .args(["build", "kit", self.name().as_ref()])
+                .arg("--project-path")
+                .arg(&project_path)
+                .args(["--arch", arch])
+                .args(frozen),
+        )
+        .await
+        .context(format!("failed to build kit '{}'", self.name()))
+    }
+
+    /// Copies the kit that was built for `arch` into `external_kits_dir`, returning a digest of its
+    /// repository metadata to identify the build.
+    #[instrument(level = "trace", skip(self), fields(kit = %self.name()))]
+    pub(crate) async fn install(&self, external_kits_dir: &Path, arch: &str) -> Result<String> {
+        let built_dir = self
+            .project_dir
+            .join("build")
+            .join("kits")
+            .join(self.name().as_ref())
+            .join(arch);
+        ensure!(
+            built_dir.is_dir(),
+            "kit '{}' has not been built for '{arch}' at '{}'",
+            self.name(),
+            built_dir.display()
+        );
+        let target_dir = external_kits_dir
+            .join(LOCAL_KIT_VENDOR)
+            .join(self.name().as_ref())
+            .join(arch);
+        if target_dir.exists() {
+            remove_dir_all(&target_dir).await?;
+        }
+        debug!(
+            "Copying kit from '{}' to '{}'",
+            built_dir.display(),
+            target_dir.display()
+        );
+        copy_dir(&built_dir, &target_dir).await?;
+
+        let repomd = read(built_dir.join("repodata").join("repomd.xml"))
+            .await
+            .context(format!(
+                "kit '{}' does not contain repository metadata",
+                self.name()
+            ))?;
+        let digest = sha2::Sha256::digest(repomd);
+        Ok(base64::engine::general_purpose::STANDARD.encode(digest.as_slice()))
+    }
+}
+
+/// Recursively copies the contents of the directory `from` into `to`.
+async fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    let mut remaining = vec![(from.to_path_buf(), to.to_path_buf())];
+    while let Some((from, to)) = remaining.pop() {
+        create_dir_all(&to).await?;
+        let mut entries = tokio::fs::read_dir(&from)
+            .await
+            .context(format!("unable to read directory '{}'", from.display()))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .context(format!("unable to read directory '{}'", from.display()))?
+        {
+            let target = to.join(entry.file_name());
+            let file_type = entry.file_type().await.context(format!(
+                "unable to read file type of '{}'",
+                entry.path().display()
+            ))?;
+            if file_type.is_dir() {
+                remaining.push((entry.path(), target));
+            } else {
+                copy(entry.path(), target).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::fs::write;
+    use tempfile::TempDir;
+
+    const KIT_PROJECT: &str = r#"
+schema-version = 1
+release-version = "2.1.0"
+
+[vendor.my-vendor]
+registry = "a.com/b"
+
+[[kit]]
+name = "my-core-kit"
+version = "^1.4"
+vendor = "my-vendor"
+"#;
+
+    #[tokio::test]
+    async fn test_load_local_kit() {
+        let tempdir = TempDir::new().unwrap();
+        let kit_project_dir = tempdir.path().join("my-kit-project");
+        create_dir_all(kit_project_dir.join("kits").join("my-kit"))
+            .await
+            .unwrap();
+        write(kit_project_dir.join("Twoliter.toml"), KIT_PROJECT)
+            .await
+            .unwrap();
+
+        let consumer_dir = tempdir.path().join("consumer");
+        create_dir_all(&consumer_dir).await.unwrap();
+        let dependency = PathKit {
+            name: ValidIdentifier("my-kit".into()),
+            path: "../my-kit-project".into(),
+        };
+        let kit = LocalKit::load(&consumer_dir, dependency.clone())
+            .await
+            .unwrap();
+        assert_eq!(kit.image().to_string(), "my-kit-2.1.0@local");
+        assert_eq!(kit.path(), Path::new("../my-kit-project"));
+        assert_eq!(kit.kit_deps().len(), 1);
+        assert_eq!(kit.kit_deps()[0].name.to_string(), "my-core-kit");
+        assert_eq!(kit.kit_deps()[0].exact_version(), None);
+        assert!(kit.sdk().is_none());
+
+        // Once the kit's project is locked, its dependencies follow the lock.
+        let lock = |version: &str| {
+            format!(
+                r#"
+schema-version = 2
+
+[sdk]
+name = "my-bottlerocket-sdk"
+version = "1.2.3"
+vendor = "my-vendor"
+source = "a.com/b/my-bottlerocket-sdk:v1.2.3"
+digest = "abc"
+
+[[kit]]
+name = "my-core-kit"
+version = "{version}"
+vendor = "my-vendor"
+source = "a.com/b/my-core-kit:v{version}"
+digest = "def"
+"#
+            )
+        };
+        let lock_file = kit_project_dir.join("Twoliter.lock");
+        write(&lock_file, lock("1.5.2")).await.unwrap();
+        let kit = LocalKit::load(&consumer_dir, dependency.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            kit.kit_deps()[0].exact_version(),
+            Some(Version::new(1, 5, 2))
+        );
+
+        // A lock that doesn't satisfy the kit's project is out of date.
+        write(&lock_file, lock("2.0.0")).await.unwrap();
+        let err = LocalKit::load(&consumer_dir, dependency.clone())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("twoliter update"), "{err}");
+        fs::remove_file(&lock_file).await.unwrap();
+
+        // The project doesn't contain the named kit
+        let missing = PathKit {
+            name: ValidIdentifier("other-kit".into()),
+            ..dependency
+        };
+        LocalKit::load(&consumer_dir, missing).await.unwrap_err();
+    }
+}
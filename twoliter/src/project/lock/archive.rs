@@ -1,7 +1,10 @@
+use super::layout::{blob_path, OCILayout};
+use super::store::OciStore;
 use super::views::{IndexView, ManifestLayoutView};
 use crate::common::fs::{create_dir_all, read, read_to_string, remove_dir_all, write};
-use anyhow::{Context, Result};
+use anyhow::{ensure, Context, Result};
 use oci_cli_wrapper::ImageTool;
+use sha2::Digest;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use tar::Archive as TarArchive;
@@ -28,6 +31,11 @@ impl OCIArchive {
         })
     }
 
+    /// The digest of the image manifest held in the archive.
+    pub fn digest(&self) -> &str {
+        &self.digest
+    }
+
     pub fn archive_path(&self) -> PathBuf {
         self.cache_dir.join(self.digest.replace(':', "-"))
     }
@@ -42,10 +50,20 @@ impl OCIArchive {
         debug!("Pulling image '{}'", digest_uri);
         let oci_archive_path = self.archive_path();
         if !oci_archive_path.exists() {
-            create_dir_all(&oci_archive_path).await?;
-            image_tool
-                .pull_oci_image(oci_archive_path.as_path(), digest_uri.as_str())
-                .await?;
+            // Images are shared between projects through the user's image store when there is one.
+            match OciStore::for_user() {
+                Some(store) => {
+                    store
+                        .pull(image_tool, &digest_uri, &self.digest, &oci_archive_path)
+                        .await?
+                }
+                None => {
+                    create_dir_all(&oci_archive_path).await?;
+                    image_tool
+                        .pull_oci_image(oci_archive_path.as_path(), digest_uri.as_str())
+                        .await?;
+                }
+            }
         } else {
             debug!(
                 "Image from '{}' already present -- no need to pull.",
@@ -55,6 +73,97 @@ impl OCIArchive {
         Ok(())
     }
 
+    /// Copies the image from a local OCI layout into the cache, unless it is already present.
+    #[instrument(level = "trace", skip_all, fields(registry = %self.registry, repository = %self.repository, digest = %self.digest))]
+    pub async fn copy_from_layout(&self, layout: &OCILayout) -> Result<()> {
+        let oci_archive_path = self.archive_path();
+        if oci_archive_path.exists() {
+            debug!(
+                "Image from '{}' already present -- no need to copy.",
+                self.uri()
+            );
+            return Ok(());
+        }
+        if let Err(e) = layout.copy_image(&self.digest, &oci_archive_path).await {
+            // Don't leave a partial copy behind to be mistaken for the complete image.
+            let _ = remove_dir_all(&oci_archive_path).await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Checks that the cached archive holds the image manifest with the expected digest, and that
+    /// the config and every layer it references are intact.
+    #[instrument(level = "trace", skip_all, fields(registry = %self.registry, repository = %self.repository, digest = %self.digest))]
+    pub async fn verify(&self) -> Result<()> {
+        let digest_uri = self.uri();
+        let index_bytes = read(self.archive_path().join("index.json")).await?;
+        let index: IndexView = serde_json::from_slice(index_bytes.as_slice())
+            .context("failed to deserialize oci image index")?;
+        let manifest_digest = &index.manifests.first().context("empty oci image")?.digest;
+        ensure!(
+            manifest_digest == &self.digest,
+            "cached image for '{}' refers to manifest '{}'",
+            digest_uri,
+            manifest_digest
+        );
+
+        let manifest_bytes = read(self.blob_path(&self.digest)?)
+            .await
+            .context("failed to read manifest blob")?;
+        let calculated_digest = format!(
+            "sha256:{:x}",
+            sha2::Sha256::digest(manifest_bytes.as_slice())
+        );
+        ensure!(
+            calculated_digest == self.digest,
+            "cached image manifest for '{}' is corrupt, found digest '{}'",
+            digest_uri,
+            calculated_digest
+        );
+
+        let manifest_layout: ManifestLayoutView = serde_json::from_slice(manifest_bytes.as_slice())
+            .context("failed to deserialize oci manifest")?;
+        for blob in std::iter::once(&manifest_layout.config).chain(&manifest_layout.layers) {
+            let expected_digest = blob.digest.to_string();
+            let calculated_digest = self.blob_digest(&expected_digest)?;
+            ensure!(
+                calculated_digest == expected_digest,
+                "cached blob '{}' of image '{}' is corrupt, found digest '{}'",
+                expected_digest,
+                digest_uri,
+                calculated_digest
+            );
+        }
+        Ok(())
+    }
+
+    fn blob_path(&self, digest: &str) -> Result<PathBuf> {
+        blob_path(&self.archive_path(), digest)
+    }
+
+    /// Calculates the digest of a blob in the archive without reading it all into memory, since
+    /// layers can be large.
+    fn blob_digest(&self, digest: &str) -> Result<String> {
+        let path = self.blob_path(digest)?;
+        let mut blob =
+            File::open(&path).context(format!("failed to open blob '{}'", path.display()))?;
+        let mut hasher = sha2::Sha256::new();
+        std::io::copy(&mut blob, &mut hasher)
+            .context(format!("failed to read blob '{}'", path.display()))?;
+        Ok(format!("sha256:{:x}", hasher.finalize()))
+    }
+
+    /// Reads the digest of the image config from the manifest in the archive.
+    pub async fn config_digest(&self) -> Result<String> {
+        let manifest_bytes = read(self.blob_path(&self.digest)?)
+            .await
+            .context("failed to read manifest blob")?;
+        let manifest_layout: ManifestLayoutView = serde_json::from_slice(manifest_bytes.as_slice())
+            .context("failed to deserialize oci manifest")?;
+        Ok(manifest_layout.config.digest.to_string())
+    }
+
     #[instrument(
         level = "trace",
         skip_all,
@@ -91,13 +200,8 @@ impl OCIArchive {
 
         // Read the manifest so we can get the layer digests
         trace!(from = %digest_uri, "Extracting layer digests from image manifest");
-        let digest = index
-            .manifests
-            .first()
-            .context("empty oci image")?
-            .digest
-            .replace(':', "/");
-        let manifest_bytes = read(self.archive_path().join(format!("blobs/{digest}")))
+        let digest = &index.manifests.first().context("empty oci image")?.digest;
+        let manifest_bytes = read(self.blob_path(digest)?)
             .await
             .context("failed to read manifest blob")?;
         let manifest_layout: ManifestLayoutView = serde_json::from_slice(manifest_bytes.as_slice())
@@ -106,8 +210,7 @@ impl OCIArchive {
         // Extract each layer into the target directory
         trace!(from = %digest_uri, "Extracting image layers");
         for layer in manifest_layout.layers {
-            let digest = layer.digest.to_string().replace(':', "/");
-            let layer_blob = File::open(self.archive_path().join(format!("blobs/{digest}")))
+            let layer_blob = File::open(self.blob_path(&layer.digest.to_string())?)
                 .context("failed to read layer of oci image")?;
             let mut layer_archive = TarArchive::new(layer_blob);
             layer_archive
@@ -124,3 +227,57 @@ impl OCIArchive {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn sha256(data: &[u8]) -> String {
+        format!("sha256:{:x}", sha2::Sha256::digest(data))
+    }
+
+    /// Writes an archive holding a config and one layer to the cache, returning the archive.
+    async fn write_archive(cache_dir: &Path) -> OCIArchive {
+        let config = br#"{"config":{}}"#.to_vec();
+        let layer = b"layer contents".to_vec();
+        let manifest = serde_json::json!({
+            "config": {"digest": sha256(&config)},
+            "layers": [{"digest": sha256(&layer)}],
+        })
+        .to_string()
+        .into_bytes();
+        let archive = OCIArchive::new("a.com", "b/kit", &sha256(&manifest), cache_dir).unwrap();
+        for blob in [&config, &layer, &manifest] {
+            let path = archive.blob_path(&sha256(blob)).unwrap();
+            create_dir_all(path.parent().unwrap()).await.unwrap();
+            write(path, blob).await.unwrap();
+        }
+        let index = serde_json::json!({"manifests": [{"digest": archive.digest()}]});
+        write(archive.archive_path().join("index.json"), index.to_string())
+            .await
+            .unwrap();
+        archive
+    }
+
+    #[tokio::test]
+    async fn test_verify_checks_every_blob() {
+        let dir = TempDir::new().unwrap();
+        let archive = write_archive(dir.path()).await;
+        archive.verify().await.unwrap();
+
+        let manifest_bytes = read(archive.blob_path(archive.digest()).unwrap())
+            .await
+            .unwrap();
+        let manifest: ManifestLayoutView = serde_json::from_slice(&manifest_bytes).unwrap();
+        let layer = archive
+            .blob_path(&manifest.layers[0].digest.to_string())
+            .unwrap();
+        write(&layer, "tampered contents").await.unwrap();
+        let err = archive.verify().await.unwrap_err().to_string();
+        assert!(err.contains("is corrupt"), "{err}");
+
+        crate::common::fs::remove_file(&layer).await.unwrap();
+        archive.verify().await.unwrap_err();
+    }
+}
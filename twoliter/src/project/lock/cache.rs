@@ -0,0 +1,430 @@
+//! Keeps track of the OCI archives that are cached when kits are fetched.
+//!
+//! Each archive is stored in a directory named for its manifest digest. The cache keeps an index
+//! recording the size of each archive, when it was last used, and the lockfiles that used it, so
+//! that stale archives can be found and removed without reading the archives themselves.
+use super::archive::OCIArchive;
+use super::image::LockedImage;
+use super::Lock;
+use crate::common::fs::{metadata, read_to_string, remove_dir_all, rename, write};
+use anyhow::{Context, Result};
+use async_walkdir::WalkDir;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, instrument};
+
+/// The name of the directory in which OCI archives are cached, within the external kits directory.
+pub(crate) const CACHE_DIRECTORY: &str = "cache";
+
+const INDEX_FILE: &str = "index.json";
+
+/// The contents of the cache index, keyed by the name of each archive's directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct CacheIndex {
+    entries: BTreeMap<String, IndexEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct IndexEntry {
+    /// The image the archive was pulled from, by digest
+    uri: Option<String>,
+    /// The digest in Twoliter.lock of the kit that the archive belongs to
+    image_digest: Option<String>,
+    /// The size of the archive in bytes
+    size: u64,
+    last_used: DateTime<Utc>,
+    /// The lockfiles of the projects that have used the archive
+    #[serde(default)]
+    lockfiles: BTreeSet<PathBuf>,
+}
+
+/// An OCI archive in the cache.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct CacheEntry {
+    /// The name of the archive's directory, which is derived from its manifest digest
+    pub name: String,
+    pub path: PathBuf,
+    /// The image the archive was pulled from, if known
+    pub uri: Option<String>,
+    /// The size of the archive in bytes. Blobs that are linked from the user's image store are
+    /// counted in full, although removing the archive only frees them once the store is garbage
+    /// collected and no other project links them.
+    pub size: u64,
+    pub last_used: DateTime<Utc>,
+    /// The lockfiles that used the archive and still refer to it
+    pub referenced_by: Vec<PathBuf>,
+    #[serde(skip)]
+    image_digest: Option<String>,
+}
+
+impl CacheEntry {
+    /// Returns true if the archive belongs to one of the kits in `lock`.
+    fn is_referenced_by(&self, lock: &Lock) -> bool {
+        let manifest_digest = self.name.replacen('-', ":", 1);
+        lock.kit.iter().any(|kit| {
+            self.image_digest.as_ref() == Some(&kit.digest)
+                || kit
+                    .platform
+                    .iter()
+                    .any(|platform| platform.manifest_digest == manifest_digest)
+        })
+    }
+}
+
+/// Which archives to remove from the cache.
+#[derive(Debug, Default)]
+pub(crate) struct PruneFilter {
+    /// Remove archives that are not used by any of these lockfiles
+    pub keep_lockfiles: Option<Vec<PathBuf>>,
+    /// Remove archives that have not been used since this time
+    pub unused_since: Option<DateTime<Utc>>,
+}
+
+/// The cache of kit OCI archives in a project's external kits directory.
+#[derive(Debug, Clone)]
+pub(crate) struct KitCache {
+    dir: PathBuf,
+}
+
+impl KitCache {
+    pub(crate) fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Records that the project with `lockfile` used `archive` for the `locked` kit.
+    #[instrument(level = "trace", skip_all, fields(archive = %archive.archive_path().display()))]
+    pub(crate) async fn record_use(
+        &self,
+        archive: &OCIArchive,
+        locked: &LockedImage,
+        lockfile: &Path,
+    ) -> Result<()> {
+        let path = archive.archive_path();
+        let name = entry_name(&path)?;
+        let size = dir_size(&path).await?;
+        let mut index = self.read_index().await?;
+        let entry = index
+            .entries
+            .entry(name.clone())
+            .or_insert_with(|| IndexEntry {
+                uri: None,
+                image_digest: None,
+                size,
+                last_used: Utc::now(),
+                lockfiles: BTreeSet::new(),
+            });
+        entry.uri = Some(archive.uri());
+        entry.image_digest = Some(locked.digest.clone());
+        entry.size = size;
+        entry.last_used = Utc::now();
+        entry.lockfiles.insert(lockfile.to_path_buf());
+        debug!("Recorded use of cached archive '{name}'");
+        self.write_index(&index).await
+    }
+
+    /// Lists the archives in the cache, ordered by name. Archives that are missing from the index,
+    /// such as those cached by older versions of Twoliter, are added to it.
+    pub(crate) async fn entries(&self) -> Result<Vec<CacheEntry>> {
+        let mut index = self.read_index().await?;
+        let mut entries = Vec::new();
+        let mut changed = false;
+        for path in self.archive_dirs().await? {
+            let name = entry_name(&path)?;
+            let indexed = match index.entries.get(&name) {
+                Some(indexed) => indexed.clone(),
+                None => {
+                    debug!("Adding cached archive '{name}' to the index");
+                    let modified = metadata(&path).await?.modified().context(format!(
+                        "failed to read modification time of '{}'",
+                        path.display()
+                    ))?;
+                    let indexed = IndexEntry {
+                        uri: None,
+                        image_digest: None,
+                        size: dir_size(&path).await?,
+                        last_used: modified.into(),
+                        lockfiles: BTreeSet::new(),
+                    };
+                    index.entries.insert(name.clone(), indexed.clone());
+                    changed = true;
+                    indexed
+                }
+            };
+            let mut entry = CacheEntry {
+                name,
+                path,
+                uri: indexed.uri,
+                size: indexed.size,
+                last_used: indexed.last_used,
+                referenced_by: Vec::new(),
+                image_digest: indexed.image_digest,
+            };
+            for lockfile in indexed.lockfiles {
+                if read_lock(&lockfile)
+                    .await?
+                    .is_some_and(|lock| entry.is_referenced_by(&lock))
+                {
+                    entry.referenced_by.push(lockfile);
+                }
+            }
+            entries.push(entry);
+        }
+
+        // Forget archives that have been removed by other means.
+        let before = index.entries.len();
+        index
+            .entries
+            .retain(|name, _| entries.iter().any(|entry| &entry.name == name));
+        if changed || index.entries.len() != before {
+            self.write_index(&index).await?;
+        }
+        Ok(entries)
+    }
+
+    /// Removes the archives selected by `filter` and returns them. An archive is removed if it is
+    /// unreferenced by the lockfiles to keep, or if it has not been used recently. When `dry_run` is
+    /// set, the archives are returned without being removed.
+    pub(crate) async fn prune(
+        &self,
+        filter: &PruneFilter,
+        dry_run: bool,
+    ) -> Result<Vec<CacheEntry>> {
+        let mut keep_locks = Vec::new();
+        for lockfile in filter.keep_lockfiles.iter().flatten() {
+            keep_locks.extend(read_lock(lockfile).await?);
+        }
+
+        let mut pruned = Vec::new();
+        for entry in self.entries().await? {
+            let unreferenced = filter.keep_lockfiles.is_some()
+                && !keep_locks.iter().any(|lock| entry.is_referenced_by(lock));
+            let unused = filter
+                .unused_since
+                .is_some_and(|since| entry.last_used < since);
+            if unreferenced || unused {
+                pruned.push(entry);
+            }
+        }
+        if dry_run || pruned.is_empty() {
+            return Ok(pruned);
+        }
+
+        let mut index = self.read_index().await?;
+        for entry in pruned.iter() {
+            info!("Removing cached archive '{}'", entry.path.display());
+            remove_dir_all(&entry.path).await?;
+            index.entries.remove(&entry.name);
+        }
+        self.write_index(&index).await?;
+        Ok(pruned)
+    }
+
+    async fn archive_dirs(&self) -> Result<Vec<PathBuf>> {
+        if !self.dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut dirs = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir)
+            .await
+            .context(format!("failed to read directory '{}'", self.dir.display()))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .context(format!("failed to read directory '{}'", self.dir.display()))?
+        {
+            if entry.path().is_dir() {
+                dirs.push(entry.path());
+            }
+        }
+        dirs.sort();
+        Ok(dirs)
+    }
+
+    async fn read_index(&self) -> Result<CacheIndex> {
+        let path = self.dir.join(INDEX_FILE);
+        if !path.exists() {
+            return Ok(CacheIndex::default());
+        }
+        let data = read_to_string(&path).await?;
+        match serde_json::from_str(&data) {
+            Ok(index) => Ok(index),
+            Err(e) => {
+                // The index only records what can be found again, so a damaged one is rebuilt.
+                debug!("Ignoring unreadable cache index '{}': {e}", path.display());
+                Ok(CacheIndex::default())
+            }
+        }
+    }
+
+    async fn write_index(&self, index: &CacheIndex) -> Result<()> {
+        let data =
+            serde_json::to_string_pretty(index).context("failed to serialize cache index")?;
+        // Write to a temporary file first so that the index is never left partially written.
+        let path = self.dir.join(INDEX_FILE);
+        let temp_path = self.dir.join(format!("{INDEX_FILE}.tmp"));
+        write(&temp_path, data).await?;
+        rename(&temp_path, &path).await
+    }
+}
+
+fn entry_name(path: &Path) -> Result<String> {
+    Ok(path
+        .file_name()
+        .context(format!("cached archive '{}' has no name", path.display()))?
+        .to_string_lossy()
+        .to_string())
+}
+
+/// Reads the lockfile at `path`, or returns `None` if it no longer exists.
+async fn read_lock(path: &Path) -> Result<Option<Lock>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let data = read_to_string(path).await?;
+    toml::from_str(&data).map(Some).context(format!(
+        "failed to deserialize lockfile '{}'",
+        path.display()
+    ))
+}
+
+/// Adds up the size of the files in `dir`.
+async fn dir_size(dir: &Path) -> Result<u64> {
+    let mut size = 0;
+    let mut entries = WalkDir::new(dir);
+    while let Some(entry) = entries.next().await {
+        let entry = entry.context(format!("failed to read directory '{}'", dir.display()))?;
+        let metadata = metadata(entry.path()).await?;
+        if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::project::lock::image::LockedPlatform;
+    use crate::project::lock::LockSchemaVersion;
+    use crate::project::ValidIdentifier;
+    use chrono::Duration;
+    use semver::Version;
+    use tempfile::TempDir;
+
+    fn locked_kit(name: &str, digest: &str) -> LockedImage {
+        LockedImage {
+            name: ValidIdentifier(name.into()),
+            version: Version::new(1, 0, 0),
+            vendor: ValidIdentifier("my-vendor".into()),
+            source: format!("a.com/b/{name}:v1.0.0"),
+            digest: digest.into(),
+            platform: vec![LockedPlatform {
+                arch: "amd64".into(),
+                manifest_digest: format!("sha256:{name}"),
+                config_digest: "sha256:config".into(),
+            }],
+        }
+    }
+
+    async fn write_lock(path: &Path, kits: Vec<LockedImage>) {
+        let lock = Lock {
+            schema_version: LockSchemaVersion::default(),
+            sdk: locked_kit("sdk", "sdk-digest"),
+            kit: kits,
+            path_kit: Vec::new(),
+        };
+        write(path, toml::to_string(&lock).unwrap()).await.unwrap();
+    }
+
+    async fn cache_archive(cache: &KitCache, name: &str, lockfile: &Path) -> OCIArchive {
+        let archive = OCIArchive::new(
+            "a.com",
+            &format!("b/{name}"),
+            &format!("sha256:{name}"),
+            &cache.dir,
+        )
+        .unwrap();
+        tokio::fs::create_dir_all(archive.archive_path())
+            .await
+            .unwrap();
+        write(archive.archive_path().join("index.json"), "{}")
+            .await
+            .unwrap();
+        cache
+            .record_use(
+                &archive,
+                &locked_kit(name, &format!("{name}-digest")),
+                lockfile,
+            )
+            .await
+            .unwrap();
+        archive
+    }
+
+    #[tokio::test]
+    async fn test_prune() {
+        let tempdir = TempDir::new().unwrap();
+        let cache = KitCache::new(tempdir.path().join("cache"));
+        let lockfile = tempdir.path().join("Twoliter.lock");
+        write_lock(&lockfile, vec![locked_kit("kit-a", "kit-a-digest")]).await;
+        let kit_a = cache_archive(&cache, "kit-a", &lockfile).await;
+        let kit_b = cache_archive(&cache, "kit-b", &lockfile).await;
+
+        let entries = cache.entries().await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "sha256-kit-a");
+        assert_eq!(entries[0].size, 2);
+        assert_eq!(entries[0].referenced_by, vec![lockfile.clone()]);
+        // The lockfile no longer refers to kit-b
+        assert!(entries[1].referenced_by.is_empty());
+
+        // Nothing has gone unused for a day
+        let filter = PruneFilter {
+            keep_lockfiles: None,
+            unused_since: Some(Utc::now() - Duration::days(1)),
+        };
+        assert!(cache.prune(&filter, false).await.unwrap().is_empty());
+
+        let filter = PruneFilter {
+            keep_lockfiles: Some(vec![lockfile.clone()]),
+            unused_since: None,
+        };
+        let pruned = cache.prune(&filter, true).await.unwrap();
+        assert_eq!(pruned.len(), 1);
+        assert!(kit_b.archive_path().exists());
+        cache.prune(&filter, false).await.unwrap();
+        assert!(!kit_b.archive_path().exists());
+        assert!(kit_a.archive_path().exists());
+        assert_eq!(cache.entries().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unindexed_entries() {
+        let tempdir = TempDir::new().unwrap();
+        let cache = KitCache::new(tempdir.path());
+        tokio::fs::create_dir_all(tempdir.path().join("sha256-abc"))
+            .await
+            .unwrap();
+        write(tempdir.path().join(INDEX_FILE), "not json")
+            .await
+            .unwrap();
+
+        let entries = cache.entries().await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].uri, None);
+        assert!(cache
+            .read_index()
+            .await
+            .unwrap()
+            .entries
+            .contains_key("sha256-abc"));
+    }
+}
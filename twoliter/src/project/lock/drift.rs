@@ -0,0 +1,252 @@
+//! Describes the differences between the state recorded in `Twoliter.lock` and the state that is
+//! found when the project's dependencies are resolved again.
+use super::image::LockedImage;
+use super::{Lock, LockedPathKit};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fmt::Write;
+
+/// The differences between two locks. An empty report means that the locks are equivalent.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct LockDrift {
+    /// The change to the SDK, if any
+    pub sdk: Option<ImageChange>,
+    /// Changes to kits, in the order in which they appear in the locks
+    pub kit: Vec<ImageChange>,
+    /// The change to the kits that are built from a local path, if any
+    pub path_kit: Option<PathKitChange>,
+}
+
+/// A change to the set of kits that are built from a local path.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct PathKitChange {
+    pub from: Vec<LockedPathKit>,
+    pub to: Vec<LockedPathKit>,
+}
+
+impl PathKitChange {
+    fn describe(&self) -> String {
+        let list = |kits: &[LockedPathKit]| {
+            kits.iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        format!(
+            "changed from [{}] to [{}]",
+            list(&self.from),
+            list(&self.to)
+        )
+    }
+}
+
+/// A change to a single image between two locks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case", tag = "change")]
+pub(crate) enum ImageChange {
+    /// The image is only present in the resolved lock
+    Added { image: LockedImage },
+    /// The image is only present in the current lock
+    Removed { image: LockedImage },
+    /// A different version of the image was resolved
+    VersionChanged { from: LockedImage, to: LockedImage },
+    /// The same version of the image is now resolved from a different location
+    SourceChanged { from: LockedImage, to: LockedImage },
+    /// The same tag of the image now refers to different content, i.e. it was re-pushed
+    DigestChanged { from: LockedImage, to: LockedImage },
+}
+
+impl ImageChange {
+    /// Compares two locked copies of the same image, returning `None` if they are equivalent.
+    pub(crate) fn between(from: &LockedImage, to: &LockedImage) -> Option<Self> {
+        let (from, to) = (from.clone(), to.clone());
+        if from.version != to.version {
+            Some(Self::VersionChanged { from, to })
+        } else if from.source != to.source {
+            Some(Self::SourceChanged { from, to })
+        } else if from.digest != to.digest {
+            Some(Self::DigestChanged { from, to })
+        } else {
+            None
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::Added { image } => format!("added {}", image),
+            Self::Removed { image } => format!("removed {}", image),
+            Self::VersionChanged { from, to } => format!(
+                "{}@{} changed version from {} to {}",
+                from.name, from.vendor, from.version, to.version
+            ),
+            Self::SourceChanged { from, to } => format!(
+                "{}-{}@{} changed source from '{}' to '{}'",
+                from.name, from.version, from.vendor, from.source, to.source
+            ),
+            Self::DigestChanged { from, to } => format!(
+                "{}-{}@{} was re-pushed to '{}', digest changed from '{}' to '{}'",
+                from.name, from.version, from.vendor, to.source, from.digest, to.digest
+            ),
+        }
+    }
+}
+
+impl LockDrift {
+    /// Lists the changes needed to turn the `current` lock into the `resolved` one.
+    pub(crate) fn between(current: &Lock, resolved: &Lock) -> Self {
+        let same_image =
+            |a: &LockedImage, b: &LockedImage| a.name == b.name && a.vendor == b.vendor;
+
+        // The source includes the image name, so a different SDK is reported as a changed source
+        // or version.
+        let sdk = ImageChange::between(&current.sdk, &resolved.sdk);
+
+        let mut kit = Vec::new();
+        for current_kit in current.kit.iter() {
+            match resolved.kit.iter().find(|kit| same_image(current_kit, kit)) {
+                Some(resolved_kit) => kit.extend(ImageChange::between(current_kit, resolved_kit)),
+                None => kit.push(ImageChange::Removed {
+                    image: current_kit.clone(),
+                }),
+            }
+        }
+        for resolved_kit in resolved.kit.iter() {
+            if !current.kit.iter().any(|kit| same_image(kit, resolved_kit)) {
+                kit.push(ImageChange::Added {
+                    image: resolved_kit.clone(),
+                });
+            }
+        }
+
+        let path_kit = (current.path_kit != resolved.path_kit).then(|| PathKitChange {
+            from: current.path_kit.clone(),
+            to: resolved.path_kit.clone(),
+        });
+
+        Self { sdk, kit, path_kit }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.sdk.is_none() && self.kit.is_empty() && self.path_kit.is_none()
+    }
+
+    /// Renders the report as a human readable list of changes.
+    pub(crate) fn render_text(&self) -> String {
+        if self.is_empty() {
+            return "Twoliter.lock is up to date\n".to_string();
+        }
+        let mut out = String::from("Twoliter.lock is out of date:\n");
+        if let Some(sdk) = &self.sdk {
+            let _ = writeln!(out, "  sdk: {}", sdk.describe());
+        }
+        for kit in self.kit.iter() {
+            let _ = writeln!(out, "  kit: {}", kit.describe());
+        }
+        if let Some(path_kit) = &self.path_kit {
+            let _ = writeln!(out, "  path kits: {}", path_kit.describe());
+        }
+        out
+    }
+
+    /// Renders the report as pretty-printed JSON.
+    pub(crate) fn render_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).context("failed to serialize lock drift")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::project::lock::LockSchemaVersion;
+    use crate::project::ValidIdentifier;
+    use semver::Version;
+
+    fn image(name: &str, version: &str, digest: &str) -> LockedImage {
+        LockedImage {
+            name: ValidIdentifier(name.into()),
+            version: Version::parse(version).unwrap(),
+            vendor: ValidIdentifier("bottlerocket".into()),
+            source: format!("public.ecr.aws/bottlerocket/{name}:v{version}"),
+            digest: digest.into(),
+            platform: Vec::new(),
+        }
+    }
+
+    fn lock(sdk: LockedImage, kit: Vec<LockedImage>) -> Lock {
+        Lock {
+            schema_version: LockSchemaVersion::default(),
+            sdk,
+            kit,
+            path_kit: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_no_drift() {
+        let current = lock(
+            image("bottlerocket-sdk", "0.50.0", "a"),
+            vec![image("core-kit", "1.0.0", "b")],
+        );
+        let drift = LockDrift::between(&current, &current.clone());
+        assert!(drift.is_empty());
+        assert_eq!(drift.render_text(), "Twoliter.lock is up to date\n");
+    }
+
+    #[test]
+    fn test_drift() {
+        let current = lock(
+            image("bottlerocket-sdk", "0.50.0", "a"),
+            vec![
+                image("core-kit", "1.0.0", "b"),
+                image("extra-1-kit", "1.0.0", "c"),
+                image("extra-2-kit", "1.0.0", "d"),
+            ],
+        );
+        let resolved = lock(
+            image("bottlerocket-sdk", "0.50.0", "a"),
+            vec![
+                image("core-kit", "1.0.0", "repushed"),
+                image("extra-1-kit", "1.1.0", "e"),
+                image("extra-3-kit", "1.0.0", "f"),
+            ],
+        );
+
+        let drift = LockDrift::between(&current, &resolved);
+        assert_eq!(drift.sdk, None);
+        assert_eq!(drift.kit.len(), 4);
+        assert!(matches!(drift.kit[0], ImageChange::DigestChanged { .. }));
+        assert!(matches!(drift.kit[1], ImageChange::VersionChanged { .. }));
+        assert!(matches!(drift.kit[2], ImageChange::Removed { .. }));
+        assert!(matches!(drift.kit[3], ImageChange::Added { .. }));
+
+        let text = drift.render_text();
+        assert!(text.contains(
+            "kit: core-kit-1.0.0@bottlerocket was re-pushed to \
+            'public.ecr.aws/bottlerocket/core-kit:v1.0.0', digest changed from 'b' to 'repushed'"
+        ));
+
+        let json: serde_json::Value = serde_json::from_str(&drift.render_json().unwrap()).unwrap();
+        assert_eq!(json["kit"][0]["change"], "digest-changed");
+        assert_eq!(json["kit"][1]["to"]["version"], "1.1.0");
+        assert!(json["sdk"].is_null());
+    }
+
+    #[test]
+    fn test_path_kit_drift() {
+        let current = lock(image("bottlerocket-sdk", "0.50.0", "a"), vec![]);
+        let mut resolved = current.clone();
+        resolved.path_kit.push(LockedPathKit {
+            name: ValidIdentifier("my-kit".into()),
+            version: Version::new(0, 1, 0),
+            path: "../my-kit".into(),
+        });
+
+        let drift = LockDrift::between(&current, &resolved);
+        assert!(!drift.is_empty());
+        assert!(drift
+            .render_text()
+            .contains("path kits: changed from [] to [my-kit-0.1.0 (from ../my-kit)]"));
+    }
+}
@@ -0,0 +1,255 @@
+//! Records the shape of the kit and SDK dependency graph that is walked while resolving a
+//! project's lock, so that we can explain why a given kit or SDK ended up in `Twoliter.lock`.
+use crate::project::{Image, ValidIdentifier};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+
+/// The dependency graph of a project as discovered by `Lock::resolve`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct DependencyGraph {
+    /// The SDK declared directly in Twoliter.toml, if any
+    pub sdk: Option<Image>,
+    /// The versions selected for the kits declared directly in Twoliter.toml
+    pub kit: Vec<Image>,
+    /// The single SDK that was selected for the project after walking all kits
+    pub resolved_sdk: Image,
+    /// Every kit that was resolved, along with the dependencies declared in its metadata
+    pub resolved_kits: Vec<ResolvedKit>,
+}
+
+/// A kit that was resolved, together with the SDK and kits declared in its metadata.
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct ResolvedKit {
+    #[serde(flatten)]
+    pub image: Image,
+    /// The SDK that the kit was built with
+    pub sdk: Image,
+    /// The kits that this kit depends on
+    pub kit: Vec<Image>,
+}
+
+impl DependencyGraph {
+    fn resolved_kit(&self, image: &Image) -> Option<&ResolvedKit> {
+        self.resolved_kits.iter().find(|kit| &kit.image == image)
+    }
+
+    /// Returns the names of the given kits along with the names of every kit that they depend on,
+    /// directly or transitively.
+    pub(crate) fn kit_closure(&self, names: &[ValidIdentifier]) -> HashSet<ValidIdentifier> {
+        let mut closure = HashSet::new();
+        let mut remaining: Vec<&ValidIdentifier> = names.iter().collect();
+        while let Some(name) = remaining.pop() {
+            if !closure.insert(name.clone()) {
+                continue;
+            }
+            for resolved in self.resolved_kits.iter() {
+                if &resolved.image.name == name {
+                    remaining.extend(resolved.kit.iter().map(|kit| &kit.name));
+                }
+            }
+        }
+        closure
+    }
+
+    /// Renders the graph as an indented tree, similar to `cargo tree`. Kits whose dependencies
+    /// have already been printed are marked with `(*)` and are not expanded again.
+    pub(crate) fn render_text(&self, root: &str) -> String {
+        let mut out = format!("{root}\n");
+        let mut children = Vec::new();
+        if let Some(sdk) = &self.sdk {
+            children.push(Edge::Sdk(sdk));
+        }
+        children.extend(self.kit.iter().map(Edge::Kit));
+
+        let mut expanded = HashSet::new();
+        self.render_children(&children, "", &mut expanded, &mut out);
+        out
+    }
+
+    fn render_children<'a>(
+        &'a self,
+        children: &[Edge<'a>],
+        prefix: &str,
+        expanded: &mut HashSet<&'a Image>,
+        out: &mut String,
+    ) {
+        for (i, child) in children.iter().enumerate() {
+            let last = i + 1 == children.len();
+            let (branch, indent) = if last {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            match child {
+                Edge::Sdk(sdk) => {
+                    let _ = writeln!(out, "{prefix}{branch}sdk: {sdk}");
+                }
+                Edge::Kit(kit) => {
+                    let Some(resolved) = self.resolved_kit(kit) else {
+                        let _ = writeln!(out, "{prefix}{branch}kit: {kit}");
+                        continue;
+                    };
+                    if !expanded.insert(*kit) {
+                        let _ = writeln!(out, "{prefix}{branch}kit: {kit} (*)");
+                        continue;
+                    }
+                    let _ = writeln!(out, "{prefix}{branch}kit: {kit}");
+                    let mut grandchildren = vec![Edge::Sdk(&resolved.sdk)];
+                    grandchildren.extend(resolved.kit.iter().map(Edge::Kit));
+                    let prefix = format!("{prefix}{indent}");
+                    self.render_children(&grandchildren, &prefix, expanded, out);
+                }
+            }
+        }
+    }
+
+    /// Renders the graph in the Graphviz DOT language. Kit dependencies are drawn as solid edges
+    /// and SDK declarations as dashed edges.
+    pub(crate) fn render_dot(&self, root: &str) -> String {
+        let mut out = String::from("digraph twoliter {\n");
+        let _ = writeln!(out, "    {:?} [shape=box];", root);
+        let _ = writeln!(
+            out,
+            "    {:?} [shape=component];",
+            self.resolved_sdk.to_string()
+        );
+
+        // Collect edges into a set so that the output is stable and free of duplicates.
+        let mut edges = BTreeMap::new();
+        if let Some(sdk) = &self.sdk {
+            edges.insert((root.to_string(), sdk.to_string()), true);
+        }
+        for kit in self.kit.iter() {
+            edges.insert((root.to_string(), kit.to_string()), false);
+        }
+        for resolved in self.resolved_kits.iter() {
+            let from = resolved.image.to_string();
+            edges.insert((from.clone(), resolved.sdk.to_string()), true);
+            for kit in resolved.kit.iter() {
+                edges.insert((from.clone(), kit.to_string()), false);
+            }
+        }
+
+        for ((from, to), is_sdk) in edges {
+            let style = if is_sdk { " [style=dashed]" } else { "" };
+            let _ = writeln!(out, "    {from:?} -> {to:?}{style};");
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Renders the graph as pretty-printed JSON.
+    pub(crate) fn render_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).context("failed to serialize dependency graph")
+    }
+}
+
+/// A child of a node in the rendered tree.
+enum Edge<'a> {
+    Sdk(&'a Image),
+    Kit(&'a Image),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::project::ValidIdentifier;
+    use semver::Version;
+
+    fn image(name: &str, version: &str) -> Image {
+        Image {
+            name: ValidIdentifier(name.into()),
+            version: Version::parse(version).unwrap(),
+            vendor: ValidIdentifier("bottlerocket".into()),
+        }
+    }
+
+    fn graph() -> DependencyGraph {
+        let sdk = image("bottlerocket-sdk", "0.50.0");
+        let core = image("core-kit", "1.0.0");
+        let extra_1 = image("extra-1-kit", "1.0.0");
+        let extra_2 = image("extra-2-kit", "1.0.0");
+        DependencyGraph {
+            sdk: Some(sdk.clone()),
+            kit: vec![extra_1.clone(), extra_2.clone()],
+            resolved_sdk: sdk.clone(),
+            resolved_kits: vec![
+                ResolvedKit {
+                    image: extra_1,
+                    sdk: sdk.clone(),
+                    kit: vec![core.clone()],
+                },
+                ResolvedKit {
+                    image: extra_2,
+                    sdk: sdk.clone(),
+                    kit: vec![core.clone()],
+                },
+                ResolvedKit {
+                    image: core,
+                    sdk,
+                    kit: vec![],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_render_text() {
+        let expected = "\
+Twoliter.toml
+├── sdk: bottlerocket-sdk-0.50.0@bottlerocket
+├── kit: extra-1-kit-1.0.0@bottlerocket
+│   ├── sdk: bottlerocket-sdk-0.50.0@bottlerocket
+│   └── kit: core-kit-1.0.0@bottlerocket
+│       └── sdk: bottlerocket-sdk-0.50.0@bottlerocket
+└── kit: extra-2-kit-1.0.0@bottlerocket
+    ├── sdk: bottlerocket-sdk-0.50.0@bottlerocket
+    └── kit: core-kit-1.0.0@bottlerocket (*)
+";
+        assert_eq!(graph().render_text("Twoliter.toml"), expected);
+    }
+
+    #[test]
+    fn test_render_dot() {
+        let dot = graph().render_dot("Twoliter.toml");
+        assert!(dot.starts_with("digraph twoliter {\n"));
+        assert!(
+            dot.contains("\"extra-1-kit-1.0.0@bottlerocket\" -> \"core-kit-1.0.0@bottlerocket\";")
+        );
+        assert!(dot.contains(
+            "\"core-kit-1.0.0@bottlerocket\" -> \"bottlerocket-sdk-0.50.0@bottlerocket\" [style=dashed];"
+        ));
+        assert_eq!(
+            dot.matches("\"extra-2-kit-1.0.0@bottlerocket\" -> \"core-kit-1.0.0@bottlerocket\";")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_kit_closure() {
+        let graph = graph();
+        let closure = graph.kit_closure(&[ValidIdentifier("extra-2-kit".into())]);
+        let mut closure: Vec<_> = closure.iter().map(ToString::to_string).collect();
+        closure.sort();
+        assert_eq!(closure, vec!["core-kit", "extra-2-kit"]);
+
+        let closure = graph.kit_closure(&[ValidIdentifier("core-kit".into())]);
+        assert_eq!(closure.len(), 1);
+    }
+
+    #[test]
+    fn test_render_json() {
+        let json: serde_json::Value =
+            serde_json::from_str(&graph().render_json().unwrap()).unwrap();
+        let kits = json["resolved-kits"].as_array().unwrap();
+        assert_eq!(kits.len(), 3);
+        assert_eq!(kits[0]["name"], "extra-1-kit");
+        assert_eq!(kits[0]["kit"][0]["name"], "core-kit");
+        assert_eq!(json["resolved-sdk"]["version"], "0.50.0");
+    }
+}
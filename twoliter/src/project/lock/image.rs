@@ -1,27 +1,43 @@
 use super::archive::OCIArchive;
-use super::views::ManifestListView;
-use crate::common::fs::create_dir_all;
-use crate::compatibility::SUPPORTED_KIT_METADATA_VERSION;
-use crate::project::{Image, ProjectImage, ValidIdentifier, VendedArtifact};
-use anyhow::{bail, Context, Result};
+use super::cache::CACHE_DIRECTORY;
+use super::layout::OCILayout;
+use super::signature::verify_image_signature;
+use super::views::{ManifestLayoutView, ManifestListView, ManifestView};
+use crate::common::fs::{create_dir_all, read, read_to_string, write};
+use crate::compatibility::{READABLE_KIT_METADATA_VERSIONS, SUPPORTED_KIT_METADATA_VERSION};
+use crate::project::{Image, ProjectImage, ValidIdentifier, VendedArtifact, VersionedArtifact};
+use anyhow::{bail, ensure, Context, Result};
 use base64::Engine;
-use futures::{pin_mut, stream, StreamExt, TryStreamExt};
+use futures::{stream, StreamExt, TryStreamExt};
 use log::trace;
 use oci_cli_wrapper::{ConfigView, DockerArchitecture, ImageTool};
 use semver::Version;
 use serde::{Deserialize, Serialize};
 use sha2::Digest;
 use std::fmt::{Debug, Display, Formatter};
-use std::path::Path;
-use tracing::{debug, error, info, instrument};
+use std::path::{Path, PathBuf};
+use tracing::{debug, error, info, instrument, warn};
 
-/// The OCI config label prefix to which the supported kit metadata version is appended.
+/// The OCI config label prefix to which each kit metadata version is appended.
 ///
-/// Kit metadata is embedded in the OCI image under this label.
+/// Kit metadata is embedded in the OCI image under these labels.
 const KIT_METADATA_LABEL_PREFIX: &str = "dev.bottlerocket.kit.";
 
-pub fn supported_kit_metadata_label() -> String {
-    format!("{KIT_METADATA_LABEL_PREFIX}{SUPPORTED_KIT_METADATA_VERSION}")
+/// The OCI config label that records the oldest release of twoliter which can read the newest kit
+/// metadata version in the image, so that older releases can say which release to upgrade to.
+const KIT_MINIMUM_TWOLITER_LABEL: &str = "dev.bottlerocket.kit.minimum-twoliter-version";
+
+/// The maximum number of image configs fetched at once when reading kit metadata.
+const MAX_CONCURRENT_CONFIG_FETCHES: usize = 4;
+
+/// The file in each extracted kit's directory which records the kit's manifest list, so that the
+/// extracted kit can later be verified against the lock without contacting the registry.
+const MANIFEST_LIST_FILE: &str = "manifest-list.json";
+
+/// Calculates the digest recorded in the lock for an image with the given manifest list.
+fn manifest_list_digest(manifest_bytes: &[u8]) -> String {
+    let digest = sha2::Sha256::digest(manifest_bytes);
+    base64::engine::general_purpose::STANDARD.encode(digest.as_slice())
 }
 
 /// Represents a locked dependency on an image
@@ -37,6 +53,10 @@ pub(crate) struct LockedImage {
     pub source: String,
     /// The digest of the image
     pub digest: String,
+    /// The image for each platform, which is verified before the image is extracted. Only kits
+    /// record their platforms, and lock files older than schema version 2 do not record them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub platform: Vec<LockedPlatform>,
 }
 
 impl PartialEq for LockedImage {
@@ -45,6 +65,80 @@ impl PartialEq for LockedImage {
     }
 }
 
+impl LockedImage {
+    /// Returns true if both locked images refer to the same version of an artifact from the same
+    /// source, regardless of the digest that was locked.
+    pub(crate) fn is_same_release(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.vendor == other.vendor
+            && self.version == other.version
+            && self.source == other.source
+    }
+
+    /// Checks the manifest and config digests found for the image's `arch` against the lock.
+    pub(crate) fn verify_platform(
+        &self,
+        arch: &DockerArchitecture,
+        manifest_digest: &str,
+        config_digest: Option<&str>,
+    ) -> Result<()> {
+        if self.platform.is_empty() {
+            debug!("No platforms are locked for '{self}', skipping platform verification");
+            return Ok(());
+        }
+        let arch = arch.to_string();
+        let platform = self
+            .platform
+            .iter()
+            .find(|platform| platform.arch == arch)
+            .context(format!(
+                "Twoliter.lock does not contain an image for architecture '{arch}' of '{self}', \
+                please run `twoliter update`"
+            ))?;
+        ensure!(
+            platform.manifest_digest == manifest_digest,
+            "the '{arch}' image of '{self}' has manifest '{manifest_digest}', but Twoliter.lock \
+            expects '{}'",
+            platform.manifest_digest
+        );
+        if let Some(config_digest) = config_digest {
+            ensure!(
+                platform.config_digest == config_digest,
+                "the '{arch}' image of '{self}' has config '{config_digest}', but Twoliter.lock \
+                expects '{}'",
+                platform.config_digest
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The digests of the image for a single platform of a locked image.
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct LockedPlatform {
+    /// The architecture of the platform, e.g. `amd64`
+    pub arch: String,
+    /// The digest of the platform's image manifest
+    pub manifest_digest: String,
+    /// The digest of the platform's image config
+    pub config_digest: String,
+}
+
+impl LockedPlatform {
+    fn new(manifest: &ManifestView, config_digest: String) -> Result<Self> {
+        let platform = manifest.platform.as_ref().context(format!(
+            "manifest '{}' in manifest list does not name a platform",
+            manifest.digest
+        ))?;
+        Ok(Self {
+            arch: platform.architecture.to_string(),
+            manifest_digest: manifest.digest.clone(),
+            config_digest,
+        })
+    }
+}
+
 impl Display for LockedImage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!(
@@ -62,7 +156,9 @@ impl VendedArtifact for LockedImage {
     fn vendor_name(&self) -> &ValidIdentifier {
         &self.vendor
     }
+}
 
+impl VersionedArtifact for LockedImage {
     fn version(&self) -> &Version {
         &self.version
     }
@@ -75,7 +171,6 @@ pub(crate) struct ImageMetadata {
     #[expect(dead_code)]
     pub name: String,
     /// The version of the kit
-    #[expect(dead_code)]
     pub version: Version,
     /// The required sdk of the kit,
     pub sdk: Image,
@@ -84,27 +179,84 @@ pub(crate) struct ImageMetadata {
     pub kits: Vec<Image>,
 }
 
+/// Kit metadata as written by version 1, in which the SDK and kits could also name the image
+/// source and digest that the kit was built with.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct ImageMetadataV1 {
+    name: String,
+    version: Version,
+    sdk: ImageV1,
+    #[serde(rename = "kit")]
+    kits: Vec<ImageV1>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct ImageV1 {
+    name: ValidIdentifier,
+    version: Version,
+    vendor: ValidIdentifier,
+    #[serde(default)]
+    #[expect(dead_code)]
+    source: Option<String>,
+    #[serde(default)]
+    #[expect(dead_code)]
+    digest: Option<String>,
+}
+
+impl From<ImageV1> for Image {
+    fn from(value: ImageV1) -> Self {
+        Self {
+            name: value.name,
+            version: value.version,
+            vendor: value.vendor,
+        }
+    }
+}
+
+impl From<ImageMetadataV1> for ImageMetadata {
+    fn from(value: ImageMetadataV1) -> Self {
+        Self {
+            name: value.name,
+            version: value.version,
+            sdk: value.sdk.into(),
+            kits: value.kits.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
 impl TryFrom<EncodedKitMetadata> for ImageMetadata {
     type Error = anyhow::Error;
 
     fn try_from(value: EncodedKitMetadata) -> Result<Self, Self::Error> {
         let bytes = base64::engine::general_purpose::STANDARD
-            .decode(value.0)
+            .decode(value.encoded)
             .context("failed to decode kit metadata as base64")?;
-        serde_json::from_slice(bytes.as_slice()).context("failed to parse kit metadata json")
+        match value.version {
+            1 => serde_json::from_slice::<ImageMetadataV1>(bytes.as_slice())
+                .map(Into::into)
+                .context("failed to parse version 1 kit metadata json"),
+            _ => serde_json::from_slice(bytes.as_slice())
+                .context("failed to parse kit metadata json"),
+        }
     }
 }
 
-/// Encoded kit metadata, which is embedded in a label of the OCI image config.
+/// Encoded kit metadata, which is embedded in a label of the OCI image config, along with the
+/// metadata version given by the label.
 #[derive(Clone, Eq, PartialEq)]
-pub(crate) struct EncodedKitMetadata(String);
+pub(crate) struct EncodedKitMetadata {
+    version: u32,
+    encoded: String,
+}
 
 impl EncodedKitMetadata {
     #[instrument(level = "trace")]
     async fn try_from_image(image_uri: &str, image_tool: &ImageTool) -> Result<Self> {
         tracing::trace!(image_uri, "Extracting kit metadata from OCI image config");
         let config = image_tool.get_config(image_uri).await?;
-        let kit_metadata = EncodedKitMetadata(Self::extract_encoded_kit_metadata(&config)?);
+        let kit_metadata = Self::try_from_config(&config)?;
 
         tracing::trace!(
             image_uri,
@@ -116,34 +268,62 @@ impl EncodedKitMetadata {
         Ok(kit_metadata)
     }
 
-    fn extract_encoded_kit_metadata(oci_config: &ConfigView) -> Result<String> {
-        let encoded_metadata = oci_config
+    fn try_from_config(oci_config: &ConfigView) -> Result<Self> {
+        let (version, encoded) = Self::extract_encoded_kit_metadata(oci_config)?;
+        Ok(Self { version, encoded })
+    }
+
+    /// Finds the newest version of the kit metadata that this version of twoliter can read among
+    /// the image's `dev.bottlerocket.kit.v<N>` labels, returning the version and the metadata.
+    fn extract_encoded_kit_metadata(oci_config: &ConfigView) -> Result<(u32, String)> {
+        let kit_labels: Vec<(&str, &String)> = oci_config
             .labels
-            .get(supported_kit_metadata_label().as_str());
+            .iter()
+            .filter(|(label, _)| label.as_str() != KIT_MINIMUM_TWOLITER_LABEL)
+            .filter_map(|(label, value)| {
+                label
+                    .strip_prefix(KIT_METADATA_LABEL_PREFIX)
+                    .map(|version| (version, value))
+            })
+            .collect();
 
-        match encoded_metadata {
-            Some(encoded_metadata) => Ok(encoded_metadata.to_owned()),
-            None => {
-                if let Some(kit_label) = oci_config
-                    .labels
-                    .keys()
-                    .find(|label| label.starts_with(KIT_METADATA_LABEL_PREFIX))
-                {
-                    let kit_version = kit_label.trim_start_matches(KIT_METADATA_LABEL_PREFIX);
-                    let meta_relation =
-                        Self::compare_version_strs(kit_version, SUPPORTED_KIT_METADATA_VERSION);
-
-                    bail!(
-                        "kit appears to be built with metadata version '{kit_version}', possibly by \
-                        {meta_relation} version of twoliter with unsupported incompatibilities. \
-                        This version of twoliter supports metadata version \
-                        '{SUPPORTED_KIT_METADATA_VERSION}'.",
-                    )
-                } else {
-                    bail!("no metadata stored on image, this image appears not to be a kit")
-                }
+        let newest_readable = kit_labels
+            .iter()
+            .filter_map(|(version, value)| Some((parse_metadata_version(version)?, *value)))
+            .filter(|(version, _)| READABLE_KIT_METADATA_VERSIONS.contains(version))
+            .max_by_key(|(version, _)| *version);
+        if let Some((version, encoded_metadata)) = newest_readable {
+            if format!("v{version}") != SUPPORTED_KIT_METADATA_VERSION {
+                debug!("Upgrading kit metadata from version 'v{version}'");
             }
+            return Ok((version, encoded_metadata.to_owned()));
         }
+
+        // Report the newest version the kit was built with, since that is what the kit's
+        // publisher intended it to be read with.
+        let Some(kit_version) = kit_labels
+            .iter()
+            .map(|(version, _)| *version)
+            .max_by_key(|version| parse_metadata_version(version))
+        else {
+            bail!("no metadata stored on image, this image appears not to be a kit")
+        };
+        let meta_relation = Self::compare_version_strs(kit_version, SUPPORTED_KIT_METADATA_VERSION);
+        let oldest = READABLE_KIT_METADATA_VERSIONS[0];
+        let upgrade = match oci_config.labels.get(KIT_MINIMUM_TWOLITER_LABEL) {
+            Some(minimum) if meta_relation == "a newer" => {
+                format!(" Upgrade to twoliter {minimum} or later to use this kit.")
+            }
+            _ => String::new(),
+        };
+
+        bail!(
+            "kit appears to be built with metadata version '{kit_version}', possibly by \
+            {meta_relation} version of twoliter with unsupported incompatibilities. \
+            This version of twoliter ({}) supports metadata versions 'v{oldest}' through \
+            '{SUPPORTED_KIT_METADATA_VERSION}'.{upgrade}",
+            env!("CARGO_PKG_VERSION"),
+        )
     }
 
     /// Compare's kit metadata versions in english. Intended to be used in error messages.
@@ -169,19 +349,26 @@ impl EncodedKitMetadata {
     /// the encoded form.
     fn try_debug_image_metadata(&self) -> String {
         self.debug_image_metadata().unwrap_or_else(|| {
-            format!("<ImageMetadata(encoded) [{}]>", self.0.replace("\n", "\\n"))
+            format!(
+                "<ImageMetadata(encoded v{}) [{}]>",
+                self.version,
+                self.encoded.replace("\n", "\\n")
+            )
         })
     }
 
     fn debug_image_metadata(&self) -> Option<String> {
-        base64::engine::general_purpose::STANDARD
-            .decode(&self.0)
+        ImageMetadata::try_from(self.clone())
             .ok()
-            .and_then(|bytes| serde_json::from_slice(bytes.as_slice()).ok())
-            .map(|metadata: ImageMetadata| format!("<ImageMetadata(decoded) [{:?}]>", metadata))
+            .map(|metadata| format!("<ImageMetadata(decoded) [{:?}]>", metadata))
     }
 }
 
+/// Parses a kit metadata version such as `v2`.
+fn parse_metadata_version(version: &str) -> Option<u32> {
+    version.strip_prefix('v')?.parse().ok()
+}
+
 impl Debug for EncodedKitMetadata {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.try_debug_image_metadata())
@@ -210,34 +397,47 @@ impl ImageResolver {
         self
     }
 
+    /// Fetches the manifest list of the image, or reads it from the image's local OCI `layout`,
+    /// returning it along with the digest that is recorded for the image in the lock. A fetched
+    /// manifest list is only returned if it is signed by a key that the image's vendor trusts.
     #[instrument(
         level = "trace",
+        skip(layout),
         fields(image = %self.image, uri = %self.image.project_image_uri())
     )]
-    /// Calculate the digest of the locked image
-    async fn calculate_digest(&self, image_tool: &ImageTool) -> Result<String> {
-        let image_uri = self.image.project_image_uri();
-        let image_uri_str = image_uri.to_string();
-        let manifest_bytes = image_tool.get_manifest(image_uri_str.as_str()).await?;
-        let digest = sha2::Sha256::digest(manifest_bytes.as_slice());
-        let digest = base64::engine::general_purpose::STANDARD.encode(digest.as_slice());
-        debug!(
-            "Calculated digest for locked image '{}': '{}'",
-            image_uri, digest,
-        );
-        Ok(digest)
-    }
-
-    #[instrument(
-        level = "trace",
-        fields(image = %self.image, uri = %self.image.project_image_uri())
-    )]
-    async fn get_manifest(&self, image_tool: &ImageTool) -> Result<ManifestListView> {
+    async fn get_manifest(
+        &self,
+        image_tool: &ImageTool,
+        layout: Option<&OCILayout>,
+    ) -> Result<(ManifestListView, String)> {
         let uri = self.image.project_image_uri().to_string();
         debug!(image=%self.image, uri, "Fetching image manifest.");
-        let manifest_bytes = image_tool.get_manifest(uri.as_str()).await?;
-        serde_json::from_slice(manifest_bytes.as_slice())
-            .context("failed to deserialize manifest list")
+        let trusted_keys = self.image.trusted_keys();
+        let manifest_bytes = match layout {
+            Some(layout) => {
+                if !trusted_keys.is_empty() {
+                    warn!(
+                        "Not verifying the signature of '{}' because it is read from a local path",
+                        self.image
+                    );
+                }
+                layout.manifest_list()?
+            }
+            None => {
+                let manifest_bytes = image_tool.get_manifest(uri.as_str()).await?;
+                if !trusted_keys.is_empty() {
+                    let repository = self.image.project_repository_uri();
+                    verify_image_signature(image_tool, &repository, &manifest_bytes, trusted_keys)
+                        .await?;
+                }
+                manifest_bytes
+            }
+        };
+        let digest = manifest_list_digest(manifest_bytes.as_slice());
+        debug!("Calculated digest for locked image '{}': '{}'", uri, digest);
+        let manifest_list = serde_json::from_slice(manifest_bytes.as_slice())
+            .context("failed to deserialize manifest list")?;
+        Ok((manifest_list, digest))
     }
 
     #[instrument(
@@ -252,19 +452,26 @@ impl ImageResolver {
         let uri = self.image.project_image_uri();
         info!("Resolving dependency image dependency '{}'.", self.image);
 
-        let manifest_list = self.get_manifest(image_tool).await?;
+        ensure!(
+            self.image.local_path().is_none() || !self.skip_metadata_retrieval,
+            "only kits can be overridden with a local path, but '{}' is not a kit",
+            self.image
+        );
+        let layout = self.image.local_layout().await?;
+        let (manifest_list, digest) = self.get_manifest(image_tool, layout).await?;
         let registry = uri
             .registry
             .as_ref()
             .context("no registry found for image")?;
 
-        let locked_image = LockedImage {
+        let mut locked_image = LockedImage {
             name: self.image.name().to_owned(),
             version: self.image.version().to_owned(),
             vendor: self.image.vendor_name().to_owned(),
             // The source is the image uri without the tag, which is the digest
             source: self.image.original_source_uri().to_string(),
-            digest: self.calculate_digest(image_tool).await?,
+            digest,
+            platform: Vec::new(),
         };
 
         if self.skip_metadata_retrieval {
@@ -272,23 +479,54 @@ impl ImageResolver {
         }
 
         debug!("Extracting kit metadata from OCI image");
-        let embedded_kit_metadata = stream::iter(manifest_list.manifests).then(|manifest| {
-            let registry = registry.clone();
-            let repo = uri.repo.clone();
-            async move {
-                let image_uri = format!("{registry}/{repo}@{}", manifest.digest);
-                EncodedKitMetadata::try_from_image(&image_uri, image_tool).await
+        let platform_images: Vec<(LockedPlatform, EncodedKitMetadata)> = match &layout {
+            Some(layout) => {
+                let mut platform_images = Vec::new();
+                for manifest in manifest_list.manifests.iter() {
+                    let config_digest = layout.config_digest(&manifest.digest).await?;
+                    let config = layout.config(&manifest.digest).await?;
+                    platform_images.push((
+                        LockedPlatform::new(manifest, config_digest)?,
+                        EncodedKitMetadata::try_from_config(&config)?,
+                    ));
+                }
+                platform_images
             }
-        });
-        pin_mut!(embedded_kit_metadata);
+            None => {
+                stream::iter(manifest_list.manifests)
+                    .map(|manifest| {
+                        let registry = registry.clone();
+                        let repo = uri.repo.clone();
+                        async move {
+                            let image_uri = format!("{registry}/{repo}@{}", manifest.digest);
+                            let manifest_bytes = image_tool.get_manifest(&image_uri).await?;
+                            let image_manifest: ManifestLayoutView =
+                                serde_json::from_slice(manifest_bytes.as_slice())
+                                    .context("failed to deserialize oci manifest")?;
+                            let platform = LockedPlatform::new(
+                                &manifest,
+                                image_manifest.config.digest.to_string(),
+                            )?;
+                            let kit_metadata =
+                                EncodedKitMetadata::try_from_image(&image_uri, image_tool).await?;
+                            Ok::<_, anyhow::Error>((platform, kit_metadata))
+                        }
+                    })
+                    .buffered(MAX_CONCURRENT_CONFIG_FETCHES)
+                    .try_collect()
+                    .await?
+            }
+        };
+        let (platform, embedded_kit_metadata): (Vec<_>, Vec<_>) =
+            platform_images.into_iter().unzip();
+        locked_image.platform = platform;
 
-        let canonical_metadata = embedded_kit_metadata
-            .try_next()
-            .await?
+        let (canonical_metadata, other_metadata) = embedded_kit_metadata
+            .split_first()
             .context(format!("could not find metadata for kit {}", uri))?;
 
         trace!("Checking that all manifests refer to the same kit.");
-        while let Some(kit_metadata) = embedded_kit_metadata.try_next().await? {
+        for kit_metadata in other_metadata {
             if kit_metadata != canonical_metadata {
                 error!(
                     ?canonical_metadata,
@@ -298,18 +536,58 @@ impl ImageResolver {
                 bail!("Metadata does not match between images in manifest list");
             }
         }
-        let metadata = canonical_metadata
+        let metadata: ImageMetadata = canonical_metadata
+            .clone()
             .try_into()
             .context("Failed to decode and parse kit metadata")?;
 
+        // A registry is trusted to serve the tagged version, but a local layout has no tag.
+        ensure!(
+            layout.is_none() || &metadata.version == self.image.version(),
+            "kit '{}' is overridden with a local path containing version {} of the kit",
+            self.image,
+            metadata.version
+        );
+
         Ok((locked_image, Some(metadata)))
     }
 
+    /// Reads the version of the kit in a local OCI layout, which is the only version available to a
+    /// kit that is overridden with a local path.
+    pub(crate) async fn layout_kit_version(layout: &OCILayout) -> Result<Version> {
+        let manifest_list: ManifestListView = serde_json::from_slice(&layout.manifest_list()?)
+            .context("failed to deserialize manifest list")?;
+        let manifest = manifest_list.manifests.first().context(format!(
+            "OCI image layout '{}' does not contain any images",
+            layout.path().display()
+        ))?;
+        let config = layout.config(&manifest.digest).await?;
+        let metadata: ImageMetadata = EncodedKitMetadata::try_from_config(&config)?
+            .try_into()
+            .context("Failed to decode and parse kit metadata")?;
+        Ok(metadata.version)
+    }
+
+    /// The directory within the external kits directory `path` into which this kit is extracted
+    fn kit_dir<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        path.as_ref().join(format!(
+            "{}/{}",
+            self.image.vendor_name(),
+            self.image.name()
+        ))
+    }
+
     #[instrument(
         level = "trace",
         fields(uri = %self.image.project_image_uri(), path = %path.as_ref().display())
     )]
-    pub(crate) async fn extract<P>(&self, image_tool: &ImageTool, path: P, arch: &str) -> Result<()>
+    pub(crate) async fn extract<P>(
+        &self,
+        image_tool: &ImageTool,
+        path: P,
+        arch: &str,
+        locked: &LockedImage,
+    ) -> Result<OCIArchive>
     where
         P: AsRef<Path>,
     {
@@ -318,45 +596,173 @@ impl ImageResolver {
             self.image.name(),
             path.as_ref().display()
         );
-        let target_path = path.as_ref().join(format!(
-            "{}/{}/{arch}",
-            self.image.vendor_name(),
-            self.image.name()
-        ));
-        let cache_path = path.as_ref().join("cache");
-        create_dir_all(&target_path).await?;
-        create_dir_all(&cache_path).await?;
+        let kit_dir = self.kit_dir(&path);
+        create_dir_all(&kit_dir).await?;
+
+        // First get the manifest for the specific requested architecture, recording the manifest
+        // list so that the kit can be verified later without access to the registry
+        let uri = self.image.project_image_uri().to_string();
+        let layout = self.image.local_layout().await?;
+        let manifest_bytes = match layout {
+            Some(layout) => layout.manifest_list()?,
+            None => image_tool.get_manifest(uri.as_str()).await?,
+        };
+        write(kit_dir.join(MANIFEST_LIST_FILE), &manifest_bytes)
+            .await
+            .context(format!("failed to record manifest list for '{uri}'"))?;
+        let manifest_list: ManifestListView = serde_json::from_slice(manifest_bytes.as_slice())
+            .context("failed to deserialize manifest list")?;
+        let oci_archive = self.oci_archive(&manifest_list, path, arch)?;
+        let docker_arch = DockerArchitecture::try_from(arch)?;
+        locked.verify_platform(&docker_arch, oci_archive.digest(), None)?;
+
+        // Checks for the saved image locally, or else pulls and saves it
+        match &layout {
+            Some(layout) => oci_archive.copy_from_layout(layout).await?,
+            None => oci_archive.pull_image(image_tool).await?,
+        }
+        let config_digest = oci_archive.config_digest().await?;
+        locked.verify_platform(&docker_arch, oci_archive.digest(), Some(&config_digest))?;
+
+        // Checks if this archive has already been extracted by checking a digest file
+        // otherwise cleans up the path and unpacks the archive
+        oci_archive.unpack_layers(kit_dir.join(arch)).await?;
+
+        Ok(oci_archive)
+    }
+
+    /// Extracts the kit using only the manifest list and OCI archive that were saved when the kit
+    /// was last fetched, after checking both against the `locked` image.
+    #[instrument(
+        level = "trace",
+        fields(uri = %self.image.project_image_uri(), path = %path.as_ref().display())
+    )]
+    pub(crate) async fn extract_offline<P>(
+        &self,
+        path: P,
+        arch: &str,
+        locked: &LockedImage,
+    ) -> Result<OCIArchive>
+    where
+        P: AsRef<Path>,
+    {
+        info!(
+            "Extracting kit '{}' to '{}' from the local cache",
+            self.image.name(),
+            path.as_ref().display()
+        );
+        let manifest_list = self.recorded_manifest_list(&path, &locked.digest).await?;
+        let oci_archive = self.oci_archive(&manifest_list, &path, arch)?;
+        ensure!(
+            oci_archive.archive_path().exists(),
+            "kit '{}' for architecture '{arch}' is not in the local cache, it must be fetched with \
+            registry access before it can be used offline",
+            self.image
+        );
+        oci_archive.verify().await?;
+        let config_digest = oci_archive.config_digest().await?;
+        locked.verify_platform(
+            &DockerArchitecture::try_from(arch)?,
+            oci_archive.digest(),
+            Some(&config_digest),
+        )?;
+        oci_archive
+            .unpack_layers(self.kit_dir(&path).join(arch))
+            .await?;
+        Ok(oci_archive)
+    }
+
+    /// Checks every architecture of the kit that has been extracted to `path` against the `locked`
+    /// image, without contacting the registry.
+    #[instrument(level = "trace", skip(locked), fields(path = %path.as_ref().display()))]
+    pub(crate) async fn verify_extracted<P>(&self, path: P, locked: &LockedImage) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let manifest_list = self.recorded_manifest_list(&path, &locked.digest).await?;
+        let kit_dir = self.kit_dir(&path);
+        let mut entries = tokio::fs::read_dir(&kit_dir)
+            .await
+            .context(format!("failed to read directory '{}'", kit_dir.display()))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .context(format!("failed to read directory '{}'", kit_dir.display()))?
+        {
+            let digest_file = entry.path().join("digest");
+            if !digest_file.exists() {
+                continue;
+            }
+            let arch = entry.file_name().to_string_lossy().to_string();
+            let docker_arch = DockerArchitecture::try_from(arch.as_str())?;
+            let expected_digest = manifest_list
+                .manifests
+                .iter()
+                .find(|x| x.platform.as_ref().unwrap().architecture == docker_arch)
+                .map(|manifest| manifest.digest.as_str());
+            let extracted_digest = read_to_string(&digest_file).await?;
+            ensure!(
+                expected_digest == Some(extracted_digest.as_str()),
+                "the extracted copy of kit '{}' for architecture '{arch}' does not match \
+                Twoliter.lock",
+                self.image
+            );
+            locked.verify_platform(&docker_arch, &extracted_digest, None)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the manifest list recorded when the kit was last fetched, ensuring that it is the one
+    /// described by the locked `digest`.
+    async fn recorded_manifest_list<P>(&self, path: P, digest: &str) -> Result<ManifestListView>
+    where
+        P: AsRef<Path>,
+    {
+        let manifest_list_file = self.kit_dir(path).join(MANIFEST_LIST_FILE);
+        ensure!(
+            manifest_list_file.exists(),
+            "kit '{}' has not been fetched, it must be fetched with registry access before it can \
+            be used offline",
+            self.image
+        );
+        let manifest_bytes = read(&manifest_list_file).await?;
+        ensure!(
+            manifest_list_digest(manifest_bytes.as_slice()) == digest,
+            "the local copy of kit '{}' does not match the digest in Twoliter.lock",
+            self.image
+        );
+        serde_json::from_slice(manifest_bytes.as_slice())
+            .context("failed to deserialize manifest list")
+    }
 
-        // First get the manifest for the specific requested architecture
+    /// Returns the cached OCI archive for the given architecture of the kit.
+    fn oci_archive<P>(
+        &self,
+        manifest_list: &ManifestListView,
+        path: P,
+        arch: &str,
+    ) -> Result<OCIArchive>
+    where
+        P: AsRef<Path>,
+    {
         let uri = self.image.project_image_uri();
-        let manifest_list = self.get_manifest(image_tool).await?;
         let docker_arch = DockerArchitecture::try_from(arch)?;
         let manifest = manifest_list
             .manifests
             .iter()
             .find(|x| x.platform.as_ref().unwrap().architecture == docker_arch)
-            .cloned()
             .context(format!(
                 "could not find image for architecture '{}' at {}",
                 docker_arch, uri
             ))?;
 
         let registry = uri.registry.context("failed to resolve image registry")?;
-        let oci_archive = OCIArchive::new(
+        OCIArchive::new(
             registry.as_str(),
             uri.repo.as_str(),
             manifest.digest.as_str(),
-            &cache_path,
-        )?;
-
-        // Checks for the saved image locally, or else pulls and saves it
-        oci_archive.pull_image(image_tool).await?;
-
-        // Checks if this archive has already been extracted by checking a digest file
-        // otherwise cleans up the path and unpacks the archive
-        oci_archive.unpack_layers(&target_path).await?;
-
-        Ok(())
+            path.as_ref().join(CACHE_DIRECTORY),
+        )
     }
 }
 
@@ -365,18 +771,58 @@ mod test {
     use super::*;
     use std::collections::HashMap;
 
+    #[test]
+    fn test_verify_platform() {
+        let mut locked = LockedImage {
+            name: ValidIdentifier("my-kit".into()),
+            version: Version::new(1, 0, 0),
+            vendor: ValidIdentifier("my-vendor".into()),
+            source: "a.com/b/my-kit:v1.0.0".into(),
+            digest: "digest".into(),
+            platform: Vec::new(),
+        };
+        let amd64 = DockerArchitecture::Amd64;
+
+        // Lock files from before platforms were recorded are not verified
+        locked
+            .verify_platform(&amd64, "sha256:other", Some("sha256:other"))
+            .unwrap();
+
+        locked.platform = vec![LockedPlatform {
+            arch: "amd64".into(),
+            manifest_digest: "sha256:manifest".into(),
+            config_digest: "sha256:config".into(),
+        }];
+        locked
+            .verify_platform(&amd64, "sha256:manifest", None)
+            .unwrap();
+        locked
+            .verify_platform(&amd64, "sha256:manifest", Some("sha256:config"))
+            .unwrap();
+        locked
+            .verify_platform(&amd64, "sha256:other", None)
+            .unwrap_err();
+        locked
+            .verify_platform(&amd64, "sha256:manifest", Some("sha256:other"))
+            .unwrap_err();
+        locked
+            .verify_platform(&DockerArchitecture::Arm64, "sha256:manifest", None)
+            .unwrap_err();
+    }
+
     #[test]
     fn test_try_debug_image_metadata_succeeds() {
         // Given a valid encoded metadata string,
         // When we attempt to decode it for debugging,
         // Then the debug string is marked as having been decoded.
-        let encoded = EncodedKitMetadata(
-            "eyJraXQiOltdLCJuYW1lIjoiYm90dGxlcm9ja2V0LWNvcmUta2l0Iiwic2RrIjp7ImRpZ2VzdCI6ImlyY09EUl\
+        let encoded = EncodedKitMetadata {
+            version: 2,
+            encoded: "eyJraXQiOltdLCJuYW1lIjoiYm90dGxlcm9ja2V0LWNvcmUta2l0Iiwic2RrIjp7ImRpZ2VzdCI6ImlyY09EUl\
             d3ZmxjTTdzaisrMmszSk5RWkovb3ZDUVRpUlkrRFpvaGdrNlk9IiwibmFtZSI6InRoYXItYmUtYmV0YS1zZGsiL\
             CJzb3VyY2UiOiJwdWJsaWMuZWNyLmF3cy91MWczYzh6NC90aGFyLWJlLWJldGEtc2RrOnYwLjQzLjAiLCJ2ZW5k\
             b3IiOiJib3R0bGVyb2NrZXQtbmV3IiwidmVyc2lvbiI6IjAuNDMuMCJ9LCJ2ZXJzaW9uIjoiMi4wLjAifQo="
-                .to_string()
-        );
+                .to_string(),
+        };
         assert!(encoded.debug_image_metadata().is_some());
     }
 
@@ -385,7 +831,10 @@ mod test {
         // Given an invalid encoded metadata string,
         // When we attempt to decode it for debugging,
         // Then the debug string is marked as remaining encoded.
-        let junk_data = EncodedKitMetadata("abcdefghijklmnophello".to_string());
+        let junk_data = EncodedKitMetadata {
+            version: 2,
+            encoded: "abcdefghijklmnophello".to_string(),
+        };
         assert!(junk_data.debug_image_metadata().is_none());
     }
 
@@ -446,7 +895,79 @@ mod test {
                 )]),
             })
             .unwrap(),
-            "bar".to_string()
+            (2, "bar".to_string())
         );
     }
+
+    #[test]
+    fn test_extract_encoded_kit_metadata_prefers_newest_readable_version() {
+        let (version, encoded) = EncodedKitMetadata::extract_encoded_kit_metadata(&ConfigView {
+            labels: HashMap::from([
+                (format!("{KIT_METADATA_LABEL_PREFIX}v1"), "old".to_string()),
+                (
+                    format!("{KIT_METADATA_LABEL_PREFIX}v2"),
+                    "current".to_string(),
+                ),
+                (
+                    format!("{KIT_METADATA_LABEL_PREFIX}v9999"),
+                    "new".to_string(),
+                ),
+                (KIT_MINIMUM_TWOLITER_LABEL.to_string(), "99.0.0".to_string()),
+            ]),
+        })
+        .unwrap();
+        assert_eq!((version, encoded.as_str()), (2, "current"));
+    }
+
+    #[test]
+    fn test_extract_encoded_kit_metadata_names_minimum_twoliter_version() {
+        let err = EncodedKitMetadata::extract_encoded_kit_metadata(&ConfigView {
+            labels: HashMap::from([
+                (format!("{KIT_METADATA_LABEL_PREFIX}v3"), "bar".to_string()),
+                (
+                    format!("{KIT_METADATA_LABEL_PREFIX}v9999"),
+                    "bar".to_string(),
+                ),
+                (KIT_MINIMUM_TWOLITER_LABEL.to_string(), "99.0.0".to_string()),
+            ]),
+        })
+        .expect_err("too new")
+        .to_string();
+
+        assert!(err.contains("'v9999'") && err.contains("Upgrade to twoliter 99.0.0"));
+    }
+
+    #[test]
+    fn test_upgrade_v1_kit_metadata() {
+        let v1 = serde_json::json!({
+            "name": "extra-kit",
+            "version": "1.2.0",
+            "sdk": {
+                "name": "bottlerocket-sdk",
+                "version": "0.43.0",
+                "vendor": "bottlerocket",
+                "source": "public.ecr.aws/bottlerocket/bottlerocket-sdk:v0.43.0",
+                "digest": "ircODRWwflcM7sj++2k3JNQZJ/ovCQTiRY+DZohgk6Y=",
+            },
+            "kit": [{
+                "name": "bottlerocket-core-kit",
+                "version": "2.0.0",
+                "vendor": "bottlerocket",
+            }],
+        });
+        let (version, encoded) = EncodedKitMetadata::extract_encoded_kit_metadata(&ConfigView {
+            labels: HashMap::from([(
+                format!("{KIT_METADATA_LABEL_PREFIX}v1"),
+                base64::engine::general_purpose::STANDARD.encode(v1.to_string()),
+            )]),
+        })
+        .unwrap();
+        assert_eq!(version, 1);
+
+        let metadata = ImageMetadata::try_from(EncodedKitMetadata { version, encoded }).unwrap();
+        assert_eq!(metadata.version, Version::new(1, 2, 0));
+        assert_eq!(metadata.sdk.name.as_ref(), "bottlerocket-sdk");
+        assert_eq!(metadata.kits.len(), 1);
+        assert_eq!(metadata.kits[0].version, Version::new(2, 0, 0));
+    }
 }
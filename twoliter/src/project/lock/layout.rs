@@ -0,0 +1,420 @@
+//! Reads kit images from an OCI image layout on the local filesystem rather than from a registry,
+//! so that a kit can be overridden in `Twoliter.override` with one that has not been pushed.
+//!
+//! The override may point at an image layout directory, an `oci-archive` tarball, or a directory of
+//! `oci-archive` tarballs such as the per-architecture archives written by `rpm2kit`. The images in
+//! a directory of tarballs are combined into a single multi-platform image.
+use super::views::{ImageConfigView, ManifestLayoutView, ManifestView};
+use crate::common::fs::{copy, create_dir_all, read, read_to_string, write};
+use anyhow::{ensure, Context, Result};
+use oci_cli_wrapper::ConfigView;
+use olpc_cjson::CanonicalFormatter as CanonicalJsonFormatter;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::cmp::Ordering;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tar::Archive as TarArchive;
+use tempfile::TempDir;
+use tokio::sync::OnceCell;
+use tracing::{debug, instrument};
+
+const INDEX_FILE: &str = "index.json";
+const OCI_LAYOUT_FILE: &str = "oci-layout";
+
+#[derive(Debug)]
+pub(crate) struct OCILayout {
+    /// The path that the layout was opened from
+    path: PathBuf,
+    /// The directory holding the layout's index and blobs
+    root: PathBuf,
+    /// The descriptors of every image manifest in the layout
+    manifests: Vec<Value>,
+    /// Holds the contents of any tarballs for as long as the layout is open
+    _unpacked: Option<TempDir>,
+}
+
+/// An [`OCILayout`] that is opened the first time it is needed and then shared by every copy of the
+/// override that points at it, so that a tarball is unpacked once rather than each time the kit is
+/// resolved or extracted.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SharedLayout(Arc<OnceCell<OCILayout>>);
+
+impl SharedLayout {
+    /// Returns the layout at `path`, opening it if it has not been opened yet.
+    pub(crate) async fn open(&self, path: &Path) -> Result<&OCILayout> {
+        self.0.get_or_try_init(|| OCILayout::open(path)).await
+    }
+}
+
+// The layout is only a cache of what is at the override's path, so it does not affect how overrides
+// compare.
+impl PartialEq for SharedLayout {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for SharedLayout {}
+
+impl PartialOrd for SharedLayout {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SharedLayout {
+    fn cmp(&self, _other: &Self) -> Ordering {
+        Ordering::Equal
+    }
+}
+
+impl Hash for SharedLayout {
+    fn hash<H: Hasher>(&self, _state: &mut H) {}
+}
+
+impl OCILayout {
+    /// Opens the image layout, archive, or directory of archives at `path`.
+    #[instrument(level = "trace")]
+    pub(crate) async fn open(path: &Path) -> Result<Self> {
+        ensure!(
+            path.exists(),
+            "OCI image layout '{}' does not exist",
+            path.display()
+        );
+        if path.is_dir() && path.join(INDEX_FILE).exists() {
+            return Ok(Self {
+                path: path.to_path_buf(),
+                root: path.to_path_buf(),
+                manifests: read_index(path).await?,
+                _unpacked: None,
+            });
+        }
+
+        let archives = if path.is_dir() {
+            list_archives(path).await?
+        } else {
+            vec![path.to_path_buf()]
+        };
+        ensure!(
+            !archives.is_empty(),
+            "'{}' is neither an OCI image layout nor a directory of oci-archive tarballs",
+            path.display()
+        );
+
+        // Blobs are content addressed, so several archives can share a directory. Only the index is
+        // overwritten by each archive, so it is read before the next archive is unpacked.
+        let unpacked = TempDir::new().context("failed to create directory for oci archives")?;
+        let mut manifests = Vec::new();
+        for archive in archives {
+            debug!("Unpacking oci archive '{}'", archive.display());
+            let file = File::open(&archive).context(format!(
+                "failed to open oci archive '{}'",
+                archive.display()
+            ))?;
+            TarArchive::new(file)
+                .unpack(unpacked.path())
+                .context(format!(
+                    "failed to unpack oci archive '{}'",
+                    archive.display()
+                ))?;
+            manifests.extend(read_index(unpacked.path()).await?);
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            root: unpacked.path().to_path_buf(),
+            manifests,
+            _unpacked: Some(unpacked),
+        })
+    }
+
+    /// The path that the layout was opened from.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the layout's images as the canonical JSON of an image index, which takes the place
+    /// of the manifest list fetched from a registry.
+    pub(crate) fn manifest_list(&self) -> Result<Vec<u8>> {
+        let index = json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.index.v1+json",
+            "manifests": self.manifests,
+        });
+        let mut bytes = Vec::new();
+        let mut ser =
+            serde_json::Serializer::with_formatter(&mut bytes, CanonicalJsonFormatter::new());
+        index
+            .serialize(&mut ser)
+            .context("failed to serialize oci image index")?;
+        Ok(bytes)
+    }
+
+    async fn blob(&self, digest: &str) -> Result<Vec<u8>> {
+        read(blob_path(&self.root, digest)?).await.context(format!(
+            "OCI image layout '{}' does not contain blob '{digest}'",
+            self.path.display()
+        ))
+    }
+
+    async fn manifest(&self, digest: &str) -> Result<ManifestLayoutView> {
+        serde_json::from_slice(self.blob(digest).await?.as_slice())
+            .context("failed to deserialize oci manifest")
+    }
+
+    /// Reads the digest of the config of the image with the given manifest digest.
+    pub(crate) async fn config_digest(&self, digest: &str) -> Result<String> {
+        Ok(self.manifest(digest).await?.config.digest.to_string())
+    }
+
+    /// Reads the config of the image with the given manifest digest.
+    pub(crate) async fn config(&self, digest: &str) -> Result<ConfigView> {
+        let manifest = self.manifest(digest).await?;
+        let config = self.blob(&manifest.config.digest.to_string()).await?;
+        let image: ImageConfigView = serde_json::from_slice(config.as_slice())
+            .context("failed to deserialize image config")?;
+        Ok(image.config)
+    }
+
+    /// Copies the image with the given manifest digest into a new image layout at `out_dir`, in the
+    /// same form as an image pulled from a registry.
+    #[instrument(level = "trace", skip(self), fields(layout = %self.path.display()))]
+    pub(crate) async fn copy_image(&self, digest: &str, out_dir: &Path) -> Result<()> {
+        let descriptor = self
+            .manifests
+            .iter()
+            .find(|manifest| manifest["digest"] == digest)
+            .context(format!(
+                "OCI image layout '{}' does not contain manifest '{digest}'",
+                self.path.display()
+            ))?;
+        let manifest = self.manifest(digest).await?;
+
+        create_dir_all(out_dir.join("blobs").join("sha256")).await?;
+        let blobs = [digest.to_string(), manifest.config.digest.to_string()]
+            .into_iter()
+            .chain(manifest.layers.iter().map(|layer| layer.digest.to_string()));
+        for blob in blobs {
+            copy(blob_path(&self.root, &blob)?, blob_path(out_dir, &blob)?).await?;
+        }
+        let index = json!({"schemaVersion": 2, "manifests": [descriptor]});
+        write(out_dir.join(INDEX_FILE), index.to_string()).await?;
+        write(
+            out_dir.join(OCI_LAYOUT_FILE),
+            r#"{"imageLayoutVersion": "1.0.0"}"#,
+        )
+        .await
+    }
+}
+
+/// Returns the path of the blob with the given digest in the OCI image layout at `root`. The digest
+/// is read from an index or manifest, so it must have the form `<algorithm>:<hex>` to be sure that
+/// it does not name a path outside the layout.
+pub(super) fn blob_path(root: &Path, digest: &str) -> Result<PathBuf> {
+    let invalid = || format!("invalid blob digest '{digest}'");
+    let (algorithm, encoded) = digest.split_once(':').with_context(invalid)?;
+    ensure!(
+        !algorithm.is_empty()
+            && algorithm
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+            && !encoded.is_empty()
+            && encoded.bytes().all(|b| b.is_ascii_hexdigit()),
+        invalid()
+    );
+    Ok(root.join("blobs").join(algorithm).join(encoded))
+}
+
+/// Reads the manifest descriptors from the index of the image layout in `dir`.
+async fn read_index(dir: &Path) -> Result<Vec<Value>> {
+    let index_file = dir.join(INDEX_FILE);
+    let index: Value = serde_json::from_str(&read_to_string(&index_file).await?)
+        .context("failed to deserialize oci image index")?;
+    let manifests = index["manifests"]
+        .as_array()
+        .cloned()
+        .context("oci image index does not list any manifests")?;
+    for manifest in manifests.iter() {
+        let view: ManifestView = serde_json::from_value(manifest.clone())
+            .context("failed to deserialize manifest descriptor in oci image index")?;
+        ensure!(
+            view.platform.is_some(),
+            "manifest '{}' in oci image index does not name a platform",
+            view.digest
+        );
+    }
+    Ok(manifests)
+}
+
+/// Lists the `.tar` files in `dir` in a stable order.
+async fn list_archives(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut archives = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir)
+        .await
+        .context(format!("failed to read directory '{}'", dir.display()))?;
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .context(format!("failed to read directory '{}'", dir.display()))?
+    {
+        let path = entry.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "tar") {
+            archives.push(path);
+        }
+    }
+    archives.sort();
+    Ok(archives)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sha2::Digest;
+
+    /// Writes a blob into the layout at `dir`, returning its digest and size.
+    fn write_blob(dir: &Path, content: &[u8]) -> (String, usize) {
+        let digest = format!("{:x}", sha2::Sha256::digest(content));
+        let blobs = dir.join("blobs/sha256");
+        std::fs::create_dir_all(&blobs).unwrap();
+        std::fs::write(blobs.join(&digest), content).unwrap();
+        (format!("sha256:{digest}"), content.len())
+    }
+
+    /// Writes a single-platform kit image layout like those created by `rpm2kit` into `dir`.
+    fn write_layout(dir: &Path, arch: &str, label: &str) -> String {
+        let (layer, layer_size) = write_blob(dir, b"layer");
+        let config = json!({
+            "architecture": arch,
+            "config": {"Labels": {"dev.bottlerocket.kit.v2": label}},
+        });
+        let (config, config_size) = write_blob(dir, config.to_string().as_bytes());
+        let manifest = json!({
+            "schemaVersion": 2,
+            "config": {"digest": config, "size": config_size},
+            "layers": [{"digest": layer, "size": layer_size}],
+        });
+        let (manifest, manifest_size) = write_blob(dir, manifest.to_string().as_bytes());
+        let index = json!({
+            "schemaVersion": 2,
+            "manifests": [{
+                "digest": manifest,
+                "size": manifest_size,
+                "platform": {"architecture": arch, "os": "linux"},
+            }],
+        });
+        std::fs::write(dir.join(INDEX_FILE), index.to_string()).unwrap();
+        std::fs::write(
+            dir.join(OCI_LAYOUT_FILE),
+            r#"{"imageLayoutVersion": "1.0.0"}"#,
+        )
+        .unwrap();
+        manifest
+    }
+
+    fn write_archive(dir: &Path, archive: &Path) {
+        let mut builder = tar::Builder::new(File::create(archive).unwrap());
+        builder.append_dir_all(".", dir).unwrap();
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    fn test_blob_path() {
+        let root = Path::new("layout");
+        assert_eq!(
+            blob_path(root, "sha256:0a1b").unwrap(),
+            root.join("blobs/sha256/0a1b")
+        );
+        for digest in [
+            "sha256:../../x",
+            "sha256:",
+            ":0a1b",
+            "0a1b",
+            "../sha256:0a1b",
+            "sha256:0a1b/..",
+        ] {
+            blob_path(root, digest).unwrap_err();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_layout_directory() {
+        let tempdir = TempDir::new().unwrap();
+        let digest = write_layout(tempdir.path(), "amd64", "metadata");
+
+        let layout = OCILayout::open(tempdir.path()).await.unwrap();
+        let index: Value = serde_json::from_slice(&layout.manifest_list().unwrap()).unwrap();
+        assert_eq!(index["manifests"][0]["digest"], digest.as_str());
+        let config = layout.config(&digest).await.unwrap();
+        assert_eq!(config.labels["dev.bottlerocket.kit.v2"], "metadata");
+
+        let out_dir = tempdir.path().join("copy");
+        layout.copy_image(&digest, &out_dir).await.unwrap();
+        let copied = OCILayout::open(&out_dir).await.unwrap();
+        assert_eq!(
+            copied.manifest_list().unwrap(),
+            layout.manifest_list().unwrap()
+        );
+        layout
+            .copy_image("sha256:missing", &out_dir)
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_directory_of_archives() {
+        let tempdir = TempDir::new().unwrap();
+        let archives = tempdir.path().join("archives");
+        std::fs::create_dir_all(&archives).unwrap();
+        let mut digests = Vec::new();
+        for arch in ["amd64", "arm64"] {
+            let layout_dir = tempdir.path().join(arch);
+            std::fs::create_dir_all(&layout_dir).unwrap();
+            digests.push(write_layout(&layout_dir, arch, arch));
+            write_archive(&layout_dir, &archives.join(format!("my-kit-{arch}.tar")));
+        }
+
+        // A single archive holds one platform
+        let layout = OCILayout::open(&archives.join("my-kit-arm64.tar"))
+            .await
+            .unwrap();
+        assert_eq!(layout.manifests.len(), 1);
+
+        // A directory of archives is combined into one multi-platform image
+        let layout = OCILayout::open(&archives).await.unwrap();
+        let index: Value = serde_json::from_slice(&layout.manifest_list().unwrap()).unwrap();
+        assert_eq!(index["manifests"].as_array().unwrap().len(), 2);
+        for (arch, digest) in ["amd64", "arm64"].iter().zip(digests) {
+            let config = layout.config(&digest).await.unwrap();
+            assert_eq!(&config.labels["dev.bottlerocket.kit.v2"], arch);
+        }
+
+        OCILayout::open(&tempdir.path().join("missing"))
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_shared_layout_is_unpacked_once() {
+        let tempdir = TempDir::new().unwrap();
+        let layout_dir = tempdir.path().join("layout");
+        std::fs::create_dir_all(&layout_dir).unwrap();
+        write_layout(&layout_dir, "amd64", "metadata");
+        let archive = tempdir.path().join("my-kit.tar");
+        write_archive(&layout_dir, &archive);
+
+        let shared = SharedLayout::default();
+        let copy = shared.clone();
+        let root = shared.open(&archive).await.unwrap().root.clone();
+        assert_eq!(copy.open(&archive).await.unwrap().root, root);
+        assert!(root.exists());
+
+        // The unpacked archive is removed with the last copy of the layout.
+        drop(shared);
+        assert!(root.exists());
+        drop(copy);
+        assert!(!root.exists());
+    }
+}
@@ -5,34 +5,60 @@
 
 /// Contains operations for working with an OCI Archive
 mod archive;
+/// Tracks the OCI archives that are cached when kits are fetched
+mod cache;
+/// Reports the differences between Twoliter.lock and a freshly resolved lock
+mod drift;
+/// Records the kit and SDK dependency graph discovered during resolution
+mod graph;
 /// Covers resolution and validation of a single image dependency in a lock file
 mod image;
+/// Reads images from OCI image layouts and archives on the local filesystem
+mod layout;
+/// Lists the versions of the SDK and kits that are available from their vendors
+mod outdated;
+/// Selects versions of kits that satisfy the version requirements placed on them
+mod resolver;
+/// Verifies the signatures that vendors attach to their images
+mod signature;
+/// Shares pulled images between projects through a per-user store of OCI blobs
+mod store;
 /// Provides tools for marking artifacts as having been verified against the Twoliter lockfile
 mod verification;
 /// Implements view models of common OCI manifest and configuration types
 mod views;
 
+pub(crate) use self::cache::{CacheEntry, KitCache, PruneFilter, CACHE_DIRECTORY};
+pub(crate) use self::drift::LockDrift;
+pub(crate) use self::graph::DependencyGraph;
+pub(crate) use self::image::LockedImage;
+pub(crate) use self::layout::OCILayout;
+pub(crate) use self::outdated::OutdatedReport;
+pub(crate) use self::store::OciStore;
 pub(crate) use self::verification::VerificationTagger;
 
 use crate::common::fs::{create_dir_all, read, write};
-use crate::project::{Project, ValidIdentifier};
-use crate::schema_version::SchemaVersion;
+use crate::compatibility::SUPPORTED_TWOLITER_LOCK_SCHEMA_VERSION;
+use crate::project::{Image, LocalKit, Project, ValidIdentifier};
 use anyhow::{bail, ensure, Context, Result};
-use image::{ImageResolver, LockedImage};
-use oci_cli_wrapper::ImageTool;
+use drift::ImageChange;
+use graph::ResolvedKit;
+use image::ImageResolver;
+use layout::SharedLayout;
 use olpc_cjson::CanonicalFormatter as CanonicalJsonFormatter;
+use resolver::KitResolver;
 use semver::Version;
 use serde::{Deserialize, Serialize};
 use std::cmp::PartialEq;
-use std::collections::{HashMap, HashSet};
-use std::fmt::Debug;
-use std::mem::take;
+use std::collections::HashSet;
+use std::fmt::{Debug, Display, Formatter};
+use std::path::PathBuf;
 use tokio::fs::read_to_string;
-use tracing::{debug, error, info, instrument};
+use tracing::{debug, error, info, instrument, warn};
 
 use super::{Locked, ProjectLock, Unlocked};
 
-const TWOLITER_LOCK: &str = "Twoliter.lock";
+pub(super) const TWOLITER_LOCK: &str = "Twoliter.lock";
 
 #[derive(Serialize, Debug)]
 struct ExternalKitMetadata {
@@ -41,11 +67,47 @@ struct ExternalKitMetadata {
     kits: Vec<LockedImage>,
 }
 
+/// An entry in Twoliter.override, which redirects an artifact to another name and registry, or to
+/// an OCI image layout on the local filesystem.
 #[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Serialize, Deserialize, Hash)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) struct Override {
     pub name: Option<String>,
     pub registry: Option<String>,
+    /// An OCI image layout directory, `oci-archive` tarball, or directory of `oci-archive` tarballs
+    /// to read the artifact from instead of a registry
+    pub path: Option<PathBuf>,
+    /// The layout at `path`, once it has been opened
+    #[serde(skip)]
+    pub layout: SharedLayout,
+}
+
+/// Controls whether loading a lock may contact registries to re-resolve the project's dependencies.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum LockMode {
+    /// Re-resolve every dependency and require that the result matches Twoliter.lock.
+    #[default]
+    Resolve,
+    /// Trust Twoliter.lock without contacting any registry. The lock is still checked against
+    /// Twoliter.toml, and kits are verified against the copies saved when they were last fetched.
+    Frozen,
+}
+
+/// Selects which entries of an existing Twoliter.lock are re-resolved by `twoliter update`.
+///
+/// The default scope updates everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct UpdateScope {
+    /// Kits to update, along with every kit that they depend on
+    pub kits: Vec<ValidIdentifier>,
+    /// Whether to update the SDK
+    pub sdk: bool,
+}
+
+impl UpdateScope {
+    fn is_everything(&self) -> bool {
+        self.kits.is_empty() && !self.sdk
+    }
 }
 
 /// A resolved and locked project SDK, typically from the Twoliter.lock file for a project.
@@ -63,10 +125,15 @@ impl LockedSDK {
     ///
     /// Re-resolves the project's SDK to ensure that the lockfile matches the state of the world.
     #[instrument(level = "trace", skip(project))]
-    pub(super) async fn load(project: &Project<Unlocked>) -> Result<Self> {
-        info!("Resolving SDK project reference to check against lock file");
-
+    pub(super) async fn load(project: &Project<Unlocked>, mode: LockMode) -> Result<Self> {
         let current_lock = Lock::current_lock_state(project).await?;
+        if mode == LockMode::Frozen {
+            info!("Checking SDK project reference against lock file without resolving it");
+            current_lock.check_sdk(project)?;
+            return Ok(Self(current_lock.sdk));
+        }
+
+        info!("Resolving SDK project reference to check against lock file");
         let resolved_lock = Self::resolve_sdk(project)
             .await?
             .context("Project does not have explicit SDK image.")?;
@@ -76,13 +143,21 @@ impl LockedSDK {
             resolved_sdk=?resolved_lock,
             "Comparing resolved SDK to current lock state"
         );
-        if &current_lock.sdk != resolved_lock.as_ref() {
+        if let Some(change) = ImageChange::between(&current_lock.sdk, resolved_lock.as_ref()) {
             error!(
                 current_sdk=?current_lock.sdk,
                 resolved_sdk=?resolved_lock,
                 "Locked SDK does not match resolved SDK",
             );
-            bail!("Changes have occured to Twoliter.toml or the remote SDK image that require an update to Twoliter.lock");
+            let drift = LockDrift {
+                sdk: Some(change),
+                ..Default::default()
+            };
+            bail!(
+                "Changes have occured to Twoliter.toml or the remote SDK image that require an \
+                update to Twoliter.lock\n{}",
+                drift.render_text().trim_end()
+            );
         }
 
         Ok(resolved_lock)
@@ -103,7 +178,7 @@ impl LockedSDK {
         };
 
         debug!(?sdk, "Resolving workspace SDK");
-        let image_tool = ImageTool::from_builtin_krane();
+        let image_tool = project.image_tool().await?;
         ImageResolver::from_image(&sdk)?
             .skip_metadata_retrieval() // SDKs don't have metadata
             .resolve(&image_tool)
@@ -112,16 +187,95 @@ impl LockedSDK {
     }
 }
 
+/// A kit that is built from another project on the local filesystem, see [`LocalKit`].
+///
+/// Since the kit is built along with the project, only the path and the version of the kit's
+/// project are locked.
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct LockedPathKit {
+    pub name: ValidIdentifier,
+    pub version: Version,
+    /// The path to the kit's project as written in Twoliter.toml
+    pub path: PathBuf,
+}
+
+impl From<&LocalKit> for LockedPathKit {
+    fn from(kit: &LocalKit) -> Self {
+        Self {
+            name: kit.name().clone(),
+            version: kit.version().clone(),
+            path: kit.path().to_path_buf(),
+        }
+    }
+}
+
+impl Display for LockedPathKit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}-{} (from {})",
+            self.name,
+            self.version,
+            self.path.display()
+        )
+    }
+}
+
+/// The schema version of a `Twoliter.lock` file.
+///
+/// Version 2 added the digests of each platform's image manifest and config to locked kits. Lock
+/// files with version 1 are still read, and are rewritten with the current version the next time
+/// the lock is resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(try_from = "u32", into = "u32")]
+pub(crate) struct LockSchemaVersion(u32);
+
+impl LockSchemaVersion {
+    /// Returns true if lock files with this version need to be rewritten with the current version.
+    pub(crate) fn needs_migration(&self) -> bool {
+        self.0 < SUPPORTED_TWOLITER_LOCK_SCHEMA_VERSION
+    }
+}
+
+impl Default for LockSchemaVersion {
+    fn default() -> Self {
+        Self(SUPPORTED_TWOLITER_LOCK_SCHEMA_VERSION)
+    }
+}
+
+impl TryFrom<u32> for LockSchemaVersion {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u32) -> Result<Self> {
+        ensure!(
+            (1..=SUPPORTED_TWOLITER_LOCK_SCHEMA_VERSION).contains(&value),
+            "Incorrect lock schema_version: got '{value}', expected at most \
+            '{SUPPORTED_TWOLITER_LOCK_SCHEMA_VERSION}'"
+        );
+        Ok(Self(value))
+    }
+}
+
+impl From<LockSchemaVersion> for u32 {
+    fn from(value: LockSchemaVersion) -> Self {
+        value.0
+    }
+}
+
 /// Represents the structure of a `Twoliter.lock` lock file.
 #[derive(Debug, Clone, Eq, Ord, PartialOrd, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) struct Lock {
-    /// The version of the Twoliter.toml this was generated from
-    pub schema_version: SchemaVersion<1>,
+    /// The schema version of the lock file
+    pub schema_version: LockSchemaVersion,
     /// The resolved bottlerocket sdk
     pub sdk: LockedImage,
     /// Resolved kit dependencies
     pub kit: Vec<LockedImage>,
+    /// Kit dependencies that are built from other projects on the local filesystem
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub path_kit: Vec<LockedPathKit>,
 }
 
 impl PartialEq for Lock {
@@ -129,6 +283,7 @@ impl PartialEq for Lock {
         self.schema_version == other.schema_version
             && self.sdk == other.sdk
             && self.kit == other.kit
+            && self.path_kit == other.path_kit
     }
 }
 
@@ -136,47 +291,207 @@ impl PartialEq for Lock {
 impl Lock {
     #[instrument(level = "trace", skip(project))]
     pub(super) async fn create(project: &Project<Unlocked>) -> Result<Self> {
-        let lock_file_path = project.project_dir().join(TWOLITER_LOCK);
-
         info!("Resolving project references to create lock file");
-        let lock_state = Self::resolve(project).await?;
-        let lock_str = toml::to_string(&lock_state).context("failed to serialize lock file")?;
+        let lock_state = Self::resolve(project, None).await?;
+        lock_state.write(project).await?;
+        Ok(lock_state)
+    }
+
+    /// Re-resolves the entries of the project's lockfile that fall within `scope`.
+    ///
+    /// Kits outside of the scope keep their locked version and digest unless Twoliter.toml or an
+    /// updated kit now requires a different version of them.
+    #[instrument(level = "trace", skip(project))]
+    pub(super) async fn update(project: &Project<Unlocked>, scope: &UpdateScope) -> Result<Self> {
+        if scope.is_everything() {
+            return Self::create(project).await;
+        }
+        let Some(current_lock) = Self::existing_lock_state(project).await? else {
+            info!("No existing lock file to update, resolving all project references");
+            return Self::create(project).await;
+        };
+        for name in scope.kits.iter() {
+            ensure!(
+                current_lock.kit.iter().any(|kit| &kit.name == name)
+                    || project
+                        .direct_kit_deps()
+                        .iter()
+                        .any(|kit| &kit.name == name),
+                "kit '{name}' is not a dependency of this project"
+            );
+        }
+
+        // The kits that an updated kit depends on are only known after resolving it, and updating
+        // them may in turn introduce new dependencies, so widen the set of unlocked kits until it
+        // covers the closure of the requested kits.
+        let mut unlocked: HashSet<ValidIdentifier> = scope.kits.iter().cloned().collect();
+        let resolved_lock = loop {
+            let preferred = Self {
+                kit: current_lock
+                    .kit
+                    .iter()
+                    .filter(|kit| !unlocked.contains(&kit.name))
+                    .cloned()
+                    .collect(),
+                ..current_lock.clone()
+            };
+            let (resolved_lock, graph) =
+                Self::resolve_with_graph(project, Some(&preferred)).await?;
+            let closure = graph.kit_closure(&scope.kits);
+            if closure.is_subset(&unlocked) {
+                break resolved_lock;
+            }
+            unlocked.extend(closure);
+        };
+        debug!(?unlocked, "Updating kits");
+
+        let kit = resolved_lock
+            .kit
+            .into_iter()
+            .map(|resolved| {
+                if unlocked.contains(&resolved.name) {
+                    return resolved;
+                }
+                match current_lock
+                    .kit
+                    .iter()
+                    .find(|kit| kit.name == resolved.name)
+                {
+                    Some(existing) => Self::keep_existing(existing, resolved),
+                    None => resolved,
+                }
+            })
+            .collect();
+        let sdk = if scope.sdk {
+            resolved_lock.sdk
+        } else {
+            Self::keep_existing(&current_lock.sdk, resolved_lock.sdk)
+        };
+
+        let lock_state = Self {
+            schema_version: resolved_lock.schema_version,
+            sdk,
+            kit,
+            path_kit: resolved_lock.path_kit,
+        };
+        lock_state.write(project).await?;
+        Ok(lock_state)
+    }
+
+    /// Keeps the existing entry for anything outside the scope of an update that resolved to the
+    /// same release, so that a re-pushed digest of an unrelated image is not picked up.
+    ///
+    /// Entries from a schema version 1 lock do not record the image of each platform. They are
+    /// completed from the resolved entry when it has the same digest, and otherwise replaced by it,
+    /// since the platforms of the old digest are no longer known.
+    fn keep_existing(existing: &LockedImage, resolved: LockedImage) -> LockedImage {
+        if !existing.is_same_release(&resolved) {
+            return resolved;
+        }
+        if !existing.platform.is_empty() || resolved.platform.is_empty() {
+            return existing.clone();
+        }
+        if existing.digest != resolved.digest {
+            warn!(
+                "'{existing}' has been re-pushed since it was locked, and its platforms can only be \
+                locked for the current digest '{}'",
+                resolved.digest
+            );
+            return resolved;
+        }
+        LockedImage {
+            platform: resolved.platform,
+            ..existing.clone()
+        }
+    }
+
+    async fn write<L: ProjectLock>(&self, project: &Project<L>) -> Result<()> {
+        let lock_file_path = project.project_dir().join(TWOLITER_LOCK);
+        let lock_str = toml::to_string(self).context("failed to serialize lock file")?;
 
         debug!("Writing new lock file to '{}'", lock_file_path.display());
         write(&lock_file_path, lock_str)
             .await
-            .context("failed to write lock file")?;
-        Ok(lock_state)
+            .context("failed to write lock file")
     }
 
     /// Loads the lockfile for the given project.
     ///
     /// Re-resolves the project's dependencies to ensure that the lockfile matches the state of the
-    /// world.
+    /// world. Kit versions recorded in the lockfile are kept as long as they still satisfy the
+    /// project's requirements, so publishing a newer kit does not invalidate the lockfile.
+    ///
+    /// In [`LockMode::Frozen`], the lockfile is instead checked against Twoliter.toml and the kits
+    /// that have already been fetched, without contacting any registry.
     #[instrument(level = "trace", skip(project))]
-    pub(super) async fn load(project: &Project<Unlocked>) -> Result<Self> {
-        info!("Resolving project references to check against lock file");
-
+    pub(super) async fn load(project: &Project<Unlocked>, mode: LockMode) -> Result<Self> {
         let current_lock = Self::current_lock_state(project).await?;
-        let resolved_lock = Self::resolve(project).await?;
+        if mode == LockMode::Frozen {
+            info!("Checking project references against lock file without resolving them");
+            if current_lock.schema_version.needs_migration() {
+                warn!(
+                    "Twoliter.lock does not record the digests of each platform's image, so they \
+                    cannot be verified. Load the lock without --frozen to migrate it."
+                );
+            }
+            current_lock.check_against_project(project)?;
+            current_lock.verify_fetched_kits(project).await?;
+            return Ok(current_lock);
+        }
+
+        info!("Resolving project references to check against lock file");
+        let resolved_lock = Self::resolve(project, Some(&current_lock)).await?;
 
         debug!(
             current_lock=?current_lock,
             resolved_lock=?resolved_lock,
             "Comparing resolved lock to current lock state"
         );
-        if current_lock != resolved_lock {
+        let drift = LockDrift::between(&current_lock, &resolved_lock);
+        if !drift.is_empty() {
             error!(
                 current_lock=?current_lock,
                 resolved_lock=?resolved_lock,
                 "Locked dependencies do not match resolved dependencies"
             );
-            bail!("changes have occured to Twoliter.toml or the remote kit images that require an update to Twoliter.lock");
+            bail!(
+                "changes have occured to Twoliter.toml or the remote kit images that require an \
+                update to Twoliter.lock\n{}",
+                drift.render_text().trim_end()
+            );
+        }
+
+        if current_lock.schema_version.needs_migration() {
+            info!(
+                "Migrating Twoliter.lock from schema version {} to {}",
+                u32::from(current_lock.schema_version),
+                u32::from(resolved_lock.schema_version)
+            );
+            resolved_lock.write(project).await?;
         }
 
         Ok(resolved_lock)
     }
 
+    /// Re-resolves the project's dependencies in the same way as [`Lock::load`], and reports how
+    /// the result differs from the lockfile.
+    #[instrument(level = "trace", skip(project))]
+    pub(super) async fn drift(project: &Project<Unlocked>) -> Result<LockDrift> {
+        info!("Resolving project references to check against lock file");
+        let current_lock = Self::current_lock_state(project).await?;
+        let resolved_lock = Self::resolve(project, Some(&current_lock)).await?;
+        Ok(LockDrift::between(&current_lock, &resolved_lock))
+    }
+
+    /// Lists the versions of the project's SDK and kits that are published to their vendors'
+    /// registries, alongside the versions in the lockfile if there is one.
+    #[instrument(level = "trace", skip(project))]
+    pub(super) async fn outdated(project: &Project<Unlocked>) -> Result<OutdatedReport> {
+        let existing_lock = Self::existing_lock_state(project).await?;
+        let image_tool = project.image_tool().await?;
+        OutdatedReport::check(project, existing_lock.as_ref(), &image_tool).await
+    }
+
     /// Returns the state of the lockfile for the given `Project`
     async fn current_lock_state<L: ProjectLock>(project: &Project<L>) -> Result<Self> {
         let lock_file_path = project.project_dir().join(TWOLITER_LOCK);
@@ -190,20 +505,127 @@ impl Lock {
             .context("failed to read lockfile")?;
         let lock: Self =
             toml::from_str(lock_str.as_str()).context("failed to deserialize lockfile")?;
+        lock.check_platforms()?;
         Ok(lock)
     }
 
-    fn external_kit_metadata(&self) -> ExternalKitMetadata {
+    /// Checks that every kit records the image of each platform, which is required from schema
+    /// version 2 onwards so that kits can be verified when they are fetched.
+    fn check_platforms(&self) -> Result<()> {
+        if self.schema_version.needs_migration() {
+            return Ok(());
+        }
+        for kit in self.kit.iter() {
+            ensure!(
+                !kit.platform.is_empty(),
+                "Twoliter.lock does not record the image of each platform of kit '{kit}', please \
+                run `twoliter update --kit {}`",
+                kit.name
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the state of the lockfile for the given `Project`, if one exists
+    pub(super) async fn existing_lock_state<L: ProjectLock>(
+        project: &Project<L>,
+    ) -> Result<Option<Self>> {
+        if project.project_dir().join(TWOLITER_LOCK).exists() {
+            Self::current_lock_state(project).await.map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Checks that the locked SDK is the one that Twoliter.toml asks for, without resolving it.
+    fn check_sdk<L: ProjectLock>(&self, project: &Project<L>) -> Result<()> {
+        if let Some(sdk) = project.direct_sdk_image_dep() {
+            let sdk = sdk?;
+            ensure!(
+                sdk.name() == &self.sdk.name
+                    && sdk.vendor_name() == &self.sdk.vendor
+                    && sdk.version() == &self.sdk.version,
+                "Twoliter.toml requires SDK '{sdk}' but Twoliter.lock contains '{}', please run \
+                `twoliter update`",
+                self.sdk
+            );
+        }
+        self.check_source(project, &self.sdk)
+    }
+
+    /// Checks that every dependency in Twoliter.toml is satisfied by the lock, without resolving
+    /// anything. Kits that are only required by other kits cannot be checked without their
+    /// metadata, so are trusted as locked.
+    fn check_against_project<L: ProjectLock>(&self, project: &Project<L>) -> Result<()> {
+        self.check_sdk(project)?;
+        for requirement in project.direct_kit_deps() {
+            ensure!(
+                self.kit.iter().any(|kit| kit.name == requirement.name
+                    && kit.vendor == requirement.vendor
+                    && requirement.version.matches(&kit.version)),
+                "Twoliter.toml requires kit '{requirement}' which is not satisfied by Twoliter.lock, \
+                please run `twoliter update`"
+            );
+        }
+        for kit in self.kit.iter() {
+            self.check_source(project, kit)?;
+        }
+        let path_kit: Vec<LockedPathKit> = project.local_kits().iter().map(Into::into).collect();
+        ensure!(
+            path_kit == self.path_kit,
+            "the kits that Twoliter.toml builds from a path do not match Twoliter.lock, please run \
+            `twoliter update`"
+        );
+        Ok(())
+    }
+
+    /// Checks that a locked image would still be pulled from the same place, for instance after a
+    /// change to the vendor's registry or to Twoliter.override.
+    fn check_source<L: ProjectLock>(
+        &self,
+        project: &Project<L>,
+        image: &LockedImage,
+    ) -> Result<()> {
+        let source = project.as_project_image(image)?.original_source_uri();
+        ensure!(
+            source.to_string() == image.source,
+            "'{image}' would now be resolved from '{source}', please run `twoliter update`"
+        );
+        Ok(())
+    }
+
+    /// Verifies the kits that have been fetched to the external kits directory against the lock.
+    async fn verify_fetched_kits<L: ProjectLock>(&self, project: &Project<L>) -> Result<()> {
+        for kit in self.kit.iter() {
+            let image = project.as_project_image(kit)?;
+            ImageResolver::from_image(&image)?
+                .verify_extracted(project.external_kits_dir(), kit)
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn external_kit_metadata(&self, local_kits: &[LockedImage]) -> ExternalKitMetadata {
         ExternalKitMetadata {
             sdk: self.sdk.clone(),
-            kits: self.kit.clone(),
+            kits: self.kit.iter().chain(local_kits).cloned().collect(),
         }
     }
 
     /// Fetches all external kits defined in a Twoliter.lock to the build directory
+    ///
+    /// In [`LockMode::Frozen`], kits are extracted from the local cache rather than the registry.
+    ///
+    /// Kits from a local path are built in their own project and copied alongside the extracted
+    /// kits.
     #[instrument(level = "trace", skip_all)]
-    pub(crate) async fn fetch(&self, project: &Project<Locked>, arch: &str) -> Result<()> {
-        let image_tool = ImageTool::from_builtin_krane();
+    pub(crate) async fn fetch(
+        &self,
+        project: &Project<Locked>,
+        arch: &str,
+        mode: LockMode,
+    ) -> Result<()> {
+        let image_tool = project.image_tool().await?;
         let target_dir = project.external_kits_dir();
         create_dir_all(&target_dir).await.context(format!(
             "failed to create external-kits directory at {}",
@@ -214,22 +636,54 @@ impl Lock {
             dependencies = ?self.kit.iter().map(ToString::to_string).collect::<Vec<_>>(),
             "Extracting kit dependencies."
         );
-        for image in self.kit.iter() {
-            let image = project.as_project_image(image)?;
+        let cache = project.kit_cache();
+        let lockfile = project.lockfile();
+        for kit in self.kit.iter() {
+            let image = project.as_project_image(kit)?;
             let resolver = ImageResolver::from_image(&image)?;
-            resolver
-                .extract(&image_tool, &project.external_kits_dir(), arch)
-                .await?;
+            let archive = match mode {
+                LockMode::Resolve => {
+                    resolver
+                        .extract(&image_tool, &project.external_kits_dir(), arch, kit)
+                        .await?
+                }
+                LockMode::Frozen => {
+                    resolver
+                        .extract_offline(&project.external_kits_dir(), arch, kit)
+                        .await?
+                }
+            };
+            cache.record_use(&archive, kit, &lockfile).await?;
+        }
+
+        let mut local_kits = Vec::new();
+        for local_kit in project.local_kits() {
+            local_kit.build(arch, mode).await?;
+            let digest = local_kit.install(&target_dir, arch).await?;
+            local_kits.push(LockedImage {
+                name: local_kit.name().clone(),
+                version: local_kit.version().clone(),
+                vendor: local_kit.image().vendor,
+                source: local_kit.source(),
+                digest,
+                platform: Vec::new(),
+            });
         }
 
-        self.synchronize_metadata(project).await
+        self.synchronize_metadata(project, &local_kits).await
     }
 
-    pub(crate) async fn synchronize_metadata(&self, project: &Project<Locked>) -> Result<()> {
+    /// Writes the external kit metadata read by buildsys, listing the locked kits along with the
+    /// given kits that were built from a local path.
+    pub(crate) async fn synchronize_metadata(
+        &self,
+        project: &Project<Locked>,
+        local_kits: &[LockedImage],
+    ) -> Result<()> {
         let mut kit_list = Vec::new();
         let mut ser =
             serde_json::Serializer::with_formatter(&mut kit_list, CanonicalJsonFormatter::new());
-        self.external_kit_metadata()
+        self.external_kit_metadata(local_kits)
             .serialize(&mut ser)
             .context("failed to serialize external kit metadata")?;
         // Compare the output of the serialize if the file exists
@@ -253,56 +707,41 @@ impl Lock {
         Ok(())
     }
 
-    #[instrument(level = "trace", skip(project))]
-    async fn resolve(project: &Project<Unlocked>) -> Result<Self> {
-        let mut known: HashMap<(ValidIdentifier, ValidIdentifier), Version> = HashMap::new();
-        let mut locked: Vec<LockedImage> = Vec::new();
-        let image_tool = ImageTool::from_builtin_krane();
-        let mut remaining = project.direct_kit_deps()?;
+    #[instrument(level = "trace", skip(project, preferred))]
+    async fn resolve(project: &Project<Unlocked>, preferred: Option<&Self>) -> Result<Self> {
+        Self::resolve_with_graph(project, preferred)
+            .await
+            .map(|(lock, _graph)| lock)
+    }
 
+    /// Resolves the project's dependencies, returning both the lock and the dependency graph that
+    /// was walked to produce it.
+    ///
+    /// Where a `preferred` lock is given, the kit versions it contains are selected whenever they
+    /// satisfy every requirement. Otherwise the newest version satisfying the requirements is used.
+    #[instrument(level = "trace", skip(project, preferred))]
+    pub(super) async fn resolve_with_graph(
+        project: &Project<Unlocked>,
+        preferred: Option<&Self>,
+    ) -> Result<(Self, DependencyGraph)> {
+        let image_tool = project.image_tool().await?;
+        let resolved = KitResolver::new(project, &image_tool)
+            .prefer(preferred.iter().flat_map(|lock| lock.kit.iter()))
+            .resolve()
+            .await?;
+
+        // The layout that an overridden image caches is not part of its hash.
+        #[allow(clippy::mutable_key_type)]
         let mut sdk_set = HashSet::new();
         if let Some(sdk) = project.direct_sdk_image_dep() {
             // We don't scan over the sdk images as they are not kit images and there is no kit metadata to fetch
             sdk_set.insert(sdk?.clone());
         }
-        while !remaining.is_empty() {
-            let working_set: Vec<_> = take(&mut remaining);
-            for image in working_set.iter() {
-                debug!(%image, "Resolving kit '{}'", image.name());
-                if let Some(version) =
-                    known.get(&(image.name().clone(), image.vendor_name().clone()))
-                {
-                    let name = image.name().clone();
-                    let left_version = image.version().clone();
-                    let vendor = image.vendor_name().clone();
-                    ensure!(
-                        image.version() == version,
-                        "cannot have multiple versions of the same kit ({name}-{left_version}@{vendor} \
-                        != {name}-{version}@{vendor}",
-                    );
-                    debug!(
-                        ?image,
-                        "Skipping kit '{}' as it has already been resolved",
-                        image.name()
-                    );
-                    continue;
-                }
-                known.insert(
-                    (image.name().clone(), image.vendor_name().clone()),
-                    image.version().clone(),
-                );
-                let image_resolver = ImageResolver::from_image(image)?;
-                let (locked_image, metadata) = image_resolver.resolve(&image_tool).await?;
-                let metadata = metadata.context(format!(
-                    "failed to validate kit image with name {} from vendor {}",
-                    locked_image.name, locked_image.vendor
-                ))?;
-                locked.push(locked_image);
-                sdk_set.insert(project.as_project_image(&metadata.sdk)?);
-                for dep in metadata.kits {
-                    remaining.push(project.as_project_image(&dep)?);
-                }
-            }
+        for sdk in resolved.sdks.iter() {
+            sdk_set.insert(project.as_project_image(sdk)?);
+        }
+        for sdk in project.local_kits().iter().filter_map(LocalKit::sdk) {
+            sdk_set.insert(project.as_project_image(sdk)?);
         }
         debug!(?sdk_set, "Resolving workspace SDK");
         ensure!(
@@ -325,10 +764,166 @@ impl Lock {
             .resolve(&image_tool)
             .await?;
 
-        Ok(Self {
-            schema_version: project.schema_version(),
-            kit: locked,
-            sdk,
-        })
+        let resolved_sdk = Image::from_vended_artifact(&sdk);
+        let mut graph = DependencyGraph {
+            sdk: project.sdk.clone(),
+            kit: resolved.direct,
+            resolved_sdk: resolved_sdk.clone(),
+            resolved_kits: resolved.graph,
+        };
+        // Kits from a local path are built with the project's SDK.
+        for (image, kit) in resolved.local {
+            graph.kit.push(image.clone());
+            graph.resolved_kits.push(ResolvedKit {
+                image,
+                sdk: resolved_sdk.clone(),
+                kit,
+            });
+        }
+
+        Ok((
+            Self {
+                schema_version: LockSchemaVersion::default(),
+                kit: resolved.kits,
+                path_kit: project.local_kits().iter().map(Into::into).collect(),
+                sdk,
+            },
+            graph,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::data_dir;
+    use image::LockedPlatform;
+    use semver::Version;
+
+    fn locked(name: &str, version: &str) -> LockedImage {
+        LockedImage {
+            name: ValidIdentifier(name.into()),
+            version: Version::parse(version).unwrap(),
+            vendor: ValidIdentifier("my-vendor".into()),
+            source: format!("a.com/b/{name}:v{version}"),
+            digest: "digest".into(),
+            platform: Vec::new(),
+        }
+    }
+
+    fn lock(core_kit_version: &str) -> Lock {
+        Lock {
+            schema_version: LockSchemaVersion::default(),
+            sdk: locked("my-bottlerocket-sdk", "1.2.3"),
+            kit: vec![
+                locked("my-core-kit", core_kit_version),
+                locked("my-extra-kit", "2.0.1"),
+            ],
+            path_kit: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_lock_schema_version() {
+        let v1 = r#"
+schema-version = 1
+
+[sdk]
+name = "my-bottlerocket-sdk"
+version = "1.2.3"
+vendor = "my-vendor"
+source = "a.com/b/my-bottlerocket-sdk:v1.2.3"
+digest = "digest"
+
+[[kit]]
+name = "my-core-kit"
+version = "1.4.2"
+vendor = "my-vendor"
+source = "a.com/b/my-core-kit:v1.4.2"
+digest = "digest"
+"#;
+        let v1_lock: Lock = toml::from_str(v1).unwrap();
+        assert!(v1_lock.schema_version.needs_migration());
+        assert!(v1_lock.kit[0].platform.is_empty());
+
+        // Locks are written with the current schema version and the image of each platform
+        let mut current = lock("1.4.2");
+        current.kit[0].platform = vec![LockedPlatform {
+            arch: "amd64".into(),
+            manifest_digest: "sha256:manifest".into(),
+            config_digest: "sha256:config".into(),
+        }];
+        let serialized = toml::to_string(&current).unwrap();
+        assert!(serialized.contains("[[kit.platform]]"));
+        let deserialized: Lock = toml::from_str(&serialized).unwrap();
+        assert!(!deserialized.schema_version.needs_migration());
+        assert_eq!(deserialized.kit[0].platform, current.kit[0].platform);
+
+        // Newer schema versions are rejected
+        toml::from_str::<Lock>(&v1.replace("schema-version = 1", "schema-version = 3"))
+            .unwrap_err();
+
+        // Only schema version 1 may leave out the platforms of a kit
+        v1_lock.check_platforms().unwrap();
+        let v2_lock: Lock =
+            toml::from_str(&v1.replace("schema-version = 1", "schema-version = 2")).unwrap();
+        v2_lock.check_platforms().unwrap_err();
+    }
+
+    #[test]
+    fn test_keep_existing_migrates_platforms() {
+        let platform = vec![LockedPlatform {
+            arch: "amd64".into(),
+            manifest_digest: "sha256:manifest".into(),
+            config_digest: "sha256:config".into(),
+        }];
+        let v1_entry = lock("1.4.2").kit[0].clone();
+        let resolved = LockedImage {
+            platform: platform.clone(),
+            ..v1_entry.clone()
+        };
+
+        // A schema version 1 entry gains the platforms of the same digest
+        let kept = Lock::keep_existing(&v1_entry, resolved.clone());
+        assert_eq!(kept.digest, v1_entry.digest);
+        assert_eq!(kept.platform, platform);
+
+        // The platforms of a re-pushed image are only known for the new digest
+        let repushed = LockedImage {
+            digest: "repushed".into(),
+            ..resolved.clone()
+        };
+        let kept = Lock::keep_existing(&v1_entry, repushed.clone());
+        assert_eq!(kept.digest, "repushed");
+        assert_eq!(kept.platform, platform);
+
+        // An entry that records its platforms keeps its digest
+        let kept = Lock::keep_existing(&resolved, repushed);
+        assert_eq!(kept.digest, v1_entry.digest);
+
+        // A different release is always replaced
+        let newer = LockedImage {
+            version: Version::new(1, 5, 0),
+            digest: "newer".into(),
+            ..v1_entry.clone()
+        };
+        assert_eq!(Lock::keep_existing(&resolved, newer).digest, "newer");
+    }
+
+    #[tokio::test]
+    async fn test_check_against_project() {
+        let project = Project::load(data_dir().join("Twoliter-2.toml"))
+            .await
+            .unwrap();
+
+        lock("1.4.2").check_against_project(&project).unwrap();
+
+        // The locked core kit no longer satisfies Twoliter.toml
+        lock("1.5.0").check_against_project(&project).unwrap_err();
+
+        // The locked kit came from a different registry
+        let mut moved = lock("1.4.2");
+        moved.kit[1].source = "c.com/d/my-extra-kit:v2.0.1".into();
+        moved.check_against_project(&project).unwrap_err();
     }
 }
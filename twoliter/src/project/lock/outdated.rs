@@ -0,0 +1,277 @@
+//! Reports the versions of a project's SDK and kits that are published to their vendors'
+//! registries, so that newer versions can be found without changing Twoliter.lock.
+use super::image::LockedImage;
+use super::resolver::published_versions;
+use super::Lock;
+use crate::project::{KitRequirement, Project, Unlocked, VendedArtifact};
+use anyhow::{Context, Result};
+use oci_cli_wrapper::ImageTool;
+use semver::{Version, VersionReq};
+use serde::Serialize;
+use std::fmt::Write;
+use tracing::debug;
+
+/// The versions available for each of the SDK and kits listed in Twoliter.toml.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct OutdatedReport {
+    /// The SDK, if Twoliter.toml names one
+    pub sdk: Option<AvailableVersions>,
+    /// The kits listed in Twoliter.toml, in the order in which they are listed
+    pub kit: Vec<AvailableVersions>,
+}
+
+/// The versions of a single SDK or kit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct AvailableVersions {
+    pub name: String,
+    pub vendor: String,
+    /// The repository that versions were listed from, after applying Twoliter.override
+    pub repository: String,
+    /// The version requirement in Twoliter.toml. An SDK is pinned to one version, so any version
+    /// that is semver-compatible with it is considered compatible.
+    pub requirement: VersionReq,
+    /// The version in Twoliter.lock, or in Twoliter.toml for an SDK when there is no lock
+    pub current: Option<Version>,
+    /// The newest published version that satisfies the requirement
+    pub latest_compatible: Option<Version>,
+    /// The newest published version that is not a pre-release
+    pub latest: Option<Version>,
+}
+
+impl AvailableVersions {
+    fn new(
+        artifact: &KitRequirement,
+        repository: String,
+        current: Option<Version>,
+        published: &[Version],
+    ) -> Self {
+        let latest_compatible = published
+            .iter()
+            .filter(|version| artifact.version.matches(version))
+            .max()
+            .cloned();
+        let latest = published
+            .iter()
+            .filter(|version| version.pre.is_empty())
+            .max()
+            .cloned();
+        Self {
+            name: artifact.name.to_string(),
+            vendor: artifact.vendor.to_string(),
+            repository,
+            requirement: artifact.version.clone(),
+            current,
+            latest_compatible,
+            latest,
+        }
+    }
+
+    /// Returns true if a newer version than the current one has been published.
+    pub(crate) fn is_outdated(&self) -> bool {
+        let newest = self
+            .latest_compatible
+            .iter()
+            .chain(self.latest.iter())
+            .max();
+        match (&self.current, newest) {
+            (Some(current), Some(newest)) => newest > current,
+            (None, newest) => newest.is_some(),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+impl OutdatedReport {
+    /// Lists the published versions of the SDK and of each kit in Twoliter.toml. Kits that are
+    /// overridden with a local path are not listed, since only one version of them is available.
+    pub(super) async fn check(
+        project: &Project<Unlocked>,
+        lock: Option<&Lock>,
+        image_tool: &ImageTool,
+    ) -> Result<Self> {
+        let locked_version = |artifact: &KitRequirement, images: &[&LockedImage]| {
+            images
+                .iter()
+                .find(|image| image.name == artifact.name && image.vendor == artifact.vendor)
+                .map(|image| image.version.clone())
+        };
+        let locked_kits: Vec<&LockedImage> = lock.iter().flat_map(|lock| lock.kit.iter()).collect();
+        let locked_sdk: Vec<&LockedImage> = lock.iter().map(|lock| &lock.sdk).collect();
+
+        let mut report = Self::default();
+        if let Some(sdk) = project.direct_sdk_image_dep() {
+            let sdk = sdk?;
+            let requirement = KitRequirement::compatible_with(&sdk.image);
+            let current =
+                locked_version(&requirement, &locked_sdk).or_else(|| Some(sdk.version().clone()));
+            report.sdk = Self::available(project, &requirement, current, image_tool).await?;
+        }
+        for kit in project.direct_kit_deps() {
+            let current = locked_version(kit, &locked_kits);
+            report
+                .kit
+                .extend(Self::available(project, kit, current, image_tool).await?);
+        }
+        Ok(report)
+    }
+
+    async fn available(
+        project: &Project<Unlocked>,
+        artifact: &KitRequirement,
+        current: Option<Version>,
+        image_tool: &ImageTool,
+    ) -> Result<Option<AvailableVersions>> {
+        let vendor = project.vendor_for(artifact).context(format!(
+            "failed to find vendor for '{}' with vendor '{}'",
+            artifact.name,
+            artifact.vendor_name()
+        ))?;
+        if let Some(path) = vendor.local_path() {
+            debug!(
+                path = %path.display(),
+                "Skipping '{}', which is overridden with a local path",
+                artifact.name
+            );
+            return Ok(None);
+        }
+        let repository = vendor.repository_uri_for(artifact);
+        debug!(%repository, "Listing available versions of '{}'", artifact.name);
+        let published = published_versions(image_tool, &repository)
+            .await
+            .context(format!("failed to list versions of '{}'", artifact.name))?;
+        Ok(Some(AvailableVersions::new(
+            artifact, repository, current, &published,
+        )))
+    }
+
+    pub(crate) fn is_outdated(&self) -> bool {
+        self.sdk
+            .iter()
+            .chain(self.kit.iter())
+            .any(AvailableVersions::is_outdated)
+    }
+
+    /// Renders the report as a table with one row for the SDK and each kit.
+    pub(crate) fn render_text(&self) -> String {
+        let version = |version: &Option<Version>| {
+            version
+                .as_ref()
+                .map_or_else(|| "-".to_string(), ToString::to_string)
+        };
+        let mut rows = vec![[
+            "TYPE",
+            "NAME",
+            "VENDOR",
+            "CURRENT",
+            "REQUIREMENT",
+            "COMPATIBLE",
+            "LATEST",
+        ]
+        .map(String::from)];
+        let entries = self
+            .sdk
+            .iter()
+            .map(|sdk| ("sdk", sdk))
+            .chain(self.kit.iter().map(|kit| ("kit", kit)));
+        for (kind, entry) in entries {
+            rows.push([
+                kind.to_string(),
+                entry.name.clone(),
+                entry.vendor.clone(),
+                version(&entry.current),
+                entry.requirement.to_string(),
+                version(&entry.latest_compatible),
+                version(&entry.latest),
+            ]);
+        }
+
+        let mut widths = [0; 7];
+        for row in rows.iter() {
+            for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(cell.len());
+            }
+        }
+        let mut out = String::new();
+        for row in rows.iter() {
+            let line = row
+                .iter()
+                .zip(widths.iter())
+                .map(|(cell, width)| format!("{cell:width$}"))
+                .collect::<Vec<_>>()
+                .join("  ");
+            let _ = writeln!(out, "{}", line.trim_end());
+        }
+        out
+    }
+
+    /// Renders the report as pretty-printed JSON.
+    pub(crate) fn render_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).context("failed to serialize outdated report")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::project::ValidIdentifier;
+
+    fn available(
+        requirement: &str,
+        current: Option<&str>,
+        published: &[&str],
+    ) -> AvailableVersions {
+        let kit = KitRequirement {
+            name: ValidIdentifier("core-kit".into()),
+            version: VersionReq::parse(requirement).unwrap(),
+            vendor: ValidIdentifier("bottlerocket".into()),
+        };
+        let published: Vec<Version> = published
+            .iter()
+            .map(|version| Version::parse(version).unwrap())
+            .collect();
+        AvailableVersions::new(
+            &kit,
+            "public.ecr.aws/bottlerocket/core-kit".into(),
+            current.map(|version| Version::parse(version).unwrap()),
+            &published,
+        )
+    }
+
+    #[test]
+    fn test_available_versions() {
+        let versions = available(
+            "2.1",
+            Some("2.1.0"),
+            &["2.1.0", "2.3.1", "3.0.0", "4.0.0-rc1"],
+        );
+        assert_eq!(versions.latest_compatible, Some(Version::new(2, 3, 1)));
+        assert_eq!(versions.latest, Some(Version::new(3, 0, 0)));
+        assert!(versions.is_outdated());
+
+        let versions = available("=3.0.0", Some("3.0.0"), &["2.1.0", "3.0.0"]);
+        assert_eq!(versions.latest_compatible, Some(Version::new(3, 0, 0)));
+        assert!(!versions.is_outdated());
+
+        let versions = available("5", None, &["2.1.0"]);
+        assert_eq!(versions.latest_compatible, None);
+        assert!(versions.is_outdated());
+    }
+
+    #[test]
+    fn test_render() {
+        let report = OutdatedReport {
+            sdk: None,
+            kit: vec![available("2.1", Some("2.1.0"), &["2.1.0", "2.3.1"])],
+        };
+        assert_eq!(
+            report.render_text(),
+            "TYPE  NAME      VENDOR        CURRENT  REQUIREMENT  COMPATIBLE  LATEST\n\
+             kit   core-kit  bottlerocket  2.1.0    ^2.1         2.3.1       2.3.1\n"
+        );
+        let json: serde_json::Value = serde_json::from_str(&report.render_json().unwrap()).unwrap();
+        assert_eq!(json["kit"][0]["latest-compatible"], "2.3.1");
+        assert_eq!(json["kit"][0]["requirement"], "^2.1");
+    }
+}
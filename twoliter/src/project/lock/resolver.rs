@@ -0,0 +1,655 @@
+//! Selects one version of every kit in a project's dependency graph such that the selected version
+//! satisfies the requirements of the project and of every kit that depends on it.
+//!
+//! Requirements are gathered by walking the graph from the kits in `Twoliter.toml`. Whenever the
+//! walk uncovers a requirement that the currently selected version of a kit does not satisfy, a new
+//! version is selected and the graph is walked again, until the selections no longer change.
+use super::graph::ResolvedKit;
+use super::image::{ImageMetadata, ImageResolver, LockedImage};
+use crate::project::{Image, KitRequirement, Project, Unlocked, ValidIdentifier};
+use anyhow::{bail, Context, Result};
+use futures::{stream, StreamExt, TryStreamExt};
+use oci_cli_wrapper::ImageTool;
+use semver::{Version, VersionReq};
+use std::collections::HashMap;
+use std::mem::take;
+use tracing::{debug, instrument};
+
+/// Identifies a kit independently of its version by its name and vendor.
+type KitKey = (ValidIdentifier, ValidIdentifier);
+
+/// The requirer used for requirements written directly in the project file.
+const PROJECT_REQUIRER: &str = "Twoliter.toml";
+
+/// The maximum number of times the graph is walked before giving up on finding a stable selection.
+const MAX_RESOLUTION_PASSES: usize = 16;
+
+/// The maximum number of kits that are resolved against their registries at the same time.
+const MAX_CONCURRENT_RESOLUTIONS: usize = 8;
+
+/// The result of resolving all kits in a project.
+#[derive(Debug)]
+pub(super) struct ResolvedKits {
+    /// The locked kits, in the order in which they were first reached while walking the graph
+    pub kits: Vec<LockedImage>,
+    /// The SDKs that the resolved kits were built with
+    pub sdks: Vec<Image>,
+    /// The versions selected for the kits listed in Twoliter.toml
+    pub direct: Vec<Image>,
+    /// Each resolved kit along with the selected versions of its dependencies
+    pub graph: Vec<ResolvedKit>,
+    /// Each kit built from a local path along with the selected versions of its dependencies
+    pub local: Vec<(Image, Vec<Image>)>,
+}
+
+/// Everything learned during a single walk of the dependency graph.
+#[derive(Debug, Default)]
+struct Walk {
+    /// Kits in the order in which they were first reached
+    order: Vec<KitKey>,
+    /// The version of each kit that was used during the walk
+    selected: HashMap<KitKey, Version>,
+    /// Every requirement placed on each kit, along with a description of its requirer
+    requirements: HashMap<KitKey, Vec<(String, KitRequirement)>>,
+}
+
+#[derive(Debug)]
+pub(super) struct KitResolver<'a> {
+    project: &'a Project<Unlocked>,
+    image_tool: &'a ImageTool,
+    /// Versions to keep whenever they still satisfy all requirements, usually from Twoliter.lock
+    preferred: HashMap<KitKey, Version>,
+    /// Versions published for each kit, populated as needed
+    available: HashMap<KitKey, Vec<Version>>,
+    /// Kits that have already been resolved, keyed by the exact image
+    resolved: HashMap<Image, (LockedImage, ImageMetadata)>,
+}
+
+impl<'a> KitResolver<'a> {
+    pub(super) fn new(project: &'a Project<Unlocked>, image_tool: &'a ImageTool) -> Self {
+        Self {
+            project,
+            image_tool,
+            preferred: HashMap::new(),
+            available: HashMap::new(),
+            resolved: HashMap::new(),
+        }
+    }
+
+    /// Prefer the versions of the given kits over newer versions, as long as they satisfy all
+    /// requirements. This keeps an existing lock stable when new versions of a kit are published.
+    pub(super) fn prefer<'b>(mut self, kits: impl IntoIterator<Item = &'b LockedImage>) -> Self {
+        self.preferred.extend(
+            kits.into_iter()
+                .map(|kit| ((kit.name.clone(), kit.vendor.clone()), kit.version.clone())),
+        );
+        self
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    pub(super) async fn resolve(mut self) -> Result<ResolvedKits> {
+        let mut selected = HashMap::new();
+        for pass in 0..MAX_RESOLUTION_PASSES {
+            debug!(pass, "Walking kit dependency graph");
+            let walk = self.walk(&selected).await?;
+
+            let mut changed = false;
+            for key in walk.order.iter() {
+                let version = &walk.selected[key];
+                let requirements = &walk.requirements[key];
+                if !requirements
+                    .iter()
+                    .all(|(_, requirement)| requirement.version.matches(version))
+                {
+                    let version = self.select(requirements).await?;
+                    debug!(kit = %key.0, %version, "Selected new kit version");
+                    selected.insert(key.clone(), version);
+                    changed = true;
+                }
+            }
+            if !changed {
+                return Ok(self.finish(walk));
+            }
+            // Keep every other selection that was made during this walk, so that the next walk
+            // only differs in the kits whose selection changed.
+            for (key, version) in walk.selected {
+                selected.entry(key).or_insert(version);
+            }
+        }
+        bail!(
+            "unable to find a consistent set of kit versions after {MAX_RESOLUTION_PASSES} attempts"
+        )
+    }
+
+    /// Walks the graph breadth first, using the `selected` version of a kit where one exists.
+    ///
+    /// The kits at each depth of the graph are resolved concurrently, but are visited in the order
+    /// in which they were required so that the result does not depend on registry response times.
+    async fn walk(&mut self, selected: &HashMap<KitKey, Version>) -> Result<Walk> {
+        let mut walk = Walk::default();
+        let mut remaining: Vec<(String, KitRequirement)> = self
+            .project
+            .direct_kit_deps()
+            .iter()
+            .map(|kit| (PROJECT_REQUIRER.to_string(), kit.clone()))
+            .collect();
+        // Kits built from a local path are not resolved, but the kits that they depend on are.
+        for local_kit in self.project.local_kits() {
+            let requirer = format!(
+                "{} (from {})",
+                local_kit.image(),
+                local_kit.path().display()
+            );
+            remaining.extend(
+                local_kit
+                    .kit_deps()
+                    .iter()
+                    .map(|kit| (requirer.clone(), kit.clone())),
+            );
+        }
+
+        while !remaining.is_empty() {
+            let working_set = take(&mut remaining);
+            let mut level = Vec::new();
+            for (requirer, requirement) in working_set {
+                let key = (requirement.name.clone(), requirement.vendor.clone());
+                walk.requirements
+                    .entry(key.clone())
+                    .or_default()
+                    .push((requirer, requirement.clone()));
+                if walk.selected.contains_key(&key) {
+                    continue;
+                }
+
+                let version = match selected.get(&key) {
+                    Some(version) => version.clone(),
+                    None => self.select(&walk.requirements[&key]).await?,
+                };
+                level.push(requirement.with_version(version.clone()));
+                walk.selected.insert(key.clone(), version);
+                walk.order.push(key);
+            }
+
+            self.resolve_images(&level).await?;
+            for image in level {
+                let (_, metadata) = &self.resolved[&image];
+                let requirer = image.to_string();
+                remaining.extend(
+                    metadata
+                        .kits
+                        .iter()
+                        .map(|dep| (requirer.clone(), KitRequirement::compatible_with(dep))),
+                );
+            }
+        }
+        Ok(walk)
+    }
+
+    /// Chooses a version of a kit which satisfies all of the given requirements.
+    async fn select(&mut self, requirements: &[(String, KitRequirement)]) -> Result<Version> {
+        let (_, first) = requirements
+            .first()
+            .context("cannot select a kit version without any requirements")?;
+        let key = (first.name.clone(), first.vendor.clone());
+        let reqs: Vec<&VersionReq> = requirements.iter().map(|(_, r)| &r.version).collect();
+
+        if let Some(preferred) = self.preferred.get(&key) {
+            if reqs.iter().all(|req| req.matches(preferred)) {
+                return Ok(preferred.clone());
+            }
+        }
+
+        // Exact requirements don't need a registry lookup, since there is only one candidate.
+        let exact = requirements.iter().find_map(|(_, r)| r.exact_version());
+        let selected = match exact {
+            Some(version) => reqs
+                .iter()
+                .all(|req| req.matches(&version))
+                .then_some(version),
+            None => highest_matching(self.available_versions(first).await?, &reqs),
+        };
+
+        selected.with_context(|| {
+            format!(
+                "no version of kit '{}' from vendor '{}' satisfies all requirements: {}",
+                first.name,
+                first.vendor,
+                requirements
+                    .iter()
+                    .map(|(requirer, r)| format!("{} (required by {requirer})", r.version))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })
+    }
+
+    /// Lists the versions of a kit that are published to its vendor's registry.
+    async fn available_versions(&mut self, kit: &KitRequirement) -> Result<&[Version]> {
+        let key = (kit.name.clone(), kit.vendor.clone());
+        if !self.available.contains_key(&key) {
+            let vendor = self.project.vendor_for(kit).context(format!(
+                "failed to find vendor for kit with name '{}' and vendor '{}'",
+                kit.name, kit.vendor
+            ))?;
+            if let Some(layout) = vendor.local_layout().await? {
+                debug!(path = %layout.path().display(), "Reading kit version from local OCI layout");
+                let version = ImageResolver::layout_kit_version(layout).await?;
+                self.available.insert(key.clone(), vec![version]);
+                return Ok(&self.available[&key]);
+            }
+            let repository = vendor.repository_uri_for(kit);
+            debug!(%repository, "Listing available kit versions");
+            let versions = published_versions(self.image_tool, &repository)
+                .await
+                .context(format!("failed to list versions of kit '{}'", kit.name))?;
+            self.available.insert(key.clone(), versions);
+        }
+        Ok(&self.available[&key])
+    }
+
+    /// Resolves each of `images` that has not already been resolved, several at a time.
+    async fn resolve_images(&mut self, images: &[Image]) -> Result<()> {
+        let project = self.project;
+        let image_tool = self.image_tool;
+        let unresolved: Vec<Image> = images
+            .iter()
+            .filter(|image| !self.resolved.contains_key(image))
+            .cloned()
+            .collect();
+
+        let resolved: Vec<_> = stream::iter(unresolved)
+            .map(|image| async move {
+                debug!(%image, "Resolving kit '{}'", image.name);
+                let project_image = project.as_project_image(&image)?;
+                let (locked_image, metadata) = ImageResolver::from_image(&project_image)?
+                    .resolve(image_tool)
+                    .await?;
+                let metadata = metadata.context(format!(
+                    "failed to validate kit image with name {} from vendor {}",
+                    locked_image.name, locked_image.vendor
+                ))?;
+                Ok::<_, anyhow::Error>((image, (locked_image, metadata)))
+            })
+            .buffered(MAX_CONCURRENT_RESOLUTIONS)
+            .try_collect()
+            .await?;
+        self.resolved.extend(resolved);
+        Ok(())
+    }
+
+    fn finish(self, walk: Walk) -> ResolvedKits {
+        let image_for = |name: &ValidIdentifier, vendor: &ValidIdentifier| Image {
+            name: name.clone(),
+            version: walk.selected[&(name.clone(), vendor.clone())].clone(),
+            vendor: vendor.clone(),
+        };
+
+        let mut resolved = ResolvedKits {
+            kits: Vec::new(),
+            sdks: Vec::new(),
+            direct: self
+                .project
+                .direct_kit_deps()
+                .iter()
+                .map(|kit| image_for(&kit.name, &kit.vendor))
+                .collect(),
+            graph: Vec::new(),
+            local: self
+                .project
+                .local_kits()
+                .iter()
+                .map(|local_kit| {
+                    let deps = local_kit
+                        .kit_deps()
+                        .iter()
+                        .map(|kit| image_for(&kit.name, &kit.vendor))
+                        .collect();
+                    (local_kit.image(), deps)
+                })
+                .collect(),
+        };
+        for (name, vendor) in walk.order.iter() {
+            let image = image_for(name, vendor);
+            let (locked_image, metadata) = &self.resolved[&image];
+            resolved.graph.push(ResolvedKit {
+                image,
+                sdk: metadata.sdk.clone(),
+                kit: metadata
+                    .kits
+                    .iter()
+                    .map(|dep| image_for(&dep.name, &dep.vendor))
+                    .collect(),
+            });
+            resolved.kits.push(locked_image.clone());
+            resolved.sdks.push(metadata.sdk.clone());
+        }
+        resolved
+    }
+}
+
+/// Lists the versions that are published to `repository`, which are tagged `v<version>`. Tags that
+/// do not name a version, such as `latest`, are ignored.
+pub(super) async fn published_versions(
+    image_tool: &ImageTool,
+    repository: &str,
+) -> Result<Vec<Version>> {
+    let tags = image_tool
+        .list_tags(repository)
+        .await
+        .context(format!("failed to list the tags of '{repository}'"))?;
+    Ok(tags
+        .iter()
+        .filter_map(|tag| tag.strip_prefix('v'))
+        .filter_map(|version| Version::parse(version).ok())
+        .collect())
+}
+
+/// Returns the highest of `versions` that satisfies every requirement in `reqs`.
+fn highest_matching(versions: &[Version], reqs: &[&VersionReq]) -> Option<Version> {
+    versions
+        .iter()
+        .filter(|version| reqs.iter().all(|req| req.matches(version)))
+        .max()
+        .cloned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_trait::async_trait;
+    use base64::Engine;
+    use oci_cli_wrapper::{ConfigView, DockerArchitecture, ImageToolImpl};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    const REGISTRY: &str = "registry.example.com/kits";
+
+    /// A kit's name and version, along with the name and version of each kit it depends on.
+    type FakeKit = (
+        &'static str,
+        &'static str,
+        Vec<(&'static str, &'static str)>,
+    );
+
+    /// An in-memory registry serving kits.
+    #[derive(Debug)]
+    struct FakeRegistry {
+        kits: Vec<FakeKit>,
+        in_flight: AtomicUsize,
+        max_in_flight: Arc<AtomicUsize>,
+    }
+
+    impl FakeRegistry {
+        fn new(kits: Vec<FakeKit>) -> Self {
+            Self {
+                kits,
+                in_flight: AtomicUsize::new(0),
+                max_in_flight: Arc::new(AtomicUsize::new(0)),
+            }
+        }
+
+        fn not_found(uri: &str) -> oci_cli_wrapper::error::Error {
+            oci_cli_wrapper::error::Error::OperationFailed {
+                message: format!("'{uri}' not found"),
+                program: "fake".into(),
+                args: Vec::new(),
+            }
+        }
+
+        fn image(name: &str, version: &str) -> serde_json::Value {
+            serde_json::json!({"name": name, "version": version, "vendor": "my-vendor"})
+        }
+    }
+
+    #[async_trait]
+    impl ImageToolImpl for FakeRegistry {
+        async fn pull_oci_image(&self, _path: &Path, uri: &str) -> oci_cli_wrapper::Result<()> {
+            Err(Self::not_found(uri))
+        }
+
+        async fn get_config(&self, uri: &str) -> oci_cli_wrapper::Result<ConfigView> {
+            let (_, name, version, deps) = self
+                .kits
+                .iter()
+                .map(|(name, version, deps)| {
+                    (
+                        format!("{REGISTRY}/{name}@sha256:{name}-{version}"),
+                        name,
+                        version,
+                        deps,
+                    )
+                })
+                .find(|(kit_uri, ..)| kit_uri == uri)
+                .ok_or_else(|| Self::not_found(uri))?;
+            let metadata = serde_json::json!({
+                "name": name,
+                "version": version,
+                "sdk": Self::image("my-bottlerocket-sdk", "1.2.3"),
+                "kit": deps.iter().map(|(name, version)| Self::image(name, version)).collect::<Vec<_>>(),
+            });
+            let encoded = base64::engine::general_purpose::STANDARD.encode(metadata.to_string());
+            Ok(ConfigView {
+                labels: [("dev.bottlerocket.kit.v2".to_string(), encoded)].into(),
+            })
+        }
+
+        async fn get_manifest(&self, uri: &str) -> oci_cli_wrapper::Result<Vec<u8>> {
+            // Requests by digest are for the image manifest of a single platform.
+            if let Some((name, version, _)) = self.kits.iter().find(|(name, version, _)| {
+                uri == format!("{REGISTRY}/{name}@sha256:{name}-{version}")
+            }) {
+                let manifest = serde_json::json!({
+                    "config": {"digest": format!("sha256:config-{name}-{version}")},
+                    "layers": [],
+                });
+                return Ok(manifest.to_string().into_bytes());
+            }
+
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            // Answer requests for kits that are listed first more slowly, so that they complete
+            // out of order when they are made concurrently.
+            let position = self
+                .kits
+                .iter()
+                .position(|(name, version, _)| uri == format!("{REGISTRY}/{name}:v{version}"));
+            for _ in 0..(self.kits.len() - position.unwrap_or_default()) * 10 {
+                tokio::task::yield_now().await;
+            }
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            let (name, version, _) = &self.kits[position.ok_or_else(|| Self::not_found(uri))?];
+            let manifest_list = serde_json::json!({
+                "manifests": [{
+                    "digest": format!("sha256:{name}-{version}"),
+                    "platform": {"architecture": "amd64"},
+                }],
+            });
+            Ok(manifest_list.to_string().into_bytes())
+        }
+
+        async fn list_tags(&self, repository: &str) -> oci_cli_wrapper::Result<Vec<String>> {
+            Ok(self
+                .kits
+                .iter()
+                .filter(|(name, ..)| repository == format!("{REGISTRY}/{name}"))
+                .map(|(_, version, _)| format!("v{version}"))
+                .chain(["latest".to_string()])
+                .collect())
+        }
+
+        async fn push_oci_archive(&self, _path: &Path, uri: &str) -> oci_cli_wrapper::Result<()> {
+            Err(Self::not_found(uri))
+        }
+
+        async fn push_multi_platform_manifest(
+            &self,
+            _platform_images: Vec<(DockerArchitecture, String)>,
+            uri: &str,
+        ) -> oci_cli_wrapper::Result<()> {
+            Err(Self::not_found(uri))
+        }
+    }
+
+    async fn project(dir: &TempDir) -> Project<Unlocked> {
+        let twoliter_toml = dir.path().join("Twoliter.toml");
+        tokio::fs::write(
+            &twoliter_toml,
+            format!(
+                r#"
+schema-version = 1
+release-version = "1.0.0"
+
+[vendor.my-vendor]
+registry = "{REGISTRY}"
+
+[[kit]]
+name = "extra-1-kit"
+version = "^1"
+vendor = "my-vendor"
+
+[[kit]]
+name = "extra-2-kit"
+version = "^1"
+vendor = "my-vendor"
+"#
+            ),
+        )
+        .await
+        .unwrap();
+        Project::load(twoliter_toml).await.unwrap()
+    }
+
+    fn registry() -> ImageTool {
+        ImageTool::new(Box::new(FakeRegistry::new(vec![
+            ("extra-1-kit", "1.0.0", vec![("core-kit", "1.2.0")]),
+            ("extra-2-kit", "1.0.0", vec![("core-kit", "1.2.1")]),
+            ("core-kit", "1.2.0", vec![]),
+            ("core-kit", "1.2.1", vec![]),
+            ("core-kit", "1.3.0", vec![]),
+        ])))
+    }
+
+    fn locked_versions(resolved: &ResolvedKits) -> Vec<String> {
+        resolved
+            .kits
+            .iter()
+            .map(|kit| format!("{}-{}", kit.name, kit.version))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_resolve_selects_highest_compatible_versions() {
+        let dir = TempDir::new().unwrap();
+        let project = project(&dir).await;
+        let image_tool = registry();
+
+        let resolved = KitResolver::new(&project, &image_tool)
+            .resolve()
+            .await
+            .unwrap();
+        // Kits appear in breadth first order even though the first kit was slowest to resolve.
+        // The two kits were built with different versions of core-kit, so the newest version that
+        // is compatible with both is selected.
+        assert_eq!(
+            locked_versions(&resolved),
+            vec!["extra-1-kit-1.0.0", "extra-2-kit-1.0.0", "core-kit-1.3.0"]
+        );
+        assert_eq!(resolved.direct.len(), 2);
+        assert_eq!(resolved.graph[0].kit[0].version, Version::new(1, 3, 0));
+        // The image of each platform is locked
+        let platform = &resolved.kits[0].platform;
+        assert_eq!(platform.len(), 1);
+        assert_eq!(platform[0].arch, "amd64");
+        assert_eq!(platform[0].manifest_digest, "sha256:extra-1-kit-1.0.0");
+        assert_eq!(platform[0].config_digest, "sha256:config-extra-1-kit-1.0.0");
+    }
+
+    #[tokio::test]
+    async fn test_resolve_prefers_compatible_locked_versions() {
+        let dir = TempDir::new().unwrap();
+        let project = project(&dir).await;
+        let image_tool = registry();
+        let locked = |name: &str, version: &str| LockedImage {
+            name: ValidIdentifier(name.into()),
+            version: Version::parse(version).unwrap(),
+            vendor: ValidIdentifier("my-vendor".into()),
+            source: String::new(),
+            digest: String::new(),
+            platform: Vec::new(),
+        };
+
+        let resolved = KitResolver::new(&project, &image_tool)
+            .prefer([&locked("core-kit", "1.2.1")])
+            .resolve()
+            .await
+            .unwrap();
+        assert_eq!(locked_versions(&resolved)[2], "core-kit-1.2.1");
+
+        // 1.2.0 does not satisfy extra-2-kit, so a newer version must be selected.
+        let resolved = KitResolver::new(&project, &image_tool)
+            .prefer([&locked("core-kit", "1.2.0")])
+            .resolve()
+            .await
+            .unwrap();
+        assert_eq!(locked_versions(&resolved)[2], "core-kit-1.3.0");
+
+        // 2.0.0 does not satisfy the project's requirement, so the newest 1.x is selected.
+        let resolved = KitResolver::new(&project, &image_tool)
+            .prefer([&locked("extra-1-kit", "2.0.0")])
+            .resolve()
+            .await
+            .unwrap();
+        assert_eq!(locked_versions(&resolved)[0], "extra-1-kit-1.0.0");
+    }
+
+    #[tokio::test]
+    async fn test_resolve_is_concurrent() {
+        let dir = TempDir::new().unwrap();
+        let project = project(&dir).await;
+        let registry = FakeRegistry::new(vec![
+            ("extra-1-kit", "1.0.0", vec![]),
+            ("extra-2-kit", "1.0.0", vec![]),
+        ]);
+        let max_in_flight = Arc::clone(&registry.max_in_flight);
+        let image_tool = ImageTool::new(Box::new(registry));
+
+        KitResolver::new(&project, &image_tool)
+            .resolve()
+            .await
+            .unwrap();
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    fn versions(versions: &[&str]) -> Vec<Version> {
+        versions
+            .iter()
+            .map(|v| Version::parse(v).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_highest_matching() {
+        let available = versions(&["1.0.0", "1.2.0", "1.2.1", "1.4.0", "2.0.0", "2.1.0-rc.1"]);
+        let caret = VersionReq::parse("1.2.0").unwrap();
+        let below = VersionReq::parse("<1.3").unwrap();
+        let major = VersionReq::parse("2").unwrap();
+
+        assert_eq!(
+            highest_matching(&available, &[&caret]),
+            Some(Version::new(1, 4, 0))
+        );
+        assert_eq!(
+            highest_matching(&available, &[&caret, &below]),
+            Some(Version::new(1, 2, 1))
+        );
+        // Pre-releases are only selected when explicitly requested.
+        assert_eq!(
+            highest_matching(&available, &[&major]),
+            Some(Version::new(2, 0, 0))
+        );
+        assert_eq!(highest_matching(&available, &[&caret, &major]), None);
+    }
+}
@@ -0,0 +1,274 @@
+//! Verifies the signatures that vendors attach to their kit and SDK images.
+//!
+//! A vendor that lists `trusted-keys` in Twoliter.toml must publish a signature image next to each
+//! of its images, as written by `pubsys publish-kit`. The signature names the digest of the image's
+//! manifest list, so a signed image cannot be swapped for another one under the same tag.
+use crate::common::fs::read_to_string;
+use anyhow::{bail, ensure, Context, Result};
+use oci_cli_wrapper::signature::{manifest_digest, ImageSignature, SignaturePayload};
+use oci_cli_wrapper::ImageTool;
+use ring::signature::{self, UnparsedPublicKey, VerificationAlgorithm};
+use spki::der::Document;
+use spki::{ObjectIdentifier, SubjectPublicKeyInfoRef};
+use std::path::{Path, PathBuf};
+use tracing::{debug, instrument};
+
+const PEM_LABEL: &str = "PUBLIC KEY";
+
+const RSA_ENCRYPTION: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+const EC_PUBLIC_KEY: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.2.1");
+const SECP256R1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.3.1.7");
+const ED25519: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.101.112");
+
+/// A public key that a vendor's images may be signed with.
+///
+/// The signature scheme follows from the type of key, matching the schemes that pubsys signs with:
+/// RSA keys use RSASSA-PSS with SHA-256, and ECDSA keys must be on the P-256 curve.
+#[derive(Debug)]
+pub(crate) struct TrustedKey {
+    path: PathBuf,
+    key: UnparsedPublicKey<Vec<u8>>,
+}
+
+impl TrustedKey {
+    /// Reads a PEM encoded `SubjectPublicKeyInfo` from `path`.
+    pub(crate) async fn load(path: &Path) -> Result<Self> {
+        let pem = read_to_string(path).await?;
+        Self::from_pem(path, &pem)
+            .context(format!("failed to parse trusted key '{}'", path.display()))
+    }
+
+    fn from_pem(path: &Path, pem: &str) -> Result<Self> {
+        let (label, der) =
+            Document::from_pem(pem.trim()).context("expected a PEM encoded public key")?;
+        ensure!(
+            label == PEM_LABEL,
+            "expected a PEM encoded public key, but found '{label}'"
+        );
+        let (algorithm, key) = parse_spki(der.as_bytes())?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            key: UnparsedPublicKey::new(algorithm, key.to_vec()),
+        })
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        self.key.verify(message, signature).is_ok()
+    }
+}
+
+/// Reads the verification algorithm and key from a DER encoded `SubjectPublicKeyInfo`.
+fn parse_spki(der: &[u8]) -> Result<(&'static dyn VerificationAlgorithm, &[u8])> {
+    let spki = SubjectPublicKeyInfoRef::try_from(der).context("public key is not a valid SPKI")?;
+    let key = spki
+        .subject_public_key
+        .as_bytes()
+        .context("public key is not a whole number of bytes")?;
+    let algorithm: &'static dyn VerificationAlgorithm = match spki
+        .algorithm
+        .oids()
+        .context("public key has invalid algorithm parameters")?
+    {
+        (RSA_ENCRYPTION, None) => &signature::RSA_PSS_2048_8192_SHA256,
+        (EC_PUBLIC_KEY, Some(SECP256R1)) => &signature::ECDSA_P256_SHA256_ASN1,
+        (EC_PUBLIC_KEY, _) => bail!("only ECDSA keys on the P-256 curve are supported"),
+        (ED25519, None) => &signature::ED25519,
+        (oid, _) => bail!("unsupported public key algorithm '{oid}'"),
+    };
+    Ok((algorithm, key))
+}
+
+/// Checks that the image in `repository` with the given manifest list carries a signature made by
+/// one of `trusted_keys`.
+#[instrument(level = "trace", skip(image_tool, manifest_list))]
+pub(crate) async fn verify_image_signature(
+    image_tool: &ImageTool,
+    repository: &str,
+    manifest_list: &[u8],
+    trusted_keys: &[PathBuf],
+) -> Result<()> {
+    let mut keys = Vec::new();
+    for path in trusted_keys {
+        keys.push(TrustedKey::load(path).await?);
+    }
+    let digest = manifest_digest(manifest_list);
+    debug!("Verifying signature of '{repository}@{digest}'");
+    let signatures = image_tool
+        .get_signatures(repository, &digest)
+        .await
+        .context(format!(
+            "failed to fetch the signature of '{repository}@{digest}', which its vendor requires"
+        ))?;
+    verify_signatures(&keys, &digest, &signatures).context(format!(
+        "'{repository}@{digest}' is not signed by a trusted key"
+    ))
+}
+
+/// Checks that at least one of `signatures` is of the image with `digest` and made by one of `keys`.
+fn verify_signatures(
+    keys: &[TrustedKey],
+    digest: &str,
+    signatures: &[ImageSignature],
+) -> Result<()> {
+    for signature in signatures {
+        let payload = match SignaturePayload::from_slice(&signature.payload) {
+            Ok(payload) => payload,
+            Err(e) => {
+                debug!("Ignoring unreadable signature payload: {e}");
+                continue;
+            }
+        };
+        if payload.digest() != digest {
+            debug!(
+                "Ignoring signature of a different image '{}'",
+                payload.digest()
+            );
+            continue;
+        }
+        let key = keys
+            .iter()
+            .find(|key| key.verify(&signature.payload, &signature.signature));
+        if let Some(key) = key {
+            debug!(
+                "Verified signature with trusted key '{}'",
+                key.path.display()
+            );
+            return Ok(());
+        }
+    }
+    bail!(
+        "none of the {} signature(s) found were made by a trusted key",
+        signatures.len()
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::data_dir;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+    use spki::der::pem::LineEnding;
+
+    /// The DER encoding of a `SubjectPublicKeyInfo` up to the bytes of an Ed25519 key.
+    const ED25519_SPKI_PREFIX: &[u8] = &[
+        0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+    ];
+
+    /// The DER encoding of a `SubjectPublicKeyInfo` up to the bytes of a P-256 ECDSA key.
+    const P256_SPKI_PREFIX: &[u8] = &[
+        0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08,
+        0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+    ];
+
+    fn trusted_key(spki_prefix: &[u8], public_key: &[u8]) -> TrustedKey {
+        let mut spki = spki_prefix.to_vec();
+        spki.extend_from_slice(public_key);
+        TrustedKey::from_pem(Path::new("key.pem"), &to_pem(&spki)).unwrap()
+    }
+
+    fn to_pem(der: &[u8]) -> String {
+        spki::der::pem::encode_string(PEM_LABEL, LineEnding::LF, der).unwrap()
+    }
+
+    fn ed25519_key() -> (Ed25519KeyPair, TrustedKey) {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let key = trusted_key(ED25519_SPKI_PREFIX, pair.public_key().as_ref());
+        (pair, key)
+    }
+
+    fn signature(pair: &Ed25519KeyPair, digest: &str) -> ImageSignature {
+        let payload = SignaturePayload::new("a.com/b/my-kit", digest)
+            .to_vec()
+            .unwrap();
+        ImageSignature {
+            signature: pair.sign(&payload).as_ref().to_vec(),
+            payload,
+        }
+    }
+
+    #[test]
+    fn test_trusted_key_formats() {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+            .unwrap();
+        let key = trusted_key(P256_SPKI_PREFIX, pair.public_key().as_ref());
+        let signature = pair.sign(&rng, b"message").unwrap();
+        assert!(key.verify(b"message", signature.as_ref()));
+        assert!(!key.verify(b"other message", signature.as_ref()));
+
+        // ECDSA keys on curves other than P-256 are not supported
+        let mut other_curve = P256_SPKI_PREFIX.to_vec();
+        other_curve[22] = 0x08;
+        other_curve.extend_from_slice(pair.public_key().as_ref());
+        let err = TrustedKey::from_pem(Path::new("key.pem"), &to_pem(&other_curve)).unwrap_err();
+        assert!(err.to_string().contains("P-256"), "{err}");
+
+        let (pair, key) = ed25519_key();
+        assert!(key.verify(b"message", pair.sign(b"message").as_ref()));
+
+        TrustedKey::from_pem(Path::new("key.pem"), "not a key").unwrap_err();
+        let truncated = to_pem(&P256_SPKI_PREFIX[..10]);
+        TrustedKey::from_pem(Path::new("key.pem"), &truncated).unwrap_err();
+    }
+
+    #[test]
+    fn test_verify_signatures() {
+        let (pair, key) = ed25519_key();
+        let (other_pair, other_key) = ed25519_key();
+        let digest = manifest_digest(b"manifest list");
+
+        let signatures = [signature(&other_pair, &digest), signature(&pair, &digest)];
+        verify_signatures(&[key], &digest, &signatures).unwrap();
+        verify_signatures(&[other_key], &digest, &signatures[1..]).unwrap_err();
+
+        // A valid signature of another image does not sign this one
+        let (pair, key) = ed25519_key();
+        let other_digest = manifest_digest(b"other manifest list");
+        verify_signatures(&[key], &digest, &[signature(&pair, &other_digest)]).unwrap_err();
+    }
+
+    /// Vectors for the RSASSA-PSS signatures that pubsys makes with a 2048 bit RSA key, SHA-256
+    /// and a 32 byte salt, created with `openssl dgst -sha256 -sigopt rsa_padding_mode:pss`.
+    #[tokio::test]
+    async fn test_rsa_pss_signatures() {
+        let dir = data_dir().join("rsa-pss");
+        let key = TrustedKey::load(&dir.join("public-key.pem")).await.unwrap();
+        let other_key = TrustedKey::load(&dir.join("other-public-key.pem"))
+            .await
+            .unwrap();
+        let payload = std::fs::read(dir.join("payload.json")).unwrap();
+        let signature = |name: &str| ImageSignature {
+            payload: payload.clone(),
+            signature: std::fs::read(dir.join(format!("payload.{name}.sig"))).unwrap(),
+        };
+        let digest = SignaturePayload::from_slice(&payload)
+            .unwrap()
+            .digest()
+            .to_string();
+
+        let pss = signature("pss");
+        assert!(key.verify(&pss.payload, &pss.signature));
+        verify_signatures(&[key], &digest, &[pss.clone()]).unwrap();
+
+        // The signature does not hold for another key, another message, or if it is altered
+        let key = TrustedKey::load(&dir.join("public-key.pem")).await.unwrap();
+        assert!(!other_key.verify(&pss.payload, &pss.signature));
+        assert!(!key.verify(b"other payload", &pss.signature));
+        let mut altered = pss.signature.clone();
+        altered[100] ^= 0x01;
+        assert!(!key.verify(&pss.payload, &altered));
+        assert!(!key.verify(&pss.payload, &pss.signature[1..]));
+
+        // Only PSS padding is accepted from RSA keys, not PKCS #1 v1.5
+        let pkcs1 = signature("pkcs1");
+        assert!(!key.verify(&pkcs1.payload, &pkcs1.signature));
+
+        // A signature made by another key is not trusted
+        let others = [signature("other-key"), pkcs1];
+        verify_signatures(&[key], &digest, &others).unwrap_err();
+        verify_signatures(&[other_key], &digest, &others).unwrap();
+    }
+}
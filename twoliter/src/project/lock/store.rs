@@ -0,0 +1,513 @@
+//! A per-user store of OCI image blobs that is shared by every project on the host.
+//!
+//! Kit images are pulled into `$XDG_CACHE_HOME/twoliter/oci`, or `~/.cache/twoliter/oci`, where
+//! each blob is stored once under its digest. The OCI archive that a project caches for a kit is
+//! assembled by hard linking the blobs it needs from the store, so checkouts of several projects
+//! that use the same kit share one copy of it. Blobs are copied instead when the store and the
+//! project are on different filesystems.
+//!
+//! Each image has a lock file in the store. A process holds the lock while it pulls and links the
+//! image, so concurrent Twoliter processes pull an image once and never see a partial copy.
+//!
+//! The store is garbage collected by `twoliter cache prune`. An image can be removed once no
+//! project archive links its manifest blob and it has not been pulled or linked recently, which is
+//! recorded by touching its index. Blobs are removed once no remaining image refers to them and no
+//! archive links them. Pulls hold a shared lock on the whole store, and garbage collection holds
+//! it exclusively, so that blobs are not removed from under an image that is being imported.
+use super::layout::blob_path;
+use super::views::{IndexView, ManifestLayoutView};
+use crate::common::fs::{
+    copy, create_dir_all, metadata, read, remove_dir_all, remove_file, rename, write,
+};
+use anyhow::{ensure, Context, Result};
+use chrono::{DateTime, Utc};
+use nix::fcntl::{Flock, FlockArg};
+use oci_cli_wrapper::ImageTool;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tempfile::TempDir;
+use tracing::{debug, instrument, trace};
+
+const STORE_DIRECTORY: &str = "twoliter/oci";
+const INDEX_FILE: &str = "index.json";
+const OCI_LAYOUT_FILE: &str = "oci-layout";
+const OCI_LAYOUT: &str = r#"{"imageLayoutVersion":"1.0.0"}"#;
+const STORE_LOCK: &str = "store";
+
+/// What [`OciStore::prune`] removed from the store, or would remove in a dry run.
+#[derive(Debug, Default)]
+pub(crate) struct StorePruned {
+    /// The manifest digests of the removed images
+    pub images: Vec<String>,
+    /// The number of blobs removed
+    pub blobs: usize,
+    /// The size of the removed blobs in bytes
+    pub size: u64,
+}
+
+/// The store of OCI blobs in the user's cache directory.
+#[derive(Debug, Clone)]
+pub(crate) struct OciStore {
+    root: PathBuf,
+}
+
+impl OciStore {
+    pub(crate) fn new(root: impl AsRef<Path>) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Returns the store in the user's cache directory, or `None` if the user has no home
+    /// directory to find it in.
+    pub(crate) fn for_user() -> Option<Self> {
+        let cache_home = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+            .or_else(|| home::home_dir().map(|home| home.join(".cache")))?;
+        Some(Self::new(cache_home.join(STORE_DIRECTORY)))
+    }
+
+    /// Places the image with the manifest `digest` in an OCI image layout at `archive_path`,
+    /// pulling it from `uri` only if the store does not already hold it.
+    #[instrument(level = "trace", skip(self, image_tool), fields(store = %self.root.display()))]
+    pub(crate) async fn pull(
+        &self,
+        image_tool: &ImageTool,
+        uri: &str,
+        digest: &str,
+        archive_path: &Path,
+    ) -> Result<()> {
+        let _store_lock = self.lock_file(STORE_LOCK, FlockArg::LockShared).await?;
+        let _lock = self.lock(digest).await?;
+        // Another process may have created the archive while this one waited for the lock.
+        if archive_path.exists() {
+            self.touch(digest);
+            return Ok(());
+        }
+        if self.blobs_for(digest).await.is_err() {
+            debug!("Pulling image '{uri}' into the shared image store");
+            create_dir_all(self.root.join("tmp")).await?;
+            let staging = TempDir::new_in(self.root.join("tmp"))
+                .context("failed to create staging directory in the shared image store")?;
+            image_tool.pull_oci_image(staging.path(), uri).await?;
+            self.import(staging.path(), digest).await?;
+        } else {
+            debug!("Image '{uri}' is already in the shared image store");
+        }
+        self.link(digest, archive_path).await?;
+        self.touch(digest);
+        Ok(())
+    }
+
+    /// Removes the images that no project archive links and that have not been used since
+    /// `unused_since`, or at all if it is `None`, along with the blobs that are left unused.
+    #[instrument(level = "trace", skip(self), fields(store = %self.root.display()))]
+    pub(crate) async fn prune(
+        &self,
+        unused_since: Option<DateTime<Utc>>,
+        dry_run: bool,
+    ) -> Result<StorePruned> {
+        let mut pruned = StorePruned::default();
+        if !self.root.exists() {
+            return Ok(pruned);
+        }
+        let _store_lock = self.lock_file(STORE_LOCK, FlockArg::LockExclusive).await?;
+
+        let mut kept_blobs = HashSet::new();
+        for digest in self.images().await? {
+            // An image whose blobs are incomplete was never usable, so it is always removed.
+            let blobs = self.blobs_for(&digest).await.unwrap_or_default();
+            let linked = match blobs.first() {
+                Some(manifest) => metadata(self.blob_path(manifest)?).await?.nlink() > 1,
+                None => false,
+            };
+            let last_used: DateTime<Utc> = metadata(self.index_path(&digest))
+                .await
+                .and_then(|metadata| {
+                    metadata
+                        .modified()
+                        .context("failed to read modification time")
+                })
+                .map(DateTime::from)
+                .unwrap_or(DateTime::UNIX_EPOCH);
+            let recent = unused_since.is_some_and(|since| last_used >= since);
+            if linked || recent {
+                kept_blobs.extend(blobs);
+                continue;
+            }
+            debug!("Removing image '{digest}' from the shared image store");
+            if !dry_run {
+                remove_dir_all(self.image_dir(&digest)).await?;
+            }
+            pruned.images.push(digest);
+        }
+
+        let blobs_dir = self.root.join("blobs").join("sha256");
+        if blobs_dir.exists() {
+            let mut entries = read_dir(&blobs_dir).await?;
+            while let Some(entry) = next_entry(&mut entries, &blobs_dir).await? {
+                let blob = format!("sha256:{}", entry.file_name().to_string_lossy());
+                let metadata = metadata(entry.path()).await?;
+                if kept_blobs.contains(&blob) || metadata.nlink() > 1 {
+                    continue;
+                }
+                trace!("Removing blob '{blob}' from the shared image store");
+                if !dry_run {
+                    remove_file(entry.path()).await?;
+                }
+                pruned.blobs += 1;
+                pruned.size += metadata.len();
+            }
+        }
+
+        // Nothing is pulled while the store is locked, so anything left in the staging directory
+        // was abandoned by an interrupted pull.
+        let tmp_dir = self.root.join("tmp");
+        if !dry_run && tmp_dir.exists() {
+            remove_dir_all(&tmp_dir).await?;
+        }
+        Ok(pruned)
+    }
+
+    /// Lists the manifest digests of the images in the store.
+    async fn images(&self) -> Result<Vec<String>> {
+        let images_dir = self.root.join("images");
+        let mut images = Vec::new();
+        if !images_dir.exists() {
+            return Ok(images);
+        }
+        let mut entries = read_dir(&images_dir).await?;
+        while let Some(entry) = next_entry(&mut entries, &images_dir).await? {
+            images.push(entry.file_name().to_string_lossy().replacen('-', ":", 1));
+        }
+        images.sort();
+        Ok(images)
+    }
+
+    /// Records that the image with the manifest `digest` was used, by updating the modification
+    /// time of its index.
+    fn touch(&self, digest: &str) {
+        let path = self.index_path(digest);
+        let result = OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        if let Err(e) = result {
+            debug!("Unable to record use of '{}': {e}", path.display());
+        }
+    }
+
+    /// Takes an exclusive lock on the image with the manifest `digest`, which is released when
+    /// the returned lock is dropped.
+    async fn lock(&self, digest: &str) -> Result<Flock<File>> {
+        self.lock_file(&digest.replace(':', "-"), FlockArg::LockExclusive)
+            .await
+    }
+
+    /// Takes the lock named `name` in the store, which is released when the returned lock is
+    /// dropped.
+    async fn lock_file(&self, name: &str, arg: FlockArg) -> Result<Flock<File>> {
+        let locks_dir = self.root.join("locks");
+        create_dir_all(&locks_dir).await?;
+        let path = locks_dir.join(format!("{name}.lock"));
+        tokio::task::spawn_blocking(move || {
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)
+                .context(format!("failed to open lock file '{}'", path.display()))?;
+            trace!("Waiting for lock '{}'", path.display());
+            Flock::lock(file, arg)
+                .map_err(|(_, e)| e)
+                .context(format!("failed to lock '{}'", path.display()))
+        })
+        .await
+        .context("lock task failed")?
+    }
+
+    fn blob_path(&self, digest: &str) -> Result<PathBuf> {
+        blob_path(&self.root, digest)
+    }
+
+    fn image_dir(&self, digest: &str) -> PathBuf {
+        self.root.join("images").join(digest.replace(':', "-"))
+    }
+
+    fn index_path(&self, digest: &str) -> PathBuf {
+        self.image_dir(digest).join(INDEX_FILE)
+    }
+
+    /// Lists the blobs of the image with the manifest `digest`, failing unless the store holds the
+    /// image's index and every one of its blobs.
+    async fn blobs_for(&self, digest: &str) -> Result<Vec<String>> {
+        ensure!(
+            self.index_path(digest).exists(),
+            "image '{digest}' is not in the store"
+        );
+        let manifest = read(self.blob_path(digest)?).await?;
+        let manifest: ManifestLayoutView =
+            serde_json::from_slice(&manifest).context("failed to deserialize oci manifest")?;
+        let blobs: Vec<String> = [digest.to_string(), manifest.config.digest.to_string()]
+            .into_iter()
+            .chain(manifest.layers.iter().map(|layer| layer.digest.to_string()))
+            .collect();
+        for blob in blobs.iter() {
+            ensure!(
+                self.blob_path(blob)?.exists(),
+                "blob '{blob}' of image '{digest}' is not in the store"
+            );
+        }
+        Ok(blobs)
+    }
+
+    /// Moves the blobs of the OCI image layout at `layout` into the store after checking their
+    /// digests, and records the layout's index for the image with the manifest `digest`.
+    async fn import(&self, layout: &Path, digest: &str) -> Result<()> {
+        let index_bytes = read(layout.join(INDEX_FILE)).await?;
+        let index: IndexView = serde_json::from_slice(&index_bytes)
+            .context("failed to deserialize oci image index")?;
+        let manifest_digest = &index.manifests.first().context("empty oci image")?.digest;
+        ensure!(
+            manifest_digest == digest,
+            "pulled image refers to manifest '{manifest_digest}' rather than '{digest}'"
+        );
+
+        let blobs_dir = layout.join("blobs").join("sha256");
+        let mut entries = read_dir(&blobs_dir).await?;
+        create_dir_all(self.root.join("blobs").join("sha256")).await?;
+        while let Some(entry) = next_entry(&mut entries, &blobs_dir).await? {
+            let blob = format!("sha256:{}", entry.file_name().to_string_lossy());
+            let path = entry.path();
+            let calculated = file_digest(&path).await?;
+            ensure!(
+                calculated == blob,
+                "blob '{blob}' of image '{digest}' has digest '{calculated}'"
+            );
+            let destination = self.blob_path(&blob)?;
+            if !destination.exists() {
+                trace!("Adding blob '{blob}' to the shared image store");
+                rename(&path, &destination).await?;
+            }
+        }
+
+        // The index is written last, since it marks the image as complete.
+        let index_path = self.index_path(digest);
+        let index_dir = index_path
+            .parent()
+            .context("index has no parent directory")?;
+        create_dir_all(index_dir).await?;
+        let temp_path = index_dir.join(format!("{INDEX_FILE}.tmp"));
+        write(&temp_path, &index_bytes).await?;
+        rename(&temp_path, &index_path).await
+    }
+
+    /// Creates an OCI image layout at `archive_path` for the image with the manifest `digest`,
+    /// linking its blobs from the store.
+    async fn link(&self, digest: &str, archive_path: &Path) -> Result<()> {
+        let blobs = self.blobs_for(digest).await?;
+        let parent = archive_path
+            .parent()
+            .context(format!("'{}' has no parent", archive_path.display()))?;
+        create_dir_all(parent).await?;
+        // Assemble the layout next to its final location, so that an interrupted link does not
+        // leave behind a directory that looks like a complete archive.
+        let staging = TempDir::new_in(parent).context(format!(
+            "failed to create staging directory in '{}'",
+            parent.display()
+        ))?;
+        create_dir_all(staging.path().join("blobs").join("sha256")).await?;
+        for blob in blobs.iter() {
+            let from = self.blob_path(blob)?;
+            let to = blob_path(staging.path(), blob)?;
+            if let Err(e) = tokio::fs::hard_link(&from, &to).await {
+                trace!("Copying blob '{blob}' since it could not be linked: {e}");
+                copy(&from, &to).await?;
+            }
+        }
+        copy(self.index_path(digest), staging.path().join(INDEX_FILE)).await?;
+        write(staging.path().join(OCI_LAYOUT_FILE), OCI_LAYOUT).await?;
+
+        let staging = staging.into_path();
+        if let Err(e) = rename(&staging, archive_path).await {
+            let _ = remove_dir_all(&staging).await;
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+async fn read_dir(dir: &Path) -> Result<tokio::fs::ReadDir> {
+    tokio::fs::read_dir(dir)
+        .await
+        .context(format!("failed to read directory '{}'", dir.display()))
+}
+
+async fn next_entry(
+    entries: &mut tokio::fs::ReadDir,
+    dir: &Path,
+) -> Result<Option<tokio::fs::DirEntry>> {
+    entries
+        .next_entry()
+        .await
+        .context(format!("failed to read directory '{}'", dir.display()))
+}
+
+/// Calculates the digest of the file at `path` in the form `sha256:<hex>`.
+async fn file_digest(path: &Path) -> Result<String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut file = File::open(&path).context(format!("failed to open '{}'", path.display()))?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher)
+            .context(format!("failed to read '{}'", path.display()))?;
+        Ok(format!("sha256:{:x}", hasher.finalize()))
+    })
+    .await
+    .context("digest task failed")?
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn digest(content: &[u8]) -> String {
+        format!("sha256:{:x}", Sha256::digest(content))
+    }
+
+    /// Writes an OCI image layout holding one image, and returns the digest of its manifest.
+    async fn write_layout(dir: &Path, layer: &[u8]) -> String {
+        let config = br#"{"config":{}}"#;
+        let manifest = json!({
+            "schemaVersion": 2,
+            "config": {"digest": digest(config)},
+            "layers": [{"digest": digest(layer)}],
+        })
+        .to_string();
+        create_dir_all(dir.join("blobs/sha256")).await.unwrap();
+        for blob in [config.as_slice(), layer, manifest.as_bytes()] {
+            write(blob_path(dir, &digest(blob)).unwrap(), blob)
+                .await
+                .unwrap();
+        }
+        let manifest_digest = digest(manifest.as_bytes());
+        let index = json!({"schemaVersion": 2, "manifests": [{"digest": manifest_digest}]});
+        write(dir.join(INDEX_FILE), index.to_string())
+            .await
+            .unwrap();
+        manifest_digest
+    }
+
+    #[tokio::test]
+    async fn test_import_and_link() {
+        let tempdir = TempDir::new().unwrap();
+        let store = OciStore::new(tempdir.path().join("store"));
+        let pulled = tempdir.path().join("pulled");
+        let manifest_digest = write_layout(&pulled, b"layer").await;
+
+        store.blobs_for(&manifest_digest).await.unwrap_err();
+        store.import(&pulled, "sha256:other").await.unwrap_err();
+        store.import(&pulled, &manifest_digest).await.unwrap();
+        assert_eq!(store.blobs_for(&manifest_digest).await.unwrap().len(), 3);
+
+        // Two projects share the blobs of the same image
+        let first = tempdir.path().join("first/cache/image");
+        let second = tempdir.path().join("second/cache/image");
+        store.link(&manifest_digest, &first).await.unwrap();
+        store.link(&manifest_digest, &second).await.unwrap();
+        for archive in [&first, &second] {
+            assert!(archive.join(OCI_LAYOUT_FILE).exists());
+            assert_eq!(
+                read(blob_path(archive, &digest(b"layer")).unwrap())
+                    .await
+                    .unwrap(),
+                b"layer"
+            );
+            let index: IndexView =
+                serde_json::from_slice(&read(archive.join(INDEX_FILE)).await.unwrap()).unwrap();
+            assert_eq!(index.manifests[0].digest, manifest_digest);
+        }
+        assert!(first.parent().unwrap().read_dir().unwrap().count() == 1);
+    }
+
+    #[tokio::test]
+    async fn test_import_rejects_corrupt_blobs() {
+        let tempdir = TempDir::new().unwrap();
+        let store = OciStore::new(tempdir.path().join("store"));
+        let pulled = tempdir.path().join("pulled");
+        let manifest_digest = write_layout(&pulled, b"layer").await;
+        write(blob_path(&pulled, &digest(b"layer")).unwrap(), "corrupt")
+            .await
+            .unwrap();
+        store.import(&pulled, &manifest_digest).await.unwrap_err();
+        store.blobs_for(&manifest_digest).await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_prune() {
+        let tempdir = TempDir::new().unwrap();
+        let store = OciStore::new(tempdir.path().join("store"));
+        let first = write_layout(&tempdir.path().join("first"), b"first").await;
+        let second = write_layout(&tempdir.path().join("second"), b"second").await;
+        store
+            .import(&tempdir.path().join("first"), &first)
+            .await
+            .unwrap();
+        store
+            .import(&tempdir.path().join("second"), &second)
+            .await
+            .unwrap();
+        let archive = tempdir.path().join("project/cache/image");
+        store.link(&first, &archive).await.unwrap();
+
+        // Images that were used recently are kept
+        let pruned = store
+            .prune(Some(Utc::now() - chrono::Duration::days(1)), false)
+            .await
+            .unwrap();
+        assert!(pruned.images.is_empty());
+        assert_eq!(pruned.blobs, 0);
+
+        // The linked image is kept, and so is the config blob that the images share
+        let pruned = store.prune(None, true).await.unwrap();
+        assert_eq!(pruned.images, vec![second.clone()]);
+        assert_eq!(pruned.blobs, 2);
+        store.blobs_for(&second).await.unwrap();
+        let pruned = store.prune(None, false).await.unwrap();
+        assert_eq!(pruned.images, vec![second.clone()]);
+        assert_eq!(pruned.blobs, 2);
+        store.blobs_for(&second).await.unwrap_err();
+        store.blobs_for(&first).await.unwrap();
+
+        // The image is removed once the project's archive is gone
+        remove_dir_all(&archive).await.unwrap();
+        let pruned = store.prune(None, false).await.unwrap();
+        assert_eq!(pruned.images, vec![first.clone()]);
+        assert_eq!(pruned.blobs, 3);
+        assert_eq!(
+            std::fs::read_dir(store.root.join("blobs/sha256"))
+                .unwrap()
+                .count(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lock_is_exclusive() {
+        let tempdir = TempDir::new().unwrap();
+        let store = OciStore::new(tempdir.path());
+        let lock = store.lock("sha256:abc").await.unwrap();
+        let path = tempdir.path().join("locks/sha256-abc.lock");
+        let file = File::open(&path).unwrap();
+        Flock::lock(file, FlockArg::LockExclusiveNonblock).unwrap_err();
+        drop(lock);
+        let file = File::open(&path).unwrap();
+        Flock::lock(file, FlockArg::LockExclusiveNonblock).unwrap();
+    }
+}
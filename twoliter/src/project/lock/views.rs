@@ -1,4 +1,4 @@
-use oci_cli_wrapper::DockerArchitecture;
+use oci_cli_wrapper::{ConfigView, DockerArchitecture};
 use serde::de::Error;
 use serde::{Deserialize, Deserializer};
 use std::fmt::{Display, Formatter};
@@ -26,9 +26,15 @@ pub(crate) struct IndexView {
 
 #[derive(Deserialize, Debug)]
 pub(crate) struct ManifestLayoutView {
+    pub config: Layer,
     pub layers: Vec<Layer>,
 }
 
+#[derive(Deserialize, Debug)]
+pub(crate) struct ImageConfigView {
+    pub config: ConfigView,
+}
+
 #[derive(Deserialize, Debug)]
 pub(crate) struct Layer {
     pub digest: ContainerDigest,
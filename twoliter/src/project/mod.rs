@@ -1,12 +1,19 @@
+mod local_kit;
 mod lock;
 pub(crate) mod vendor;
 
+pub(crate) use self::local_kit::LocalKit;
 pub(crate) use self::vendor::ArtifactVendor;
-pub(crate) use lock::VerificationTagger;
+pub(crate) use lock::{
+    CacheEntry, DependencyGraph, KitCache, LockDrift, LockMode, LockedImage, OciStore,
+    OutdatedReport, PruneFilter, UpdateScope, VerificationTagger,
+};
 
-use self::lock::{Lock, LockedSDK, Override};
+use self::local_kit::{PathKit, LOCAL_KIT_VENDOR};
+use self::lock::{Lock, LockedSDK, OCILayout, Override, CACHE_DIRECTORY, TWOLITER_LOCK};
 use crate::common::fs::{self, read_to_string};
 use crate::compatibility::SUPPORTED_TWOLITER_PROJECT_SCHEMA_VERSION;
+use crate::config::Config;
 use crate::docker::ImageUri;
 use crate::schema_version::SchemaVersion;
 use anyhow::{ensure, Context, Result};
@@ -15,7 +22,8 @@ use async_trait::async_trait;
 use async_walkdir::WalkDir;
 use buildsys_config::{EXTERNAL_KIT_DIRECTORY, EXTERNAL_KIT_METADATA};
 use futures::stream::StreamExt;
-use semver::Version;
+use oci_cli_wrapper::{CredentialSource, ImageTool, RegistryCredentials};
+use semver::{Comparator, Op, Version, VersionReq};
 use serde::de::Error;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::BTreeMap;
@@ -46,7 +54,7 @@ pub(crate) async fn load_or_find_project(user_path: Option<PathBuf>) -> Result<P
 }
 
 /// Represents the structure of a `Twoliter.toml` project file.
-#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub(crate) struct Project<L: ProjectLock> {
     filepath: PathBuf,
     project_dir: PathBuf,
@@ -64,7 +72,10 @@ pub(crate) struct Project<L: ProjectLock> {
     vendor: BTreeMap<ValidIdentifier, Vendor>,
 
     /// Set of kit dependencies
-    kit: Vec<Image>,
+    kit: Vec<KitRequirement>,
+
+    /// Kit dependencies that are built from other projects on the local filesystem
+    path_kit: Vec<LocalKit>,
 
     overrides: BTreeMap<String, BTreeMap<String, Override>>,
 
@@ -123,15 +134,39 @@ impl Project<Unlocked> {
         Self::find_and_load(parent).await
     }
 
-    pub(crate) async fn create_lock(self) -> Result<Project<Locked>> {
-        let lock = Lock::create(&self).await?;
+    /// Updates the parts of the project's lock that fall within `scope`, leaving the rest of the
+    /// existing lock untouched. The default scope recreates the lock from scratch.
+    pub(crate) async fn update_lock(self, scope: &UpdateScope) -> Result<Project<Locked>> {
+        let lock = Lock::update(&self, scope).await?;
         Ok(self.with_new_lock(lock))
     }
 
-    pub(crate) async fn load_lock<NL: ProjectLock>(&self) -> Result<Project<NL>> {
+    /// Resolves the project's dependencies and returns the graph of kits and SDKs that was walked
+    /// to do so. Kit versions in an existing Twoliter.lock are preferred, but the lock is not
+    /// written.
+    pub(crate) async fn dependency_graph(&self) -> Result<DependencyGraph> {
+        let existing_lock = Lock::existing_lock_state(self).await?;
+        Lock::resolve_with_graph(self, existing_lock.as_ref())
+            .await
+            .map(|(_lock, graph)| graph)
+    }
+
+    /// Re-resolves the project's dependencies and reports how they differ from Twoliter.lock,
+    /// without writing it.
+    pub(crate) async fn lock_drift(&self) -> Result<LockDrift> {
+        Lock::drift(self).await
+    }
+
+    /// Lists the newer versions of the SDK and kits in Twoliter.toml that have been published,
+    /// without changing Twoliter.lock.
+    pub(crate) async fn outdated(&self) -> Result<OutdatedReport> {
+        Lock::outdated(self).await
+    }
+
+    pub(crate) async fn load_lock<NL: ProjectLock>(&self, mode: LockMode) -> Result<Project<NL>> {
         VerificationTagger::cleanup_existing_tags(self.external_kits_dir()).await?;
 
-        let resolved_lock = NL::load_lock(self, private::SealToken).await?;
+        let resolved_lock = NL::load_lock(self, mode, private::SealToken).await?;
 
         resolved_lock
             .verification_tagger(private::SealToken)
@@ -153,6 +188,7 @@ impl<L: ProjectLock> Project<L> {
             sdk: self.sdk.clone(),
             vendor: self.vendor.clone(),
             kit: self.kit.clone(),
+            path_kit: self.path_kit.clone(),
             overrides: self.overrides.clone(),
             lock: new_lock.into(),
         }
@@ -166,27 +202,79 @@ impl<L: ProjectLock> Project<L> {
         self.project_dir.clone()
     }
 
+    /// The path to the project's Twoliter.lock, which may not exist yet.
+    pub(crate) fn lockfile(&self) -> PathBuf {
+        self.project_dir.join(TWOLITER_LOCK)
+    }
+
     pub(crate) fn external_kits_dir(&self) -> PathBuf {
         self.project_dir.join(EXTERNAL_KIT_DIRECTORY)
     }
 
-    pub(crate) fn external_kits_metadata(&self) -> PathBuf {
-        self.project_dir.join(EXTERNAL_KIT_METADATA)
+    /// The cache of OCI archives that kits are extracted from.
+    pub(crate) fn kit_cache(&self) -> KitCache {
+        KitCache::new(self.external_kits_dir().join(CACHE_DIRECTORY))
     }
 
-    pub(crate) fn schema_version(&self) -> SchemaVersion<1> {
-        self.schema_version
+    pub(crate) fn external_kits_metadata(&self) -> PathBuf {
+        self.project_dir.join(EXTERNAL_KIT_METADATA)
     }
 
     pub(crate) fn release_version(&self) -> &str {
         self.release_version.as_str()
     }
 
-    pub(crate) fn direct_kit_deps(&self) -> Result<Vec<ProjectImage>> {
-        self.kit
+    pub(crate) fn direct_kit_deps(&self) -> &[KitRequirement] {
+        self.kit.as_slice()
+    }
+
+    /// The kits that are built from other projects on the local filesystem.
+    pub(crate) fn local_kits(&self) -> &[LocalKit] {
+        self.path_kit.as_slice()
+    }
+
+    /// The credential source for each vendor's registry, where configuration files take
+    /// precedence over Twoliter.toml. Vendors that share a registry host can use different
+    /// sources, but vendors with the same registry must use the same source.
+    pub(crate) fn registry_credentials(&self, config: &Config) -> Result<RegistryCredentials> {
+        let mut credentials = RegistryCredentials::new();
+        for (name, vendor) in self.vendor.iter() {
+            let auth = config.vendor_auth(name.as_ref()).or(vendor.auth.as_ref());
+            if let Some(auth) = auth {
+                credentials
+                    .insert(&vendor.registry, auth.clone())
+                    .context(format!("Unable to use the credentials of vendor '{name}'"))?;
+            }
+        }
+        Ok(credentials)
+    }
+
+    /// Returns the credential source for the registry of the vendor named `vendor`, if one is
+    /// configured.
+    pub(crate) fn vendor_auth<'a>(
+        &'a self,
+        config: &'a Config,
+        vendor: &str,
+    ) -> Option<&'a CredentialSource> {
+        let (name, vendor) = self
+            .vendor
             .iter()
-            .map(|kit| self.as_project_image(kit))
-            .collect()
+            .find(|(name, _)| name.as_ref() == vendor)?;
+        config.vendor_auth(name.as_ref()).or(vendor.auth.as_ref())
+    }
+
+    /// Returns the image tool to use with the project's registries, which uses the credentials
+    /// configured for each vendor.
+    pub(crate) async fn image_tool(&self) -> Result<ImageTool> {
+        let config = Config::load(Some(&self.project_dir)).await?;
+        Ok(ImageTool::from_builtin_krane_with_credentials(
+            self.registry_credentials(&config)?,
+        ))
+    }
+
+    /// Returns true if a vendor named `vendor` is specified in Twoliter.toml.
+    pub(crate) fn has_vendor(&self, vendor: &str) -> bool {
+        self.vendor.keys().any(|name| name.as_ref() == vendor)
     }
 
     pub(crate) fn direct_sdk_image_dep(&self) -> Option<Result<ProjectImage>> {
@@ -212,7 +300,7 @@ impl<L: ProjectLock> Project<L> {
 
     pub(crate) fn as_project_image<'proj, 'arti: 'proj>(
         &'proj self,
-        image: &'arti impl VendedArtifact,
+        image: &'arti impl VersionedArtifact,
     ) -> Result<ProjectImage> {
         let vendor = self
             .vendor_for(image)
@@ -281,9 +369,9 @@ impl Project<SDKLocked> {
 
 impl Project<Locked> {
     /// Fetches all external kits defined in a Twoliter.lock to the build directory
-    pub(crate) async fn fetch(&self, arch: &str) -> Result<()> {
+    pub(crate) async fn fetch(&self, arch: &str, mode: LockMode) -> Result<()> {
         let Locked(lock) = &self.lock;
-        lock.fetch(self, arch).await
+        lock.fetch(self, arch, mode).await
     }
 
     #[expect(dead_code)]
@@ -301,6 +389,12 @@ impl Project<Locked> {
         self.as_project_image(&lock.sdk)
             .expect("Could not find SDK vendor despite lock resolution succeeding?")
     }
+
+    /// The SDK and external kits recorded in Twoliter.lock, with the SDK first.
+    pub(crate) fn locked_images(&self) -> impl Iterator<Item = &LockedImage> {
+        let Locked(lock) = &self.lock;
+        std::iter::once(&lock.sdk).chain(lock.kit.iter())
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
@@ -311,6 +405,16 @@ pub(crate) struct ProjectImage {
 
 impl Display for ProjectImage {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(path) = self.local_path() {
+            return write!(
+                f,
+                "{}-{}@{} (overridden-to: {})",
+                self.name(),
+                self.version(),
+                self.original_source_uri(),
+                path.display(),
+            );
+        }
         match self.vendor {
             ArtifactVendor::Overridden(_) => write!(
                 f,
@@ -344,6 +448,23 @@ impl ProjectImage {
         self.vendor.vendor_name()
     }
 
+    /// Returns the OCI image layout that this image is read from, if it is overridden with a local
+    /// path rather than a registry.
+    pub(crate) fn local_path(&self) -> Option<&Path> {
+        self.vendor.local_path()
+    }
+
+    /// Opens the OCI image layout that this image is read from, if it has a local path.
+    pub(crate) async fn local_layout(&self) -> Result<Option<&OCILayout>> {
+        self.vendor.local_layout().await
+    }
+
+    /// Returns the keys that the image must be signed with, which are empty if the vendor does not
+    /// require signatures.
+    pub(crate) fn trusted_keys(&self) -> &[PathBuf] {
+        self.vendor.trusted_keys()
+    }
+
     /// Returns the URI for the original vendor.
     pub(crate) fn original_source_uri(&self) -> ImageUri {
         match &self.vendor {
@@ -355,6 +476,11 @@ impl ProjectImage {
         }
     }
 
+    /// Returns the untagged repository that the project will fetch this image from.
+    pub(crate) fn project_repository_uri(&self) -> String {
+        self.vendor.repository_uri_for(&self.image)
+    }
+
     /// Returns the image URI that the project will use for this image
     ///
     /// This could be different than the source_uri if overridden.
@@ -373,6 +499,10 @@ impl ProjectImage {
 pub(crate) trait VendedArtifact: std::fmt::Debug {
     fn artifact_name(&self) -> &ValidIdentifier;
     fn vendor_name(&self) -> &ValidIdentifier;
+}
+
+/// A [`VendedArtifact`] that refers to one exact version of the artifact.
+pub(crate) trait VersionedArtifact: VendedArtifact {
     fn version(&self) -> &Version;
 }
 
@@ -447,6 +577,16 @@ fn is_valid_id_char(c: char) -> bool {
 #[serde(rename_all = "kebab-case")]
 pub(crate) struct Vendor {
     pub registry: String,
+    /// Public keys, in PEM format, that the vendor's kits and SDKs must be signed with. When any
+    /// are given, images from the vendor are only resolved if they carry a signature made with one
+    /// of these keys. Paths are relative to the project directory.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_keys: Vec<PathBuf>,
+    /// Where to find the credentials for the vendor's registry, e.g.
+    /// `auth = { credential-helper = "ecr-login" }`. The docker credential configuration from the
+    /// environment is used when absent. Paths are relative to the project directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<CredentialSource>,
 }
 
 /// This represents a dependency on a container, primarily used for kits
@@ -459,7 +599,7 @@ pub(crate) struct Image {
 }
 
 impl Image {
-    fn from_vended_artifact(artifact: &impl VendedArtifact) -> Self {
+    fn from_vended_artifact(artifact: &impl VersionedArtifact) -> Self {
         Self {
             name: artifact.artifact_name().clone(),
             vendor: artifact.vendor_name().clone(),
@@ -482,12 +622,168 @@ impl VendedArtifact for Image {
     fn vendor_name(&self) -> &ValidIdentifier {
         &self.vendor
     }
+}
 
+impl VersionedArtifact for Image {
     fn version(&self) -> &Version {
         &self.version
     }
 }
 
+/// This represents a dependency on a kit which may be satisfied by a range of versions. A bare
+/// version such as `version = "1.2.3"` pins that exact version, as it always has. A range must be
+/// written with an operator, e.g. `version = "^1.2"` or `version = ">=1.2, <1.5"`, which is then
+/// interpreted the same way that Cargo interprets dependency versions. Projects that want
+/// `twoliter update` to pick up compatible releases need to change `"1.2.3"` to `"^1.2.3"`.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct KitRequirement {
+    pub name: ValidIdentifier,
+    #[serde(deserialize_with = "deserialize_version_requirement")]
+    pub version: VersionReq,
+    pub vendor: ValidIdentifier,
+}
+
+impl KitRequirement {
+    /// Creates a requirement that is only satisfied by the version of the given image.
+    pub(crate) fn exactly(image: &Image) -> Self {
+        Self::with_op(image, Op::Exact)
+    }
+
+    /// Creates a requirement that is satisfied by any version that is semver-compatible with the
+    /// given image. This is how the kit dependencies recorded in a kit's metadata are interpreted.
+    pub(crate) fn compatible_with(image: &Image) -> Self {
+        Self::with_op(image, Op::Caret)
+    }
+
+    fn with_op(image: &Image, op: Op) -> Self {
+        Self {
+            name: image.name.clone(),
+            version: version_requirement(&image.version, op),
+            vendor: image.vendor.clone(),
+        }
+    }
+
+    /// Returns the only version that can satisfy this requirement, if the requirement is of the
+    /// form `=x.y.z`.
+    pub(crate) fn exact_version(&self) -> Option<Version> {
+        match self.version.comparators.as_slice() {
+            [Comparator {
+                op: Op::Exact,
+                major,
+                minor: Some(minor),
+                patch: Some(patch),
+                pre,
+            }] => Some(Version {
+                major: *major,
+                minor: *minor,
+                patch: *patch,
+                pre: pre.clone(),
+                build: Default::default(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Returns the image that results from choosing `version` to satisfy this requirement.
+    pub(crate) fn with_version(&self, version: Version) -> Image {
+        Image {
+            name: self.name.clone(),
+            version,
+            vendor: self.vendor.clone(),
+        }
+    }
+}
+
+fn version_requirement(version: &Version, op: Op) -> VersionReq {
+    VersionReq {
+        comparators: vec![Comparator {
+            op,
+            major: version.major,
+            minor: Some(version.minor),
+            patch: Some(version.patch),
+            pre: version.pre.clone(),
+        }],
+    }
+}
+
+/// Parses the version of a `[[kit]]` entry. Each part of the requirement that is a bare version
+/// pins that exact version, unlike Cargo where it means `^`.
+fn parse_version_requirement(input: &str) -> Result<VersionReq> {
+    let parts = input
+        .split(',')
+        .map(|part| {
+            let part = part.trim();
+            if !part.starts_with(|c: char| c.is_ascii_digit()) {
+                return Ok(part.to_string());
+            }
+            let version = Version::parse(part).with_context(|| {
+                format!(
+                    "'{part}' is not a complete version. A bare version pins one exact version, \
+                    so write a range with an operator instead, e.g. '^{part}'"
+                )
+            })?;
+            Ok(version_requirement(&version, Op::Exact).to_string())
+        })
+        .collect::<Result<Vec<_>>>()?;
+    VersionReq::parse(&parts.join(", "))
+        .with_context(|| format!("invalid version requirement '{input}'"))
+}
+
+fn deserialize_version_requirement<'de, D>(
+    deserializer: D,
+) -> std::result::Result<VersionReq, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let input = String::deserialize(deserializer)?;
+    parse_version_requirement(&input).map_err(|e| D::Error::custom(format!("{e:#}")))
+}
+
+impl Display for KitRequirement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{} ({})", self.name, self.vendor, self.version)
+    }
+}
+
+impl VendedArtifact for KitRequirement {
+    fn artifact_name(&self) -> &ValidIdentifier {
+        &self.name
+    }
+
+    fn vendor_name(&self) -> &ValidIdentifier {
+        &self.vendor
+    }
+}
+
+/// A `[[kit]]` entry in Twoliter.toml, which either names a kit published to a vendor's registry
+/// or points at another project which builds the kit.
+#[derive(Debug, Clone, Serialize, Eq, PartialEq, Hash)]
+#[serde(untagged)]
+enum KitDependency {
+    Registry(KitRequirement),
+    Path(PathKit),
+}
+
+impl<'de> Deserialize<'de> for KitDependency {
+    // This is not `#[serde(untagged)]` so that a mistake in an entry, such as an invalid version
+    // requirement, is reported instead of "data did not match any variant".
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let table = Table::deserialize(deserializer)?;
+        if table.contains_key("path") {
+            table.try_into().map(Self::Path).map_err(D::Error::custom)
+        } else {
+            table
+                .try_into()
+                .map(Self::Registry)
+                .map_err(D::Error::custom)
+        }
+    }
+}
+
 /// This is used to `Deserialize` a project, then run validation code before returning a valid
 /// [`Project`]. This is necessary both because there is no post-deserialization serde hook for
 /// validation and, even if there was, we need to know the project directory path in order to check
@@ -499,7 +795,7 @@ struct UnvalidatedProject {
     release_version: String,
     sdk: Option<Image>,
     vendor: Option<BTreeMap<ValidIdentifier, Vendor>>,
-    kit: Option<Vec<Image>>,
+    kit: Option<Vec<KitDependency>>,
 }
 
 impl UnvalidatedProject {
@@ -517,6 +813,18 @@ impl UnvalidatedProject {
         self.check_vendor_availability().await?;
         self.check_release_toml(&project_dir).await?;
         let overrides = self.check_and_load_overrides(&project_dir).await?;
+        let (kit, path_kit) = self.load_kit_dependencies(&project_dir).await?;
+        let mut vendor = self.vendor.unwrap_or_default();
+        for vendor in vendor.values_mut() {
+            // Paths are relative to the project rather than the working directory.
+            for key in vendor.trusted_keys.iter_mut() {
+                *key = project_dir.join(&*key);
+            }
+            vendor.auth = vendor
+                .auth
+                .take()
+                .map(|auth| auth.relative_to(&project_dir));
+        }
 
         Ok(Project {
             filepath,
@@ -524,8 +832,9 @@ impl UnvalidatedProject {
             schema_version: self.schema_version,
             release_version: self.release_version,
             sdk: self.sdk,
-            vendor: self.vendor.unwrap_or_default(),
-            kit: self.kit.unwrap_or_default(),
+            vendor,
+            kit,
+            path_kit,
             overrides,
             lock: Unlocked,
         })
@@ -544,27 +853,100 @@ impl UnvalidatedProject {
         let overrides_str = read_to_string(&overrides_file_path)
             .await
             .context("failed to read overrides file")?;
-        let overrides: BTreeMap<String, BTreeMap<String, Override>> =
+        let mut overrides: BTreeMap<String, BTreeMap<String, Override>> =
             toml::from_str(overrides_str.as_str())
                 .context("failed to deserialize overrides file")?;
+        for (vendor, artifacts) in overrides.iter_mut() {
+            for (artifact, override_) in artifacts.iter_mut() {
+                if let Some(local_path) = override_.path.as_mut() {
+                    ensure!(
+                        override_.name.is_none() && override_.registry.is_none(),
+                        "the override for '{artifact}' from vendor '{vendor}' cannot set a name or \
+                        registry along with a path"
+                    );
+                    // Paths are relative to the project rather than the working directory.
+                    *local_path = path.as_ref().join(&*local_path);
+                }
+            }
+        }
         Ok(overrides)
     }
 
+    /// Separates kits from a registry from kits built from a local path, and loads the projects of
+    /// the latter. The registry dependencies and SDK of those projects become dependencies of this
+    /// project, so their vendors must also be defined here.
+    async fn load_kit_dependencies(
+        &self,
+        project_dir: &Path,
+    ) -> Result<(Vec<KitRequirement>, Vec<LocalKit>)> {
+        let mut kit = Vec::new();
+        let mut path_kit = Vec::new();
+        for dependency in self.kit.iter().flatten().cloned() {
+            match dependency {
+                KitDependency::Registry(requirement) => kit.push(requirement),
+                KitDependency::Path(dependency) => {
+                    path_kit.push(LocalKit::load(project_dir, dependency).await?)
+                }
+            }
+        }
+        if path_kit.is_empty() {
+            return Ok((kit, path_kit));
+        }
+
+        ensure!(
+            !self
+                .vendor
+                .iter()
+                .flatten()
+                .any(|(name, _)| name.as_ref() == LOCAL_KIT_VENDOR),
+            "the vendor name '{LOCAL_KIT_VENDOR}' is reserved for kits that are built from a path"
+        );
+        for local_kit in path_kit.iter() {
+            ensure!(
+                !kit.iter().any(|kit| &kit.name == local_kit.name()),
+                "kit '{}' is listed both from a registry and from a path",
+                local_kit.name()
+            );
+            let mut dependency_vendors: Vec<&ValidIdentifier> =
+                local_kit.kit_deps().iter().map(|kit| &kit.vendor).collect();
+            dependency_vendors.extend(local_kit.sdk().map(|sdk| &sdk.vendor));
+            for dependency_vendor in dependency_vendors {
+                ensure!(
+                    self.has_vendor(dependency_vendor),
+                    "kit '{}' from '{}' depends on vendor '{dependency_vendor}' which is not \
+                    specified in Twoliter.toml",
+                    local_kit.name(),
+                    local_kit.path().display()
+                );
+            }
+        }
+        Ok((kit, path_kit))
+    }
+
+    fn has_vendor(&self, vendor: &ValidIdentifier) -> bool {
+        self.vendor
+            .as_ref()
+            .is_some_and(|vendors| vendors.contains_key(vendor))
+    }
+
     /// Errors if the user has defined a sdk and/or kit dependency without specifying the associated
     /// vendor
     async fn check_vendor_availability(&self) -> Result<()> {
-        let mut dependency_list = self.kit.clone().unwrap_or_default();
+        let mut dependency_vendors: Vec<&ValidIdentifier> = self
+            .kit
+            .iter()
+            .flatten()
+            .filter_map(|kit| match kit {
+                KitDependency::Registry(kit) => Some(&kit.vendor),
+                KitDependency::Path(_) => None,
+            })
+            .collect();
         if let Some(sdk) = self.sdk.as_ref() {
-            dependency_list.push(sdk.clone());
+            dependency_vendors.push(&sdk.vendor);
         }
-        for dependency in dependency_list.iter() {
+        for dependency_vendor in dependency_vendors {
             ensure!(
-                self.vendor.is_some()
-                    && self
-                        .vendor
-                        .as_ref()
-                        .unwrap()
-                        .contains_key(&dependency.vendor),
+                self.has_vendor(dependency_vendor),
                 "cannot define a dependency on a vendor that is not specified in Twoliter.toml"
             );
         }
@@ -621,7 +1003,11 @@ impl UnvalidatedProject {
 #[async_trait]
 pub(crate) trait ProjectLock: Sized + Debug + Send + Sync + 'static {
     /// Loads the project lock for the given project.
-    async fn load_lock(project: &Project<Unlocked>, _: private::SealToken) -> Result<Self>;
+    async fn load_lock(
+        project: &Project<Unlocked>,
+        mode: LockMode,
+        _: private::SealToken,
+    ) -> Result<Self>;
 
     /// Returns a `VerificationTagger` for this lock type.
     fn verification_tagger(&self, _: private::SealToken) -> VerificationTagger;
@@ -633,7 +1019,11 @@ pub struct Unlocked;
 
 #[async_trait]
 impl ProjectLock for Unlocked {
-    async fn load_lock(_project: &Project<Unlocked>, _: private::SealToken) -> Result<Self> {
+    async fn load_lock(
+        _project: &Project<Unlocked>,
+        _mode: LockMode,
+        _: private::SealToken,
+    ) -> Result<Self> {
         Ok(Unlocked)
     }
 
@@ -648,8 +1038,12 @@ pub struct SDKLocked(LockedSDK);
 
 #[async_trait]
 impl ProjectLock for SDKLocked {
-    async fn load_lock(project: &Project<Unlocked>, _: private::SealToken) -> Result<Self> {
-        LockedSDK::load(project).await.map(Self)
+    async fn load_lock(
+        project: &Project<Unlocked>,
+        mode: LockMode,
+        _: private::SealToken,
+    ) -> Result<Self> {
+        LockedSDK::load(project, mode).await.map(Self)
     }
 
     fn verification_tagger(&self, _: private::SealToken) -> VerificationTagger {
@@ -669,8 +1063,12 @@ pub struct Locked(Lock);
 
 #[async_trait]
 impl ProjectLock for Locked {
-    async fn load_lock(project: &Project<Unlocked>, _: private::SealToken) -> Result<Self> {
-        Lock::load(project).await.map(Self)
+    async fn load_lock(
+        project: &Project<Unlocked>,
+        mode: LockMode,
+        _: private::SealToken,
+    ) -> Result<Self> {
+        Lock::load(project, mode).await.map(Self)
     }
 
     fn verification_tagger(&self, _: private::SealToken) -> VerificationTagger {
@@ -726,10 +1124,69 @@ mod test {
 
         assert_eq!(1, deserialized.kit.len());
         assert_eq!("my-core-kit", deserialized.kit[0].name.to_string());
-        assert_eq!(Version::new(1, 2, 3), deserialized.kit[0].version);
+        assert_eq!(
+            VersionReq::parse("=1.2.3").unwrap(),
+            deserialized.kit[0].version
+        );
+        assert_eq!(
+            deserialized.kit[0].exact_version(),
+            Some(Version::new(1, 2, 3))
+        );
         assert_eq!("my-vendor", deserialized.kit[0].vendor.to_string());
     }
 
+    /// Ensure that kit dependencies can be given as version ranges.
+    #[tokio::test]
+    async fn deserialize_kit_version_requirements() {
+        let path = data_dir().join("Twoliter-2.toml");
+        let deserialized = Project::load(path).await.unwrap();
+
+        assert_eq!(2, deserialized.kit.len());
+        let core_kit = &deserialized.kit[0];
+        assert!(core_kit.version.matches(&Version::new(1, 4, 9)));
+        assert!(!core_kit.version.matches(&Version::new(1, 5, 0)));
+        assert_eq!(core_kit.exact_version(), None);
+
+        let extra_kit = &deserialized.kit[1];
+        assert_eq!(extra_kit.exact_version(), Some(Version::new(2, 0, 1)));
+    }
+
+    #[test]
+    fn test_kit_requirement_from_image() {
+        let image = Image {
+            name: ValidIdentifier("core-kit".into()),
+            version: Version::new(1, 2, 0),
+            vendor: ValidIdentifier("bottlerocket".into()),
+        };
+        let requirement = KitRequirement::exactly(&image);
+        assert!(requirement.version.matches(&Version::new(1, 2, 0)));
+        assert!(!requirement.version.matches(&Version::new(1, 2, 1)));
+        assert_eq!(requirement.exact_version(), Some(Version::new(1, 2, 0)));
+
+        let requirement = KitRequirement::compatible_with(&image);
+        assert!(requirement.version.matches(&Version::new(1, 2, 0)));
+        assert!(requirement.version.matches(&Version::new(1, 9, 1)));
+        assert!(!requirement.version.matches(&Version::new(1, 1, 9)));
+        assert!(!requirement.version.matches(&Version::new(2, 0, 0)));
+        assert_eq!(requirement.exact_version(), None);
+        assert_eq!(requirement.with_version(Version::new(1, 2, 0)), image);
+    }
+
+    #[test]
+    fn test_parse_version_requirement() {
+        let parse = |input| parse_version_requirement(input).unwrap();
+        assert_eq!(parse("1.2.3"), VersionReq::parse("=1.2.3").unwrap());
+        assert_eq!(parse("^1.2"), VersionReq::parse("^1.2").unwrap());
+        assert_eq!(
+            parse(">=1.2, 1.4.0"),
+            VersionReq::parse(">=1.2, =1.4.0").unwrap()
+        );
+        assert_eq!(parse("1.0.0-rc.1").to_string(), "=1.0.0-rc.1");
+        // A partial bare version would be a range, so an operator must be given.
+        assert!(parse_version_requirement("1.2").is_err());
+        assert!(parse_version_requirement("^x").is_err());
+    }
+
     /// Ensure that a `Twoliter.toml` cannot be serialized if the `schema_version` is incorrect.
     #[tokio::test]
     async fn deserialize_invalid_version() {
@@ -807,10 +1264,14 @@ mod test {
                 sdk.vendor_name().clone(),
                 Vendor {
                     registry: "a.com/b".parse().unwrap(),
+                    trusted_keys: Vec::new(),
+                    auth: None,
                 },
                 Override {
                     name: Some("my-overridden-sdk".parse().unwrap()),
                     registry: Some("c.com/d".parse().unwrap()),
+                    path: None,
+                    layout: Default::default(),
                 },
             )
         );
@@ -825,6 +1286,63 @@ mod test {
         )
     }
 
+    /// Ensure that an override path is read relative to the project and can't be combined with a
+    /// registry.
+    #[tokio::test]
+    async fn test_path_override() {
+        let tempdir = TempDir::new().unwrap();
+        let twoliter_toml = tempdir.path().join("Twoliter.toml");
+        let override_file = tempdir.path().join(TWOLITER_OVERRIDES);
+        fs::copy(data_dir().join("Twoliter-1.toml"), &twoliter_toml)
+            .await
+            .unwrap();
+
+        fs::write(
+            &override_file,
+            "[my-vendor.my-core-kit]\npath = \"build/kits/my-core-kit\"\n",
+        )
+        .await
+        .unwrap();
+        let project = Project::load(&twoliter_toml).await.unwrap();
+        let kit = project.as_project_image(&project.kit[0].with_version(Version::new(1, 2, 3)));
+        assert_eq!(
+            kit.unwrap().local_path(),
+            Some(tempdir.path().join("build/kits/my-core-kit").as_path())
+        );
+        let sdk = project.direct_sdk_image_dep().unwrap().unwrap();
+        assert_eq!(sdk.local_path(), None);
+
+        fs::write(
+            &override_file,
+            "[my-vendor.my-core-kit]\npath = \"my-core-kit.tar\"\nregistry = \"c.com/d\"\n",
+        )
+        .await
+        .unwrap();
+        Project::load(&twoliter_toml).await.unwrap_err();
+    }
+
+    /// Ensure that a vendor's trusted keys are read relative to the project.
+    #[tokio::test]
+    async fn test_trusted_keys() {
+        let tempdir = TempDir::new().unwrap();
+        let twoliter_toml = tempdir.path().join("Twoliter.toml");
+        let contents = fs::read_to_string(data_dir().join("Twoliter-1.toml"))
+            .await
+            .unwrap()
+            .replace(
+                "registry = \"a.com/b\"",
+                "registry = \"a.com/b\"\ntrusted-keys = [\"keys/my-vendor.pem\"]",
+            );
+        fs::write(&twoliter_toml, contents).await.unwrap();
+
+        let project = Project::load(&twoliter_toml).await.unwrap();
+        let sdk = project.direct_sdk_image_dep().unwrap().unwrap();
+        assert_eq!(
+            sdk.trusted_keys(),
+            &[tempdir.path().join("keys/my-vendor.pem")]
+        );
+    }
+
     #[tokio::test]
     async fn test_vendor_specifications() {
         let project = UnvalidatedProject {
@@ -839,17 +1357,71 @@ mod test {
                 ValidIdentifier("not-bottlerocket".into()),
                 Vendor {
                     registry: "public.ecr.aws/not-bottlerocket".into(),
+                    trusted_keys: Vec::new(),
+                    auth: None,
                 },
             )])),
-            kit: Some(vec![Image {
+            kit: Some(vec![KitDependency::Registry(KitRequirement {
                 name: ValidIdentifier("bottlerocket-core-kit".into()),
-                version: Version::new(1, 20, 0),
+                version: VersionReq::parse("1.20.0").unwrap(),
                 vendor: ValidIdentifier("not-bottlerocket".into()),
-            }]),
+            })]),
         };
         assert!(project.check_vendor_availability().await.is_err());
     }
 
+    /// Ensure that kits can be given as a path to another project, whose dependencies then need
+    /// vendors in this project.
+    #[tokio::test]
+    async fn deserialize_path_kit() {
+        let tempdir = TempDir::new().unwrap();
+        let kit_project = tempdir.path().join("my-kit-project");
+        fs::create_dir_all(kit_project.join("kits").join("my-kit"))
+            .await
+            .unwrap();
+        fs::copy(
+            data_dir().join("Twoliter-1.toml"),
+            kit_project.join("Twoliter.toml"),
+        )
+        .await
+        .unwrap();
+
+        let consumer = tempdir.path().join("consumer");
+        fs::create_dir_all(&consumer).await.unwrap();
+        let twoliter_toml = consumer.join("Twoliter.toml");
+        let project_toml = |vendors: &[&str]| {
+            let mut toml = String::from("schema-version = 1\nrelease-version = \"1.0.0\"\n");
+            for vendor in vendors {
+                toml.push_str(&format!("[vendor.{vendor}]\nregistry = \"a.com/b\"\n"));
+            }
+            toml.push_str("[[kit]]\nname = \"my-kit\"\npath = \"../my-kit-project\"\n");
+            toml
+        };
+
+        fs::write(&twoliter_toml, project_toml(&["my-vendor"]))
+            .await
+            .unwrap();
+        let project = Project::load(&twoliter_toml).await.unwrap();
+        assert!(project.direct_kit_deps().is_empty());
+        assert_eq!(project.local_kits().len(), 1);
+        assert_eq!(
+            project.local_kits()[0].image().to_string(),
+            "my-kit-1.0.0@local"
+        );
+
+        // The kit's project depends on a vendor which this project does not define
+        fs::write(&twoliter_toml, project_toml(&["other-vendor"]))
+            .await
+            .unwrap();
+        Project::load(&twoliter_toml).await.unwrap_err();
+
+        // The vendor name used for kits from a path is reserved
+        fs::write(&twoliter_toml, project_toml(&["my-vendor", "local"]))
+            .await
+            .unwrap();
+        Project::load(&twoliter_toml).await.unwrap_err();
+    }
+
     #[tokio::test]
     async fn test_release_toml_check_ok() {
         let tempdir = TempDir::new().unwrap();
@@ -2,9 +2,12 @@
 //!
 //! Most users of this module will need [`ArtifactVendor`], which represents a vendor which may have
 //! been overridden in a `Twoliter.override` file.
-use super::{Override, ValidIdentifier, VendedArtifact, Vendor};
+use super::lock::OCILayout;
+use super::{Override, ValidIdentifier, VendedArtifact, Vendor, VersionedArtifact};
 use crate::docker::ImageUri;
+use anyhow::Result;
 use std::fmt::Debug;
+use std::path::{Path, PathBuf};
 
 /// `ArtifactVendor` represents a vendor associated with an image artifact used in a project.
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
@@ -30,7 +33,12 @@ impl ArtifactVendor {
         }
     }
 
-    pub(crate) fn image_uri_for<V: VendedArtifact>(&self, image: &V) -> ImageUri {
+    /// Returns the untagged repository in which all versions of the given artifact are published.
+    pub(crate) fn repository_uri_for<V: VendedArtifact>(&self, image: &V) -> String {
+        format!("{}/{}", self.registry(), self.repo_for(image))
+    }
+
+    pub(crate) fn image_uri_for<V: VersionedArtifact>(&self, image: &V) -> ImageUri {
         ImageUri {
             registry: Some(self.registry().to_string()),
             repo: self.repo_for(image).to_string(),
@@ -38,6 +46,32 @@ impl ArtifactVendor {
         }
     }
 
+    /// Returns the OCI image layout to read the artifact from instead of the registry, if the
+    /// artifact is overridden with a local path.
+    pub(crate) fn local_path(&self) -> Option<&Path> {
+        match self {
+            ArtifactVendor::Verbatim(_) => None,
+            ArtifactVendor::Overridden(vendor) => vendor.local_path(),
+        }
+    }
+    /// Opens the OCI image layout that the artifact is read from, if the artifact is overridden
+    /// with a local path. The layout is only opened once however many times this is called.
+    pub(crate) async fn local_layout(&self) -> Result<Option<&OCILayout>> {
+        match self {
+            ArtifactVendor::Verbatim(_) => Ok(None),
+            ArtifactVendor::Overridden(vendor) => vendor.local_layout().await,
+        }
+    }
+
+    /// Returns the keys that artifacts from the vendor must be signed with. An override does not
+    /// change which keys are trusted, since it only changes where the same images are found.
+    pub(crate) fn trusted_keys(&self) -> &[PathBuf] {
+        match self {
+            ArtifactVendor::Verbatim(vendor) => &vendor.vendor.trusted_keys,
+            ArtifactVendor::Overridden(vendor) => &vendor.original_vendor.trusted_keys,
+        }
+    }
+
     pub(crate) fn vendor_name(&self) -> &ValidIdentifier {
         match self {
             ArtifactVendor::Verbatim(vendor) => &vendor.vendor_name,
@@ -105,6 +139,17 @@ impl OverriddenVendor {
             .unwrap_or(image.artifact_name().as_ref())
     }
 
+    pub(crate) fn local_path(&self) -> Option<&Path> {
+        self.override_.path.as_deref()
+    }
+
+    pub(crate) async fn local_layout(&self) -> Result<Option<&OCILayout>> {
+        match &self.override_.path {
+            Some(path) => self.override_.layout.open(path).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
     pub(crate) fn original_vendor(&self) -> VerbatimVendor {
         VerbatimVendor {
             vendor_name: self.original_vendor_name.clone(),